@@ -0,0 +1,49 @@
+// Compares the latency of dispatching a single ready source, with this source alone in the
+// loop, which takes the dedicated single source path, and with an idle second source forcing
+// the general dispatching path.
+//
+// Run with `cargo bench --bench single_source`. Criterion compares each run with the
+// previous one, so a change can be measured by running it before and after.
+
+extern crate calloop;
+extern crate criterion;
+
+use std::time::Duration;
+
+use calloop::ping::make_ping;
+use calloop::EventLoop;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+fn bench_dispatch(c: &mut Criterion, name: &str, second_source: bool) {
+    let mut event_loop = EventLoop::<u64>::try_new().unwrap();
+    let handle = event_loop.handle();
+    let (ping, source) = make_ping().unwrap();
+    handle
+        .insert_source(source, |(), &mut (), count| *count += 1)
+        .unwrap();
+    let (_idle_ping, idle_source) = make_ping().unwrap();
+    if second_source {
+        handle
+            .insert_source(idle_source, |(), &mut (), _| {})
+            .unwrap();
+    }
+
+    let mut count = 0;
+    c.bench_function(name, |b| {
+        b.iter(|| {
+            ping.ping();
+            event_loop
+                .dispatch(Some(Duration::from_millis(0)), &mut count)
+                .unwrap();
+        })
+    });
+    assert!(count > 0);
+}
+
+fn single_source(c: &mut Criterion) {
+    bench_dispatch(c, "single source", false);
+    bench_dispatch(c, "two sources", true);
+}
+
+criterion_group!(benches, single_source);
+criterion_main!(benches);
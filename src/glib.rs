@@ -0,0 +1,118 @@
+//! Helpers to drive an event loop from the main loop of glib
+//!
+//! Only available with the `glib` cargo feature.
+//!
+//! The main context of glib polls its sources in four steps, described by the callbacks of
+//! `GSourceFuncs`. An [`EventLoop`] can be embedded in it as a custom `GSource`, which
+//! monitors the [file descriptor](poll_fd) of the loop with `g_source_add_unix_fd` and maps
+//! each callback to one of the functions of this module:
+//!
+//! - `prepare` calls [`prepare`], which tells whether the loop can be dispatched right away,
+//!   and otherwise the timeout glib should not wait past, in milliseconds or `-1`
+//! - `query` is handled by glib itself, polling the file descriptor of the loop
+//! - `check` calls [`check`], which tells whether the loop has events to dispatch after the
+//!   poll of glib
+//! - `dispatch` calls [`dispatch`], which dispatches the loop without blocking
+//!
+//! These functions do not depend on the glib crate, so that they can be used with any
+//! binding of glib. The event loop must be dispatched from the thread of its main context.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::glib;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! // in the `prepare` callback of the GSource
+//! let (ready, timeout) = glib::prepare(&event_loop);
+//! // in its `check` callback
+//! let ready = glib::check(&event_loop);
+//! // in its `dispatch` callback
+//! glib::dispatch(&mut event_loop, &mut ()).expect("Failed to dispatch the event loop");
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use crate::EventLoop;
+
+/// The file descriptor glib must monitor for reading
+///
+/// It becomes readable when some events are waiting to be dispatched.
+pub fn poll_fd<Data>(event_loop: &EventLoop<'_, Data>) -> RawFd {
+    event_loop.as_raw_fd()
+}
+
+/// The `prepare` step: whether the loop can be dispatched, and the timeout of the poll
+///
+/// The timeout is the number of milliseconds until the next
+/// [deadline](EventLoop#method.next_deadline) of the sources of the loop, rounded up, or `-1`
+/// if no source is driven by time. It is `0` when the loop can be dispatched right away.
+pub fn prepare<Data>(event_loop: &EventLoop<'_, Data>) -> (bool, i32) {
+    if ready(event_loop) {
+        return (true, 0);
+    }
+    let timeout = match event_loop.time_to_next_event() {
+        Some(left) => ((left.as_nanos() + 999_999) / 1_000_000).min(std::i32::MAX as u128) as i32,
+        None => -1,
+    };
+    (timeout == 0, timeout)
+}
+
+/// The `check` step: whether the loop has events to dispatch
+pub fn check<Data>(event_loop: &EventLoop<'_, Data>) -> bool {
+    ready(event_loop)
+}
+
+/// The `dispatch` step: dispatch the pending events of the loop
+///
+/// This dispatches the loop without waiting for events, glib having already waited.
+pub fn dispatch<Data>(event_loop: &mut EventLoop<'_, Data>, data: &mut Data) -> io::Result<()> {
+    event_loop.dispatch(Duration::from_millis(0), data)
+}
+
+fn ready<Data>(event_loop: &EventLoop<'_, Data>) -> bool {
+    event_loop.time_to_next_event() == Some(Duration::from_millis(0))
+        || event_loop.handle().has_pending_events()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::make_ping;
+    use crate::timer::Timer;
+
+    #[test]
+    fn glib_steps() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+        assert_eq!(prepare(&event_loop), (false, -1));
+        assert!(!check(&event_loop));
+
+        ping.ping();
+        assert!(prepare(&event_loop).0);
+        assert!(check(&event_loop));
+        let mut count = 0;
+        dispatch(&mut event_loop, &mut count).unwrap();
+        assert_eq!(count, 1);
+        assert!(!check(&event_loop));
+
+        // the timeout of glib is bounded by the deadlines of the sources
+        let timer = Timer::<()>::new().unwrap();
+        timer.handle().add_timeout(Duration::from_secs(5), ());
+        event_loop
+            .handle()
+            .insert_source(timer, |(), _, _| {})
+            .unwrap();
+        let (ready, timeout) = prepare(&event_loop);
+        assert!(!ready);
+        assert!(timeout > 4000 && timeout <= 5000);
+    }
+}
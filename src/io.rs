@@ -65,6 +65,7 @@ impl<'l, F: AsRawFd> Async<'l, F> {
             last_readiness: Readiness::EMPTY,
         }));
         let key = inner.sources.borrow_mut().insert(dispatcher.clone());
+        inner.invalidate_sole();
         *(dispatcher.borrow_mut().token) = Token { key, sub_id: 0 };
         inner.register(&dispatcher)?;
 
@@ -206,6 +207,7 @@ impl<'l, Data> IoLoopInner for LoopInner<'l, Data> {
             .borrow_mut()
             .remove(key)
             .expect("Attempting to remove a non-existent source?!");
+        self.invalidate_sole();
     }
 }
 
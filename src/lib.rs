@@ -105,8 +105,34 @@
 //! This crate also provide some adapters for common event sources such as:
 //!
 //! - [MPSC channels](channel)
+//! - [Watched shared state](watch)
 //! - [Timers](timer)
-//! - [unix signals](signals) on Linux
+//! - [Periodic ticks](tick) without file descriptor
+//! - [Flushes scheduled](flush_scheduler) by number of passes and by time
+//! - [unix signals](signals) on Linux, or [received in a thread](threaded_signals) on all platforms
+//! - [POSIX message queues](mqueue) on Linux
+//! - [netlink sockets](netlink) on Linux
+//! - [Pressure stall information](psi) triggers on Linux
+//! - [Control group events](cgroup), like OOM kills, on Linux
+//! - [Changes of the wall clock](clock) on Linux
+//! - [io_uring completions](uring) on Linux, with the `uring` cargo feature
+//! - [The standard input](stdin), line by line or in raw mode
+//! - [The output of child processes](child), line by line
+//! - [Buffered writers](writer) for FD-backed IO objects
+//! - [The connection of a Wayland client](wayland), without depending on a Wayland library
+//! - [Sets of file descriptors](fd_set) delivered to a single callback
+//! - [Separate read and write handlers](split) for a file descriptor
+//! - [Logical streams multiplexed](shared_fd) over a single file descriptor
+//! - [Shared flags](flag) waking up the loop when they are set
+//! - [Counting semaphores](semaphore) invoking the callback once per unit on Linux
+//! - [File descriptors passed](fd_receiver) over Unix sockets
+//! - [Retrying](retry) failing sources with an exponential backoff
+//! - [Debouncing](debounce) the events of a source until it is quiet
+//! - [Dropping the repeated events](dedup) of a source
+//! - [Skipping or taking](limit) the first events of a source
+//! - [Timeouts](timeout) for file descriptors and sources staying idle
+//! - [Broadcasting](broadcast) the events of a source to several subscribers
+//! - [Recording](recent) the latest events of a source for debugging
 //!
 //! As well as generic objects backed by file descriptors.
 //!
@@ -126,6 +152,12 @@
 //! method. Waking up the futures using these objects is handled by the associated [`EventLoop`]
 //! directly.
 //!
+//! ## Integration with glib
+//!
+//! Activating the `glib` cargo feature will add the [`glib`] module, which maps the steps of
+//! the main loop of glib to the methods of an [`EventLoop`], so that it can be driven from a
+//! GTK application.
+//!
 //! ## Custom event sources
 //!
 //! You can create custom event sources can will be inserted in the event loop by
@@ -143,11 +175,16 @@
 
 mod sys;
 
-pub use sys::{Interest, Mode, Poll, Readiness, Token, TokenFactory};
+pub use sys::{Interest, Mode, Poll, Readiness, Registration, Token, TokenFactory};
 
-pub use self::loop_logic::{EventLoop, InsertError, LoopHandle, LoopSignal, RegistrationToken};
+pub use self::loop_logic::{
+    poll_any, CapturedReadiness, EventLoop, IdleState, InsertError, LoopHandle, LoopSignal,
+    PollStats, RegistrationToken, SourceBatch, StopMode,
+};
 pub use self::sources::*;
 
+#[cfg(feature = "glib")]
+pub mod glib;
 pub mod io;
 mod loop_logic;
 mod sources;
@@ -1,19 +1,84 @@
 use std::cell::{Cell, RefCell};
 use std::fmt::{self, Debug, Formatter};
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use slotmap::SlotMap;
 
-use crate::sources::{Dispatcher, EventSource, Idle, IdleDispatcher};
-use crate::{EventDispatcher, Poll, PostAction, TokenFactory};
+use crate::sources::{poll_fn::PollFn, Dispatcher, EventSource, Idle, IdleDispatcher};
+use crate::sys::{PollEvent, EVENTS_CAPACITY};
+use crate::{EventDispatcher, Poll, PostAction, Readiness, Token, TokenFactory};
 
 type IdleCallback<'i, Data> = Rc<RefCell<dyn IdleDispatcher<Data> + 'i>>;
 
+// An idle callback waiting for a number of consecutive passes without events
+struct QuiescentCallback<'i, Data> {
+    passes: u32,
+    empty_passes: u32,
+    callback: IdleCallback<'i, Data>,
+}
+type SlowCallbackHook<'l> = Box<dyn FnMut(RegistrationToken, Duration) + 'l>;
+type BufferOverflowHook<'l> = Box<dyn FnMut(usize) + 'l>;
+type IdleTransitionHook<'l> = Box<dyn FnMut(IdleState) + 'l>;
+type RegistrationPressureHook<'l, Data> = Box<dyn FnMut(&LoopHandle<'l, Data>) + 'l>;
+type SoleSource<'l, Data> = (CalloopKey, Rc<dyn EventDispatcher<Data> + 'l>);
+
+/// How the event loop is about to wait for events
+///
+/// See [`EventLoop::set_idle_transition_hook`](EventLoop#method.set_idle_transition_hook).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleState {
+    /// Nothing is pending, the loop will block until an event is received
+    BlockIndefinitely,
+    /// Nothing is pending, the loop will block until an event is received or this timeout
+    /// is reached
+    BlockFor(Duration),
+    /// The loop will not block, as some events are already pending or the timeout is zero
+    Immediate,
+}
+
+/// How the event loop handles the events of the pass during which it is stopped
+///
+/// See [`EventLoop::set_stop_mode`](EventLoop#method.set_stop_mode).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StopMode {
+    /// Dispatch all the events of the pass before returning
+    Graceful,
+    /// Skip the events of the pass which are not dispatched yet
+    Immediate,
+}
+
+/// What the polling system returned during the last dispatch of the event loop
+///
+/// See [`EventLoop::last_poll_stats`](EventLoop#method.last_poll_stats).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PollStats {
+    /// The number of events returned by the polling system
+    pub events_returned: usize,
+    /// Whether the polling system filled the whole event buffer
+    ///
+    /// The remaining ready file descriptors are then only returned by the next poll.
+    pub buffer_saturated: bool,
+}
+
+/// A readiness event recorded by an event loop in capture mode
+///
+/// See [`EventLoop::capture_mode`](EventLoop#method.capture_mode).
+#[derive(Copy, Clone, Debug)]
+pub struct CapturedReadiness {
+    /// The source which received the event
+    pub token: RegistrationToken,
+    /// The readiness reported by the polling system
+    ///
+    /// It is empty for a source whose [deadline](crate::EventSource#method.next_deadline)
+    /// was reached.
+    pub readiness: Readiness,
+}
+
 slotmap::new_key_type! {
     pub(crate) struct CalloopKey;
 }
@@ -24,6 +89,11 @@ slotmap::new_key_type! {
 /// a [`Dispatcher`] is registered. You can use it to [disable](LoopHandle#method.disable),
 /// [enable](LoopHandle#method.enable), [update`](LoopHandle#method.update),
 /// [remove](LoopHandle#method.remove) or [kill](LoopHandle#method.kill) it.
+///
+/// Registration tokens, like the [`Token`]s derived from them, are slots of the loop reused
+/// once their source is removed: they are not drawn from a numeric range, and a range cannot
+/// be reserved for a subsystem. To tell which part of an application a registration belongs
+/// to, keep the tokens returned by the insertions of each subsystem.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct RegistrationToken {
     key: CalloopKey,
@@ -33,7 +103,58 @@ pub(crate) struct LoopInner<'l, Data> {
     pub(crate) poll: RefCell<Poll>,
     pub(crate) sources: RefCell<SlotMap<CalloopKey, Rc<dyn EventDispatcher<Data> + 'l>>>,
     idles: RefCell<Vec<IdleCallback<'l, Data>>>,
+    quiescent: RefCell<Vec<QuiescentCallback<'l, Data>>>,
     pending_action: Cell<PostAction>,
+    dispatching: Cell<bool>,
+    // the sources processed first during the next pass they are ready in
+    boosted: RefCell<Vec<CalloopKey>>,
+    // the keys of the sources in their order of insertion, including removed ones
+    insertion_order: RefCell<Vec<CalloopKey>>,
+    registration_pressure: RefCell<Option<RegistrationPressureHook<'l, Data>>>,
+    // the only source besides the wakeup one, if there is one, looked up again once the
+    // sources changed
+    sole: RefCell<Option<SoleSource<'l, Data>>>,
+}
+
+impl<'l, Data> LoopInner<'l, Data> {
+    // Forget the sole source, after a source was inserted or removed
+    pub(crate) fn invalidate_sole(&self) {
+        self.sole.replace(None);
+    }
+
+    fn record_insertion(
+        &self,
+        sources: &SlotMap<CalloopKey, Rc<dyn EventDispatcher<Data> + 'l>>,
+        key: CalloopKey,
+    ) {
+        let mut order = self.insertion_order.borrow_mut();
+        if order.len() >= 2 * sources.len() {
+            // forget the removed sources from time to time
+            order.retain(|&key| sources.contains_key(key));
+        }
+        order.push(key);
+    }
+}
+
+// Marks the loop as dispatching for as long as it is alive, even if a callback panics
+struct DispatchGuard<'a>(&'a Cell<bool>);
+
+impl<'a> DispatchGuard<'a> {
+    fn enter(dispatching: &'a Cell<bool>) -> io::Result<DispatchGuard<'a>> {
+        if dispatching.replace(true) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "nested dispatching of an event loop is not supported",
+            ));
+        }
+        Ok(DispatchGuard(dispatching))
+    }
+}
+
+impl<'a> Drop for DispatchGuard<'a> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
 }
 
 /// An handle to an event loop
@@ -96,6 +217,67 @@ impl<E> std::error::Error for InsertError<E> {
     }
 }
 
+/// A set of sources to be inserted together in an event loop
+///
+/// The sources are inserted using [`LoopHandle::insert_batch`](LoopHandle#method.insert_batch),
+/// either all of them or none.
+pub struct SourceBatch<'l, Data> {
+    dispatchers: Vec<Rc<dyn EventDispatcher<Data> + 'l>>,
+}
+
+impl<'l, Data> std::fmt::Debug for SourceBatch<'l, Data> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SourceBatch { ... }")
+    }
+}
+
+impl<'l, Data> Default for SourceBatch<'l, Data> {
+    fn default() -> Self {
+        SourceBatch {
+            dispatchers: Vec::new(),
+        }
+    }
+}
+
+impl<'l, Data> SourceBatch<'l, Data> {
+    /// Create a new empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a source and its callback to the batch
+    ///
+    /// See [`LoopHandle::insert_source`](LoopHandle#method.insert_source).
+    pub fn insert_source<S, F>(&mut self, source: S, callback: F)
+    where
+        S: EventSource + 'l,
+        F: FnMut(S::Event, &mut S::Metadata, &mut Data) -> S::Ret + 'l,
+    {
+        self.register_dispatcher(Dispatcher::new(source, callback));
+    }
+
+    /// Add a `Dispatcher` to the batch
+    ///
+    /// See [`LoopHandle::register_dispatcher`](LoopHandle#method.register_dispatcher).
+    pub fn register_dispatcher<S>(&mut self, dispatcher: Dispatcher<'l, S, Data>)
+    where
+        S: EventSource + 'l,
+    {
+        self.dispatchers
+            .push(dispatcher.clone_as_event_dispatcher());
+    }
+
+    /// The number of sources in the batch
+    pub fn len(&self) -> usize {
+        self.dispatchers.len()
+    }
+
+    /// Whether the batch is empty
+    pub fn is_empty(&self) -> bool {
+        self.dispatchers.is_empty()
+    }
+}
+
 impl<'l, Data> LoopHandle<'l, Data> {
     /// Inserts a new event source in the loop.
     ///
@@ -121,6 +303,53 @@ impl<'l, Data> LoopHandle<'l, Data> {
             })
     }
 
+    /// Inserts a new event source in the loop, retrying once if resources are exhausted
+    ///
+    /// This behaves like [`insert_source`](LoopHandle#method.insert_source), except when the
+    /// registration of the source fails because the process or the system ran out of file
+    /// descriptors (`EMFILE`, `ENFILE`), or the polling system reached its limit of
+    /// registrations (`ENOSPC`). The hook set with
+    /// [`EventLoop::set_registration_pressure_hook`](EventLoop#method.set_registration_pressure_hook)
+    /// is then invoked, giving the application a chance to shed load, for example by removing
+    /// idle sources, and the registration is retried once.
+    ///
+    /// This is best-effort: the error of the second attempt is returned as is, and if no hook
+    /// is set, the registration is not retried. Only the resources allocated when the source
+    /// is registered are covered, not the ones it allocated when it was created.
+    pub fn insert_source_or_retry<S, F>(
+        &self,
+        source: S,
+        callback: F,
+    ) -> Result<RegistrationToken, InsertError<S>>
+    where
+        S: EventSource + 'l,
+        F: FnMut(S::Event, &mut S::Metadata, &mut Data) -> S::Ret + 'l,
+    {
+        let dispatcher = Dispatcher::new(source, callback);
+        let mut ret = self.register_dispatcher(dispatcher.clone());
+        let exhausted = match ret {
+            Err(ref error) => [nix::libc::EMFILE, nix::libc::ENFILE, nix::libc::ENOSPC]
+                .contains(&error.raw_os_error().unwrap_or(0)),
+            Ok(_) => false,
+        };
+        if exhausted {
+            // the hook is taken out while it runs, as it may use this handle
+            let hook = self.inner.registration_pressure.borrow_mut().take();
+            if let Some(mut hook) = hook {
+                hook(self);
+                self.inner
+                    .registration_pressure
+                    .borrow_mut()
+                    .get_or_insert(hook);
+                ret = self.register_dispatcher(dispatcher.clone());
+            }
+        }
+        ret.map_err(|error| InsertError {
+            error,
+            source: dispatcher.into_source_inner(),
+        })
+    }
+
     /// Registers a `Dispatcher` in the loop.
     ///
     /// Use this function if you need access to the event source after its insertion in the loop.
@@ -146,15 +375,131 @@ impl<'l, Data> LoopHandle<'l, Data> {
             sources.remove(key).expect("Source was just inserted?!");
             return Err(error);
         }
+        self.inner.record_insertion(&sources, key);
+        self.inner.invalidate_sole();
 
         Ok(RegistrationToken { key })
     }
 
+    /// Inserts a batch of sources in the loop
+    ///
+    /// Either all the sources are inserted, in which case their tokens are returned in the
+    /// order they were added to the batch, or none of them is: if one of them fails to
+    /// register, the ones already registered are unregistered before the error is returned.
+    /// In that case the sources of the batch are dropped, except for the ones you kept a
+    /// `Dispatcher` to.
+    ///
+    /// The rollback only covers the registration into the polling system. Side effects that
+    /// a source has when it is created are not undone, and are only reverted when it is
+    /// dropped: for example the [`Signals`](crate::signals::Signals) source blocks its signals
+    /// for the thread on creation, and unblocks them on drop. All the sources provided by
+    /// calloop support being rolled back.
+    ///
+    /// Note that the polling systems have no way to register several file descriptors at
+    /// once, so this does not save any system call compared to inserting the sources one
+    /// by one.
+    pub fn insert_batch(&self, batch: SourceBatch<'l, Data>) -> io::Result<Vec<RegistrationToken>> {
+        let mut sources = self.inner.sources.borrow_mut();
+        let mut poll = self.inner.poll.borrow_mut();
+
+        let mut keys = Vec::with_capacity(batch.len());
+        for dispatcher in batch.dispatchers {
+            let key = sources.insert(dispatcher);
+            let ret = sources
+                .get(key)
+                .unwrap()
+                .register(&mut *poll, &mut TokenFactory::new(key));
+            keys.push(key);
+
+            if let Err(error) = ret {
+                sources.remove(key).expect("Source was just inserted?!");
+                keys.pop();
+                // roll back the sources that were already registered
+                for key in keys {
+                    let source = sources.remove(key).expect("Source was just inserted?!");
+                    if let Err(e) = source.unregister(&mut poll) {
+                        log::warn!(
+                            "[calloop] Failed to unregister source from the polling system: {:?}",
+                            e
+                        );
+                    }
+                }
+                return Err(error);
+            }
+        }
+
+        for &key in &keys {
+            self.inner.record_insertion(&sources, key);
+        }
+        self.inner.invalidate_sole();
+        Ok(keys
+            .into_iter()
+            .map(|key| RegistrationToken { key })
+            .collect())
+    }
+
+    /// Check whether some sources have new events waiting to be dispatched
+    ///
+    /// This does not consume the events, which will be dispatched by the next dispatching
+    /// pass as usual. It allows a callback doing a long operation to cooperatively yield:
+    /// it can regularly check this method, and when it returns `true`, save its progress and
+    /// return, resuming its work later from an [idle callback](LoopHandle#method.insert_idle)
+    /// once the other sources have been serviced.
+    ///
+    /// Note that a level-triggered source whose readiness was not fully consumed, including
+    /// the one currently being dispatched, counts as having events waiting.
+    pub fn has_pending_events(&self) -> bool {
+        match self.inner.poll.borrow().has_pending_events() {
+            Ok(pending) => pending,
+            Err(e) => {
+                log::warn!("[calloop] Failed to check for pending events: {:?}", e);
+                false
+            }
+        }
+    }
+
+    /// Whether the event loop is currently dispatching
+    ///
+    /// This is `true` while a dispatching pass of the loop is running, that is when called
+    /// from a callback of the loop, be it the callback of a source, an idle callback or a
+    /// hook. As dispatching is not reentrant, a helper can use it to insert an
+    /// [idle callback](LoopHandle#method.insert_idle) rather than dispatching the loop
+    /// when it is called from within a callback.
+    pub fn is_dispatching(&self) -> bool {
+        self.inner.dispatching.get()
+    }
+
+    /// Inserts a source driven by a readiness predicate
+    ///
+    /// The predicate is checked at the start of each dispatching pass, and the callback is
+    /// invoked if it returns `true`. As long as such a source is registered, the event loop
+    /// does not wait for events, see the [`poll_fn`](crate::poll_fn) module for the
+    /// performance implications.
+    pub fn insert_poll_fn<P, F>(
+        &self,
+        predicate: P,
+        mut callback: F,
+    ) -> io::Result<RegistrationToken>
+    where
+        P: FnMut() -> bool + 'l,
+        F: FnMut(&mut Data) + 'l,
+    {
+        self.insert_source(PollFn::new(predicate), move |(), &mut (), data| {
+            callback(data)
+        })
+        .map_err(Into::into)
+    }
+
     /// Inserts an idle callback.
     ///
     /// This callback will be called during a dispatching cycle when the event loop has
     /// finished processing all pending events from the sources and becomes idle.
-    pub fn insert_idle<F: FnOnce(&mut Data) + 'l>(&self, callback: F) -> Idle {
+    ///
+    /// The idle callbacks are run in the order of their insertion. The callbacks inserted by
+    /// an idle callback are not run during the same cycle, but during the next one, after
+    /// the events of the sources. Cancelling an idle callback which was not run yet, even from
+    /// within another idle callback of the same cycle, prevents it from running.
+    pub fn insert_idle<F: FnOnce(&mut Data) + 'l>(&self, callback: F) -> Idle<'l> {
         let mut opt_cb = Some(callback);
         let callback = Rc::new(RefCell::new(Some(move |data: &mut Data| {
             if let Some(cb) = opt_cb.take() {
@@ -165,6 +510,35 @@ impl<'l, Data> LoopHandle<'l, Data> {
         Idle { callback }
     }
 
+    /// Inserts a callback to be run once the loop has quiesced
+    ///
+    /// This callback will be called once the event loop has gone through `passes`
+    /// consecutive dispatching cycles without receiving any event, counted from the
+    /// insertion of the callback. Any event received resets the count. It is then run
+    /// like an [idle callback](LoopHandle#method.insert_idle), at the end of the last of
+    /// these cycles.
+    ///
+    /// This is useful to defer some work until the startup activity of a program has
+    /// settled down.
+    pub fn insert_on_quiescent<F: FnOnce(&mut Data) + 'l>(
+        &self,
+        passes: u32,
+        callback: F,
+    ) -> Idle<'l> {
+        let mut opt_cb = Some(callback);
+        let callback = Rc::new(RefCell::new(Some(move |data: &mut Data| {
+            if let Some(cb) = opt_cb.take() {
+                cb(data);
+            }
+        })));
+        self.inner.quiescent.borrow_mut().push(QuiescentCallback {
+            passes,
+            empty_passes: 0,
+            callback: callback.clone(),
+        });
+        Idle { callback }
+    }
+
     /// Enables this previously disabled event source.
     ///
     /// This previously disabled source will start generating events again.
@@ -197,6 +571,42 @@ impl<'l, Data> LoopHandle<'l, Data> {
         Ok(())
     }
 
+    /// Replace the wrapped object of a [`Generic`](crate::generic::Generic) source right away
+    ///
+    /// The new object is registered under the same token before the old one is unregistered,
+    /// and the source keeps its callback. The old object is given back, and is no longer
+    /// polled: the events which were pending on it can still be drained from it. See
+    /// [`Generic::swap_file`](crate::generic::Generic#method.swap_file).
+    ///
+    /// Like [`Dispatcher::as_source_mut`](crate::Dispatcher#method.as_source_mut), this panics
+    /// if invoked from within the callback of the source.
+    pub fn swap_file<F: AsRawFd + 'l>(
+        &self,
+        dispatcher: &Dispatcher<'l, crate::generic::Generic<F>, Data>,
+        file: F,
+    ) -> io::Result<F> {
+        dispatcher
+            .as_source_mut()
+            .swap_file(file, &mut self.inner.poll.borrow_mut())
+    }
+
+    /// Makes the polling system check the file descriptors of this source again
+    ///
+    /// A source registered in [edge-triggered](crate::Mode::Edge) mode is only notified when
+    /// its file descriptor becomes ready. If the program reads the file descriptor by itself,
+    /// outside of the callback, or leaves some data unread, the source may never be notified
+    /// again for the data that were already there. Rearming the source updates its
+    /// registration, and the polling system then notifies it if its file descriptor is still
+    /// ready.
+    ///
+    /// This is not needed for the level-triggered sources, which are notified as long as their
+    /// file descriptor is ready, like the [`Signals`](crate::signals::Signals) source. Like
+    /// [`update`](LoopHandle#method.update), if called from within the callback of the source,
+    /// the source is rearmed once the callback returns.
+    pub fn rearm(&self, token: &RegistrationToken) -> io::Result<()> {
+        self.update(token)
+    }
+
     /// Disables this event source.
     ///
     /// The source remains in the event loop, but it'll no longer generate events
@@ -211,8 +621,17 @@ impl<'l, Data> LoopHandle<'l, Data> {
     }
 
     /// Removes this source from the event loop.
+    ///
+    /// A source can remove itself from within its callback, for example once a one-shot
+    /// handshake is over. It then stops receiving events right away, but it is only
+    /// unregistered from the polling system and dropped at the end of the dispatching pass.
     pub fn remove(&self, token: RegistrationToken) {
+        self.inner
+            .boosted
+            .borrow_mut()
+            .retain(|&key| key != token.key);
         if let Some(source) = self.inner.sources.borrow_mut().remove(token.key) {
+            self.inner.invalidate_sole();
             if let Err(e) = source.unregister(&mut self.inner.poll.borrow_mut()) {
                 log::warn!(
                     "[calloop] Failed to unregister source from the polling system: {:?}",
@@ -222,6 +641,23 @@ impl<'l, Data> LoopHandle<'l, Data> {
         }
     }
 
+    /// Process the events of a source first, the next time it is ready
+    ///
+    /// During the next dispatching pass where this source has events, they are processed
+    /// before the events of all the other sources, instead of the order in which the polling
+    /// system reported them. The boost is one-shot: it is cleared after this pass, and the
+    /// following passes process the source as usual. Boosting several sources processes all
+    /// of them first, in the order in which they were boosted.
+    ///
+    /// This can be used for a control source which must be handled promptly, for example to
+    /// receive a throttling command once an overload was detected.
+    pub fn request_priority_boost(&self, token: &RegistrationToken) {
+        let mut boosted = self.inner.boosted.borrow_mut();
+        if !boosted.contains(&token.key) {
+            boosted.push(token.key);
+        }
+    }
+
     /// Wrap an IO object into an async adapter
     ///
     /// This adapter turns the IO object into an async-aware one that can be used in futures.
@@ -241,6 +677,25 @@ pub struct EventLoop<'l, Data> {
     handle: LoopHandle<'l, Data>,
     stop_signal: Arc<AtomicBool>,
     ping: crate::sources::ping::Ping,
+    slow_callback: Option<(Duration, SlowCallbackHook<'l>)>,
+    buffer_budget: Option<(usize, BufferOverflowHook<'l>)>,
+    idle_transition: Option<IdleTransitionHook<'l>>,
+    coalesce_window: Option<Duration>,
+    // the key of the ping source waking up the loop when it is stopped
+    signal_key: CalloopKey,
+    // the read end of this ping, waited for during the coalescing window
+    signal_fd: RawFd,
+    // whether the sources were torn down since the loop last started running
+    shut_down: bool,
+    // whether the loop is running, as opposed to being dispatched by hand
+    running: bool,
+    stop_mode: StopMode,
+    last_poll: PollStats,
+    // the sources disabled while capturing, if the loop is in capture mode
+    capture: Option<Vec<CalloopKey>>,
+    captured: Vec<CapturedReadiness>,
+    // the ready events left for the next calls to `step`
+    stepped: Vec<PollEvent>,
 }
 
 impl<'l, Data> std::fmt::Debug for EventLoop<'l, Data> {
@@ -254,21 +709,55 @@ impl<'l, Data> EventLoop<'l, Data> {
     ///
     /// Fails if the initialization of the polling system failed.
     pub fn try_new() -> io::Result<Self> {
-        let poll = Poll::new()?;
+        Self::from_poll(Poll::new()?)
+    }
+
+    /// Create a new event loop using the provided polling system
+    ///
+    /// This allows you to create the [`Poll`] yourself before the event loop, for example to
+    /// handle its creation failure separately.
+    ///
+    /// The event loop takes ownership of the `Poll`, and all registrations must then go
+    /// through it. In particular, calloop cannot share its token space with other code:
+    /// every event returned by the polling system is interpreted as belonging to one of the
+    /// sources of this event loop.
+    ///
+    /// Fails if the initialization of the internal wakeup source failed.
+    pub fn from_poll(poll: Poll) -> io::Result<Self> {
         let handle = LoopHandle {
             inner: Rc::new(LoopInner {
                 poll: RefCell::new(poll),
                 sources: RefCell::new(SlotMap::with_key()),
                 idles: RefCell::new(Vec::new()),
+                quiescent: RefCell::new(Vec::new()),
                 pending_action: Cell::new(PostAction::Continue),
+                dispatching: Cell::new(false),
+                boosted: RefCell::new(Vec::new()),
+                insertion_order: RefCell::new(Vec::new()),
+                registration_pressure: RefCell::new(None),
+                sole: RefCell::new(None),
             }),
         };
         let (ping, ping_source) = crate::sources::ping::make_ping()?;
-        handle.insert_source(ping_source, |_, _, _| {})?;
+        let signal_fd = ping_source.raw_fd();
+        let signal_key = handle.insert_source(ping_source, |_, _, _| {})?.key;
         Ok(EventLoop {
             handle,
             stop_signal: Arc::new(AtomicBool::new(false)),
             ping,
+            slow_callback: None,
+            buffer_budget: None,
+            idle_transition: None,
+            coalesce_window: None,
+            signal_key,
+            signal_fd,
+            shut_down: false,
+            running: false,
+            stop_mode: StopMode::Graceful,
+            last_poll: PollStats::default(),
+            capture: None,
+            captured: Vec::new(),
+            stepped: Vec::new(),
         })
     }
 
@@ -277,12 +766,292 @@ impl<'l, Data> EventLoop<'l, Data> {
         self.handle.clone()
     }
 
-    fn dispatch_events(
-        &mut self,
-        mut timeout: Option<Duration>,
-        data: &mut Data,
-    ) -> io::Result<()> {
-        let events = {
+    /// Whether the event loop is currently dispatching
+    ///
+    /// See [`LoopHandle::is_dispatching`](LoopHandle#method.is_dispatching).
+    pub fn is_dispatching(&self) -> bool {
+        self.handle.is_dispatching()
+    }
+
+    /// Record the readiness events instead of dispatching them
+    ///
+    /// This is a debugging aid, to pause the processing of the events and inspect them. In
+    /// capture mode, the events received by the sources are not given to them: they are
+    /// recorded, and can be retrieved with
+    /// [`take_captured`](EventLoop#method.take_captured). The internal wakeup of the loop,
+    /// the idle callbacks and the hooks keep running as usual.
+    ///
+    /// As the sources do not process their events, the file descriptors are not drained:
+    /// the loop does not know how to read the file descriptors of the sources, and reading
+    /// them on its own would lose the data they would have delivered. A level-triggered
+    /// source would thus wake the loop up again immediately. To avoid spinning, each source
+    /// is disabled when it receives its first event in capture mode, and enabled again when
+    /// leaving capture mode, which thus replays the readiness of the file descriptors still
+    /// ready. An edge-triggered source is only notified again on the
+    /// next change of readiness of its file descriptor: the data left unread while capturing
+    /// is not lost, but it is not announced again until new data arrives. Sources which rely
+    /// on reading all their data on each event, like the channels, should not be captured
+    /// for long.
+    ///
+    /// The sources disabled through the [`LoopHandle`] while capturing are enabled again as
+    /// well when leaving capture mode, if they received an event in between.
+    pub fn capture_mode(&mut self, capture: bool) -> io::Result<()> {
+        if capture {
+            if self.capture.is_none() {
+                self.capture = Some(Vec::new());
+            }
+            return Ok(());
+        }
+        for key in self.capture.take().unwrap_or_default() {
+            let disp = self.handle.inner.sources.borrow().get(key).cloned();
+            if let Some(disp) = disp {
+                disp.register(
+                    &mut self.handle.inner.poll.borrow_mut(),
+                    &mut TokenFactory::new(key),
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieve the events recorded in capture mode, since the last call to this method
+    ///
+    /// See [`capture_mode`](EventLoop#method.capture_mode).
+    pub fn take_captured(&mut self) -> Vec<CapturedReadiness> {
+        std::mem::take(&mut self.captured)
+    }
+
+    /// Set a hook to be notified of slow event sources
+    ///
+    /// Whenever processing the events of a source (which includes running its callback)
+    /// takes longer than `threshold`, the hook is invoked with the token of this source and
+    /// the time it took. This is useful to detect callbacks that block and stall the whole
+    /// event loop.
+    ///
+    /// Setting a new hook replaces the previous one. When no hook is set, the processing
+    /// of the sources is not timed at all.
+    pub fn set_slow_callback_hook<F>(&mut self, threshold: Duration, hook: F)
+    where
+        F: FnMut(RegistrationToken, Duration) + 'l,
+    {
+        self.slow_callback = Some((threshold, Box::new(hook)));
+    }
+
+    /// Remove the hook set by [`set_slow_callback_hook`](EventLoop#method.set_slow_callback_hook)
+    pub fn clear_slow_callback_hook(&mut self) {
+        self.slow_callback = None;
+    }
+
+    /// Set a budget on the memory buffered by the event sources
+    ///
+    /// At the end of each dispatching pass, once the sources have been
+    /// [flushed](EventSource#method.flush), the [buffered bytes](EventSource#method.buffered_bytes)
+    /// reported by all the sources of the loop are summed. If the total exceeds `max_bytes`,
+    /// the hook is invoked with it, so that the program can shed load, for example by
+    /// disabling the sources producing the data or dropping some clients.
+    ///
+    /// The budget is only checked once per pass: a source can exceed it while processing its
+    /// events. Sources that do not report their buffers, like the [channels](crate::channel)
+    /// whose messages cannot be measured, are not accounted for.
+    ///
+    /// Setting a new budget replaces the previous one. When no budget is set, the buffers of
+    /// the sources are not measured at all.
+    pub fn set_buffer_budget<F>(&mut self, max_bytes: usize, hook: F)
+    where
+        F: FnMut(usize) + 'l,
+    {
+        self.buffer_budget = Some((max_bytes, Box::new(hook)));
+    }
+
+    /// Remove the budget set by [`set_buffer_budget`](EventLoop#method.set_buffer_budget)
+    pub fn clear_buffer_budget(&mut self) {
+        self.buffer_budget = None;
+    }
+
+    /// Set how the loop handles the events of the pass during which it is stopped
+    ///
+    /// The internal wakeup of the loop, sent by [`LoopSignal::wakeup`], is always processed
+    /// before the events of the sources in a dispatching pass. With the default
+    /// [`StopMode::Graceful`], the loop stopped by [`LoopSignal::stop`] still dispatches all
+    /// the events of the pass, and [`run`](EventLoop#method.run) returns afterwards. With
+    /// [`StopMode::Immediate`], the events which are not dispatched yet once the loop is
+    /// stopped are skipped, so that a stop request takes effect promptly even when many
+    /// sources are ready at once. This only applies while the loop is running.
+    ///
+    /// The skipped events are not delivered: the level-triggered sources are notified again
+    /// the next time the loop is dispatched, but the edge-triggered ones are not.
+    pub fn set_stop_mode(&mut self, mode: StopMode) {
+        self.stop_mode = mode;
+    }
+
+    /// Set a hook to be notified of how the loop is about to wait for events
+    ///
+    /// The hook is invoked during each dispatching pass, right before the loop polls for
+    /// events, with the [`IdleState`] describing this wait: whether the loop will genuinely
+    /// block because there is nothing to do, or return immediately because some events
+    /// are already pending. This can be used for example to submit a last frame before the
+    /// program goes to sleep.
+    ///
+    /// The loop is only aware of the timeout given to
+    /// [`dispatch`](EventLoop#method.dispatch): timers managed by event sources are not
+    /// taken into account. Setting a new hook replaces the previous one.
+    pub fn set_idle_transition_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(IdleState) + 'l,
+    {
+        self.idle_transition = Some(Box::new(hook));
+    }
+
+    /// Remove the hook set by [`set_idle_transition_hook`](EventLoop#method.set_idle_transition_hook)
+    pub fn clear_idle_transition_hook(&mut self) {
+        self.idle_transition = None;
+    }
+
+    /// Set a hook to shed load when the registration of a source exhausts resources
+    ///
+    /// The hook is invoked by
+    /// [`LoopHandle::insert_source_or_retry`](LoopHandle#method.insert_source_or_retry)
+    /// before it retries a registration that failed for lack of resources. It receives a
+    /// handle to the loop, which it can use to remove sources. Setting a new hook replaces
+    /// the previous one.
+    pub fn set_registration_pressure_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&LoopHandle<'l, Data>) + 'l,
+    {
+        *self.handle.inner.registration_pressure.borrow_mut() = Some(Box::new(hook));
+    }
+
+    /// Remove the hook set by
+    /// [`set_registration_pressure_hook`](EventLoop#method.set_registration_pressure_hook)
+    pub fn clear_registration_pressure_hook(&mut self) {
+        *self.handle.inner.registration_pressure.borrow_mut() = None;
+    }
+
+    fn idle_state(&self, timeout: Option<Duration>) -> IdleState {
+        let poll = self.handle.inner.poll.borrow();
+        if timeout == Some(Duration::from_millis(0))
+            || poll.has_busy_sources()
+            || poll.has_pending_events().unwrap_or(false)
+        {
+            return IdleState::Immediate;
+        }
+        match timeout {
+            Some(timeout) => IdleState::BlockFor(timeout),
+            None => IdleState::BlockIndefinitely,
+        }
+    }
+
+    /// What the polling system returned during the last dispatch
+    ///
+    /// The polling system returns at most 32 events at once. If its buffer is saturated
+    /// during many dispatches, the loop needs several passes to process all the file
+    /// descriptors that are ready at the same time. The events received during the
+    /// [coalescing window](EventLoop#method.set_coalesce_window) are not counted.
+    pub fn last_poll_stats(&self) -> PollStats {
+        self.last_poll
+    }
+
+    /// Set a coalescing window for the wakeups of the loop
+    ///
+    /// When the loop is woken up by some events, it waits for `window` before dispatching
+    /// them, gathering the events that become ready in the meantime. Under heavy activity,
+    /// for example many pings or channel messages sent from other threads, this batches the
+    /// work into fewer dispatching passes.
+    ///
+    /// This trades latency for throughput: every event is delayed by up to `window` before
+    /// being dispatched, so it should be kept short. The window never extends past the
+    /// timeout given to the dispatch, and is cut short when a [`LoopSignal`] stops or wakes
+    /// up the loop. It does not apply when the loop is woken up by a [`LoopSignal`], or only
+    /// at the deadline of a source.
+    pub fn set_coalesce_window(&mut self, window: Duration) {
+        self.coalesce_window = Some(window);
+    }
+
+    /// Remove the window set by [`set_coalesce_window`](EventLoop#method.set_coalesce_window)
+    pub fn clear_coalesce_window(&mut self) {
+        self.coalesce_window = None;
+    }
+
+    // Wait for the coalescing window, and add the events received in the meantime
+    fn coalesce_events(
+        &self,
+        mut events: Vec<PollEvent>,
+        window: Duration,
+    ) -> io::Result<Vec<PollEvent>> {
+        use nix::poll::{poll, PollFd, PollFlags};
+
+        // The level-triggered sources already ready would keep the polling system from
+        // blocking, so only the wakeup of the loop is waited for, which ends the window early.
+        let end = Instant::now() + window;
+        let mut fds = [PollFd::new(self.signal_fd, PollFlags::POLLIN)];
+        loop {
+            let left = end.saturating_duration_since(Instant::now());
+            let left = ((left.as_nanos() + 999_999) / 1_000_000).min(std::i32::MAX as u128);
+            match poll(&mut fds, left as i32) {
+                Ok(_) => break,
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let more = match self
+            .handle
+            .inner
+            .poll
+            .borrow_mut()
+            .poll(Some(Duration::from_millis(0)))
+        {
+            Ok(more) => more,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        merge_events(&mut events, more);
+        Ok(events)
+    }
+
+    // The sources woken up by the loop at their deadline, with these deadlines
+    fn deadline_sources(&self) -> Vec<(Token, Instant)> {
+        let inner = &self.handle.inner;
+        let poll = inner.poll.borrow();
+        let sources = inner.sources.borrow();
+        poll.deadline_sources()
+            .iter()
+            .filter_map(|&token| {
+                let deadline = sources.get(token.key)?.next_deadline()?;
+                Some((token, deadline))
+            })
+            .collect()
+    }
+
+    fn dispatch_events(&mut self, timeout: Option<Duration>, data: &mut Data) -> io::Result<bool> {
+        let events = self.poll_events(timeout)?;
+        self.process_ready(events, data)
+    }
+
+    // Poll the ready events, along with those left by `step`, in the order of processing
+    fn poll_events(&mut self, mut timeout: Option<Duration>) -> io::Result<Vec<PollEvent>> {
+        if !self.stepped.is_empty() {
+            // some sources are already known to be ready
+            timeout = Some(Duration::from_millis(0));
+        }
+        // the coalescing window is bounded by the timeout, not by the deadlines of the sources
+        let coalesce_end = timeout.map(|timeout| Instant::now() + timeout);
+        if self.idle_transition.is_some() {
+            let state = self.idle_state(timeout);
+            if let Some(hook) = &mut self.idle_transition {
+                hook(state);
+            }
+        }
+
+        let deadlines = self.deadline_sources();
+        if let Some(deadline) = deadlines.iter().map(|&(_, deadline)| deadline).min() {
+            // wake up at the deadline, rounded up to the granularity of the polling system
+            let left = deadline.saturating_duration_since(Instant::now());
+            let left = Duration::from_millis(((left.as_nanos() + 999_999) / 1_000_000) as u64);
+            timeout = Some(timeout.map_or(left, |timeout| timeout.min(left)));
+        }
+
+        self.last_poll = PollStats::default();
+        let mut events = {
             let mut poll = self.handle.inner.poll.borrow_mut();
             loop {
                 let now = std::time::Instant::now();
@@ -295,7 +1064,8 @@ impl<'l, Data> EventLoop<'l, Data> {
                         if let Some(to) = timeout {
                             let elapsed = now.elapsed();
                             if elapsed >= to {
-                                return Ok(());
+                                // the events left by `step` are still processed
+                                break Vec::new();
                             } else {
                                 timeout = Some(to - elapsed);
                             }
@@ -305,48 +1075,188 @@ impl<'l, Data> EventLoop<'l, Data> {
                 };
             }
         };
+        self.last_poll = PollStats {
+            events_returned: events.len(),
+            buffer_saturated: events.len() == EVENTS_CAPACITY,
+        };
 
-        for event in events {
-            let opt_disp = self
-                .handle
-                .inner
-                .sources
-                .borrow()
-                .get(event.token.key)
-                .cloned();
+        if let Some(window) = self.coalesce_window {
+            // there is no window if the loop is already woken up
+            if !events.is_empty()
+                && events
+                    .iter()
+                    .all(|event| event.token.key != self.signal_key)
+            {
+                let window = match coalesce_end {
+                    Some(end) => window.min(end.saturating_duration_since(Instant::now())),
+                    None => window,
+                };
+                events = self.coalesce_events(events, window)?;
+            }
+        }
 
-            if let Some(disp) = opt_disp {
-                let mut ret = disp.process_events(event.readiness, event.token, data)?;
+        if !deadlines.is_empty() {
+            // the sources whose deadline passed are processed like the ready ones
+            let now = Instant::now();
+            for (token, deadline) in deadlines {
+                if deadline <= now && !events.iter().any(|event| event.token == token) {
+                    events.push(PollEvent {
+                        readiness: Readiness::EMPTY,
+                        token,
+                    });
+                }
+            }
+        }
 
-                // if the returned PostAction is Continue, it may be overwritten by an user-specified pending action
-                let pending_action = self
-                    .handle
-                    .inner
-                    .pending_action
-                    .replace(PostAction::Continue);
-                if let PostAction::Continue = ret {
-                    ret = pending_action;
+        if !self.stepped.is_empty() {
+            // the events left by `step` go first, those of the removed sources are dropped
+            let mut stepped = std::mem::take(&mut self.stepped);
+            let sources = self.handle.inner.sources.borrow();
+            stepped.retain(|event| sources.contains_key(event.token.key));
+            merge_events(&mut stepped, events);
+            events = stepped;
+        }
+
+        {
+            let mut boosted = self.handle.inner.boosted.borrow_mut();
+            if !boosted.is_empty() {
+                // the boosted sources which are ready go first, and lose their boost
+                let rank = |key: CalloopKey| boosted.iter().position(|&k| k == key);
+                events.sort_by_key(|event| rank(event.token.key).unwrap_or(std::usize::MAX));
+                boosted.retain(|&key| !events.iter().any(|event| event.token.key == key));
+            }
+        }
+
+        // the wakeup goes first, so that a stop request is seen before the sources
+        if let Some(pos) = events
+            .iter()
+            .position(|event| event.token.key == self.signal_key)
+        {
+            let wakeup = events.remove(pos);
+            events.insert(0, wakeup);
+        }
+
+        Ok(events)
+    }
+
+    // Process the ready events, returning whether there were some
+    fn process_ready(&mut self, events: Vec<PollEvent>, data: &mut Data) -> io::Result<bool> {
+        // post actions are only applied once all events of this pass have been processed
+        let mut post_actions: Vec<(CalloopKey, Rc<dyn EventDispatcher<Data> + 'l>, PostAction)> =
+            Vec::new();
+        // the sources which removed themselves from within their callback, kept alive until
+        // the end of the pass
+        let mut removed: Vec<(CalloopKey, Rc<dyn EventDispatcher<Data> + 'l>)> = Vec::new();
+        let mut result = Ok(());
+        let received = !events.is_empty();
+
+        // With a single source besides the wakeup one, only this source can remove itself
+        // during the pass: its dispatcher is kept until the sources change, rather than looked
+        // up for each event.
+        let mut sole = {
+            let mut sole = self.handle.inner.sole.borrow_mut();
+            if sole.is_none() {
+                let sources = self.handle.inner.sources.borrow();
+                if sources.len() == 2 {
+                    *sole = sources
+                        .iter()
+                        .find(|&(key, _)| key != self.signal_key)
+                        .map(|(key, disp)| (key, disp.clone()));
                 }
+            }
+            sole.clone()
+        };
 
-                match ret {
-                    PostAction::Reregister => {
-                        disp.reregister(
-                            &mut self.handle.inner.poll.borrow_mut(),
-                            &mut TokenFactory::new(event.token.key),
-                        )?;
-                    }
-                    PostAction::Disable => {
-                        disp.unregister(&mut self.handle.inner.poll.borrow_mut())?;
-                    }
-                    PostAction::Remove => {
-                        // delete the source from the list, it'll be cleaned up with the if just below
-                        self.handle
+        for event in events {
+            if self.running
+                && self.stop_mode == StopMode::Immediate
+                && event.token.key != self.signal_key
+                && self.stop_signal.load(Ordering::Acquire)
+            {
+                break;
+            }
+            let pending = post_actions
+                .iter()
+                .find(|(key, _, _)| *key == event.token.key)
+                .map(|(_, _, action)| action);
+            if let Some(PostAction::Disable) | Some(PostAction::Remove) = pending {
+                // this source asked not to receive any more events
+                continue;
+            }
+            if removed.iter().any(|&(key, _)| key == event.token.key) {
+                continue;
+            }
+
+            if let Some(disabled) = &mut self.capture {
+                if event.token.key != self.signal_key {
+                    self.captured.push(CapturedReadiness {
+                        token: RegistrationToken {
+                            key: event.token.key,
+                        },
+                        readiness: event.readiness,
+                    });
+                    if !disabled.contains(&event.token.key) {
+                        disabled.push(event.token.key);
+                        let disp = self
+                            .handle
                             .inner
                             .sources
-                            .borrow_mut()
-                            .remove(event.token.key);
+                            .borrow()
+                            .get(event.token.key)
+                            .cloned();
+                        if let Some(disp) = disp {
+                            // like the post actions, a failure does not abort the pass
+                            let ret = disp.unregister(&mut self.handle.inner.poll.borrow_mut());
+                            if let (Ok(()), Err(e)) = (&result, ret) {
+                                result = Err(e);
+                            }
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            let opt_disp = match sole {
+                Some((key, ref disp)) if key == event.token.key => Some(disp.clone()),
+                _ => self
+                    .handle
+                    .inner
+                    .sources
+                    .borrow()
+                    .get(event.token.key)
+                    .cloned(),
+            };
+
+            if let Some(disp) = opt_disp {
+                let start = self.slow_callback.as_ref().map(|_| Instant::now());
+                let ret = disp.process_events(event.readiness, event.token, data);
+                if let (Some(start), Some((threshold, hook))) = (start, &mut self.slow_callback) {
+                    let elapsed = start.elapsed();
+                    if elapsed >= *threshold {
+                        hook(
+                            RegistrationToken {
+                                key: event.token.key,
+                            },
+                            elapsed,
+                        );
+                    }
+                }
+                let mut ret = match ret {
+                    Ok(ret) => ret,
+                    Err(e) => {
+                        result = Err(e);
+                        break;
                     }
-                    PostAction::Continue => {}
+                };
+
+                // if the returned PostAction is Continue, it may be overwritten by an user-specified pending action
+                let pending_action = self
+                    .handle
+                    .inner
+                    .pending_action
+                    .replace(PostAction::Continue);
+                if let PostAction::Continue = ret {
+                    ret = pending_action;
                 }
 
                 if !self
@@ -356,14 +1266,27 @@ impl<'l, Data> EventLoop<'l, Data> {
                     .borrow()
                     .contains_key(event.token.key)
                 {
-                    // the source has been removed from within its callback, unregister it
-                    let mut poll = self.handle.inner.poll.borrow_mut();
-                    if let Err(e) = disp.unregister(&mut *poll) {
-                        log::warn!(
-                            "[calloop] Failed to unregister source from the polling system: {:?}",
-                            e
-                        );
+                    // the source has been removed from within its callback, it is unregistered
+                    // and dropped once the pass is over
+                    if sole.as_ref().map(|&(key, _)| key) == Some(event.token.key) {
+                        sole = None;
+                    }
+                    removed.push((event.token.key, disp));
+                    continue;
+                }
+
+                if let PostAction::Continue = ret {
+                    continue;
+                }
+                if let Some(entry) = post_actions
+                    .iter_mut()
+                    .find(|(key, _, _)| *key == event.token.key)
+                {
+                    if action_rank(&ret) > action_rank(&entry.2) {
+                        entry.2 = ret;
                     }
+                } else {
+                    post_actions.push((event.token.key, disp, ret));
                 }
             } else {
                 log::warn!(
@@ -373,19 +1296,138 @@ impl<'l, Data> EventLoop<'l, Data> {
             }
         }
 
+        for (_, disp) in removed {
+            let mut poll = self.handle.inner.poll.borrow_mut();
+            if let Err(e) = disp.unregister(&mut *poll) {
+                log::warn!(
+                    "[calloop] Failed to unregister source from the polling system: {:?}",
+                    e
+                );
+            }
+        }
+
+        for (key, disp, action) in post_actions {
+            let ret = self.apply_post_action(key, &disp, action);
+            if result.is_ok() {
+                result = ret;
+            }
+        }
+
+        result.map(|()| received)
+    }
+
+    fn apply_post_action(
+        &self,
+        key: CalloopKey,
+        disp: &Rc<dyn EventDispatcher<Data> + 'l>,
+        action: PostAction,
+    ) -> io::Result<()> {
+        if !self.handle.inner.sources.borrow().contains_key(key) {
+            // the source was removed by an other callback of this pass, and
+            // has already been unregistered
+            return Ok(());
+        }
+        match action {
+            PostAction::Reregister | PostAction::SetInterest(_) => {
+                disp.reregister(
+                    &mut self.handle.inner.poll.borrow_mut(),
+                    &mut TokenFactory::new(key),
+                )?;
+            }
+            PostAction::Disable => {
+                disp.unregister(&mut self.handle.inner.poll.borrow_mut())?;
+            }
+            PostAction::Remove => {
+                self.handle.inner.sources.borrow_mut().remove(key);
+                self.handle.inner.invalidate_sole();
+                if let Err(e) = disp.unregister(&mut self.handle.inner.poll.borrow_mut()) {
+                    log::warn!(
+                        "[calloop] Failed to unregister source from the polling system: {:?}",
+                        e
+                    );
+                }
+            }
+            PostAction::Continue => {}
+        }
         Ok(())
     }
 
+    fn dispatch_quiescent(&mut self, received_events: bool, data: &mut Data) {
+        let mut ready = Vec::new();
+        {
+            let mut quiescent = self.handle.inner.quiescent.borrow_mut();
+            for mut entry in std::mem::take(&mut *quiescent) {
+                if received_events {
+                    entry.empty_passes = 0;
+                } else {
+                    entry.empty_passes += 1;
+                }
+                if entry.empty_passes >= entry.passes {
+                    ready.push(entry.callback);
+                } else {
+                    quiescent.push(entry);
+                }
+            }
+        }
+        // the callbacks are run once the list is released, as they may insert new ones
+        for callback in ready {
+            callback.borrow_mut().dispatch(data);
+        }
+    }
+
     fn dispatch_idles(&mut self, data: &mut Data) {
+        // the idles inserted from now on are run during the next pass
         let idles = ::std::mem::take(&mut *self.handle.inner.idles.borrow_mut());
         for idle in idles {
             idle.borrow_mut().dispatch(data);
         }
     }
 
+    fn dispatch_flushes(&self) -> io::Result<()> {
+        let actions = self
+            .handle
+            .inner
+            .sources
+            .borrow()
+            .iter()
+            .filter_map(|(key, disp)| match disp.flush() {
+                Ok(PostAction::Continue) => None,
+                ret => Some((key, disp.clone(), ret)),
+            })
+            .collect::<Vec<_>>();
+        for (key, disp, ret) in actions {
+            self.apply_post_action(key, &disp, ret?)?;
+        }
+        Ok(())
+    }
+
+    fn check_buffer_budget(&mut self) {
+        if let Some((max_bytes, ref mut hook)) = self.buffer_budget {
+            let total = self
+                .handle
+                .inner
+                .sources
+                .borrow()
+                .values()
+                .map(|disp| disp.buffered_bytes())
+                .fold(0usize, |total, bytes| total.saturating_add(bytes));
+            if total > max_bytes {
+                hook(total);
+            }
+        }
+    }
+
     fn dispatch_starts(&self, data: &mut Data) {
-        let  sources = self.handle.inner.sources.borrow();
-        sources.values().for_each(|value| {
+        // the sources may invoke their callbacks, which can insert or remove sources
+        let sources: Vec<_> = self
+            .handle
+            .inner
+            .sources
+            .borrow()
+            .values()
+            .cloned()
+            .collect();
+        sources.iter().for_each(|value| {
             value.on_dispatch_start(data);
         })
     }
@@ -403,24 +1445,295 @@ impl<'l, Data> EventLoop<'l, Data> {
     /// Otherwise this will wait until an event is receive or the provided `timeout`
     /// is reached. If `timeout` is `None`, it will wait without a duration limit.
     ///
-    /// Once pending events have been processed or the timeout is reached, all pending
-    /// idle callbacks will be fired before this method returns.
+    /// Once pending events have been processed or the timeout is reached, the sources are
+    /// given the opportunity to [flush](EventSource#method.flush) their pending output, and all
+    /// pending idle callbacks will be fired before this method returns.
+    ///
+    /// The idle callbacks are run at the end of every pass, whether it ended because events
+    /// were received or because the timeout elapsed without any. This includes the idle
+    /// callbacks inserted during the pass by the callbacks of the sources, while those inserted
+    /// by an idle callback are run at the end of the next pass.
+    ///
+    /// Dispatching is not reentrant: calling this method again from within a callback of
+    /// this loop returns an error. If you need to do some processing once the current
+    /// events have been handled, insert an [idle callback](LoopHandle#method.insert_idle)
+    /// instead.
     pub fn dispatch<D: Into<Option<Duration>>>(
         &mut self,
         timeout: D,
         data: &mut Data,
     ) -> io::Result<()> {
+        self.dispatch_pass(timeout.into(), data)?;
+        Ok(())
+    }
+
+    // A full dispatching pass, returning whether some events were received
+    fn dispatch_pass(&mut self, timeout: Option<Duration>, data: &mut Data) -> io::Result<bool> {
+        self.run_pass(data, |event_loop, data| {
+            let received_events = event_loop.dispatch_events(timeout, data)?;
+            Ok((received_events, received_events))
+        })
+    }
+
+    // The phases of a dispatching pass around the dispatching of the events, done by
+    // `dispatch`, which returns whether some events were received along with its result
+    fn run_pass<R, F>(&mut self, data: &mut Data, dispatch: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut Self, &mut Data) -> io::Result<(bool, R)>,
+    {
+        let inner = self.handle.inner.clone();
+        let _guard = DispatchGuard::enter(&inner.dispatching)?;
+
         self.dispatch_starts(data);
 
-        self.dispatch_events(timeout.into(), data)?;
+        let (received_events, ret) = dispatch(self, data)?;
 
         self.dispatch_ends();
 
+        self.dispatch_flushes()?;
+
+        self.check_buffer_budget();
+
+        self.dispatch_quiescent(received_events, data);
+
         self.dispatch_idles(data);
 
+        Ok(ret)
+    }
+
+    /// Dispatch the events of a single source
+    ///
+    /// This waits for events like [`dispatch`](EventLoop#method.dispatch), but only invokes
+    /// the callback of the first ready source, and returns its token, or `None` if the
+    /// `timeout` elapsed without any event. The events of the other ready sources are kept by
+    /// the loop, and processed by the next calls to `step` or `dispatch`, which do not wait as
+    /// long as some are left. The events of the edge-triggered sources are thus not lost
+    /// between the steps. The events left for a source which is removed in the meantime are
+    /// dropped.
+    ///
+    /// Each step is a full dispatching pass: the sources are notified of the start and the end
+    /// of the pass, are [flushed](EventSource#method.flush), and the idle callbacks are run.
+    ///
+    /// This is much slower than dispatching all the ready sources at once, and is meant for
+    /// single-stepping through the event handling in a debugger, or for tests controlling the
+    /// order of the callbacks finely, not for production use.
+    pub fn step<D: Into<Option<Duration>>>(
+        &mut self,
+        timeout: D,
+        data: &mut Data,
+    ) -> io::Result<Option<RegistrationToken>> {
+        let timeout = timeout.into();
+        self.run_pass(data, |event_loop, data| {
+            let events = event_loop.poll_events(timeout)?;
+            // the wakeup of the loop is always processed, along with the first source
+            let signal_key = event_loop.signal_key;
+            let first = events
+                .iter()
+                .map(|event| event.token.key)
+                .find(|&key| key != signal_key);
+            let (events, left): (Vec<_>, Vec<_>) = events
+                .into_iter()
+                .partition(|event| event.token.key == signal_key || Some(event.token.key) == first);
+            event_loop.stepped = left;
+            let received_events = event_loop.process_ready(events, data)?;
+            Ok((received_events, first.map(|key| RegistrationToken { key })))
+        })
+    }
+
+    /// Dispatch events, failing if none is received within `max`
+    ///
+    /// This is primarily a testing aid: a test expecting an event which never comes fails
+    /// with a `TimedOut` error after `max`, instead of hanging forever like with a
+    /// [`dispatch`](EventLoop#method.dispatch) without timeout. The events received before
+    /// `max` elapses are dispatched as usual.
+    pub fn dispatch_scoped(&mut self, max: Duration, data: &mut Data) -> io::Result<()> {
+        if self.dispatch_pass(Some(max), data)? {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no event was received before the timeout",
+            ))
+        }
+    }
+
+    /// Dispatch events until a condition holds
+    ///
+    /// This repeatedly [dispatches](EventLoop#method.dispatch) the loop, checking the
+    /// predicate after each pass, for example to wait for the response to a request to be
+    /// received through a channel. It returns `true` as soon as the predicate holds, or
+    /// `false` if the total `timeout` elapsed before that. With no timeout, it dispatches
+    /// for as long as needed.
+    pub fn dispatch_until<F, D>(
+        &mut self,
+        timeout: D,
+        data: &mut Data,
+        mut predicate: F,
+    ) -> io::Result<bool>
+    where
+        F: FnMut(&mut Data) -> bool,
+        D: Into<Option<Duration>>,
+    {
+        let deadline = timeout.into().map(|timeout| Instant::now() + timeout);
+        loop {
+            let remaining = deadline.map(|deadline| {
+                deadline
+                    .checked_duration_since(Instant::now())
+                    .unwrap_or_default()
+            });
+            self.dispatch_pass(remaining, data)?;
+            if predicate(data) {
+                return Ok(true);
+            }
+            if remaining == Some(Duration::from_millis(0)) {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Dispatch events until none are pending anymore
+    ///
+    /// This repeatedly [dispatches](EventLoop#method.dispatch) the loop without waiting, until
+    /// a pass receives no event. Combined with the callbacks of
+    /// [`test_util::collector`](crate::test_util::collector), it lets tests gather all the
+    /// events their sources are ready to generate in a deterministic way.
+    ///
+    /// This is only available with the `test-util` cargo feature.
+    #[cfg(feature = "test-util")]
+    pub fn dispatch_until_quiescent(&mut self, data: &mut Data) -> io::Result<()> {
+        while self.dispatch_pass(Some(Duration::from_millis(0)), data)? {}
         Ok(())
     }
 
+    /// The earliest deadline at which a source of this loop will generate events
+    ///
+    /// This is the minimum of the [deadlines](EventSource#method.next_deadline) of all the
+    /// sources inserted in the loop, like the next timeout of the
+    /// [`Timer`](crate::timer::Timer) sources, or `None` if no source is driven by time.
+    ///
+    /// A program embedding this loop in another one, by monitoring its
+    /// [file descriptor](EventLoop#impl-AsRawFd), should not wait past this deadline
+    /// before dispatching the loop.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.handle
+            .inner
+            .sources
+            .borrow()
+            .values()
+            .filter_map(|source| source.next_deadline())
+            .min()
+    }
+
+    /// How long the loop would wait before waking up on its own
+    ///
+    /// This is the time left until the [next deadline](EventLoop#method.next_deadline) of its
+    /// sources, or zero if this deadline has already passed or if the loop has work to do
+    /// without waiting, like pending idle callbacks. It returns `None` if nothing is scheduled,
+    /// in which case the loop only wakes up when a source receives events: this is the case
+    /// of a loop with only [`Signals`](crate::signals::Signals) sources, for example.
+    ///
+    /// It is meant to be called right before dispatching, to decide how deeply the program
+    /// can sleep.
+    pub fn time_to_next_event(&self) -> Option<Duration> {
+        if self.has_immediate_work() {
+            return Some(Duration::from_millis(0));
+        }
+        let now = Instant::now();
+        self.next_deadline().map(|deadline| {
+            deadline
+                .checked_duration_since(now)
+                .unwrap_or_else(|| Duration::from_millis(0))
+        })
+    }
+
+    /// Process the events of a source right away, without polling
+    ///
+    /// This invokes the source associated with this token as if all its file descriptors were
+    /// ready for their registered interest, so that it re-examines them immediately instead of
+    /// waiting for the polling system to report them. This is meant for recovery, for example
+    /// to drain a source again after its processing was interrupted by a transient error, and
+    /// for testing. The sources read and write non-blocking file descriptors, so they simply
+    /// find nothing to do if they were not actually ready. The post action returned by the
+    /// source is applied as during a regular dispatch.
+    ///
+    /// This bypasses the polling system and the fairness of the dispatching between sources,
+    /// and should thus be used sparingly. Like [`dispatch`](EventLoop#method.dispatch), it is
+    /// not reentrant and returns an error if called while the loop is dispatching. It does
+    /// nothing for a source without any registered file descriptor, like a disabled source,
+    /// and returns a `NotFound` error if the source was removed from the loop.
+    pub fn poke(&mut self, token: &RegistrationToken, data: &mut Data) -> io::Result<()> {
+        let inner = self.handle.inner.clone();
+        let _guard = DispatchGuard::enter(&inner.dispatching)?;
+
+        let key = token.key;
+        let disp =
+            inner.sources.borrow().get(key).cloned().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "No source for this token")
+            })?;
+        let registrations = inner.poll.borrow().registrations_for(key);
+
+        let mut action = PostAction::Continue;
+        for (token, interest) in registrations {
+            let readiness = Readiness {
+                readable: interest.readable,
+                writable: interest.writable,
+                error: false,
+            };
+            let mut ret = disp.process_events(readiness, token, data)?;
+            let pending_action = inner.pending_action.replace(PostAction::Continue);
+            if let PostAction::Continue = ret {
+                ret = pending_action;
+            }
+            if !inner.sources.borrow().contains_key(key) {
+                // the source has been removed from within its callback, unregister it
+                if let Err(e) = disp.unregister(&mut inner.poll.borrow_mut()) {
+                    log::warn!(
+                        "[calloop] Failed to unregister source from the polling system: {:?}",
+                        e
+                    );
+                }
+                return Ok(());
+            }
+            if action_rank(&ret) > action_rank(&action) {
+                action = ret;
+            }
+            if let PostAction::Disable | PostAction::Remove = action {
+                break;
+            }
+        }
+        self.apply_post_action(key, &disp, action)
+    }
+
+    /// The file descriptors of the source associated with this token, as currently registered
+    ///
+    /// This reflects the interest and mode last passed to the polling system, which can
+    /// differ from the ones the source was created with once it reregistered itself, for
+    /// example after returning [`PostAction::SetInterest`]. The registrations are sorted by
+    /// file descriptor, and are empty for a disabled or removed source.
+    pub fn effective_registrations(&self, token: &RegistrationToken) -> Vec<crate::Registration> {
+        self.handle
+            .inner
+            .poll
+            .borrow()
+            .effective_registrations(token.key)
+    }
+
+    /// Check whether the source associated with this token is registered in the polling system
+    ///
+    /// This returns `true` as long as at least one file descriptor of this source is registered.
+    /// A removed or disabled source has no registered file descriptor anymore.
+    ///
+    /// This is only available with the `test-util` cargo feature, and is meant to assert the
+    /// correct registration lifecycle of event sources in tests.
+    #[cfg(feature = "test-util")]
+    pub fn is_registered(&self, token: &RegistrationToken) -> bool {
+        self.handle
+            .inner
+            .poll
+            .borrow()
+            .has_registrations_for(token.key)
+    }
+
     /// Get a signal to stop this event loop from running
     ///
     /// To be used in conjunction with the `run()` method.
@@ -440,6 +1753,11 @@ impl<'l, Data> EventLoop<'l, Data> {
     ///
     /// You can use the `get_signal()` method to retrieve a way to stop or wakeup
     /// the event loop from anywhere.
+    ///
+    /// Once the loop is stopped, the [`on_shutdown`](EventSource#method.on_shutdown) method
+    /// of its sources is called, in the reverse order of their insertion, before this method
+    /// returns. The sources remain in the loop, which can be run again. If the dispatching
+    /// fails instead, the sources are only torn down when the loop is dropped.
     pub fn run<F, D: Into<Option<Duration>>>(
         &mut self,
         timeout: D,
@@ -451,12 +1769,178 @@ impl<'l, Data> EventLoop<'l, Data> {
     {
         let timeout = timeout.into();
         self.stop_signal.store(false, Ordering::Release);
+        self.shut_down = false;
+        self.running = true;
+        while !self.stop_signal.load(Ordering::Acquire) {
+            if let Err(e) = self.dispatch(timeout, data) {
+                self.running = false;
+                return Err(e);
+            }
+            cb(data);
+        }
+        self.running = false;
+        self.shutdown();
+        Ok(())
+    }
+
+    /// Run this event loop until a deadline
+    ///
+    /// This behaves like [`run`](EventLoop#method.run), except that the loop also stops once
+    /// the deadline is reached, whichever of the deadline or the stop signal comes first: each
+    /// dispatch waits at most the time remaining until the deadline. This bounds the total
+    /// running time of the loop, while the timeout of `run` bounds each wait.
+    ///
+    /// The remaining time is computed again after each pass, so that callbacks running for
+    /// long are taken into account. The deadline is however only checked between passes: it
+    /// can be overrun by the duration of the last pass.
+    pub fn run_until_deadline<F>(
+        &mut self,
+        deadline: Instant,
+        data: &mut Data,
+        mut cb: F,
+    ) -> io::Result<()>
+    where
+        F: FnMut(&mut Data),
+    {
+        self.stop_signal.store(false, Ordering::Release);
+        self.shut_down = false;
+        self.running = true;
         while !self.stop_signal.load(Ordering::Acquire) {
-            self.dispatch(timeout, data)?;
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if remaining > Duration::from_millis(0) => remaining,
+                _ => break,
+            };
+            if let Err(e) = self.dispatch(remaining, data) {
+                self.running = false;
+                return Err(e);
+            }
             cb(data);
         }
+        self.running = false;
+        self.shutdown();
         Ok(())
     }
+
+    // Tear down the sources still in the loop, the most recently inserted first
+    fn shutdown(&mut self) {
+        if self.shut_down {
+            return;
+        }
+        self.shut_down = true;
+        let inner = &self.handle.inner;
+        let dispatchers = {
+            let sources = inner.sources.borrow();
+            inner
+                .insertion_order
+                .borrow()
+                .iter()
+                .rev()
+                .filter_map(|&key| sources.get(key).cloned())
+                .collect::<Vec<_>>()
+        };
+        // the sources are not borrowed, so that they can be removed from their teardown
+        for dispatcher in dispatchers {
+            dispatcher.on_shutdown();
+        }
+    }
+
+    // Whether this loop has some work to do without waiting for events
+    fn has_immediate_work(&self) -> bool {
+        let inner = &self.handle.inner;
+        inner.poll.borrow().has_busy_sources() || !inner.idles.borrow().is_empty()
+    }
+}
+
+impl<'l, Data> Drop for EventLoop<'l, Data> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl<'l, Data> AsRawFd for EventLoop<'l, Data> {
+    /// The file descriptor of the polling system of the loop
+    ///
+    /// It becomes readable when some events are waiting to be dispatched, and can thus be
+    /// monitored by another polling system.
+    fn as_raw_fd(&self) -> RawFd {
+        self.handle.inner.poll.borrow().as_raw_fd()
+    }
+}
+
+/// Wait on several event loops at once and dispatch those which are ready
+///
+/// This waits until at least one of the `loops` has events to process, or the `timeout` is
+/// reached, and then [dispatches](EventLoop#method.dispatch) each loop that is ready, without
+/// waiting. The loops remain independent of one another: this is meant to co-run several
+/// subsystems which each built their own loop, from a single thread.
+///
+/// The wait is bounded by the given `timeout`, and by the earliest of the
+/// [deadlines](EventLoop#method.next_deadline) of the loops, so that their sources driven by
+/// time, like the [`TickSource`](crate::tick::TickSource), fire on time. The loops whose
+/// deadline passed are dispatched along with the ready ones. It does not wait at all if some
+/// loop has pending idle callbacks or a source that needs to be checked at each pass, like
+/// a [`PollFn`](crate::poll_fn::PollFn).
+pub fn poll_any<'l, Data, D: Into<Option<Duration>>>(
+    loops: &mut [&mut EventLoop<'l, Data>],
+    timeout: D,
+    data: &mut Data,
+) -> io::Result<()> {
+    use nix::poll::{poll, PollFd, PollFlags};
+
+    let timeout = loops
+        .iter()
+        .filter_map(|l| l.time_to_next_event())
+        .chain(timeout.into())
+        .min();
+    let timeout = match timeout {
+        // round up, to not return before the timeout is reached
+        Some(t) => ((t.as_nanos() + 999_999) / 1_000_000).min(std::i32::MAX as u128) as i32,
+        None => -1,
+    };
+
+    let mut fds = loops
+        .iter()
+        .map(|l| PollFd::new(l.as_raw_fd(), PollFlags::POLLIN))
+        .collect::<Vec<_>>();
+    loop {
+        match poll(&mut fds, timeout) {
+            Ok(_) => break,
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    for (event_loop, fd) in loops.iter_mut().zip(&fds) {
+        let ready = fd.revents().map_or(false, |revents| !revents.is_empty());
+        if ready || event_loop.time_to_next_event() == Some(Duration::from_millis(0)) {
+            event_loop.dispatch(Some(Duration::from_millis(0)), data)?;
+        }
+    }
+    Ok(())
+}
+
+// Add the events of a new poll, merging the readiness of those already present
+fn merge_events(events: &mut Vec<PollEvent>, more: Vec<PollEvent>) {
+    for event in more {
+        // level-triggered sources are reported again, merge their readiness
+        if let Some(previous) = events.iter_mut().find(|e| e.token == event.token) {
+            previous.readiness.readable |= event.readiness.readable;
+            previous.readiness.writable |= event.readiness.writable;
+            previous.readiness.error |= event.readiness.error;
+        } else {
+            events.push(event);
+        }
+    }
+}
+
+// How drastic a post action is, when a source requested several in the same pass
+pub(crate) fn action_rank(action: &PostAction) -> u8 {
+    match action {
+        PostAction::Continue => 0,
+        PostAction::Reregister | PostAction::SetInterest(_) => 1,
+        PostAction::Disable => 2,
+        PostAction::Remove => 3,
+    }
 }
 
 /// A signal that can be shared between thread to stop or wakeup a running
@@ -480,6 +1964,8 @@ impl LoopSignal {
     /// waiting for events, it will return rather than starting to wait again.
     ///
     /// This is only usefull if you are using the `EventLoop::run()` method.
+    /// Whether the events remaining in the current pass are still dispatched depends on
+    /// the [`StopMode`] of the loop.
     pub fn stop(&self) {
         self.signal.store(true, Ordering::Release);
     }
@@ -497,14 +1983,16 @@ impl LoopSignal {
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
 
     use crate::{
         generic::Generic, ping::*, timer::Timer, Dispatcher, Interest, Mode, Poll, PostAction,
         Readiness, RegistrationToken, Token, TokenFactory,
     };
 
-    use super::EventLoop;
+    use super::{EventLoop, IdleState, SourceBatch};
 
     #[test]
     fn dispatch_idle() {
@@ -524,38 +2012,106 @@ mod tests {
     }
 
     #[test]
-    fn cancel_idle() {
-        let mut event_loop = EventLoop::try_new().unwrap();
+    fn idle_order_and_reentrancy() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
 
-        let mut dispatched = false;
+        use crate::Idle;
 
+        let mut event_loop = EventLoop::<Vec<u32>>::try_new().unwrap();
         let handle = event_loop.handle();
-        let idle = handle.insert_idle(move |d| {
-            *d = true;
-        });
 
-        idle.cancel();
+        let cancelled = Rc::new(RefCell::new(None));
+        let (handle2, cancelled2) = (handle.clone(), cancelled.clone());
+        handle.insert_idle(move |order| {
+            order.push(1);
+            // runs during the next pass
+            handle2.insert_idle(|order| order.push(4));
+            // cancels an idle which did not run yet
+            if let Some(idle) = cancelled2.borrow_mut().take() {
+                Idle::cancel(idle);
+            }
+        });
+        *cancelled.borrow_mut() = Some(handle.insert_idle(|order| order.push(0)));
+        let myself = Rc::new(RefCell::new(None));
+        let myself2 = myself.clone();
+        *myself.borrow_mut() = Some(handle.insert_idle(move |order: &mut Vec<u32>| {
+            order.push(2);
+            // cancelling a running idle does nothing
+            if let Some(idle) = myself2.borrow_mut().take() {
+                Idle::cancel(idle);
+            }
+        }));
+        handle.insert_idle(|order| order.push(3));
 
+        let mut order = Vec::new();
         event_loop
-            .dispatch(Duration::from_millis(0), &mut dispatched)
+            .dispatch(Duration::from_millis(0), &mut order)
             .unwrap();
-
-        assert!(!dispatched);
+        assert_eq!(order, &[1, 2, 3]);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut order)
+            .unwrap();
+        assert_eq!(order, &[1, 2, 3, 4]);
     }
 
     #[test]
-    fn wakeup() {
+    fn dispatch_idle_on_timeout() {
         let mut event_loop = EventLoop::try_new().unwrap();
+        // a source which is never ready, the pass ends with the timeout
+        let (_ping, ping_source) = crate::ping::make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), _| panic!("Unexpected ping"))
+            .unwrap();
 
-        let signal = event_loop.get_signal();
-
-        ::std::thread::spawn(move || {
-            ::std::thread::sleep(Duration::from_millis(500));
-            signal.wakeup();
+        let mut dispatched = false;
+        event_loop.handle().insert_idle(|d| {
+            *d = true;
         });
 
-        // the test should return
-        event_loop.dispatch(None, &mut ()).unwrap();
+        let start = Instant::now();
+        event_loop
+            .dispatch(Some(Duration::from_millis(20)), &mut dispatched)
+            .unwrap();
+
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert!(dispatched);
+    }
+
+    #[test]
+    fn cancel_idle() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+
+        let mut dispatched = false;
+
+        let handle = event_loop.handle();
+        let idle = handle.insert_idle(move |d| {
+            *d = true;
+        });
+
+        idle.cancel();
+
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut dispatched)
+            .unwrap();
+
+        assert!(!dispatched);
+    }
+
+    #[test]
+    fn wakeup() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+
+        let signal = event_loop.get_signal();
+
+        ::std::thread::spawn(move || {
+            ::std::thread::sleep(Duration::from_millis(500));
+            signal.wakeup();
+        });
+
+        // the test should return
+        event_loop.dispatch(None, &mut ()).unwrap();
     }
 
     #[test]
@@ -574,6 +2130,366 @@ mod tests {
         event_loop.run(None, &mut (), |_| {}).unwrap();
     }
 
+    #[test]
+    fn slow_callback_hook() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let (ping1, source1) = make_ping().unwrap();
+        let (ping2, source2) = make_ping().unwrap();
+
+        let slow_token = event_loop
+            .handle()
+            .insert_source(source1, |(), &mut (), _| {
+                ::std::thread::sleep(Duration::from_millis(50));
+            })
+            .unwrap();
+        event_loop
+            .handle()
+            .insert_source(source2, |(), &mut (), _| {})
+            .unwrap();
+
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let hook_reported = reported.clone();
+        event_loop.set_slow_callback_hook(Duration::from_millis(20), move |token, duration| {
+            hook_reported.borrow_mut().push((token, duration));
+        });
+
+        ping1.ping();
+        ping2.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+
+        // only the slow source is reported
+        let reported = reported.borrow();
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].0, slow_token);
+        assert!(reported[0].1 >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn from_poll() {
+        let poll = Poll::new().unwrap();
+        let mut event_loop = EventLoop::<bool>::from_poll(poll).unwrap();
+        let (ping, ping_source) = make_ping().unwrap();
+
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), dispatched| *dispatched = true)
+            .unwrap();
+
+        ping.ping();
+        let mut dispatched = false;
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut dispatched)
+            .unwrap();
+        assert!(dispatched);
+    }
+
+    #[test]
+    fn nested_dispatch_rejected() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+
+        // simulate a dispatch in progress
+        event_loop.handle.inner.dispatching.set(true);
+        assert!(event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .is_err());
+        event_loop.handle.inner.dispatching.set(false);
+
+        // the guard is released even if a callback panics
+        let (ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), &mut ()| {
+                panic!("callback failure")
+            })
+            .unwrap();
+        ping.ping();
+        let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            event_loop.dispatch(Duration::from_millis(0), &mut ())
+        }));
+        assert!(ret.is_err());
+        assert!(!event_loop.handle.inner.dispatching.get());
+    }
+
+    #[test]
+    fn pending_events_from_callback() {
+        let mut event_loop = EventLoop::<Vec<bool>>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let (ping1, source1) = make_ping().unwrap();
+        let (ping2, source2) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source1, move |(), &mut (), pending| {
+                pending.push(handle.has_pending_events());
+                ping2.ping();
+                pending.push(handle.has_pending_events());
+            })
+            .unwrap();
+        event_loop
+            .handle()
+            .insert_source(source2, |(), &mut (), _| {})
+            .unwrap();
+
+        ping1.ping();
+        let mut pending = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut pending)
+            .unwrap();
+        assert_eq!(pending, &[false, true]);
+    }
+
+    #[test]
+    fn on_quiescent() {
+        let mut event_loop = EventLoop::<bool>::try_new().unwrap();
+        let (ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), _| {})
+            .unwrap();
+        event_loop
+            .handle()
+            .insert_on_quiescent(2, |fired| *fired = true);
+
+        let mut fired = false;
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut fired)
+            .unwrap();
+        // an event resets the count
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut fired)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut fired)
+            .unwrap();
+        assert!(!fired);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut fired)
+            .unwrap();
+        assert!(fired);
+    }
+
+    #[test]
+    fn insert_batch() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let mut batch = SourceBatch::new();
+        let (_ping1, source1) = make_ping().unwrap();
+        let (_ping2, source2) = make_ping().unwrap();
+        batch.insert_source(source1, |(), &mut (), _| {});
+        batch.insert_source(source2, |(), &mut (), _| {});
+        let tokens = handle.insert_batch(batch).unwrap();
+        assert_eq!(tokens.len(), 2);
+
+        // one failing source rolls back the whole batch
+        let sources = handle.inner.sources.borrow().len();
+        let mut batch = SourceBatch::new();
+        let (_ping3, source3) = make_ping().unwrap();
+        batch.insert_source(source3, |(), &mut (), _| {});
+        batch.insert_source(
+            crate::sources::generic::Generic::new(420, Interest::READ, Mode::Level),
+            |_, _, _| Ok(PostAction::Continue),
+        );
+        assert!(handle.insert_batch(batch).is_err());
+        assert_eq!(handle.inner.sources.borrow().len(), sources);
+    }
+
+    #[test]
+    fn coalesce_window() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        event_loop.set_coalesce_window(Duration::from_millis(50));
+
+        let (ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        ping.ping();
+        let ping2 = ping.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            ping2.ping();
+        });
+
+        let mut count = 0;
+        let start = std::time::Instant::now();
+        event_loop.dispatch(None, &mut count).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        // both pings are dispatched in a single pass
+        assert_eq!(count, 1);
+        thread.join().unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn coalesce_window_bounds() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        event_loop.set_coalesce_window(Duration::from_secs(10));
+
+        let (ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), _| {})
+            .unwrap();
+
+        // the window does not extend past the timeout
+        ping.ping();
+        let start = Instant::now();
+        event_loop
+            .dispatch(Duration::from_millis(20), &mut ())
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // a wakeup of the loop ends the window
+        ping.ping();
+        let signal = event_loop.get_signal();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            signal.wakeup();
+        });
+        let start = Instant::now();
+        event_loop.dispatch(None, &mut ()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread.join().unwrap();
+
+        // nor does it apply to the deadlines of the sources
+        let ticks = Rc::new(Cell::new(0));
+        let ticks2 = ticks.clone();
+        event_loop
+            .handle()
+            .insert_source(
+                crate::sources::tick::TickSource::new(Duration::from_millis(10)),
+                move |_, &mut (), _| ticks2.set(ticks2.get() + 1),
+            )
+            .unwrap();
+        let start = Instant::now();
+        event_loop
+            .dispatch(Duration::from_secs(1), &mut ())
+            .unwrap();
+        assert_eq!(ticks.get(), 1);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn idle_transition_hook() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let states = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let states2 = states.clone();
+        event_loop.set_idle_transition_hook(move |state| states2.borrow_mut().push(state));
+
+        let (ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), _| {})
+            .unwrap();
+
+        event_loop
+            .dispatch(Duration::from_millis(1), &mut ())
+            .unwrap();
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(1), &mut ())
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+
+        assert_eq!(
+            *states.borrow(),
+            &[
+                IdleState::BlockFor(Duration::from_millis(1)),
+                IdleState::Immediate,
+                IdleState::Immediate
+            ]
+        );
+    }
+
+    #[test]
+    fn poll_any_dispatches_ready_loops() {
+        let mut first = EventLoop::<Vec<u32>>::try_new().unwrap();
+        let mut second = EventLoop::<Vec<u32>>::try_new().unwrap();
+
+        let (ping1, source1) = make_ping().unwrap();
+        let (ping2, source2) = make_ping().unwrap();
+        first
+            .handle()
+            .insert_source(source1, |(), &mut (), seen| seen.push(1))
+            .unwrap();
+        second
+            .handle()
+            .insert_source(source2, |(), &mut (), seen| seen.push(2))
+            .unwrap();
+
+        let mut seen = Vec::new();
+        ping2.ping();
+        let start = std::time::Instant::now();
+        super::poll_any(
+            &mut [&mut first, &mut second],
+            Duration::from_secs(10),
+            &mut seen,
+        )
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(seen, &[2]);
+
+        ping1.ping();
+        ping2.ping();
+        super::poll_any(
+            &mut [&mut first, &mut second],
+            Duration::from_secs(10),
+            &mut seen,
+        )
+        .unwrap();
+        assert_eq!(seen, &[2, 1, 2]);
+
+        // nothing ready, the timeout is reached
+        super::poll_any(
+            &mut [&mut first, &mut second],
+            Duration::from_millis(1),
+            &mut seen,
+        )
+        .unwrap();
+        assert_eq!(seen, &[2, 1, 2]);
+    }
+
+    #[test]
+    fn poll_any_wakes_up_at_deadlines() {
+        let mut first = EventLoop::<u32>::try_new().unwrap();
+        let mut second = EventLoop::<u32>::try_new().unwrap();
+        second
+            .handle()
+            .insert_source(
+                crate::tick::TickSource::new(Duration::from_millis(20)),
+                |_, &mut (), ticks| *ticks += 1,
+            )
+            .unwrap();
+
+        // the wait stops at the deadline of the tick source
+        let mut ticks = 0;
+        let start = Instant::now();
+        while ticks == 0 {
+            super::poll_any(
+                &mut [&mut first, &mut second],
+                Duration::from_secs(10),
+                &mut ticks,
+            )
+            .unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(elapsed < Duration::from_secs(5));
+    }
+
     #[test]
     fn insert_bad_source() {
         let event_loop = EventLoop::<()>::try_new().unwrap();
@@ -731,38 +2647,121 @@ mod tests {
     }
 
     #[test]
-    fn change_interests() {
-        use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
-        use nix::unistd::{read, write};
-        let mut event_loop = EventLoop::<bool>::try_new().unwrap();
+    fn post_action_deferred_to_end_of_pass() {
+        // A source with two fds that requests its removal after its first event
+        struct RemovingSource {
+            ping1: PingSource,
+            ping2: PingSource,
+        }
 
-        let (sock1, sock2) = socketpair(
-            AddressFamily::Unix,
-            SockType::Stream,
-            None,
-            SockFlag::SOCK_NONBLOCK,
-        )
-        .unwrap();
+        impl crate::EventSource for RemovingSource {
+            type Event = ();
+            type Metadata = ();
+            type Ret = ();
 
-        let source = Generic::new(sock1, Interest::READ, Mode::Level);
-        let dispatcher = Dispatcher::new(source, |_, &mut fd, dispatched| {
-            *dispatched = true;
-            // read all contents available to drain the socket
-            let mut buf = [0u8; 32];
-            loop {
-                match read(fd, &mut buf) {
-                    Ok(0) => break, // closed pipe, we are now inert
-                    Ok(_) => {}
-                    Err(e) => {
-                        let e: std::io::Error = e.into();
-                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                            break;
-                        // nothing more to read
-                        } else {
-                            // propagate error
-                            return Err(e);
-                        }
-                    }
+            fn process_events<F>(
+                &mut self,
+                readiness: Readiness,
+                token: Token,
+                mut callback: F,
+            ) -> std::io::Result<PostAction>
+            where
+                F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+            {
+                self.ping1
+                    .process_events(readiness, token, |(), &mut ()| callback((), &mut ()))?;
+                self.ping2
+                    .process_events(readiness, token, |(), &mut ()| callback((), &mut ()))?;
+                Ok(PostAction::Remove)
+            }
+
+            fn register(
+                &mut self,
+                poll: &mut Poll,
+                token_factory: &mut TokenFactory,
+            ) -> std::io::Result<()> {
+                self.ping1.register(poll, token_factory)?;
+                self.ping2.register(poll, token_factory)
+            }
+
+            fn reregister(
+                &mut self,
+                poll: &mut Poll,
+                token_factory: &mut TokenFactory,
+            ) -> std::io::Result<()> {
+                self.ping1.reregister(poll, token_factory)?;
+                self.ping2.reregister(poll, token_factory)
+            }
+
+            fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+                self.ping1.unregister(poll)?;
+                self.ping2.unregister(poll)
+            }
+        }
+
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+
+        let (ping1, source1) = make_ping().unwrap();
+        let (ping2, source2) = make_ping().unwrap();
+        let dispatcher = Dispatcher::new(
+            RemovingSource {
+                ping1: source1,
+                ping2: source2,
+            },
+            |(), &mut (), d| *d += 1,
+        );
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        // both fds are ready during the same pass, but the source is removed after
+        // the first event and does not receive the second one
+        ping1.ping();
+        ping2.ping();
+        let mut dispatched = 0;
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut dispatched)
+            .unwrap();
+        assert_eq!(dispatched, 1);
+
+        // the source has been removed and unregistered
+        let _ = dispatcher.into_source_inner();
+    }
+
+    #[test]
+    fn change_interests() {
+        use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+        use nix::unistd::{read, write};
+        let mut event_loop = EventLoop::<bool>::try_new().unwrap();
+
+        let (sock1, sock2) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::SOCK_NONBLOCK,
+        )
+        .unwrap();
+
+        let source = Generic::new(sock1, Interest::READ, Mode::Level);
+        let dispatcher = Dispatcher::new(source, |_, &mut fd, dispatched| {
+            *dispatched = true;
+            // read all contents available to drain the socket
+            let mut buf = [0u8; 32];
+            loop {
+                match read(fd, &mut buf) {
+                    Ok(0) => break, // closed pipe, we are now inert
+                    Ok(_) => {}
+                    Err(e) => {
+                        let e: std::io::Error = e.into();
+                        if e.kind() == std::io::ErrorKind::WouldBlock {
+                            break;
+                        // nothing more to read
+                        } else {
+                            // propagate error
+                            return Err(e);
+                        }
+                    }
                 }
             }
             Ok(PostAction::Continue)
@@ -818,6 +2817,32 @@ mod tests {
         assert!(!dispatched);
     }
 
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn is_registered() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let (_ping, ping_source) = make_ping().unwrap();
+
+        let token = event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), _| {})
+            .unwrap();
+        assert!(event_loop.is_registered(&token));
+
+        event_loop.handle().disable(&token).unwrap();
+        assert!(!event_loop.is_registered(&token));
+
+        event_loop.handle().enable(&token).unwrap();
+        assert!(event_loop.is_registered(&token));
+
+        event_loop.handle().remove(token);
+        assert!(!event_loop.is_registered(&token));
+
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+    }
+
     #[test]
     fn kill_source() {
         let mut event_loop = EventLoop::<Option<RegistrationToken>>::try_new().unwrap();
@@ -942,6 +2967,324 @@ mod tests {
         drop(sender);
     }
 
+    #[test]
+    fn priority_boost() {
+        let mut event_loop = EventLoop::<Vec<char>>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let (ping_a, source_a) = make_ping().unwrap();
+        let (ping_b, source_b) = make_ping().unwrap();
+        handle
+            .insert_source(source_a, |(), &mut (), order| order.push('a'))
+            .unwrap();
+        let token_b = handle
+            .insert_source(source_b, |(), &mut (), order| order.push('b'))
+            .unwrap();
+
+        let mut order = Vec::new();
+        let mut pass = |order: &mut Vec<char>| {
+            ping_a.ping();
+            ping_b.ping();
+            event_loop
+                .dispatch(Duration::from_millis(0), order)
+                .unwrap();
+        };
+        pass(&mut order);
+        assert_eq!(order, &['a', 'b']);
+
+        // the boost only applies to the next pass
+        handle.request_priority_boost(&token_b);
+        order.clear();
+        pass(&mut order);
+        assert_eq!(order, &['b', 'a']);
+        order.clear();
+        pass(&mut order);
+        assert_eq!(order, &['a', 'b']);
+    }
+
+    #[test]
+    fn rearm_edge_triggered() {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(
+                Generic::new(stream, Interest::READ, Mode::Edge),
+                |_, _, count| {
+                    // the data are left unread
+                    *count += 1;
+                    Ok(PostAction::Continue)
+                },
+            )
+            .unwrap();
+
+        let mut count = 0;
+        peer.write_all(b"abc").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        event_loop.handle().rearm(&token).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn single_source_fallback() {
+        let mut event_loop = EventLoop::<Vec<char>>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let (ping_a, source_a) = make_ping().unwrap();
+        let (ping_b, source_b) = make_ping().unwrap();
+        let mut source_b = Some(source_b);
+        let inner_handle = handle.clone();
+        handle
+            .insert_source(source_a, move |(), &mut (), order| {
+                order.push('a');
+                // the second source is inserted from the callback of the first one
+                if let Some(source_b) = source_b.take() {
+                    inner_handle
+                        .insert_source(source_b, |(), &mut (), order| order.push('b'))
+                        .unwrap();
+                }
+            })
+            .unwrap();
+
+        let mut order = Vec::new();
+        ping_a.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut order)
+            .unwrap();
+        assert_eq!(order, &['a']);
+
+        ping_a.ping();
+        ping_b.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut order)
+            .unwrap();
+        order.sort();
+        assert_eq!(order, &['a', 'a', 'b']);
+    }
+
+    #[test]
+    fn single_source_replaced() {
+        let mut event_loop = EventLoop::<Vec<char>>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let (ping_a, source_a) = make_ping().unwrap();
+        let alive = Rc::new(());
+        let alive_a = alive.clone();
+        let token_a = handle
+            .insert_source(source_a, move |(), &mut (), order| {
+                let _ = &alive_a;
+                order.push('a')
+            })
+            .unwrap();
+
+        let mut order = Vec::new();
+        ping_a.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut order)
+            .unwrap();
+        assert_eq!(order, &['a']);
+
+        // the removed source is dropped, and the sole source is looked up again
+        handle.remove(token_a);
+        assert_eq!(Rc::strong_count(&alive), 1);
+        let (ping_b, source_b) = make_ping().unwrap();
+        handle
+            .insert_source(source_b, |(), &mut (), order| order.push('b'))
+            .unwrap();
+        ping_b.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut order)
+            .unwrap();
+        assert_eq!(order, &['a', 'b']);
+    }
+
+    #[test]
+    fn poke_source() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (ping, ping_source) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        // nothing to process, the source does not generate any event
+        event_loop.poke(&token, &mut count).unwrap();
+        assert_eq!(count, 0);
+
+        // the ping is processed without polling
+        ping.ping();
+        event_loop.poke(&token, &mut count).unwrap();
+        assert_eq!(count, 1);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        event_loop.handle().remove(token);
+        let err = event_loop.poke(&token, &mut count).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn poke_self_removal() {
+        // A ping source failing to unregister
+        struct FailingUnregister(crate::sources::ping::PingSource);
+
+        impl crate::EventSource for FailingUnregister {
+            type Event = ();
+            type Metadata = ();
+            type Ret = ();
+
+            fn process_events<F>(
+                &mut self,
+                readiness: Readiness,
+                token: Token,
+                callback: F,
+            ) -> std::io::Result<PostAction>
+            where
+                F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+            {
+                self.0.process_events(readiness, token, callback)
+            }
+
+            fn register(
+                &mut self,
+                poll: &mut Poll,
+                factory: &mut TokenFactory,
+            ) -> std::io::Result<()> {
+                self.0.register(poll, factory)
+            }
+
+            fn reregister(
+                &mut self,
+                poll: &mut Poll,
+                factory: &mut TokenFactory,
+            ) -> std::io::Result<()> {
+                self.0.reregister(poll, factory)
+            }
+
+            fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+                self.0.unregister(poll)?;
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "unregister failed",
+                ))
+            }
+        }
+
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let (ping, ping_source) = make_ping().unwrap();
+        let own_token = Rc::new(Cell::new(None));
+        let callback_token = own_token.clone();
+        let inner_handle = handle.clone();
+        let token = handle
+            .insert_source(FailingUnregister(ping_source), move |(), &mut (), count| {
+                *count += 1;
+                // a one-shot source removing itself
+                inner_handle.remove(callback_token.get().unwrap());
+            })
+            .unwrap();
+        own_token.set(Some(token));
+
+        // the failure to unregister the removed source is only logged, as during a dispatch
+        ping.ping();
+        let mut count = 0;
+        event_loop.poke(&token, &mut count).unwrap();
+        assert_eq!(count, 1);
+        let err = event_loop.poke(&token, &mut count).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn shutdown_order() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // A source recording its teardown
+        struct Teardown(u32, Rc<RefCell<Vec<u32>>>);
+
+        impl crate::EventSource for Teardown {
+            type Event = ();
+            type Metadata = ();
+            type Ret = ();
+
+            fn process_events<F>(
+                &mut self,
+                _: Readiness,
+                _: Token,
+                _: F,
+            ) -> std::io::Result<PostAction>
+            where
+                F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+            {
+                Ok(PostAction::Continue)
+            }
+
+            fn register(&mut self, _: &mut Poll, _: &mut TokenFactory) -> std::io::Result<()> {
+                Ok(())
+            }
+
+            fn reregister(&mut self, _: &mut Poll, _: &mut TokenFactory) -> std::io::Result<()> {
+                Ok(())
+            }
+
+            fn unregister(&mut self, _: &mut Poll) -> std::io::Result<()> {
+                Ok(())
+            }
+
+            fn on_shutdown(&mut self) {
+                self.1.borrow_mut().push(self.0);
+            }
+        }
+
+        let torn_down = Rc::new(RefCell::new(Vec::new()));
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let tokens = (1..=3)
+            .map(|i| {
+                handle
+                    .insert_source(Teardown(i, torn_down.clone()), |_, _, _| {})
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        handle.remove(tokens[1]);
+
+        let signal = event_loop.get_signal();
+        event_loop
+            .run(Duration::from_millis(0), &mut (), |_| signal.stop())
+            .unwrap();
+        assert_eq!(*torn_down.borrow(), &[3, 1]);
+
+        // the sources were already torn down when the loop stopped
+        drop(event_loop);
+        assert_eq!(*torn_down.borrow(), &[3, 1]);
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        for i in 4..=5 {
+            event_loop
+                .handle()
+                .insert_source(Teardown(i, torn_down.clone()), |_, _, _| {})
+                .unwrap();
+        }
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        drop(event_loop);
+        assert_eq!(*torn_down.borrow(), &[3, 1, 5, 4]);
+    }
+
     // A dummy EventSource to test insertion and removal of sources
     struct DummySource;
 
@@ -975,4 +3318,487 @@ mod tests {
             Ok(())
         }
     }
+
+    #[test]
+    fn insert_source_or_retry() {
+        // a source failing to register until the others are removed
+        struct Exhausting(Rc<Cell<bool>>);
+
+        impl crate::EventSource for Exhausting {
+            type Event = ();
+            type Metadata = ();
+            type Ret = ();
+
+            fn process_events<F>(
+                &mut self,
+                _: Readiness,
+                _: Token,
+                _: F,
+            ) -> std::io::Result<PostAction>
+            where
+                F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+            {
+                Ok(PostAction::Continue)
+            }
+
+            fn register(&mut self, _: &mut Poll, _: &mut TokenFactory) -> std::io::Result<()> {
+                if self.0.get() {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::from_raw_os_error(nix::libc::EMFILE))
+                }
+            }
+
+            fn reregister(&mut self, _: &mut Poll, _: &mut TokenFactory) -> std::io::Result<()> {
+                Ok(())
+            }
+
+            fn unregister(&mut self, _: &mut Poll) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let room = Rc::new(Cell::new(false));
+
+        // without a hook, the registration is not retried
+        let err = handle
+            .insert_source_or_retry(Exhausting(room.clone()), |_, _, _| {})
+            .unwrap_err();
+        assert_eq!(err.error.raw_os_error(), Some(nix::libc::EMFILE));
+
+        let idle = handle.insert_source(DummySource, |_, _, _| {}).unwrap();
+        let calls = Rc::new(Cell::new(0));
+        let (hook_room, hook_calls) = (room.clone(), calls.clone());
+        let idle = Cell::new(Some(idle));
+        event_loop.set_registration_pressure_hook(move |handle| {
+            hook_calls.set(hook_calls.get() + 1);
+            if let Some(idle) = idle.take() {
+                handle.remove(idle);
+                hook_room.set(true);
+            }
+        });
+
+        // the hook made room for the source
+        handle
+            .insert_source_or_retry(Exhausting(room.clone()), |_, _, _| {})
+            .unwrap();
+        assert_eq!(calls.get(), 1);
+        assert_eq!(handle.inner.sources.borrow().len(), 2);
+
+        // the hook is only invoked once per insertion
+        room.set(false);
+        assert!(handle
+            .insert_source_or_retry(Exhausting(room), |_, _, _| {})
+            .is_err());
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn effective_registrations() {
+        use std::os::unix::io::AsRawFd;
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        let fd = stream.as_raw_fd();
+        let token = event_loop
+            .handle()
+            .insert_source(
+                Generic::new(stream, Interest::READ, Mode::Level),
+                |_, _, _| Ok(PostAction::SetInterest(Interest::WRITE)),
+            )
+            .unwrap();
+
+        let registrations = event_loop.effective_registrations(&token);
+        assert_eq!(registrations.len(), 1);
+        assert_eq!(registrations[0].fd, fd);
+        assert!(registrations[0].interest.readable);
+        assert!(!registrations[0].interest.writable);
+
+        // the source changed its interest
+        std::io::Write::write_all(&mut peer, b"a").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        let registrations = event_loop.effective_registrations(&token);
+        assert!(!registrations[0].interest.readable);
+        assert!(registrations[0].interest.writable);
+
+        event_loop.handle().disable(&token).unwrap();
+        assert!(event_loop.effective_registrations(&token).is_empty());
+    }
+
+    #[test]
+    fn dispatch_until() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        let pinger = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            ping.ping();
+            ping
+        });
+        assert!(event_loop
+            .dispatch_until(None, &mut count, |&mut count| count > 0)
+            .unwrap());
+        assert_eq!(count, 1);
+        let _ping = pinger.join().unwrap();
+
+        // the source stays quiet until the timeout
+        let start = Instant::now();
+        assert!(!event_loop
+            .dispatch_until(Duration::from_millis(30), &mut count, |&mut count| count
+                > 1)
+            .unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn run_until_deadline() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        // the loop returns at the deadline despite a source being active
+        ping.ping();
+        let start = Instant::now();
+        let mut count = 0;
+        event_loop
+            .run_until_deadline(start + Duration::from_millis(30), &mut count, |_| {})
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+        assert_eq!(count, 1);
+
+        // the stop signal ends the run before the deadline
+        let signal = event_loop.get_signal();
+        ping.ping();
+        let start = Instant::now();
+        event_loop
+            .run_until_deadline(start + Duration::from_secs(10), &mut count, |_| {
+                signal.stop()
+            })
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn last_poll_stats() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let mut pings = Vec::new();
+        for _ in 0..40 {
+            let (ping, source) = make_ping().unwrap();
+            event_loop
+                .handle()
+                .insert_source(source, |(), &mut (), &mut ()| {})
+                .unwrap();
+            pings.push(ping);
+        }
+
+        pings[0].ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        let stats = event_loop.last_poll_stats();
+        assert_eq!(stats.events_returned, 1);
+        assert!(!stats.buffer_saturated);
+
+        // more sources are ready than the polling system returns at once
+        for ping in &pings {
+            ping.ping();
+        }
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert!(event_loop.last_poll_stats().buffer_saturated);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert_eq!(event_loop.last_poll_stats().events_returned, 8);
+    }
+
+    #[test]
+    fn dispatch_scoped() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        ping.ping();
+        event_loop
+            .dispatch_scoped(Duration::from_secs(1), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        let start = Instant::now();
+        let err = event_loop
+            .dispatch_scoped(Duration::from_millis(20), &mut count)
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn buffer_budget() {
+        use crate::writer::BufferedWriter;
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let (_tx, rx) = UnixStream::pair().unwrap();
+        rx.set_nonblocking(true).unwrap();
+        let dispatcher =
+            Dispatcher::new(BufferedWriter::new(rx), |_, _, _| Ok(PostAction::Continue));
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        let overflows = Rc::new(Cell::new(None));
+        let hook_overflows = overflows.clone();
+        event_loop.set_buffer_budget(1024, move |total| hook_overflows.set(Some(total)));
+
+        // nothing is buffered
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert_eq!(overflows.get(), None);
+
+        // the peer does not read, the socket cannot take all the data
+        dispatcher
+            .as_source_mut()
+            .buffer()
+            .queue(&vec![0u8; 4 * 1024 * 1024]);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        let pending = dispatcher.as_source_mut().buffer().pending();
+        assert!(pending > 1024);
+        assert_eq!(overflows.get(), Some(pending));
+
+        overflows.set(None);
+        event_loop.clear_buffer_budget();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert_eq!(overflows.get(), None);
+    }
+
+    #[test]
+    fn is_dispatching() {
+        let mut event_loop = EventLoop::<Vec<bool>>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        let handle = event_loop.handle();
+        event_loop
+            .handle()
+            .insert_source(source, move |(), &mut (), seen| {
+                seen.push(handle.is_dispatching());
+                let idle_handle = handle.clone();
+                handle.insert_idle(move |seen| seen.push(idle_handle.is_dispatching()));
+            })
+            .unwrap();
+        assert!(!event_loop.is_dispatching());
+
+        let mut seen = Vec::new();
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert_eq!(seen, &[true, true]);
+        assert!(!event_loop.is_dispatching());
+    }
+
+    #[test]
+    fn capture_mode() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        event_loop.capture_mode(true).unwrap();
+        ping.ping();
+        for _ in 0..2 {
+            event_loop
+                .dispatch(Duration::from_millis(0), &mut count)
+                .unwrap();
+        }
+        // the source was disabled after its first event
+        let captured = event_loop.take_captured();
+        assert_eq!(count, 0);
+        assert_eq!(captured.len(), 1);
+        assert_eq!(captured[0].token, token);
+        assert!(captured[0].readiness.readable);
+
+        // the pending ping is delivered once the capture ends
+        event_loop.capture_mode(false).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(event_loop.take_captured().is_empty());
+    }
+
+    #[test]
+    fn capture_level_triggered() {
+        use std::io::{Read, Write};
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = EventLoop::<Vec<u8>>::try_new().unwrap();
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        event_loop
+            .handle()
+            .insert_source(
+                Generic::new(stream, Interest::READ, Mode::Level),
+                |_, stream, read: &mut Vec<u8>| {
+                    let mut buffer = [0u8; 3];
+                    stream.read_exact(&mut buffer)?;
+                    read.extend_from_slice(&buffer);
+                    Ok(PostAction::Continue)
+                },
+            )
+            .unwrap();
+
+        let mut read = Vec::new();
+        event_loop.capture_mode(true).unwrap();
+        peer.write_all(b"abc").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut read)
+            .unwrap();
+        // the undrained source does not wake the loop up again
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut read)
+            .unwrap();
+        assert_eq!(event_loop.last_poll_stats().events_returned, 0);
+        assert_eq!(event_loop.take_captured().len(), 1);
+        assert!(read.is_empty());
+
+        // the data were left in the file descriptor
+        event_loop.capture_mode(false).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut read)
+            .unwrap();
+        assert_eq!(read, b"abc");
+    }
+
+    #[test]
+    fn wakeup_is_drained() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let signal = event_loop.get_signal();
+
+        // the wakeups coalesce into a single event
+        signal.wakeup();
+        signal.wakeup();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert_eq!(event_loop.last_poll_stats().events_returned, 1);
+
+        // the pipe was drained, the loop waits for the whole timeout
+        let start = Instant::now();
+        event_loop
+            .dispatch(Duration::from_millis(50), &mut ())
+            .unwrap();
+        assert_eq!(event_loop.last_poll_stats().events_returned, 0);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn immediate_stop() {
+        for &(mode, expected) in &[
+            (crate::StopMode::Graceful, 10),
+            (crate::StopMode::Immediate, 1),
+        ] {
+            let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+            event_loop.set_stop_mode(mode);
+            let signal = event_loop.get_signal();
+            let mut pings = Vec::new();
+            for _ in 0..10 {
+                let (ping, source) = make_ping().unwrap();
+                let signal = signal.clone();
+                event_loop
+                    .handle()
+                    .insert_source(source, move |(), &mut (), count| {
+                        // the first dispatched source stops the loop
+                        *count += 1;
+                        signal.stop();
+                    })
+                    .unwrap();
+                ping.ping();
+                pings.push(ping);
+            }
+
+            let mut count = 0;
+            event_loop
+                .run(Duration::from_millis(0), &mut count, |_| {})
+                .unwrap();
+            assert_eq!(count, expected);
+        }
+    }
+
+    #[test]
+    fn step_one_source() {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = EventLoop::<Vec<u32>>::try_new().unwrap();
+        let mut peers = Vec::new();
+        let mut tokens = Vec::new();
+        for id in 0..2 {
+            let (stream, peer) = UnixStream::pair().unwrap();
+            let token = event_loop
+                .handle()
+                .insert_source(
+                    Generic::new(stream, Interest::READ, Mode::Edge),
+                    move |_, _, stepped: &mut Vec<u32>| {
+                        // the data are left unread, the source is not notified again
+                        stepped.push(id);
+                        Ok(PostAction::Continue)
+                    },
+                )
+                .unwrap();
+            peers.push(peer);
+            tokens.push(token);
+        }
+        for peer in &mut peers {
+            peer.write_all(b"a").unwrap();
+        }
+
+        // each step dispatches a single source, the other one is kept for the next step
+        let mut stepped = Vec::new();
+        let first = event_loop
+            .step(Duration::from_millis(0), &mut stepped)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stepped.len(), 1);
+        let second = event_loop
+            .step(Duration::from_millis(0), &mut stepped)
+            .unwrap()
+            .unwrap();
+        assert_ne!(first, second);
+        assert!(tokens.contains(&first) && tokens.contains(&second));
+        stepped.sort_unstable();
+        assert_eq!(stepped, &[0, 1]);
+
+        assert_eq!(
+            event_loop
+                .step(Duration::from_millis(0), &mut stepped)
+                .unwrap(),
+            None
+        );
+        assert_eq!(stepped.len(), 2);
+    }
 }
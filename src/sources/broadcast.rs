@@ -0,0 +1,367 @@
+//! An adapter fanning the events of a source out to several subscribers
+//!
+//! Some event sources cannot be duplicated: for example several signalfds watching the same
+//! signals would compete for their delivery. The [`Broadcast`] adapter wraps a single source
+//! and gives a copy of each of its events to every subscriber registered through its
+//! [`BroadcastHandle`], before invoking the callback it was inserted with.
+//!
+//! Subscribers can be added and removed at any time, including from within a subscriber or
+//! a callback of the event loop. Changes made while an event is being delivered take effect
+//! for the next event.
+//!
+//! ```
+//! # extern crate calloop;
+//! use calloop::{broadcast::Broadcast, ping::make_ping};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (ping, ping_source) = make_ping().unwrap();
+//! let source = Broadcast::new(ping_source);
+//! let broadcast = source.handle();
+//!
+//! broadcast.add_subscriber(|()| println!("First subscriber notified"));
+//! broadcast.add_subscriber(|()| println!("Second subscriber notified"));
+//!
+//! handle
+//!     .insert_source(source, |(), &mut (), _| println!("Loop callback notified"))
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::{
+    cell::RefCell,
+    fmt, io,
+    panic::{catch_unwind, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An identifier for a subscriber of a [`Broadcast`] source
+///
+/// It is returned by [`BroadcastHandle::add_subscriber`] and can be used to remove the
+/// subscriber later.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SubscriberId(u64);
+
+type Subscriber<E> = Rc<RefCell<dyn FnMut(E)>>;
+
+struct Subscribers<E> {
+    list: Vec<(SubscriberId, Subscriber<E>)>,
+    next_id: u64,
+    catch_panics: bool,
+}
+
+impl<E: Clone> Subscribers<E> {
+    fn notify(subscribers: &RefCell<Subscribers<E>>, event: &E) {
+        // take a snapshot of the subscribers, so that they can modify the list
+        let (snapshot, catch_panics) = {
+            let subs = subscribers.borrow();
+            let snapshot: Vec<_> = subs.list.iter().map(|(_, s)| s.clone()).collect();
+            (snapshot, subs.catch_panics)
+        };
+        for sub in snapshot {
+            // a subscriber re-entering itself is skipped
+            let mut sub = match sub.try_borrow_mut() {
+                Ok(sub) => sub,
+                Err(_) => continue,
+            };
+            if catch_panics {
+                let event = event.clone();
+                if catch_unwind(AssertUnwindSafe(|| (*sub)(event))).is_err() {
+                    log::warn!("[calloop] A subscriber of a broadcast source panicked");
+                }
+            } else {
+                (*sub)(event.clone());
+            }
+        }
+    }
+}
+
+/// A handle to manage the subscribers of a [`Broadcast`] source
+///
+/// It can be cloned, and remains usable after the source has been inserted into the
+/// event loop.
+pub struct BroadcastHandle<E> {
+    subscribers: Rc<RefCell<Subscribers<E>>>,
+}
+
+impl<E> Clone for BroadcastHandle<E> {
+    fn clone(&self) -> Self {
+        BroadcastHandle {
+            subscribers: self.subscribers.clone(),
+        }
+    }
+}
+
+impl<E> fmt::Debug for BroadcastHandle<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BroadcastHandle")
+            .field("subscribers", &self.subscribers.borrow().list.len())
+            .finish()
+    }
+}
+
+impl<E> BroadcastHandle<E> {
+    /// Add a subscriber, which will receive a copy of every event of the source
+    pub fn add_subscriber<F: FnMut(E) + 'static>(&self, subscriber: F) -> SubscriberId {
+        let mut subs = self.subscribers.borrow_mut();
+        let id = SubscriberId(subs.next_id);
+        subs.next_id += 1;
+        subs.list.push((id, Rc::new(RefCell::new(subscriber))));
+        id
+    }
+
+    /// Remove a subscriber
+    ///
+    /// Returns `false` if there was no subscriber with this id.
+    pub fn remove_subscriber(&self, id: SubscriberId) -> bool {
+        let mut subs = self.subscribers.borrow_mut();
+        let len = subs.list.len();
+        subs.list.retain(|&(sub_id, _)| sub_id != id);
+        subs.list.len() != len
+    }
+
+    /// The number of current subscribers
+    pub fn subscribers(&self) -> usize {
+        self.subscribers.borrow().list.len()
+    }
+}
+
+/// An event source giving a copy of the events of another source to several subscribers
+///
+/// See the [module documentation](self) for details.
+pub struct Broadcast<S: EventSource> {
+    source: S,
+    subscribers: Rc<RefCell<Subscribers<S::Event>>>,
+}
+
+impl<S: EventSource + fmt::Debug> fmt::Debug for Broadcast<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Broadcast")
+            .field("source", &self.source)
+            .field("subscribers", &self.subscribers.borrow().list.len())
+            .finish()
+    }
+}
+
+impl<S> Broadcast<S>
+where
+    S: EventSource,
+    S::Event: Clone,
+{
+    /// Wrap a source, initially without any subscriber
+    pub fn new(source: S) -> Broadcast<S> {
+        Broadcast {
+            source,
+            subscribers: Rc::new(RefCell::new(Subscribers {
+                list: Vec::new(),
+                next_id: 0,
+                catch_panics: false,
+            })),
+        }
+    }
+
+    /// Catch the panics of the subscribers
+    ///
+    /// By default, a panicking subscriber unwinds through the event loop. With this option,
+    /// the panic is caught and logged, and the event is still given to the other
+    /// subscribers and to the callback of the source.
+    pub fn with_panic_isolation(self) -> Broadcast<S> {
+        self.subscribers.borrow_mut().catch_panics = true;
+        self
+    }
+
+    /// Retrieve a handle to manage the subscribers of this source
+    pub fn handle(&self) -> BroadcastHandle<S::Event> {
+        BroadcastHandle {
+            subscribers: self.subscribers.clone(),
+        }
+    }
+
+    /// Access the wrapped source
+    pub fn get_ref(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter to retrieve the wrapped source
+    pub fn unwrap(self) -> S {
+        self.source
+    }
+}
+
+impl<S> EventSource for Broadcast<S>
+where
+    S: EventSource,
+    S::Event: Clone,
+{
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = S::Ret;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let subscribers = &self.subscribers;
+        self.source.process_events(readiness, token, |event, meta| {
+            Subscribers::notify(subscribers, &event);
+            callback(event, meta)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let subscribers = &self.subscribers;
+        self.source.on_dispatch_start(|event, meta| {
+            Subscribers::notify(subscribers, &event);
+            callback(event, meta)
+        })
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end()
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        self.source.next_deadline()
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ping::make_ping;
+
+    #[test]
+    fn all_subscribers_notified() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+
+        let (ping, ping_source) = make_ping().unwrap();
+        let source = Broadcast::new(ping_source);
+        let broadcast = source.handle();
+
+        let first = Rc::new(Cell::new(0));
+        let second = Rc::new(Cell::new(0));
+        let first2 = first.clone();
+        let second2 = second.clone();
+        let first_id = broadcast.add_subscriber(move |()| first2.set(first2.get() + 1));
+        broadcast.add_subscriber(move |()| second2.set(second2.get() + 1));
+
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!((first.get(), second.get(), count), (1, 1, 1));
+
+        assert!(broadcast.remove_subscriber(first_id));
+        assert!(!broadcast.remove_subscriber(first_id));
+        assert_eq!(broadcast.subscribers(), 1);
+
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!((first.get(), second.get(), count), (1, 2, 2));
+    }
+
+    #[test]
+    fn panicking_subscriber_isolated() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+
+        let (ping, ping_source) = make_ping().unwrap();
+        let source = Broadcast::new(ping_source).with_panic_isolation();
+        let broadcast = source.handle();
+
+        let notified = Rc::new(Cell::new(false));
+        let notified2 = notified.clone();
+        broadcast.add_subscriber(|()| panic!("Subscriber failure"));
+        broadcast.add_subscriber(move |()| notified2.set(true));
+
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert!(notified.get());
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn subscribe_from_subscriber() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+
+        let (ping, ping_source) = make_ping().unwrap();
+        let source = Broadcast::new(ping_source);
+        let broadcast = source.handle();
+
+        let broadcast2 = broadcast.clone();
+        broadcast.add_subscriber(move |()| {
+            broadcast2.add_subscriber(|()| {});
+        });
+
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), &mut ()| {})
+            .unwrap();
+
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        // the new subscriber did not receive the event it was added during
+        assert_eq!(broadcast.subscribers(), 2);
+    }
+}
@@ -0,0 +1,268 @@
+//! Event source for the events of a control group
+//!
+//! Only available on Linux.
+//!
+//! The control groups of the version 2 of the cgroup hierarchy report some of their events
+//! through counters in flat-keyed files: `cgroup.events` tells whether the group has live
+//! processes and whether it is frozen, and `memory.events` counts how many times the memory
+//! of the group reached its limits, or how many of its processes were killed because it ran
+//! out of memory. The kernel signals each change of these files to the processes polling
+//! them, which container runtimes use to react to the end of a container or to its OOM kills.
+//!
+//! A [`CgroupEventSource`] watches one of these files, and delivers a [`CgroupEvent`] for each
+//! counter whose value changed, with its new value. The initial values are read when the
+//! source is created, and are not delivered. Like the [`psi`](crate::psi) source, it watches
+//! the file with an epoll instance of its own, as the kernel signals the changes with
+//! `POLLPRI`, and the file is read again after each change. Once the control group is
+//! removed, the source removes itself from the event loop.
+//!
+//! The creation of the source fails with a `NotFound` error if the directory is not a control
+//! group of the version 2 hierarchy, which notably happens on systems still using the version
+//! 1, and if the file does not exist, for example because the memory controller is not
+//! enabled for the group. The root control group has no `cgroup.events` file.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::cgroup::{CgroupEventSource, EventsFile};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let source = CgroupEventSource::new("/sys/fs/cgroup/my-container", EventsFile::Memory)
+//!     .expect("Failed to watch the memory events");
+//!
+//! handle
+//!     .insert_source(source, |event, &mut (), _| {
+//!         if event.key == "oom_kill" {
+//!             println!("{} processes were killed", event.value);
+//!         }
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use nix::{
+    errno::Errno,
+    sys::epoll::{self, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp},
+    unistd::close,
+};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// A file of events of a control group
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventsFile {
+    /// The `cgroup.events` file, with the `populated` and `frozen` states of the group
+    Cgroup,
+    /// The `memory.events` file, with the memory events like `max` or `oom_kill`
+    Memory,
+}
+
+impl EventsFile {
+    fn name(self) -> &'static str {
+        match self {
+            EventsFile::Cgroup => "cgroup.events",
+            EventsFile::Memory => "memory.events",
+        }
+    }
+}
+
+/// A change of a counter of a control group
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CgroupEvent {
+    /// The name of the counter, like `populated` or `oom_kill`
+    pub key: String,
+    /// The new value of the counter
+    pub value: u64,
+}
+
+/// An event source generating an event when a counter of a control group changes
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct CgroupEventSource {
+    file: File,
+    // the kernel signals the changes with `POLLPRI`, which is not an interest of the event
+    // loop: the file is watched by an epoll instance of its own, readable on events
+    epoll: Generic<RawFd>,
+    which: EventsFile,
+    values: Vec<(String, u64)>,
+}
+
+impl CgroupEventSource {
+    /// Watch a file of events of the control group at the given path
+    pub fn new<P: AsRef<Path>>(cgroup_path: P, which: EventsFile) -> io::Result<CgroupEventSource> {
+        let dir = cgroup_path.as_ref();
+        // only the groups of the version 2 hierarchy have this file
+        if !dir.join("cgroup.controllers").exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the path is not a control group of the cgroup v2 hierarchy",
+            ));
+        }
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::libc::O_CLOEXEC)
+            .open(dir.join(which.name()))
+        {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("the control group has no {} file", which.name()),
+                ))
+            }
+            ret => ret?,
+        };
+        let values = read_values(&mut file)?;
+
+        let epoll_fd = epoll::epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let mut event = EpollEvent::new(EpollFlags::EPOLLPRI, 0);
+        if let Err(e) = epoll::epoll_ctl(
+            epoll_fd,
+            EpollOp::EpollCtlAdd,
+            file.as_raw_fd(),
+            Some(&mut event),
+        ) {
+            let _ = close(epoll_fd);
+            return Err(e.into());
+        }
+        Ok(CgroupEventSource {
+            file,
+            epoll: Generic::new(epoll_fd, Interest::READ, Mode::Level),
+            which,
+            values,
+        })
+    }
+
+    /// The file watched by this source
+    pub fn events_file(&self) -> EventsFile {
+        self.which
+    }
+
+    /// The values of the counters, as last read by this source
+    pub fn values(&self) -> &[(String, u64)] {
+        &self.values
+    }
+}
+
+// Read the whole file again, which acknowledges its change
+fn read_values(file: &mut File) -> io::Result<Vec<(String, u64)>> {
+    let mut content = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut content)?;
+    Ok(parse_values(&content))
+}
+
+// Parse the lines of a flat-keyed file, like `oom_kill 2`
+fn parse_values(content: &str) -> Vec<(String, u64)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let key = fields.next()?;
+            let value = fields.next()?.parse().ok()?;
+            Some((key.to_owned(), value))
+        })
+        .collect()
+}
+
+impl Drop for CgroupEventSource {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.epoll.file) {
+            log::warn!("[calloop] Failed to close cgroup epoll instance: {:?}", e);
+        }
+    }
+}
+
+impl EventSource for CgroupEventSource {
+    type Event = CgroupEvent;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (file, values) = (&mut self.file, &mut self.values);
+        self.epoll.process_events(readiness, token, |_, &mut fd| {
+            let mut events = [EpollEvent::empty()];
+            loop {
+                match epoll::epoll_wait(fd, &mut events, 0) {
+                    Ok(0) => return Ok(PostAction::Continue),
+                    Ok(_) => break,
+                    Err(Errno::EINTR) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            let new_values = match read_values(file) {
+                Ok(new_values) => new_values,
+                // the control group was removed
+                Err(e) if e.raw_os_error() == Some(nix::libc::ENODEV) => {
+                    return Ok(PostAction::Remove)
+                }
+                Err(e) => return Err(e),
+            };
+            for (key, value) in &new_values {
+                let old = values.iter().find(|(k, _)| k == key).map(|&(_, v)| v);
+                if old != Some(*value) {
+                    callback(
+                        CgroupEvent {
+                            key: key.clone(),
+                            value: *value,
+                        },
+                        &mut (),
+                    );
+                }
+            }
+            *values = new_values;
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.epoll.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.epoll.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.epoll.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn not_a_cgroup() {
+        let err = CgroupEventSource::new("/nonexistent", EventsFile::Memory).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = CgroupEventSource::new("/proc", EventsFile::Cgroup).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        assert_eq!(
+            parse_values("low 0\nhigh 3\noom_kill 12\n"),
+            &[
+                ("low".to_owned(), 0),
+                ("high".to_owned(), 3),
+                ("oom_kill".to_owned(), 12)
+            ]
+        );
+    }
+}
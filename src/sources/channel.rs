@@ -7,15 +7,30 @@
 //!
 //! A synchronous version of the channel is provided by [`sync_channel`], in which
 //! the [`SyncSender`] will block when the channel is full.
+//!
+//! If you already have a [`std::sync::mpsc`] channel, you can turn it into a calloop
+//! channel using [`from_std`] or [`from_std_sync`]. They wrap the std sender into a
+//! [`Sender`] (or [`SyncSender`]) that wakes the event loop whenever a message is sent.
+//!
+//! The [`stats`](Channel#method.stats) of a channel count the messages sent, delivered and
+//! rejected because a synchronous channel was full, and can be retrieved from both ends.
+//!
+//! Workers that also need to run periodically, for example to do some maintenance every
+//! second or right away when a message arrives, can use [`channel_with_tick`], whose source
+//! delivers both the messages and the ticks to a single callback.
 
-use std::sync::mpsc;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
-use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+use crate::{BufferStats, EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
 
 use super::ping::{make_ping, Ping, PingSource};
+use super::timer::{ThreadConfig, Timer};
 
 /// The events generated by the channel event source
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Event<T> {
     /// A message was received and is bundled here
     Msg(T),
@@ -26,6 +41,36 @@ pub enum Event<T> {
     Closed,
 }
 
+// The counters of a channel, shared by its ends
+#[derive(Debug, Default)]
+struct Counters {
+    sent: AtomicU64,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Counters {
+    fn sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn delivered(&self) {
+        self.delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> BufferStats {
+        let sent = self.sent.load(Ordering::Relaxed);
+        let delivered = self.delivered.load(Ordering::Relaxed);
+        BufferStats {
+            sent,
+            delivered,
+            dropped: self.dropped.load(Ordering::Relaxed),
+            // the messages of unwrapped std senders are delivered without being counted as sent
+            occupancy: sent.saturating_sub(delivered),
+        }
+    }
+}
+
 /// The sender end of a channel
 ///
 /// It can be cloned and sent accross threads (if `T` is).
@@ -33,6 +78,7 @@ pub enum Event<T> {
 pub struct Sender<T> {
     sender: mpsc::Sender<T>,
     ping: Ping,
+    counters: Arc<Counters>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -41,6 +87,7 @@ impl<T> Clone for Sender<T> {
         Sender {
             sender: self.sender.clone(),
             ping: self.ping.clone(),
+            counters: self.counters.clone(),
         }
     }
 }
@@ -51,7 +98,15 @@ impl<T> Sender<T> {
     /// This will wake the event loop and deliver an `Event::Msg` to
     /// it containing the provided value.
     pub fn send(&self, t: T) -> Result<(), mpsc::SendError<T>> {
-        self.sender.send(t).map(|()| self.ping.ping())
+        self.sender.send(t).map(|()| {
+            self.counters.sent();
+            self.ping.ping()
+        })
+    }
+
+    /// The statistics of the channel
+    pub fn stats(&self) -> BufferStats {
+        self.counters.stats()
     }
 }
 
@@ -69,6 +124,7 @@ impl<T> Drop for Sender<T> {
 pub struct SyncSender<T> {
     sender: mpsc::SyncSender<T>,
     ping: Ping,
+    counters: Arc<Counters>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -77,6 +133,7 @@ impl<T> Clone for SyncSender<T> {
         SyncSender {
             sender: self.sender.clone(),
             ping: self.ping.clone(),
+            counters: self.counters.clone(),
         }
     }
 }
@@ -92,10 +149,21 @@ impl<T> SyncSender<T> {
     /// Due to the blocking behavior, this method should not be used on the
     /// same thread as the one running the event loop, as it could cause deadlocks.
     pub fn send(&self, t: T) -> Result<(), mpsc::SendError<T>> {
-        let ret = self.try_send(t);
+        let ret = self.sender.try_send(t);
         match ret {
-            Ok(()) => Ok(()),
-            Err(mpsc::TrySendError::Full(t)) => self.sender.send(t).map(|()| self.ping.ping()),
+            Ok(()) => {
+                self.counters.sent();
+                self.ping.ping();
+                Ok(())
+            }
+            Err(mpsc::TrySendError::Full(t)) => {
+                // wake the loop so that it empties the channel, this is not a drop
+                self.ping.ping();
+                self.sender.send(t).map(|()| {
+                    self.counters.sent();
+                    self.ping.ping()
+                })
+            }
             Err(mpsc::TrySendError::Disconnected(t)) => Err(mpsc::SendError(t)),
         }
     }
@@ -105,14 +173,25 @@ impl<T> SyncSender<T> {
     /// This will wake the event loop and deliver an `Event::Msg` to
     /// it containing the provided value. If the channel is full, this
     /// function will return an error, but the event loop will still be
-    /// signaled for readiness.
+    /// signaled for readiness. The message is then counted as dropped in the
+    /// [`stats`](SyncSender#method.stats) of the channel.
     pub fn try_send(&self, t: T) -> Result<(), mpsc::TrySendError<T>> {
         let ret = self.sender.try_send(t);
-        if let Ok(()) | Err(mpsc::TrySendError::Full(_)) = ret {
-            self.ping.ping();
+        match ret {
+            Ok(()) => self.counters.sent(),
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => return ret,
         }
+        self.ping.ping();
         ret
     }
+
+    /// The statistics of the channel
+    pub fn stats(&self) -> BufferStats {
+        self.counters.stats()
+    }
 }
 
 /// The receiving end of the channel
@@ -122,6 +201,7 @@ impl<T> SyncSender<T> {
 pub struct Channel<T> {
     receiver: mpsc::Receiver<T>,
     source: PingSource,
+    counters: Arc<Counters>,
 }
 
 // This impl is safe because the Channel is only able to move around threads
@@ -134,15 +214,71 @@ unsafe impl<T: Send> Send for Channel<T> {}
 /// Create a new asynchronous channel
 pub fn channel<T>() -> (Sender<T>, Channel<T>) {
     let (sender, receiver) = mpsc::channel();
-    let (ping, source) = make_ping().expect("Failed to create a Ping.");
-    (Sender { sender, ping }, Channel { receiver, source })
+    from_std(sender, receiver).expect("Failed to create a Ping.")
 }
 
 /// Create a new synchronous, bounded channel
 pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Channel<T>) {
     let (sender, receiver) = mpsc::sync_channel(bound);
-    let (ping, source) = make_ping().expect("Failed to create a Ping.");
-    (SyncSender { sender, ping }, Channel { receiver, source })
+    from_std_sync(sender, receiver).expect("Failed to create a Ping.")
+}
+
+/// Turn an existing std asynchronous channel into a calloop channel
+///
+/// The returned [`Sender`] sends its messages through the provided std sender and wakes
+/// the event loop. Messages sent through other clones of the std sender that are not wrapped
+/// will still be received, but only the next time the [`Channel`] is woken up.
+///
+/// The [`Channel`] reports [`Event::Closed`] once all senders of the std channel
+/// have been dropped.
+pub fn from_std<T>(
+    sender: mpsc::Sender<T>,
+    receiver: mpsc::Receiver<T>,
+) -> std::io::Result<(Sender<T>, Channel<T>)> {
+    let (ping, source) = make_ping()?;
+    let counters = Arc::new(Counters::default());
+    Ok((
+        Sender {
+            sender,
+            ping,
+            counters: counters.clone(),
+        },
+        Channel {
+            receiver,
+            source,
+            counters,
+        },
+    ))
+}
+
+/// Turn an existing std synchronous channel into a calloop channel
+///
+/// This behaves like [`from_std`] for synchronous channels.
+pub fn from_std_sync<T>(
+    sender: mpsc::SyncSender<T>,
+    receiver: mpsc::Receiver<T>,
+) -> std::io::Result<(SyncSender<T>, Channel<T>)> {
+    let (ping, source) = make_ping()?;
+    let counters = Arc::new(Counters::default());
+    Ok((
+        SyncSender {
+            sender,
+            ping,
+            counters: counters.clone(),
+        },
+        Channel {
+            receiver,
+            source,
+            counters,
+        },
+    ))
+}
+
+impl<T> Channel<T> {
+    /// The statistics of the channel
+    pub fn stats(&self) -> BufferStats {
+        self.counters.stats()
+    }
 }
 
 impl<T> EventSource for Channel<T> {
@@ -159,11 +295,14 @@ impl<T> EventSource for Channel<T> {
     where
         C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
     {
-        let receiver = &self.receiver;
+        let (receiver, counters) = (&self.receiver, &self.counters);
         self.source
             .process_events(readiness, token, |(), &mut ()| loop {
                 match receiver.try_recv() {
-                    Ok(val) => callback(Event::Msg(val), &mut ()),
+                    Ok(val) => {
+                        counters.delivered();
+                        callback(Event::Msg(val), &mut ())
+                    }
                     Err(mpsc::TryRecvError::Empty) => break,
                     Err(mpsc::TryRecvError::Disconnected) => {
                         callback(Event::Closed, &mut ());
@@ -194,6 +333,186 @@ impl<T> EventSource for Channel<T> {
     }
 }
 
+/// The events generated by the source of [`channel_with_tick`]
+#[derive(Debug)]
+pub enum TickEvent<T> {
+    /// A message was received and is bundled here
+    Item(T),
+    /// The tick interval elapsed
+    Tick,
+    /// The channel was closed, see [`Event::Closed`]
+    Closed,
+}
+
+/// How the ticks of a [`ChannelWithTick`] are scheduled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TickMode {
+    /// Tick every interval, regardless of the messages
+    Periodic,
+    /// Tick once no message was received for a whole interval
+    ResetOnActivity,
+}
+
+/// A channel source also ticking at a regular interval
+///
+/// See [`channel_with_tick`] for details.
+#[derive(Debug)]
+pub struct ChannelWithTick<T> {
+    channel: Channel<T>,
+    timer: Timer<()>,
+    tick: Duration,
+    mode: TickMode,
+}
+
+/// Create a new asynchronous channel whose source also ticks every `tick`
+///
+/// The source delivers a [`TickEvent::Item`] per message and a [`TickEvent::Tick`] each time
+/// the interval elapses. The ticks are [periodic](TickMode::Periodic) by default, use
+/// [`ChannelWithTick::with_tick_mode`](ChannelWithTick#method.with_tick_mode) to change
+/// that. The first tick happens one interval after the source is inserted in an event loop.
+///
+/// When some messages and a tick are ready during the same dispatch of the event loop, the
+/// messages are delivered first.
+pub fn channel_with_tick<T>(tick: Duration) -> io::Result<(Sender<T>, ChannelWithTick<T>)> {
+    channel_with_tick_and_thread_config(tick, &ThreadConfig::new())
+}
+
+/// Create a new asynchronous channel ticking every `tick`, whose timer thread is configured by
+/// `config`
+///
+/// This is like [`channel_with_tick`], the ticks being driven by a [`Timer`] configured as
+/// described in [`ThreadConfig`].
+pub fn channel_with_tick_and_thread_config<T>(
+    tick: Duration,
+    config: &ThreadConfig,
+) -> io::Result<(Sender<T>, ChannelWithTick<T>)> {
+    let (sender, channel) = channel();
+    Ok((
+        sender,
+        ChannelWithTick {
+            channel,
+            timer: Timer::with_thread_config(config)?,
+            tick,
+            mode: TickMode::Periodic,
+        },
+    ))
+}
+
+impl<T> ChannelWithTick<T> {
+    /// Set how the ticks are scheduled
+    pub fn with_tick_mode(mut self, mode: TickMode) -> ChannelWithTick<T> {
+        self.mode = mode;
+        self
+    }
+
+    /// The statistics of the channel
+    pub fn stats(&self) -> BufferStats {
+        self.channel.stats()
+    }
+
+    // Schedule the next tick one interval from now
+    fn restart(&self) {
+        let handle = self.timer.handle();
+        handle.cancel_all_timeouts();
+        handle.add_timeout(self.tick, ());
+    }
+}
+
+// Deliver the messages waiting in the channel, returning whether there were some
+fn drain<T, C>(receiver: &mpsc::Receiver<T>, counters: &Counters, callback: &mut C) -> bool
+where
+    C: FnMut(TickEvent<T>, &mut ()),
+{
+    let mut received = false;
+    loop {
+        match receiver.try_recv() {
+            Ok(val) => {
+                received = true;
+                counters.delivered();
+                callback(TickEvent::Item(val), &mut ());
+            }
+            Err(mpsc::TryRecvError::Empty) => break,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                callback(TickEvent::Closed, &mut ());
+                break;
+            }
+        }
+    }
+    received
+}
+
+impl<T> EventSource for ChannelWithTick<T> {
+    type Event = TickEvent<T>;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mut ticked = false;
+        self.timer
+            .process_events(readiness, token, |(), _| ticked = true)?;
+        if ticked {
+            // the messages come first, then the tick
+            let received = drain(
+                &self.channel.receiver,
+                &self.channel.counters,
+                &mut callback,
+            );
+            self.restart();
+            if !received || self.mode == TickMode::Periodic {
+                callback(TickEvent::Tick, &mut ());
+            }
+            return Ok(PostAction::Continue);
+        }
+
+        let mut received = false;
+        let (receiver, counters) = (&self.channel.receiver, &self.channel.counters);
+        let action = self
+            .channel
+            .source
+            .process_events(readiness, token, |(), &mut ()| {
+                received = drain(receiver, counters, &mut callback);
+            })?;
+        if received && self.mode == TickMode::ResetOnActivity {
+            self.restart();
+        }
+        Ok(action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.timer.register(poll, token_factory)?;
+        if let Err(e) = self.channel.register(poll, token_factory) {
+            let _ = self.timer.unregister(poll);
+            return Err(e);
+        }
+        self.restart();
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.timer.reregister(poll, token_factory)?;
+        self.channel.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        // a disabled source does not tick
+        self.timer.handle().cancel_all_timeouts();
+        self.timer.unregister(poll)?;
+        self.channel.unregister(poll)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.timer.next_deadline()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -245,6 +564,40 @@ mod tests {
         assert_eq!(got, (true, true));
     }
 
+    #[test]
+    fn channel_stats() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+        let (tx, rx) = sync_channel::<u32>(1);
+        let dispatcher = crate::Dispatcher::new(rx, |_, &mut (), _: &mut ()| {});
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        tx.try_send(1).unwrap();
+        assert!(tx.try_send(2).is_err());
+        let stats = BufferStats {
+            sent: 1,
+            delivered: 0,
+            dropped: 1,
+            occupancy: 1,
+        };
+        assert_eq!(tx.stats(), stats);
+        assert_eq!(dispatcher.as_source_ref().stats(), stats);
+
+        event_loop
+            .dispatch(Some(Duration::from_millis(0)), &mut ())
+            .unwrap();
+        assert_eq!(
+            tx.stats(),
+            BufferStats {
+                delivered: 1,
+                occupancy: 0,
+                ..stats
+            }
+        );
+    }
+
     #[test]
     fn basic_sync_channel() {
         let mut event_loop = crate::EventLoop::try_new().unwrap();
@@ -303,4 +656,133 @@ mod tests {
         assert_eq!(received.0, 3);
         assert!(received.1);
     }
+
+    #[test]
+    fn wrapped_std_channel() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (std_tx, std_rx) = std::sync::mpsc::channel::<u32>();
+        let raw_tx = std_tx.clone();
+        let (tx, rx) = from_std(std_tx, std_rx).unwrap();
+
+        let mut received = (Vec::new(), false);
+
+        let _channel_token = handle
+            .insert_source(
+                rx,
+                move |evt, &mut (), received: &mut (Vec<u32>, bool)| match evt {
+                    Event::Msg(v) => received.0.push(v),
+                    Event::Closed => received.1 = true,
+                },
+            )
+            .map_err(Into::<std::io::Error>::into)
+            .unwrap();
+
+        // a message sent through the raw std sender does not wake the loop...
+        raw_tx.send(1).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut received)
+            .unwrap();
+        assert!(received.0.is_empty());
+
+        // ...but it is delivered along with the next wrapped one
+        tx.send(2).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut received)
+            .unwrap();
+        assert_eq!(received.0, &[1, 2]);
+        assert!(!received.1);
+
+        // the channel is closed once all std senders are dropped
+        ::std::mem::drop(raw_tx);
+        ::std::mem::drop(tx);
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut received)
+            .unwrap();
+        assert!(received.1);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Seen {
+        Item(u32),
+        Tick,
+    }
+
+    fn insert_ticking(event_loop: &crate::EventLoop<Vec<Seen>>, source: ChannelWithTick<u32>) {
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), seen: &mut Vec<Seen>| match event {
+                TickEvent::Item(val) => seen.push(Seen::Item(val)),
+                TickEvent::Tick => seen.push(Seen::Tick),
+                TickEvent::Closed => {}
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn message_before_tick() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+        let (sender, source) = channel_with_tick(Duration::from_millis(20)).unwrap();
+        insert_ticking(&event_loop, source);
+
+        // the message and the tick are ready in the same pass
+        sender.send(1).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        let mut seen = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert_eq!(seen, &[Seen::Item(1), Seen::Tick]);
+    }
+
+    #[test]
+    fn tick_with_thread_config() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+        let config = ThreadConfig::new().name_prefix("ticking");
+        let (_sender, source) =
+            channel_with_tick_and_thread_config(Duration::from_millis(20), &config).unwrap();
+        insert_ticking(&event_loop, source);
+
+        let mut seen = Vec::new();
+        while seen.is_empty() {
+            event_loop
+                .dispatch(Duration::from_millis(50), &mut seen)
+                .unwrap();
+        }
+        assert_eq!(seen, &[Seen::Tick]);
+    }
+
+    #[test]
+    fn tick_reset_on_activity() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+        let (sender, source) = channel_with_tick(Duration::from_millis(20)).unwrap();
+        insert_ticking(
+            &event_loop,
+            source.with_tick_mode(TickMode::ResetOnActivity),
+        );
+
+        // the message received as the interval elapses delays the tick
+        sender.send(1).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        let mut seen = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert_eq!(seen, &[Seen::Item(1)]);
+
+        while seen.len() < 2 {
+            event_loop
+                .dispatch(Duration::from_millis(50), &mut seen)
+                .unwrap();
+        }
+        assert_eq!(seen, &[Seen::Item(1), Seen::Tick]);
+    }
 }
@@ -0,0 +1,339 @@
+//! Event source for the output of a child process
+//!
+//! The [`ChildOutput`] source reads the standard output and error of a child process from
+//! the pipes created by [`std::process::Command`], and delivers their content line by line.
+//! Lines are delivered without their trailing newline: each of them as an [`Event::Line`]
+//! if it is valid UTF-8, or as an [`Event::Raw`] with its bytes otherwise. When an output
+//! is closed, usually when the child exits, its last incomplete line is delivered, followed
+//! by an [`Event::Closed`], and the output is removed from the polling system. Once both
+//! outputs are closed, an [`Event::Done`] is generated and the source removes itself from
+//! the event loop.
+//!
+//! Only the outputs the child has pipes for are read: the ones which are not configured with
+//! [`Stdio::piped`](std::process::Stdio::piped) are considered closed from the start.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::process::{Command, Stdio};
+//!
+//! use calloop::child::{ChildOutput, Event};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let mut child = Command::new("ls")
+//!     .stdout(Stdio::piped())
+//!     .stderr(Stdio::piped())
+//!     .spawn()
+//!     .expect("Failed to spawn ls");
+//! let source = ChildOutput::from_child(&mut child).expect("Failed to setup the pipes");
+//!
+//! handle
+//!     .insert_source(source, |event, &mut (), _| match event {
+//!         Event::Line(stream, line) => println!("{:?}: {}", stream, line),
+//!         Event::Raw(stream, bytes) => println!("{:?}: {} bytes", stream, bytes.len()),
+//!         Event::Closed(stream) => println!("{:?} closed", stream),
+//!         Event::Done => println!("ls is done"),
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::process::{Child, ChildStderr, ChildStdout};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    unistd::read,
+};
+
+use super::generic::Generic;
+use super::lines::split_lines;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An output of a child process
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stream {
+    /// The standard output
+    Stdout,
+    /// The standard error
+    Stderr,
+}
+
+/// An event generated by the [`ChildOutput`] source
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A line of output, without its trailing newline
+    Line(Stream, String),
+    /// A line of output which is not valid UTF-8, without its trailing newline
+    Raw(Stream, Vec<u8>),
+    /// The output was closed, after its last line was delivered
+    Closed(Stream),
+    /// Both outputs are closed
+    ///
+    /// This is the last event of the source, which then removes itself from the event loop.
+    Done,
+}
+
+// One of the pipes of the child, with its incomplete line
+#[derive(Debug)]
+struct Pipe<F: AsRawFd> {
+    fd: Option<Generic<F>>,
+    stream: Stream,
+    line: Vec<u8>,
+    closed: bool,
+    registered: bool,
+}
+
+impl<F: AsRawFd> Pipe<F> {
+    fn new(pipe: Option<F>, stream: Stream) -> io::Result<Pipe<F>> {
+        if let Some(ref pipe) = pipe {
+            let fd = pipe.as_raw_fd();
+            let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+            fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        }
+        Ok(Pipe {
+            closed: pipe.is_none(),
+            fd: pipe.map(|pipe| Generic::new(pipe, Interest::READ, Mode::Level)),
+            stream,
+            line: Vec::new(),
+            registered: false,
+        })
+    }
+
+    fn process_events<C>(&mut self, readiness: Readiness, token: Token, callback: &mut C)
+    where
+        C: FnMut(Event, &mut ()),
+    {
+        let (stream, line, closed) = (self.stream, &mut self.line, &mut self.closed);
+        let fd = match self.fd {
+            Some(ref mut fd) if !*closed => fd,
+            _ => return,
+        };
+        let ret = fd.process_events(readiness, token, |_, pipe| {
+            let mut buffer = [0u8; 1024];
+            loop {
+                match read(pipe.as_raw_fd(), &mut buffer) {
+                    Ok(0) => {
+                        if !line.is_empty() {
+                            callback(to_event(stream, std::mem::take(line)), &mut ());
+                        }
+                        *closed = true;
+                        callback(Event::Closed(stream), &mut ());
+                        return Ok(PostAction::Continue);
+                    }
+                    Ok(len) => {
+                        line.extend_from_slice(&buffer[..len]);
+                        split_lines(line, |line| callback(to_event(stream, line), &mut ()));
+                    }
+                    Err(Errno::EAGAIN) => return Ok(PostAction::Continue),
+                    Err(Errno::EINTR) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        });
+        if let Err(e) = ret {
+            // the output cannot be read anymore
+            log::warn!("[calloop] Error reading from child {:?}: {}", stream, e);
+            self.closed = true;
+            callback(Event::Closed(stream), &mut ());
+        }
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        if let Some(ref mut fd) = self.fd {
+            if !self.closed {
+                fd.register(poll, token_factory)?;
+                self.registered = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        match self.fd {
+            Some(ref mut fd) if self.registered && self.closed => {
+                // the output was closed since the last registration
+                self.registered = false;
+                fd.unregister(poll)
+            }
+            Some(ref mut fd) if self.registered => fd.reregister(poll, token_factory),
+            _ => self.register(poll, token_factory),
+        }
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        if let Some(ref mut fd) = self.fd {
+            if self.registered {
+                self.registered = false;
+                fd.unregister(poll)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_event(stream: Stream, line: Vec<u8>) -> Event {
+    match String::from_utf8(line) {
+        Ok(line) => Event::Line(stream, line),
+        Err(e) => Event::Raw(stream, e.into_bytes()),
+    }
+}
+
+/// An event source reading the output of a child process line by line
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct ChildOutput {
+    stdout: Pipe<ChildStdout>,
+    stderr: Pipe<ChildStderr>,
+}
+
+impl ChildOutput {
+    /// Read the given outputs of a child process
+    ///
+    /// The pipes are made non-blocking. Fails if neither output is given.
+    pub fn new(
+        stdout: Option<ChildStdout>,
+        stderr: Option<ChildStderr>,
+    ) -> io::Result<ChildOutput> {
+        if stdout.is_none() && stderr.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the child has no output to read",
+            ));
+        }
+        Ok(ChildOutput {
+            stdout: Pipe::new(stdout, Stream::Stdout)?,
+            stderr: Pipe::new(stderr, Stream::Stderr)?,
+        })
+    }
+
+    /// Read the outputs of a child process, taking its pipes
+    pub fn from_child(child: &mut Child) -> io::Result<ChildOutput> {
+        ChildOutput::new(child.stdout.take(), child.stderr.take())
+    }
+
+    /// Whether the given output is closed
+    pub fn is_closed(&self, stream: Stream) -> bool {
+        match stream {
+            Stream::Stdout => self.stdout.closed,
+            Stream::Stderr => self.stderr.closed,
+        }
+    }
+}
+
+impl EventSource for ChildOutput {
+    type Event = Event;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let open = (self.stdout.closed, self.stderr.closed);
+        self.stdout.process_events(readiness, token, &mut callback);
+        self.stderr.process_events(readiness, token, &mut callback);
+        if self.stdout.closed && self.stderr.closed {
+            callback(Event::Done, &mut ());
+            Ok(PostAction::Remove)
+        } else if (self.stdout.closed, self.stderr.closed) != open {
+            // remove the closed output from the polling system
+            Ok(PostAction::Reregister)
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.stdout.register(poll, token_factory)?;
+        if let Err(e) = self.stderr.register(poll, token_factory) {
+            let _ = self.stdout.unregister(poll);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.stdout.reregister(poll, token_factory)?;
+        self.stderr.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.stdout.unregister(poll)?;
+        self.stderr.unregister(poll)
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.stdout.line.len() + self.stderr.line.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn lines_of_both_outputs() {
+        let mut event_loop = crate::EventLoop::<Vec<Event>>::try_new().unwrap();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(r"echo out; printf 'err\npartial' >&2; printf '\377\n'")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let source = ChildOutput::from_child(&mut child).unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), events| events.push(event))
+            .unwrap();
+
+        let mut events = Vec::new();
+        while events.last() != Some(&Event::Done) {
+            event_loop
+                .dispatch(Duration::from_millis(100), &mut events)
+                .unwrap();
+        }
+        child.wait().unwrap();
+
+        let of = |stream| {
+            events
+                .iter()
+                .filter(|event| match event {
+                    Event::Line(s, _) | Event::Raw(s, _) | Event::Closed(s) => *s == stream,
+                    Event::Done => false,
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            of(Stream::Stdout),
+            &[
+                Event::Line(Stream::Stdout, "out".into()),
+                Event::Raw(Stream::Stdout, vec![0xff]),
+                Event::Closed(Stream::Stdout),
+            ]
+        );
+        assert_eq!(
+            of(Stream::Stderr),
+            &[
+                Event::Line(Stream::Stderr, "err".into()),
+                Event::Line(Stream::Stderr, "partial".into()),
+                Event::Closed(Stream::Stderr),
+            ]
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Event source for the changes of the wall clock
+//!
+//! Only available on Linux.
+//!
+//! The [`ClockChangeSource`] generates an event each time the system clock is set, by the
+//! synchronization of NTP or manually, rather than running continuously. Programs scheduling
+//! work at absolute times of the day must then recompute their deadlines, as the
+//! [`Timer`](crate::timer::Timer) and the other sources of calloop measure time with the
+//! monotonic clock, which does not follow these changes.
+//!
+//! The source uses a `CLOCK_REALTIME` timerfd which never expires, armed with
+//! `TFD_TIMER_CANCEL_ON_SET`: the kernel cancels it when the clock is set, and reading it
+//! then fails with `ECANCELED`. The source rearms the timer after each change. The gradual
+//! adjustments of the clock, with `adjtime`, do not generate any event.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::clock::ClockChangeSource;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let source = ClockChangeSource::new().expect("Failed to create the timerfd");
+//!
+//! handle
+//!     .insert_source(source, |(), &mut (), _| {
+//!         println!("The clock was set, recomputing the schedule");
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use nix::{errno::Errno, libc, unistd::close};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event source generating an event when the wall clock is set
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct ClockChangeSource {
+    timer: Generic<RawFd>,
+}
+
+impl ClockChangeSource {
+    /// Create a new source watching the changes of the wall clock
+    pub fn new() -> io::Result<ClockChangeSource> {
+        let fd = unsafe {
+            libc::timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)
+        };
+        let fd = Errno::result(fd)?;
+        if let Err(e) = arm(fd) {
+            let _ = close(fd);
+            return Err(e);
+        }
+        Ok(ClockChangeSource {
+            timer: Generic::new(fd, Interest::READ, Mode::Level),
+        })
+    }
+}
+
+// Arm the timer at the end of time, to be cancelled when the clock is set
+fn arm(fd: RawFd) -> io::Result<()> {
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        },
+        it_value: libc::timespec {
+            tv_sec: libc::time_t::max_value(),
+            tv_nsec: 0,
+        },
+    };
+    let ret = unsafe {
+        libc::timerfd_settime(
+            fd,
+            libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET,
+            &spec,
+            std::ptr::null_mut(),
+        )
+    };
+    Errno::result(ret)?;
+    Ok(())
+}
+
+impl Drop for ClockChangeSource {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.timer.file) {
+            log::warn!("[calloop] Failed to close the clock timerfd: {:?}", e);
+        }
+    }
+}
+
+impl EventSource for ClockChangeSource {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.timer.process_events(readiness, token, |_, &mut fd| {
+            let mut expirations = [0u8; 8];
+            loop {
+                match nix::unistd::read(fd, &mut expirations) {
+                    // the timer expired, which cannot happen before the end of time
+                    Ok(_) => return Ok(PostAction::Continue),
+                    Err(Errno::ECANCELED) => {
+                        arm(fd)?;
+                        callback((), &mut ());
+                        return Ok(PostAction::Continue);
+                    }
+                    Err(Errno::EAGAIN) => return Ok(PostAction::Continue),
+                    Err(Errno::EINTR) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.timer.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.timer.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.timer.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn no_change() {
+        // setting the clock requires privileges the tests should not use
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ClockChangeSource::new().unwrap(), |(), &mut (), count| {
+                *count += 1
+            })
+            .unwrap();
+
+        let mut count = 0;
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut count)
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,67 @@
+//! A deadline driven by the timeout of the event loop, for the adapters which need one
+//!
+//! Like the [`TickSource`](crate::tick::TickSource), the deadline does not use any file
+//! descriptor nor thread: it is registered as a deadline source of the polling system, and the
+//! event loop bounds its waits by it, and processes its token once it is reached. The adapter
+//! using it must report it from its [`next_deadline`](crate::EventSource#method.next_deadline).
+
+use std::time::{Duration, Instant};
+
+use crate::{Poll, Token, TokenFactory};
+
+#[derive(Debug)]
+pub(crate) struct Deadline {
+    token: Option<Token>,
+    at: Option<Instant>,
+}
+
+impl Deadline {
+    pub(crate) fn new() -> Deadline {
+        Deadline {
+            token: None,
+            at: None,
+        }
+    }
+
+    // Schedule the deadline `delay` from now, replacing the previous one
+    pub(crate) fn set(&mut self, delay: Duration) {
+        self.at = Some(Instant::now() + delay);
+    }
+
+    pub(crate) fn cancel(&mut self) {
+        self.at = None;
+    }
+
+    // Whether this token is the one of the deadline, rather than of the wrapped source
+    pub(crate) fn owns(&self, token: Token) -> bool {
+        self.token == Some(token)
+    }
+
+    // Whether the deadline is reached, in which case it is cleared
+    pub(crate) fn expired(&mut self) -> bool {
+        match self.at {
+            Some(at) if at <= Instant::now() => {
+                self.at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) {
+        let token = token_factory.token();
+        poll.add_deadline_source(token);
+        self.token = Some(token);
+    }
+
+    pub(crate) fn unregister(&mut self, poll: &mut Poll) {
+        if let Some(token) = self.token.take() {
+            poll.remove_deadline_source(token);
+        }
+    }
+
+    // The deadline, if it is set and registered
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.token.and(self.at)
+    }
+}
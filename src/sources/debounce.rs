@@ -0,0 +1,278 @@
+//! An adapter debouncing the events of a source
+//!
+//! The [`Debounce`] adapter, created with [`debounce()`](debounce), waits for its wrapped
+//! source to be quiet before delivering its events: each event of the source (re)arms a
+//! deadline, and only when the deadline is reached without a newer event coming in is the latest
+//! event delivered to the callback. The previous events of the burst are dropped. This is
+//! typically used for user input, like reacting to a search field once the user stopped
+//! typing.
+//!
+//! As the events are delivered after the source generated them, the metadata of the source
+//! is not available when delivering them: the callback of the adapter receives `()` as its
+//! metadata, and the wrapped source must not expect any return value from its callback.
+//!
+//! When the wrapped source requests its removal from the event loop while an event is
+//! waiting for the end of its delay, this event is either delivered right away or dropped,
+//! depending on the [`OnRemove`] policy of the adapter. This policy does not apply when the
+//! adapter itself is removed using the [`LoopHandle`](crate::LoopHandle): the pending event
+//! can then be retrieved with [`Debounce::take_pending`](Debounce#method.take_pending).
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::time::Duration;
+//!
+//! use calloop::channel::{channel, Event};
+//! use calloop::debounce::debounce;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (sender, keystrokes) = channel::<String>();
+//! let source = debounce(keystrokes, Duration::from_millis(300));
+//!
+//! handle
+//!     .insert_source(source, |event, &mut (), _| {
+//!         if let Event::Msg(query) = event {
+//!             println!("Searching for {}", query);
+//!         }
+//!     })
+//!     .unwrap();
+//!
+//! for query in &["c", "ca", "cal", "calloop"] {
+//!     sender.send(query.to_string()).unwrap();
+//! }
+//! // only "calloop" is searched for, 300ms after it was sent
+//! # }
+//! ```
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use super::deadline::Deadline;
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// What to do with a pending event when the wrapped source removes itself
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnRemove {
+    /// Deliver the pending event right away
+    Flush,
+    /// Drop the pending event
+    Drop,
+}
+
+/// An event source delivering the latest event of its wrapped source once it is quiet
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct Debounce<S: EventSource> {
+    source: S,
+    delay: Duration,
+    deadline: Deadline,
+    pending: Option<S::Event>,
+    on_remove: OnRemove,
+}
+
+/// Debounce the events of a source by the given delay
+///
+/// By default, the pending event is [flushed](OnRemove::Flush) when the source removes
+/// itself.
+pub fn debounce<S: EventSource>(source: S, delay: Duration) -> Debounce<S> {
+    Debounce {
+        source,
+        delay,
+        deadline: Deadline::new(),
+        pending: None,
+        on_remove: OnRemove::Flush,
+    }
+}
+
+// Keep the new event as the pending one, and restart the delay
+fn store<E>(pending: &mut Option<E>, deadline: &mut Deadline, delay: Duration, event: E) {
+    *pending = Some(event);
+    deadline.set(delay);
+}
+
+impl<S: EventSource> Debounce<S> {
+    /// Set what to do with the pending event when the wrapped source removes itself
+    pub fn on_remove(mut self, policy: OnRemove) -> Debounce<S> {
+        self.on_remove = policy;
+        self
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// The event waiting for the end of the delay, if any
+    pub fn pending(&self) -> Option<&S::Event> {
+        self.pending.as_ref()
+    }
+
+    /// Take the event waiting for the end of the delay, so that it is not delivered
+    pub fn take_pending(&mut self) -> Option<S::Event> {
+        self.deadline.cancel();
+        self.pending.take()
+    }
+}
+
+impl<S: EventSource<Ret = ()>> EventSource for Debounce<S> {
+    type Event = S::Event;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.deadline.owns(token) {
+            if !self.deadline.expired() {
+                return Ok(PostAction::Continue);
+            }
+            // the source was quiet for the whole delay
+            if let Some(event) = self.pending.take() {
+                callback(event, &mut ());
+            }
+            return Ok(PostAction::Continue);
+        }
+
+        let (pending, delay, deadline) = (&mut self.pending, self.delay, &mut self.deadline);
+        let action = self.source.process_events(readiness, token, |event, _| {
+            store(pending, deadline, delay, event)
+        })?;
+        if let PostAction::Remove = action {
+            self.deadline.cancel();
+            if let Some(event) = self.pending.take() {
+                if self.on_remove == OnRemove::Flush {
+                    callback(event, &mut ());
+                }
+            }
+        }
+        Ok(action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)?;
+        self.deadline.register(poll, token_factory);
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)?;
+        self.deadline.unregister(poll);
+        self.deadline.register(poll, token_factory);
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.deadline.unregister(poll);
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, _callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (pending, delay, deadline) = (&mut self.pending, self.delay, &mut self.deadline);
+        self.source
+            .on_dispatch_start(|event, _| store(pending, deadline, delay, event));
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end();
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        // the end of the delay, or the deadline of the wrapped source
+        match (self.deadline.next_deadline(), self.source.next_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{channel, Event};
+    use crate::ping::make_ping;
+
+    #[test]
+    fn latest_event_after_quiet() {
+        let mut event_loop = crate::EventLoop::<Vec<u32>>::try_new().unwrap();
+        let (sender, source) = channel();
+        let source = debounce(source, Duration::from_millis(20));
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), received| {
+                if let Event::Msg(msg) = event {
+                    received.push(msg)
+                }
+            })
+            .unwrap();
+
+        let mut received = Vec::new();
+        for i in 1..=3 {
+            sender.send(i).unwrap();
+        }
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert!(received.is_empty());
+
+        for _ in 0..10 {
+            event_loop
+                .dispatch(Duration::from_millis(20), &mut received)
+                .unwrap();
+            if !received.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(received, &[3]);
+    }
+
+    #[test]
+    fn pending_on_remove() {
+        for &(policy, expected) in &[(OnRemove::Flush, 1), (OnRemove::Drop, 0)] {
+            let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+            let (ping, source) = make_ping().unwrap();
+            let source = debounce(source, Duration::from_secs(10)).on_remove(policy);
+            event_loop
+                .handle()
+                .insert_source(source, |(), &mut (), count| *count += 1)
+                .unwrap();
+
+            // the source generates an event and removes itself in the same pass
+            ping.ping();
+            drop(ping);
+            let mut count = 0;
+            event_loop
+                .dispatch(Duration::from_millis(0), &mut count)
+                .unwrap();
+            assert_eq!(count, expected);
+        }
+    }
+}
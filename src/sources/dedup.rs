@@ -0,0 +1,253 @@
+//! An adapter dropping the repeated events of a source
+//!
+//! The [`Dedup`] adapter, created with [`dedup()`](dedup), remembers the last event its
+//! wrapped source delivered, and drops the events which are equal to it. This avoids
+//! redundant work in callbacks for sources reporting a state, like a resize source firing
+//! twice with the same dimensions. The first event of the source is always delivered.
+//!
+//! Only consecutive events are compared: an event equal to an older one is delivered again
+//! if a different event came in between. The [`DedupHandle`] of the adapter can
+//! [reset](DedupHandle#method.reset) it, so that the next event is delivered whatever the
+//! previous one was, for example after the state it describes was changed elsewhere.
+//!
+//! As the dropped events do not invoke the callback, the wrapped source must not expect any
+//! return value from its callback.
+//!
+//! ```
+//! # extern crate calloop;
+//! use calloop::channel::{channel, Event};
+//! use calloop::dedup::dedup;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (sender, sizes) = channel::<(u16, u16)>();
+//! let source = dedup(sizes);
+//! let forcer = source.handle();
+//!
+//! handle
+//!     .insert_source(source, |event, &mut (), _| {
+//!         if let Event::Msg((cols, rows)) = event {
+//!             println!("Resized to {}x{}", cols, rows);
+//!         }
+//!     })
+//!     .unwrap();
+//!
+//! sender.send((80, 24)).unwrap();
+//! // dropped if the previous size was not delivered yet
+//! sender.send((80, 24)).unwrap();
+//! // deliver the next size even if it did not change
+//! forcer.reset();
+//! sender.send((80, 24)).unwrap();
+//! # }
+//! ```
+
+use std::{cell::Cell, io, rc::Rc};
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// A handle resetting a [`Dedup`] source
+///
+/// It can be cloned, and remains usable after the source has been inserted into the
+/// event loop.
+#[derive(Clone, Debug)]
+pub struct DedupHandle {
+    reset: Rc<Cell<bool>>,
+}
+
+impl DedupHandle {
+    /// Forget the last delivered event, so that the next one is delivered
+    pub fn reset(&self) {
+        self.reset.set(true);
+    }
+}
+
+/// An event source dropping the events equal to the previous one of its wrapped source
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct Dedup<S: EventSource> {
+    source: S,
+    last: Option<S::Event>,
+    reset: Rc<Cell<bool>>,
+}
+
+/// Drop the consecutive identical events of a source
+pub fn dedup<S>(source: S) -> Dedup<S>
+where
+    S: EventSource<Ret = ()>,
+    S::Event: PartialEq + Clone,
+{
+    Dedup {
+        source,
+        last: None,
+        reset: Rc::new(Cell::new(false)),
+    }
+}
+
+// Whether the event differs from the last delivered one, which it then replaces
+fn is_new<E: PartialEq + Clone>(last: &mut Option<E>, reset: &Cell<bool>, event: &E) -> bool {
+    if reset.replace(false) {
+        *last = None;
+    }
+    if last.as_ref() == Some(event) {
+        return false;
+    }
+    *last = Some(event.clone());
+    true
+}
+
+impl<S> Dedup<S>
+where
+    S: EventSource<Ret = ()>,
+    S::Event: PartialEq + Clone,
+{
+    /// Retrieve a handle to reset this source
+    pub fn handle(&self) -> DedupHandle {
+        DedupHandle {
+            reset: self.reset.clone(),
+        }
+    }
+
+    /// The last event delivered by this source, if any
+    pub fn last(&self) -> Option<&S::Event> {
+        if self.reset.get() {
+            None
+        } else {
+            self.last.as_ref()
+        }
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> S {
+        self.source
+    }
+}
+
+impl<S> EventSource for Dedup<S>
+where
+    S: EventSource<Ret = ()>,
+    S::Event: PartialEq + Clone,
+{
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (last, reset) = (&mut self.last, &self.reset);
+        self.source.process_events(readiness, token, |event, meta| {
+            if is_new(last, reset, &event) {
+                callback(event, meta)
+            }
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (last, reset) = (&mut self.last, &self.reset);
+        self.source.on_dispatch_start(|event, meta| {
+            if is_new(last, reset, &event) {
+                callback(event, meta)
+            }
+        })
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end()
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        self.source.next_deadline()
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::channel::{channel, Event};
+
+    #[test]
+    fn drops_repeated_events() {
+        let mut event_loop = crate::EventLoop::<Vec<u32>>::try_new().unwrap();
+        let (sender, source) = channel::<u32>();
+        let source = dedup(source);
+        let forcer = source.handle();
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), received| {
+                if let Event::Msg(msg) = event {
+                    received.push(msg);
+                }
+            })
+            .unwrap();
+
+        let mut received = Vec::new();
+        for &msg in &[1, 1, 2, 2, 1] {
+            sender.send(msg).unwrap();
+        }
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert_eq!(received, &[1, 2, 1]);
+
+        // the comparison spans the dispatching passes, until it is reset
+        sender.send(1).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert_eq!(received, &[1, 2, 1]);
+        forcer.reset();
+        sender.send(1).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert_eq!(received, &[1, 2, 1, 1]);
+    }
+}
@@ -0,0 +1,32 @@
+//! A file descriptor closed when dropped, for the sources sharing one with their handles
+
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use nix::unistd::close;
+
+#[derive(Debug)]
+pub(crate) struct FdGuard {
+    fd: RawFd,
+    // what the file descriptor is, for the warning logged if it cannot be closed
+    name: &'static str,
+}
+
+impl FdGuard {
+    pub(crate) fn new(fd: RawFd, name: &'static str) -> FdGuard {
+        FdGuard { fd, name }
+    }
+}
+
+impl AsRawFd for FdGuard {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for FdGuard {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.fd) {
+            log::warn!("[calloop] Failed to close {}: {:?}", self.name, e);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! An event source receiving file descriptors passed over a Unix socket
+//!
+//! Processes can pass file descriptors to each other over a Unix domain socket, as
+//! `SCM_RIGHTS` ancillary data, for example from a privileged process opening devices to an
+//! unprivileged one using them. The [`FdReceiver`] source wraps such a socket, either a
+//! `UnixStream` or a `UnixDatagram`, and delivers each received message as the passed file
+//! descriptors along with the payload bytes accompanying them.
+//!
+//! The ownership of the received file descriptors is transferred to the callback, which is
+//! responsible for closing them. They are received with the close-on-exec flag set.
+//!
+//! If a message carries more file descriptors than the source has room for, the kernel
+//! truncates its ancillary data: the file descriptors of this message which were received are
+//! closed, and the dispatching of the event loop returns an error. The same happens for a
+//! datagram larger than the payload buffer of the source. Use
+//! [`FdReceiver::with_max_fds`](FdReceiver#method.with_max_fds) and
+//! [`FdReceiver::with_buffer_size`](FdReceiver#method.with_buffer_size) to accept larger
+//! messages.
+//!
+//! When the peer closes a stream or a seqpacket socket, the source removes itself from the
+//! event loop. On a datagram socket, an empty message is delivered like any other.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::fs::File;
+//! use std::os::unix::io::FromRawFd;
+//! use std::os::unix::net::UnixStream;
+//!
+//! use calloop::fd_receiver::FdReceiver;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (socket, _peer) = UnixStream::pair().unwrap();
+//!
+//! handle
+//!     .insert_source(FdReceiver::new(socket), |(fds, payload), _socket, _| {
+//!         println!("Received {} files with {:?}", fds.len(), payload);
+//!         for fd in fds {
+//!             // the callback owns the received file descriptors
+//!             let _file = unsafe { File::from_raw_fd(fd) };
+//!         }
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use nix::{
+    errno::Errno,
+    sys::socket::{getsockopt, recvmsg, sockopt, ControlMessageOwned, MsgFlags, SockType},
+    sys::uio::IoVec,
+    unistd::close,
+};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// The default maximum number of file descriptors received with a single message
+pub const DEFAULT_MAX_FDS: usize = 16;
+
+/// The default size of the buffer receiving the payload of a message
+pub const DEFAULT_BUFFER_SIZE: usize = 4096;
+
+/// An event source receiving file descriptors over a Unix socket
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct FdReceiver<F: AsRawFd> {
+    socket: Generic<F>,
+    max_fds: usize,
+    buffer_size: usize,
+}
+
+impl<F: AsRawFd> FdReceiver<F> {
+    /// Wrap a Unix socket
+    ///
+    /// The socket does not need to be in non-blocking mode.
+    pub fn new(socket: F) -> FdReceiver<F> {
+        FdReceiver {
+            socket: Generic::new(socket, Interest::READ, Mode::Level),
+            max_fds: DEFAULT_MAX_FDS,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    /// Set the maximum number of file descriptors received with a single message
+    ///
+    /// Defaults to [`DEFAULT_MAX_FDS`]. Due to the alignment of the ancillary data, a few more
+    /// file descriptors may actually fit.
+    pub fn with_max_fds(mut self, max_fds: usize) -> FdReceiver<F> {
+        self.max_fds = max_fds;
+        self
+    }
+
+    /// Set the size of the buffer receiving the payload of a message
+    ///
+    /// Defaults to [`DEFAULT_BUFFER_SIZE`]. With a stream socket, a larger payload is
+    /// delivered over several events.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> FdReceiver<F> {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Access the socket
+    pub fn socket(&self) -> &F {
+        &self.socket.file
+    }
+
+    /// Unwrap the source, retrieving the socket
+    pub fn into_socket(self) -> F {
+        self.socket.unwrap()
+    }
+}
+
+impl<F: AsRawFd> EventSource for FdReceiver<F> {
+    type Event = (Vec<RawFd>, Vec<u8>);
+    type Metadata = F;
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let control_len = (self.max_fds * std::mem::size_of::<RawFd>()) as u32;
+        let mut control = vec![0u8; unsafe { nix::libc::CMSG_SPACE(control_len) } as usize];
+        let mut payload = vec![0u8; self.buffer_size];
+        self.socket
+            .process_events(readiness, token, |_, socket| loop {
+                let (len, fds, flags) = {
+                    let iov = [IoVec::from_mut_slice(&mut payload)];
+                    let msg = match recvmsg(
+                        socket.as_raw_fd(),
+                        &iov,
+                        Some(&mut control),
+                        MsgFlags::MSG_DONTWAIT | MsgFlags::MSG_CMSG_CLOEXEC,
+                    ) {
+                        Ok(msg) => msg,
+                        Err(Errno::EAGAIN) => return Ok(PostAction::Continue),
+                        Err(Errno::EINTR) => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    let mut fds = Vec::new();
+                    for cmsg in msg.cmsgs() {
+                        if let ControlMessageOwned::ScmRights(received) = cmsg {
+                            fds.extend(received);
+                        }
+                    }
+                    (msg.bytes, fds, msg.flags)
+                };
+
+                if flags.intersects(MsgFlags::MSG_CTRUNC | MsgFlags::MSG_TRUNC) {
+                    // the message is incomplete, do not leak what was received of it
+                    for fd in fds {
+                        let _ = close(fd);
+                    }
+                    let what = if flags.contains(MsgFlags::MSG_CTRUNC) {
+                        "file descriptors"
+                    } else {
+                        "payload"
+                    };
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Truncated message: too many {} received", what),
+                    ));
+                }
+                if len == 0
+                    && fds.is_empty()
+                    && getsockopt(socket.as_raw_fd(), sockopt::SockType)? != SockType::Datagram
+                {
+                    // the peer closed the connection
+                    return Ok(PostAction::Remove);
+                }
+                callback((fds, payload[..len].to_vec()), socket);
+            })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.socket.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.socket.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.socket.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use nix::sys::socket::{sendmsg, ControlMessage};
+    use nix::unistd::{pipe, read, write};
+
+    use super::*;
+
+    fn send_fds(socket: &UnixStream, fds: &[RawFd], payload: &[u8]) {
+        sendmsg(
+            socket.as_raw_fd(),
+            &[IoVec::from_slice(payload)],
+            &[ControlMessage::ScmRights(fds)],
+            MsgFlags::empty(),
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn receive_fds() {
+        let mut event_loop = crate::EventLoop::<Vec<(Vec<RawFd>, Vec<u8>)>>::try_new().unwrap();
+        let (socket, peer) = UnixStream::pair().unwrap();
+        event_loop
+            .handle()
+            .insert_source(FdReceiver::new(socket), |message, _, received| {
+                received.push(message)
+            })
+            .unwrap();
+
+        let (read_fd, write_fd) = pipe().unwrap();
+        send_fds(&peer, &[write_fd], b"pipe");
+        close(write_fd).unwrap();
+
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        let (fds, payload) = received.pop().unwrap();
+        assert_eq!(payload, b"pipe");
+        assert_eq!(fds.len(), 1);
+
+        // the received fd is the write end of the pipe
+        write(fds[0], b"a").unwrap();
+        let mut buffer = [0u8; 1];
+        assert_eq!(read(read_fd, &mut buffer).unwrap(), 1);
+        close(fds[0]).unwrap();
+        close(read_fd).unwrap();
+
+        // the source removes itself once the peer is gone
+        drop(peer);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn truncated_fds() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+        let (socket, peer) = UnixStream::pair().unwrap();
+        event_loop
+            .handle()
+            .insert_source(FdReceiver::new(socket).with_max_fds(1), |_, _, _| {
+                panic!("Unexpected message")
+            })
+            .unwrap();
+
+        let (read_fd, write_fd) = pipe().unwrap();
+        // more than the room of a single fd, even after alignment
+        send_fds(&peer, &[read_fd, write_fd, read_fd], b"three");
+        close(read_fd).unwrap();
+        close(write_fd).unwrap();
+
+        let err = event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn empty_datagram() {
+        use std::os::unix::net::UnixDatagram;
+
+        let mut event_loop = crate::EventLoop::<Vec<(Vec<RawFd>, Vec<u8>)>>::try_new().unwrap();
+        let (socket, peer) = UnixDatagram::pair().unwrap();
+        event_loop
+            .handle()
+            .insert_source(FdReceiver::new(socket), |message, _, received| {
+                received.push(message)
+            })
+            .unwrap();
+
+        // an empty datagram is a message, not the end of the connection
+        peer.send(b"").unwrap();
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert_eq!(received, &[(Vec::new(), Vec::new())]);
+
+        peer.send(b"next").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[1].1, b"next");
+    }
+}
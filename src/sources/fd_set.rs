@@ -0,0 +1,298 @@
+//! An event source monitoring a dynamic set of file descriptors
+//!
+//! When a program manages many file descriptors of the same kind, like the client
+//! connections of a proxy, inserting each of them as its own [`Generic`](crate::generic)
+//! source is cumbersome. The [`FdSet`] source registers any number of file descriptors and
+//! delivers their readiness to a single callback, as `(fd, readiness)` events.
+//!
+//! File descriptors are added and removed through a [`FdSetHandle`], which can be used
+//! before the source is inserted in the event loop or while it is running, for example from
+//! the callback. The changes made while the source is inserted are applied during the next
+//! dispatch of the event loop: a removed file descriptor may still generate events during
+//! this dispatch.
+//!
+//! The file descriptors are registered in level-triggered mode, and are neither owned nor
+//! closed by the source: remove them from the set before closing them.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::net::TcpListener;
+//! use std::os::unix::io::{AsRawFd, IntoRawFd};
+//!
+//! use calloop::{fd_set::FdSet, Interest};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+//! let fds = FdSet::new().unwrap();
+//! let fds_handle = fds.handle();
+//! fds_handle.add_fd(listener.as_raw_fd(), Interest::READ);
+//!
+//! handle
+//!     .insert_source(fds, move |(fd, _readiness), &mut (), _| {
+//!         if fd == listener.as_raw_fd() {
+//!             // accept the client and watch it as well
+//!             let (client, _) = listener.accept().unwrap();
+//!             fds_handle.add_fd(client.into_raw_fd(), Interest::READ);
+//!         } else {
+//!             // read from the client
+//!         }
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+
+use super::ping::{make_ping, Ping, PingSource};
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+#[derive(Debug)]
+enum FdChange {
+    Add(RawFd, Interest),
+    Remove(RawFd),
+}
+
+#[derive(Debug)]
+struct Entry {
+    interest: Interest,
+    // boxed, as the polling system keeps a pointer to it
+    token: Box<Token>,
+    registered: bool,
+}
+
+/// An event source monitoring a set of file descriptors
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct FdSet {
+    fds: HashMap<RawFd, Entry>,
+    tokens: HashMap<Token, RawFd>,
+    changes: Arc<Mutex<Vec<FdChange>>>,
+    ping: Ping,
+    ping_source: PingSource,
+}
+
+impl FdSet {
+    /// Create a new empty set of file descriptors
+    pub fn new() -> io::Result<FdSet> {
+        let (ping, ping_source) = make_ping()?;
+        Ok(FdSet {
+            fds: HashMap::new(),
+            tokens: HashMap::new(),
+            changes: Arc::new(Mutex::new(Vec::new())),
+            ping,
+            ping_source,
+        })
+    }
+
+    /// Get a handle to add and remove file descriptors
+    pub fn handle(&self) -> FdSetHandle {
+        FdSetHandle {
+            changes: self.changes.clone(),
+            ping: self.ping.clone(),
+        }
+    }
+
+    /// The number of file descriptors in the set
+    ///
+    /// The changes requested through a [`FdSetHandle`] are not taken into account until
+    /// they are applied.
+    pub fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    /// Whether the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.fds.is_empty()
+    }
+
+    // Apply the requested changes and (re)register all the file descriptors with new tokens
+    fn sync(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) {
+        let changes = std::mem::take(&mut *self.changes.lock().unwrap());
+        for change in changes {
+            match change {
+                FdChange::Add(fd, interest) => {
+                    let entry = self.fds.entry(fd).or_insert_with(|| Entry {
+                        interest,
+                        token: Box::new(Token::invalid()),
+                        registered: false,
+                    });
+                    entry.interest = interest;
+                }
+                FdChange::Remove(fd) => {
+                    if let Some(entry) = self.fds.remove(&fd) {
+                        if entry.registered {
+                            // the fd may already be closed
+                            let _ = poll.unregister(fd);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.tokens.clear();
+        let tokens = &mut self.tokens;
+        self.fds.retain(|&fd, entry| {
+            *entry.token = token_factory.token();
+            let ret = unsafe {
+                if entry.registered {
+                    poll.reregister(fd, entry.interest, Mode::Level, &*entry.token)
+                } else {
+                    poll.register(fd, entry.interest, Mode::Level, &*entry.token)
+                }
+            };
+            match ret {
+                Ok(()) => {
+                    entry.registered = true;
+                    tokens.insert(*entry.token, fd);
+                    true
+                }
+                Err(e) => {
+                    log::warn!("[calloop] Failed to register fd {} in a FdSet: {}", fd, e);
+                    if entry.registered {
+                        let _ = poll.unregister(fd);
+                    }
+                    false
+                }
+            }
+        });
+    }
+}
+
+/// A handle to add and remove the file descriptors of a [`FdSet`]
+///
+/// It can be cloned and sent across threads.
+#[derive(Clone, Debug)]
+pub struct FdSetHandle {
+    changes: Arc<Mutex<Vec<FdChange>>>,
+    ping: Ping,
+}
+
+impl FdSetHandle {
+    /// Add a file descriptor to the set, or change its interest if it is already there
+    pub fn add_fd(&self, fd: RawFd, interest: Interest) {
+        self.push(FdChange::Add(fd, interest));
+    }
+
+    /// Remove a file descriptor from the set
+    ///
+    /// Once the removal is applied, no more event is generated for this file descriptor.
+    pub fn remove_fd(&self, fd: RawFd) {
+        self.push(FdChange::Remove(fd));
+    }
+
+    fn push(&self, change: FdChange) {
+        self.changes.lock().unwrap().push(change);
+        // wake the source up, so that it gets reregistered
+        self.ping.ping();
+    }
+}
+
+impl EventSource for FdSet {
+    type Event = (RawFd, Readiness);
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if let Some(&fd) = self.tokens.get(&token) {
+            callback((fd, readiness), &mut ());
+            return Ok(PostAction::Continue);
+        }
+        let mut changed = false;
+        self.ping_source
+            .process_events(readiness, token, |(), &mut ()| changed = true)?;
+        if changed {
+            // the changes are applied with the polling system, when reregistering
+            Ok(PostAction::Reregister)
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.ping_source.register(poll, token_factory)?;
+        self.sync(poll, token_factory);
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.ping_source.reregister(poll, token_factory)?;
+        self.sync(poll, token_factory);
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.ping_source.unregister(poll)?;
+        for (&fd, entry) in self.fds.iter_mut() {
+            if entry.registered {
+                let _ = poll.unregister(fd);
+                entry.registered = false;
+            }
+        }
+        self.tokens.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn add_and_remove_live() {
+        let mut event_loop = crate::EventLoop::<Vec<RawFd>>::try_new().unwrap();
+        let (mut tx1, rx1) = UnixStream::pair().unwrap();
+        let (mut tx2, rx2) = UnixStream::pair().unwrap();
+
+        let fds = FdSet::new().unwrap();
+        let handle = fds.handle();
+        handle.add_fd(rx1.as_raw_fd(), Interest::READ);
+        event_loop
+            .handle()
+            .insert_source(fds, |(fd, readiness), &mut (), seen| {
+                assert!(readiness.readable);
+                seen.push(fd);
+            })
+            .unwrap();
+
+        let mut seen = Vec::new();
+        tx1.write_all(b"a").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert_eq!(seen, &[rx1.as_raw_fd()]);
+
+        // change the set while the source is inserted
+        handle.remove_fd(rx1.as_raw_fd());
+        handle.add_fd(rx2.as_raw_fd(), Interest::READ);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        seen.clear();
+        tx2.write_all(b"b").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        // rx1 is still readable, but not watched anymore
+        assert_eq!(seen, &[rx2.as_raw_fd()]);
+    }
+}
@@ -0,0 +1,186 @@
+//! A shared flag waking up the event loop when it is set
+//!
+//! The [`flag()`](flag) function creates a [`FlagSource`] and a [`FlagSetter`]. The setter can
+//! be cloned and sent to other threads, which [`set`](FlagSetter#method.set) and
+//! [`clear`](FlagSetter#method.clear) the flag, an atomic boolean shared with the source. The
+//! callback of the source is invoked when the flag goes from cleared to set, so it only sees
+//! the transitions to `true`: setting a flag which is already set does nothing.
+//!
+//! The changes are coalesced until the event loop is dispatched: if the flag was set and then
+//! cleared in the meantime, the callback is not invoked, and if it was set several times, with
+//! clears in between, it is invoked once.
+//!
+//! Like the [`ping`](crate::ping) source it is built on, the source removes itself from the
+//! event loop once all the [`FlagSetter`]s are dropped.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::flag::flag;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (setter, source) = flag().unwrap();
+//!
+//! handle
+//!     .insert_source(source, |(), &mut (), _| {
+//!         println!("The configuration changed");
+//!     })
+//!     .unwrap();
+//!
+//! std::thread::spawn(move || {
+//!     // ... detect a change of the configuration
+//!     setter.set();
+//! });
+//! # }
+//! ```
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use super::ping::{make_ping, Ping, PingSource};
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// Create a new flag, initially cleared
+pub fn flag() -> io::Result<(FlagSetter, FlagSource)> {
+    let (ping, source) = make_ping()?;
+    let flag = Arc::new(AtomicBool::new(false));
+    Ok((
+        FlagSetter {
+            flag: flag.clone(),
+            ping,
+        },
+        FlagSource { flag, source },
+    ))
+}
+
+/// The handle changing the state of a flag
+///
+/// It can be cloned and sent accross threads.
+#[derive(Clone, Debug)]
+pub struct FlagSetter {
+    flag: Arc<AtomicBool>,
+    ping: Ping,
+}
+
+impl FlagSetter {
+    /// Set the flag, waking up the event loop if it was cleared
+    pub fn set(&self) {
+        if !self.flag.swap(true, Ordering::AcqRel) {
+            self.ping.ping();
+        }
+    }
+
+    /// Clear the flag
+    pub fn clear(&self) {
+        self.flag.store(false, Ordering::Release);
+    }
+
+    /// Whether the flag is currently set
+    pub fn is_set(&self) -> bool {
+        self.flag.load(Ordering::Acquire)
+    }
+}
+
+/// The event source of a flag
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct FlagSource {
+    flag: Arc<AtomicBool>,
+    source: PingSource,
+}
+
+impl FlagSource {
+    /// Whether the flag is currently set
+    pub fn is_set(&self) -> bool {
+        self.flag.load(Ordering::Acquire)
+    }
+}
+
+impl EventSource for FlagSource {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let flag = &self.flag;
+        self.source.process_events(readiness, token, |(), &mut ()| {
+            // the flag may have been cleared since it was set
+            if flag.load(Ordering::Acquire) {
+                callback((), &mut ());
+            }
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn transitions_to_set() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let (setter, source) = flag().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        // setting an already set flag is coalesced
+        setter.set();
+        setter.set();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+        setter.set();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        // a new transition after a clear
+        setter.clear();
+        setter.set();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 2);
+
+        // the flag is cleared again before the loop is dispatched
+        setter.clear();
+        setter.set();
+        setter.clear();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 2);
+        assert!(!setter.is_set());
+    }
+}
@@ -0,0 +1,223 @@
+//! A source scheduling the flushes of accumulated data by activity and by time
+//!
+//! A metrics exporter accumulating statistics wants to flush them regularly: often enough
+//! when the loop is busy, and still from time to time when it is idle. The
+//! [`FlushScheduler`] source invokes its callback every `passes` dispatching passes of the
+//! event loop, or once `period` elapsed since the last flush, whichever comes first. Both
+//! thresholds are reset after each flush. The event of the source tells which threshold was
+//! reached.
+//!
+//! The passes are counted when the loop starts dispatching, through the
+//! [`on_dispatch_start`](crate::EventSource#method.on_dispatch_start) method of the source,
+//! and the period is driven by the timeout of the loop, like the [`tick`](crate::tick)
+//! source. A disabled source does not count the passes, and does not wake up the loop.
+//!
+//! The callback of the source cannot be invoked once the loop shuts down. For a final flush,
+//! give a hook to [`with_final_flush`](FlushScheduler#method.with_final_flush), which is
+//! invoked when the loop shuts down, see
+//! [`EventSource::on_shutdown`](crate::EventSource#method.on_shutdown).
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::time::Duration;
+//!
+//! use calloop::flush_scheduler::FlushScheduler;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let scheduler = FlushScheduler::new(1000, Duration::from_secs(10))
+//!     .with_final_flush(|| println!("Flushing the last statistics"));
+//!
+//! handle
+//!     .insert_source(scheduler, |reason, &mut (), _| {
+//!         println!("Flushing the statistics, {:?} reached", reason);
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// The threshold which triggered a flush
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlushReason {
+    /// The number of dispatching passes was reached
+    Passes,
+    /// The period elapsed since the last flush
+    Period,
+}
+
+/// An event source flushing every number of passes or every period
+///
+/// See the [module documentation](self) for details.
+pub struct FlushScheduler {
+    passes: u32,
+    period: Duration,
+    counted: u32,
+    next: Option<Instant>,
+    token: Option<Token>,
+    final_flush: Option<Box<dyn FnMut()>>,
+}
+
+impl std::fmt::Debug for FlushScheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FlushScheduler")
+            .field("passes", &self.passes)
+            .field("period", &self.period)
+            .field("counted", &self.counted)
+            .field("next", &self.next)
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+impl FlushScheduler {
+    /// Create a source flushing every `passes` dispatching passes, or every `period`
+    ///
+    /// A number of passes of `0` is treated as `1`.
+    pub fn new(passes: u32, period: Duration) -> FlushScheduler {
+        FlushScheduler {
+            passes: passes.max(1),
+            period,
+            counted: 0,
+            next: None,
+            token: None,
+            final_flush: None,
+        }
+    }
+
+    /// Set a hook invoked when the loop shuts down, for a final flush
+    pub fn with_final_flush<F: FnMut() + 'static>(mut self, hook: F) -> FlushScheduler {
+        self.final_flush = Some(Box::new(hook));
+        self
+    }
+
+    /// The number of passes counted since the last flush
+    pub fn counted_passes(&self) -> u32 {
+        self.counted
+    }
+
+    // Reset both thresholds after a flush
+    fn reset(&mut self) {
+        self.counted = 0;
+        self.next = Some(Instant::now() + self.period);
+    }
+}
+
+impl EventSource for FlushScheduler {
+    type Event = FlushReason;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        _readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.token != Some(token) {
+            return Ok(PostAction::Continue);
+        }
+        if self.next.map_or(false, |next| next <= Instant::now()) {
+            self.reset();
+            callback(FlushReason::Period, &mut ());
+        }
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        let token = token_factory.token();
+        poll.add_deadline_source(token);
+        self.token = Some(token);
+        if self.next.is_none() {
+            self.next = Some(Instant::now() + self.period);
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.unregister(poll)?;
+        self.register(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        if let Some(token) = self.token.take() {
+            poll.remove_deadline_source(token);
+        }
+        Ok(())
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.token.is_none() {
+            return;
+        }
+        self.counted += 1;
+        if self.counted >= self.passes {
+            self.reset();
+            callback(FlushReason::Passes, &mut ());
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.token.and(self.next)
+    }
+
+    fn on_shutdown(&mut self) {
+        if let Some(hook) = &mut self.final_flush {
+            hook();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn flushes_by_passes_and_period() {
+        let mut event_loop = crate::EventLoop::<Vec<FlushReason>>::try_new().unwrap();
+        let final_flushes = Rc::new(Cell::new(0));
+        let counter = final_flushes.clone();
+        let scheduler = FlushScheduler::new(3, Duration::from_millis(50))
+            .with_final_flush(move || counter.set(counter.get() + 1));
+        event_loop
+            .handle()
+            .insert_source(scheduler, |reason, &mut (), reasons| reasons.push(reason))
+            .unwrap();
+
+        // a busy loop flushes by count
+        let mut reasons = Vec::new();
+        for _ in 0..3 {
+            event_loop
+                .dispatch(Duration::from_millis(0), &mut reasons)
+                .unwrap();
+        }
+        assert_eq!(reasons, &[FlushReason::Passes]);
+
+        // an idle loop flushes by time, which resets the count
+        let start = Instant::now();
+        event_loop
+            .dispatch(Duration::from_secs(5), &mut reasons)
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(reasons, &[FlushReason::Passes, FlushReason::Period]);
+
+        assert_eq!(final_flushes.get(), 0);
+        drop(event_loop);
+        assert_eq!(final_flushes.get(), 1);
+    }
+}
@@ -27,6 +27,9 @@
 //!         // your callback needs to return a Result<PostAction, std::io::Error>
 //!         // if it returns an error, the event loop will consider this event
 //!         // event source as erroring and report it to the user.
+//!         //
+//!         // The returned PostAction lets you keep the registration as is, disable
+//!         // or remove the source, or change its interest with PostAction::SetInterest.
 //!         Ok(PostAction::Continue)
 //!     }
 //! );
@@ -39,9 +42,27 @@
 //!
 //! If you need to directly work with a [`RawFd`](std::os::unix::io::RawFd), rather than an
 //! FD-backed object, see [`Generic::from_fd`](Generic#method.from_fd).
+//!
+//! ## Replacing the wrapped object
+//!
+//! The wrapped object can be swapped for a new one while the source remains in the event loop,
+//! keeping its callback and its [`RegistrationToken`](crate::RegistrationToken). This is useful
+//! for example to reopen a log file after it has been rotated. The `Generic` source remembers
+//! which file descriptor it registered, and will unregister the old one and register the new one
+//! the next time it is re-registered.
+//!
+//! From within the callback, you can directly replace the object you are given as second argument
+//! (using [`std::mem::replace`]) and return [`PostAction::Reregister`](crate::PostAction). Doing
+//! so right after draining the old object ensures no event is lost during the swap.
+//!
+//! From outside the callback, use [`LoopHandle::swap_file`](crate::LoopHandle#method.swap_file),
+//! which swaps the registrations right away: the new object is registered before the old one is
+//! unregistered, and the events pending on the old object can still be drained from it once it
+//! is given back. An [`EventSource`](crate::EventSource) wrapping a `Generic` can do the same
+//! with [`Generic::swap_file`](Generic#method.swap_file).
 
 use std::io;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
 
@@ -55,6 +76,7 @@ pub struct Generic<F: AsRawFd> {
     /// The programmed mode
     pub mode: Mode,
     token: Box<Token>,
+    registered_fd: Option<RawFd>,
 }
 
 impl<F: AsRawFd> Generic<F> {
@@ -65,6 +87,7 @@ impl<F: AsRawFd> Generic<F> {
             interest,
             mode,
             token: Box::new(Token::invalid()),
+            registered_fd: None,
         }
     }
 
@@ -72,6 +95,51 @@ impl<F: AsRawFd> Generic<F> {
     pub fn unwrap(self) -> F {
         self.file
     }
+
+    /// Replace the wrapped FD-backed type, returning the previous one
+    ///
+    /// If the source is currently inserted in an event loop, the new object will be
+    /// registered in place of the old one with the same token the next time the source is
+    /// re-registered, for example by calling [`LoopHandle::update`](crate::LoopHandle#method.update).
+    ///
+    /// You are given back ownership of the old object, but you must keep it open until the
+    /// source has been re-registered. Any event that was pending on the old object and that
+    /// you did not drain before calling this method will be lost.
+    ///
+    /// To swap the registrations right away, see [`swap_file`](Generic#method.swap_file).
+    pub fn replace_file(&mut self, file: F) -> F {
+        std::mem::replace(&mut self.file, file)
+    }
+
+    /// Replace the wrapped FD-backed type right away, returning the previous one
+    ///
+    /// If the source is registered, the new object is registered under the same token before
+    /// the old one is unregistered, so that the source keeps watching a file descriptor at all
+    /// times. The old object is no longer polled once it is given back, but the events which
+    /// were pending on it can still be drained from it.
+    pub fn swap_file(&mut self, file: F, poll: &mut Poll) -> io::Result<F> {
+        let fd = file.as_raw_fd();
+        match self.registered_fd {
+            Some(old_fd) if old_fd != fd => {
+                unsafe {
+                    poll.register(fd, self.interest, self.mode, &*self.token as *const _)?;
+                }
+                self.registered_fd = Some(fd);
+                if let Err(e) = poll.unregister(old_fd) {
+                    log::warn!(
+                        "[calloop] Failed to unregister replaced fd from the polling system: {:?}",
+                        e
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(std::mem::replace(&mut self.file, file))
+    }
+
+    pub(crate) fn is_registered(&self) -> bool {
+        self.registered_fd.is_some()
+    }
 }
 
 impl<F: AsRawFd> EventSource for Generic<F> {
@@ -91,7 +159,13 @@ impl<F: AsRawFd> EventSource for Generic<F> {
         if token != *self.token {
             return Ok(PostAction::Continue);
         }
-        callback(readiness, &mut self.file)
+        match callback(readiness, &mut self.file)? {
+            PostAction::SetInterest(interest) => {
+                self.interest = interest;
+                Ok(PostAction::Reregister)
+            }
+            action => Ok(action),
+        }
     }
 
     fn register(
@@ -100,15 +174,12 @@ impl<F: AsRawFd> EventSource for Generic<F> {
         token_factory: &mut TokenFactory,
     ) -> std::io::Result<()> {
         let token = Box::new(token_factory.token());
+        let fd = self.file.as_raw_fd();
         unsafe {
-            poll.register(
-                self.file.as_raw_fd(),
-                self.interest,
-                self.mode,
-                &*token as *const _,
-            )?;
+            poll.register(fd, self.interest, self.mode, &*token as *const _)?;
         }
         self.token = token;
+        self.registered_fd = Some(fd);
         Ok(())
     }
 
@@ -117,22 +188,34 @@ impl<F: AsRawFd> EventSource for Generic<F> {
         poll: &mut Poll,
         token_factory: &mut TokenFactory,
     ) -> std::io::Result<()> {
+        let fd = self.file.as_raw_fd();
+        match self.registered_fd {
+            Some(old_fd) if old_fd != fd => {
+                // the wrapped object was replaced, swap the registrations
+                if let Err(e) = poll.unregister(old_fd) {
+                    log::warn!(
+                        "[calloop] Failed to unregister replaced fd from the polling system: {:?}",
+                        e
+                    );
+                }
+                self.registered_fd = None;
+                return self.register(poll, token_factory);
+            }
+            _ => {}
+        }
         let token = Box::new(token_factory.token());
         unsafe {
-            poll.reregister(
-                self.file.as_raw_fd(),
-                self.interest,
-                self.mode,
-                &*token as *const _,
-            )?;
+            poll.reregister(fd, self.interest, self.mode, &*token as *const _)?;
         }
         self.token = token;
         Ok(())
     }
 
     fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
-        poll.unregister(self.file.as_raw_fd())?;
+        let fd = self.registered_fd.unwrap_or_else(|| self.file.as_raw_fd());
+        poll.unregister(fd)?;
         self.token = Box::new(Token::invalid());
+        self.registered_fd = None;
         Ok(())
     }
 }
@@ -257,4 +340,149 @@ mod test {
         // the has now been properly dispatched
         assert!(dispached);
     }
+
+    #[test]
+    fn replace_file_unix() {
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (mut tx1, rx1) = UnixStream::pair().unwrap();
+        let (mut tx2, rx2) = UnixStream::pair().unwrap();
+
+        let generic = Generic::new(rx1, Interest::READ, Mode::Level);
+        let dispatcher = Dispatcher::new(generic, move |_, file: &mut UnixStream, d| {
+            let mut buffer = vec![0; 10];
+            let ret = file.read(&mut buffer).unwrap();
+            *d = ret;
+            Ok(PostAction::Continue)
+        });
+
+        let generic_token = handle.register_dispatcher(dispatcher.clone()).unwrap();
+
+        // swap the underlying stream while the source is inserted
+        let _old = dispatcher.as_source_mut().replace_file(rx2);
+        handle.update(&generic_token).unwrap();
+
+        let mut read = 0;
+
+        // the old stream no longer generates events
+        tx1.write_all(&[1, 2, 3]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut read)
+            .unwrap();
+        assert_eq!(read, 0);
+
+        // but the new one does, through the same callback
+        tx2.write_all(&[1, 2, 3, 4]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut read)
+            .unwrap();
+        assert_eq!(read, 4);
+
+        // removing the source unregisters the new stream
+        event_loop.handle().remove(generic_token);
+        let _ = dispatcher.into_source_inner();
+    }
+
+    #[test]
+    fn swap_file_unix() {
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (mut tx1, rx1) = UnixStream::pair().unwrap();
+        let (mut tx2, rx2) = UnixStream::pair().unwrap();
+
+        let generic = Generic::new(rx1, Interest::READ, Mode::Level);
+        let dispatcher =
+            Dispatcher::new(generic, move |_, file: &mut UnixStream, d: &mut Vec<u8>| {
+                let mut buffer = vec![0; 10];
+                let ret = file.read(&mut buffer).unwrap();
+                d.extend_from_slice(&buffer[..ret]);
+                Ok(PostAction::Continue)
+            });
+        handle.register_dispatcher(dispatcher.clone()).unwrap();
+
+        let mut read = Vec::new();
+        tx1.write_all(&[1]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut read)
+            .unwrap();
+        assert_eq!(read, &[1]);
+
+        // the data pending on the old stream are drained from it once given back
+        tx1.write_all(&[2, 3]).unwrap();
+        tx2.write_all(&[4]).unwrap();
+        let mut old = handle.swap_file(&dispatcher, rx2).unwrap();
+        let mut buffer = [0; 10];
+        assert_eq!(old.read(&mut buffer).unwrap(), 2);
+
+        // the new stream is polled right away, the old one no longer
+        tx1.write_all(&[5]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut read)
+            .unwrap();
+        assert_eq!(read, &[1, 4]);
+        tx2.write_all(&[6]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut read)
+            .unwrap();
+        assert_eq!(read, &[1, 4, 6]);
+    }
+
+    #[test]
+    fn set_interest_from_callback() {
+        use std::os::unix::net::UnixStream;
+
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+
+        let generic = Generic::new(rx, Interest::READ, Mode::Level);
+
+        let _generic_token = handle
+            .insert_source(generic, move |readiness, file, d: &mut Vec<bool>| {
+                if readiness.readable {
+                    let mut buffer = vec![0; 10];
+                    let ret = file.read(&mut buffer).unwrap();
+                    assert_eq!(ret, 3);
+                    d.push(true);
+                    // we now want to write
+                    Ok(PostAction::SetInterest(Interest::WRITE))
+                } else {
+                    assert!(readiness.writable);
+                    d.push(false);
+                    Ok(PostAction::Disable)
+                }
+            })
+            .map_err(Into::<io::Error>::into)
+            .unwrap();
+
+        let mut events = Vec::new();
+
+        tx.write_all(&[1, 2, 3]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut events)
+            .unwrap();
+        assert_eq!(events, &[true]);
+
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut events)
+            .unwrap();
+        assert_eq!(events, &[true, false]);
+
+        // the source is now disabled
+        tx.write_all(&[1, 2, 3]).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut events)
+            .unwrap();
+        assert_eq!(events, &[true, false]);
+    }
 }
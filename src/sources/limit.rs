@@ -0,0 +1,309 @@
+//! Adapters limiting the events a source delivers by their number
+//!
+//! The [`SkipFirst`] adapter, created with [`skip_first()`](skip_first), swallows the first
+//! `n` events of its wrapped source, and then delivers all the following ones. This can be
+//! used for warm-up logic, like ignoring the first spurious notifications of a source.
+//!
+//! The [`Take`] adapter, created with [`take()`](take), delivers only the first `n` events of
+//! its wrapped source, and then removes itself from the event loop. With a
+//! [`Signals`](crate::signals::Signals) source, `take(signals, 1)` handles exactly one signal
+//! and stops. The removal takes place at the end of the dispatching pass of the last event.
+//!
+//! The swallowed events are still read by the wrapped source, which drains its file
+//! descriptors as usual, so that the edge-triggered sources keep being notified. The events
+//! a [`Take`] source reads in the same pass after its last delivered one are dropped.
+//!
+//! As the swallowed events do not invoke the callback, the wrapped source must not expect any
+//! return value from its callback.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::limit::take;
+//! use calloop::signals::{Signal, Signals};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let signals = Signals::new(&[Signal::SIGUSR1]).expect("Failed to create the signalfd");
+//!
+//! handle
+//!     .insert_source(take(signals, 1), |event, &mut (), _| {
+//!         println!("Received {:?}, stopping", event.signal());
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event source swallowing the first events of its wrapped source
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct SkipFirst<S> {
+    source: S,
+    left: u64,
+}
+
+/// Swallow the first `n` events of a source
+pub fn skip_first<S: EventSource<Ret = ()>>(source: S, n: u64) -> SkipFirst<S> {
+    SkipFirst { source, left: n }
+}
+
+impl<S> SkipFirst<S> {
+    /// The number of events still to be swallowed
+    pub fn left(&self) -> u64 {
+        self.left
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> S {
+        self.source
+    }
+}
+
+// Count an event against the limit, returning false once it is reached
+fn consume(left: &mut u64) -> bool {
+    if *left == 0 {
+        return false;
+    }
+    *left -= 1;
+    true
+}
+
+impl<S: EventSource<Ret = ()>> EventSource for SkipFirst<S> {
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let left = &mut self.left;
+        self.source.process_events(readiness, token, |event, meta| {
+            if !consume(left) {
+                callback(event, meta)
+            }
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let left = &mut self.left;
+        self.source.on_dispatch_start(|event, meta| {
+            if !consume(left) {
+                callback(event, meta)
+            }
+        })
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end()
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        self.source.next_deadline()
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+/// An event source delivering only the first events of its wrapped source
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct Take<S> {
+    source: S,
+    left: u64,
+}
+
+/// Deliver only the first `n` events of a source, and then remove it from the event loop
+pub fn take<S: EventSource<Ret = ()>>(source: S, n: u64) -> Take<S> {
+    Take { source, left: n }
+}
+
+impl<S> Take<S> {
+    /// The number of events still to be delivered
+    pub fn left(&self) -> u64 {
+        self.left
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> S {
+        self.source
+    }
+}
+
+impl<S: EventSource<Ret = ()>> EventSource for Take<S> {
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let left = &mut self.left;
+        let action = self
+            .source
+            .process_events(readiness, token, |event, meta| {
+                if consume(left) {
+                    callback(event, meta)
+                }
+            })?;
+        if self.left == 0 {
+            return Ok(PostAction::Remove);
+        }
+        Ok(action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let left = &mut self.left;
+        self.source.on_dispatch_start(|event, meta| {
+            if consume(left) {
+                callback(event, meta)
+            }
+        })
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end()
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        // the last events may have been delivered outside of `process_events`
+        if self.left == 0 {
+            return Ok(PostAction::Remove);
+        }
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        self.source.next_deadline()
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::channel::{channel, Event};
+
+    #[test]
+    fn skip_and_take() {
+        let mut event_loop = crate::EventLoop::<Vec<u32>>::try_new().unwrap();
+        let (skipped, source) = channel::<u32>();
+        event_loop
+            .handle()
+            .insert_source(skip_first(source, 2), |event, &mut (), received| {
+                if let Event::Msg(msg) = event {
+                    received.push(msg);
+                }
+            })
+            .unwrap();
+        let (taken, source) = channel::<u32>();
+        event_loop
+            .handle()
+            .insert_source(take(source, 2), |event, &mut (), received| {
+                if let Event::Msg(msg) = event {
+                    received.push(msg);
+                }
+            })
+            .unwrap();
+
+        let mut received = Vec::new();
+        for msg in 1..4 {
+            skipped.send(msg).unwrap();
+            taken.send(10 + msg).unwrap();
+        }
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut received)
+            .unwrap();
+        received.sort_unstable();
+        assert_eq!(received, &[3, 11, 12]);
+        // the taken source removed itself, dropping its channel
+        assert!(taken.send(20).is_err());
+    }
+}
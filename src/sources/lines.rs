@@ -0,0 +1,12 @@
+//! Splitting of the data read from a pipe or a terminal into lines, for the sources
+//! delivering their input line by line
+
+// Deliver the complete lines of the buffer without their terminator, keeping the incomplete
+// one in the buffer
+pub(crate) fn split_lines<F: FnMut(Vec<u8>)>(buffer: &mut Vec<u8>, mut callback: F) {
+    while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+        let rest = buffer.split_off(pos + 1);
+        buffer.pop();
+        callback(std::mem::replace(buffer, rest));
+    }
+}
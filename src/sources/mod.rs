@@ -2,27 +2,84 @@ use std::{
     cell::{Ref, RefCell, RefMut},
     io,
     rc::Rc,
+    time::Instant,
 };
 
 use crate::{sys::TokenFactory, Poll, Readiness, Token};
 
+pub mod broadcast;
+#[cfg(target_os = "linux")]
+pub mod cgroup;
 pub mod channel;
+pub mod child;
+#[cfg(target_os = "linux")]
+pub mod clock;
+pub(crate) mod deadline;
+pub mod debounce;
+pub mod dedup;
+pub(crate) mod fd_guard;
+pub mod fd_receiver;
+pub mod fd_set;
+pub mod flag;
+pub mod flush_scheduler;
 #[cfg(feature = "executor")]
 pub mod futures;
 pub mod generic;
+pub mod limit;
+pub(crate) mod lines;
+#[cfg(target_os = "linux")]
+pub mod mqueue;
+#[cfg(target_os = "linux")]
+pub mod netlink;
 pub mod ping;
+pub mod poll_fn;
+#[cfg(target_os = "linux")]
+pub mod psi;
+pub mod recent;
+pub mod retry;
+#[cfg(target_os = "linux")]
+pub mod semaphore;
+pub mod shared_fd;
+pub(crate) mod signal_mask;
 #[cfg(target_os = "linux")]
 pub mod signals;
+pub mod split;
+pub mod stdin;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod threaded_signals;
+pub mod tick;
+pub mod timeout;
 pub mod timer;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub mod uring;
+pub mod watch;
+pub mod wayland;
+pub mod writer;
 
 /// Possible actions that can be requested to the event loop by an
 /// event source once its events have been processed
+///
+/// The actions requested by the sources are not applied right away, but once all the
+/// events of the current dispatching pass have been processed. This avoids changing
+/// registrations while the loop is still iterating over the events it received. A
+/// source that requested to be disabled or removed will not receive the remaining events
+/// of the pass.
+///
+/// If a source requests several actions during the same pass, the most drastic one is
+/// applied (`Remove`, then `Disable`, then re-registration).
 #[derive(Debug)]
 pub enum PostAction {
     /// Continue listening for events on this source as before
     Continue,
     /// Trigger a re-registration of this source
     Reregister,
+    /// Change the interest of this source and re-register it
+    ///
+    /// This is handled by sources that have a notion of interest, like the
+    /// [`Generic`](generic::Generic) source, which updates its interest before
+    /// requesting a re-registration. Other sources treat it like `Reregister`.
+    SetInterest(crate::Interest),
     /// Disable this source
     ///
     /// Has the same effect as [`LoopHandle::disable`](crate::LoopHandle#method.disable)
@@ -33,6 +90,24 @@ pub enum PostAction {
     Remove,
 }
 
+/// Statistics of the buffer of a source, for monitoring
+///
+/// They are given by the sources buffering their events between a producer and the
+/// callback, like the [channels](channel) or the [buffered delivery](signals) of the
+/// signals, so that the health of producers and consumers can be graphed the same way
+/// for all of them. The counters start at the creation of the source.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BufferStats {
+    /// The number of events given to the buffer by the producers
+    pub sent: u64,
+    /// The number of events delivered to the callback
+    pub delivered: u64,
+    /// The number of events dropped because the buffer was full
+    pub dropped: u64,
+    /// The number of events currently in the buffer
+    pub occupancy: u64,
+}
+
 /// Trait representing an event source
 ///
 /// This is the trait you need to implement if you wish to create your own
@@ -113,6 +188,49 @@ pub trait EventSource {
         {()}
 
     fn on_dispatch_end(&mut self) -> () {()}
+
+    /// Flush any pending output
+    ///
+    /// This method is called by the event loop at the end of each dispatching pass, once
+    /// all events have been processed and before the idle callbacks are run. Sources that
+    /// buffer outgoing data can use it to try writing it, rather than toggling their
+    /// writable interest from within their read callbacks.
+    ///
+    /// The returned [`PostAction`] is applied by the event loop, so a source can for example
+    /// request a re-registration after changing its interest. The default implementation does
+    /// nothing.
+    fn flush(&mut self) -> io::Result<PostAction> {
+        Ok(PostAction::Continue)
+    }
+
+    /// The earliest deadline at which this source will generate events
+    ///
+    /// Sources driven by time, like the [`Timer`](crate::timer::Timer), return the deadline
+    /// of their next timeout, so that a program embedding the event loop in another one can
+    /// bound its waits, see [`EventLoop::next_deadline`](crate::EventLoop#method.next_deadline).
+    /// The default implementation returns `None`, for sources which are not driven by time.
+    fn next_deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Tear down this source as the event loop shuts down
+    ///
+    /// This method is called once the [`EventLoop::run`](crate::EventLoop#method.run) method
+    /// returns after the loop was stopped, or when the [`EventLoop`](crate::EventLoop) is
+    /// dropped, for each source still inserted in the loop, in the reverse order of their
+    /// insertion. Sources depending on one another can thus rely on the sources inserted
+    /// after them being torn down first. The default implementation does nothing.
+    fn on_shutdown(&mut self) {}
+
+    /// The number of bytes this source currently holds in its buffers
+    ///
+    /// Sources buffering data, like the [`BufferedWriter`](crate::writer::BufferedWriter),
+    /// report the size of their buffers, so that the event loop can enforce its
+    /// [buffer budget](crate::EventLoop#method.set_buffer_budget). The default
+    /// implementation returns `0`, for sources which do not buffer data.
+    fn buffered_bytes(&self) -> usize {
+        0
+    }
 }
 
 pub(crate) struct DispatcherInner<S, F> {
@@ -174,6 +292,32 @@ where
         let source = &mut self.borrow_mut().source;
         source.on_dispatch_end();
     }
+
+    fn flush(&self) -> io::Result<PostAction> {
+        if let Ok(mut me) = self.try_borrow_mut() {
+            me.source.flush()
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.try_borrow()
+            .ok()
+            .and_then(|me| me.source.next_deadline())
+    }
+
+    fn on_shutdown(&self) {
+        if let Ok(mut me) = self.try_borrow_mut() {
+            me.source.on_shutdown();
+        }
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.try_borrow()
+            .map(|me| me.source.buffered_bytes())
+            .unwrap_or(0)
+    }
 }
 
 pub(crate) trait EventDispatcher<Data> {
@@ -193,6 +337,20 @@ pub(crate) trait EventDispatcher<Data> {
     fn on_dispatch_start(&self, data: &mut Data) -> () {()}
 
     fn on_dispatch_end(&self) -> () {()}
+
+    fn flush(&self) -> io::Result<PostAction> {
+        Ok(PostAction::Continue)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    fn on_shutdown(&self) {}
+
+    fn buffered_bytes(&self) -> usize {
+        0
+    }
 }
 
 // An internal trait to erase the `F` type parameter of `DispatcherInner`
@@ -237,6 +395,13 @@ where
 /// The `Dispatcher` can be registered in an event loop.
 /// Use the `as_source_{ref,mut}` functions to interact with the event source.
 /// Use `into_source_inner` to get the event source back.
+///
+/// The callback of a `Dispatcher` cannot be replaced. To change how several sources handle
+/// their events at once, for example when a protocol moves to a new phase, keep the phase in
+/// the shared data of the loop and have the callbacks dispatch on it: the callbacks of a loop
+/// all run on its thread, one at a time, so a change of phase made from a callback or between
+/// two dispatches is seen by every callback running after it, and never by one running
+/// before it.
 pub struct Dispatcher<'a, S, Data>(Rc<dyn ErasedDispatcher<'a, S, Data> + 'a>);
 
 impl<'a, S, Data> std::fmt::Debug for Dispatcher<'a, S, Data> {
@@ -319,8 +484,13 @@ impl<'i> std::fmt::Debug for Idle<'i> {
 
 impl<'i> Idle<'i> {
     /// Cancel the idle callback if it was not already run
+    ///
+    /// This does nothing if the idle callback already ran, or is currently running.
     pub fn cancel(self) {
-        self.callback.borrow_mut().cancel();
+        // the callback is borrowed while it runs, and cannot run again afterwards
+        if let Ok(mut callback) = self.callback.try_borrow_mut() {
+            callback.cancel();
+        }
     }
 }
 
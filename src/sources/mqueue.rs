@@ -0,0 +1,217 @@
+//! Event source for POSIX message queues
+//!
+//! Only available on Linux.
+//!
+//! On Linux, a POSIX message queue descriptor is a file descriptor that becomes readable when
+//! messages are available in the queue. The [`PosixMq`] source wraps such a descriptor and
+//! generates one event per received message, containing its bytes and its priority.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! # extern crate nix;
+//! use calloop::mqueue::{PosixMq, MQ_OFlag};
+//! use nix::sys::stat::Mode;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let queue = PosixMq::open("/my-queue", MQ_OFlag::O_RDONLY, Mode::empty(), None)
+//!     .expect("Failed to open the message queue");
+//!
+//! handle.insert_source(queue, |msg, &mut (), _| {
+//!     println!("Received {:?} with priority {}", msg.data, msg.priority);
+//! });
+//! # }
+//! ```
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+pub use nix::mqueue::{MQ_OFlag, MqAttr};
+use nix::{errno::Errno, libc, mqueue, sys::stat::Mode as FileMode};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// A message received from the queue
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    /// The contents of the message
+    pub data: Vec<u8>,
+    /// The priority of the message
+    pub priority: u32,
+}
+
+/// An event source for receiving messages from a POSIX message queue
+#[derive(Debug)]
+pub struct PosixMq {
+    mq: Generic<RawFd>,
+    buffer: Vec<u8>,
+}
+
+impl PosixMq {
+    /// Open a message queue
+    ///
+    /// The arguments are the same as for `mq_open(3)`: the name of the queue, the open flags,
+    /// the permissions used if the queue is created and its attributes. The queue must be
+    /// opened for reading. It is always opened in non-blocking and close-on-exec mode, so you
+    /// don't need to add these flags yourself.
+    pub fn open(
+        name: &str,
+        flags: MQ_OFlag,
+        mode: FileMode,
+        attr: Option<&MqAttr>,
+    ) -> io::Result<PosixMq> {
+        let name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mqd = mqueue::mq_open(
+            &name,
+            flags | MQ_OFlag::O_NONBLOCK | MQ_OFlag::O_CLOEXEC,
+            mode,
+            attr,
+        )?;
+        // retrieve the maximum message size of the queue, mq_receive requires
+        // a buffer at least this large
+        let msgsize = unsafe {
+            let mut attr = std::mem::MaybeUninit::<libc::mq_attr>::uninit();
+            if let Err(e) = Errno::result(libc::mq_getattr(mqd, attr.as_mut_ptr())) {
+                let _ = mqueue::mq_close(mqd);
+                return Err(e.into());
+            }
+            attr.assume_init().mq_msgsize as usize
+        };
+        Ok(PosixMq {
+            mq: Generic::new(mqd, Interest::READ, Mode::Level),
+            buffer: vec![0; msgsize],
+        })
+    }
+}
+
+impl AsRawFd for PosixMq {
+    fn as_raw_fd(&self) -> RawFd {
+        self.mq.file
+    }
+}
+
+impl Drop for PosixMq {
+    fn drop(&mut self) {
+        if let Err(e) = mqueue::mq_close(self.mq.file) {
+            log::warn!("[calloop] Failed to close message queue: {:?}", e);
+        }
+    }
+}
+
+impl EventSource for PosixMq {
+    type Event = Message;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> std::io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let buffer = &mut self.buffer;
+        self.mq.process_events(readiness, token, |_, &mut mqd| {
+            loop {
+                let mut priority = 0;
+                match mqueue::mq_receive(mqd, buffer, &mut priority) {
+                    Ok(len) => callback(
+                        Message {
+                            data: buffer[..len].to_vec(),
+                            priority,
+                        },
+                        &mut (),
+                    ),
+                    Err(Errno::EAGAIN) => break,
+                    Err(e) => {
+                        log::warn!("[calloop] Error reading from message queue: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.mq.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.mq.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+        self.mq.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receive_messages() {
+        let name = format!("/calloop-test-{}", std::process::id());
+        let attr = MqAttr::new(0, 4, 16, 0);
+        let queue = PosixMq::open(
+            &name,
+            MQ_OFlag::O_CREAT | MQ_OFlag::O_RDWR,
+            FileMode::S_IRUSR | FileMode::S_IWUSR,
+            Some(&attr),
+        )
+        .unwrap();
+        mqueue::mq_unlink(&CString::new(name).unwrap()).unwrap();
+        let mqd = queue.as_raw_fd();
+
+        let mut event_loop = crate::EventLoop::<Vec<Message>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(queue, |msg, &mut (), received| received.push(msg))
+            .unwrap();
+
+        let mut received = Vec::new();
+
+        // nothing is sent, nothing is received
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut received)
+            .unwrap();
+        assert!(received.is_empty());
+
+        mqueue::mq_send(mqd, b"hello", 1).unwrap();
+        mqueue::mq_send(mqd, b"urgent", 5).unwrap();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(0)), &mut received)
+            .unwrap();
+
+        // messages are received in priority order
+        assert_eq!(
+            received,
+            &[
+                Message {
+                    data: b"urgent".to_vec(),
+                    priority: 5
+                },
+                Message {
+                    data: b"hello".to_vec(),
+                    priority: 1
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Event source for netlink sockets
+//!
+//! Only available on Linux.
+//!
+//! Netlink sockets are used to receive notifications from the kernel, such as changes to
+//! the network interfaces (`NETLINK_ROUTE`) or device events (`NETLINK_KOBJECT_UEVENT`).
+//! The [`NetlinkSource`] opens such a socket, subscribed to the multicast groups of your
+//! choice, and generates one event per received message.
+//!
+//! When messages arrive faster than they are processed, the kernel drops some of them. The
+//! source then generates an [`Event::Overrun`], after which you should resynchronize your
+//! state, for example by requesting a dump with [`NetlinkSource::send`]. The receive buffer
+//! of the source grows to fit the largest message received, so that no message is truncated.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::netlink::{Event, NetlinkSource, SockProtocol};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! // RTMGRP_LINK, notifications about the network interfaces
+//! let source = NetlinkSource::new(SockProtocol::NetlinkRoute, 1)
+//!     .expect("Failed to open the netlink socket");
+//!
+//! handle.insert_source(source, |event, &mut (), _| match event {
+//!     Event::Message { header, payload } => {
+//!         println!("Received message {} ({} bytes)", header.message_type, payload.len())
+//!     }
+//!     Event::Raw(data) => println!("Received {} bytes", data.len()),
+//!     Event::Overrun => println!("Messages were lost, resync needed"),
+//! });
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+pub use nix::sys::socket::SockProtocol;
+use nix::{
+    errno::Errno,
+    sys::socket::{self, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType},
+    unistd::close,
+};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+// size of struct nlmsghdr
+const HEADER_LEN: usize = 16;
+
+/// The header of a netlink message
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MessageHeader {
+    /// The type of the message, its meaning depends on the protocol
+    pub message_type: u16,
+    /// The flags of the message
+    pub flags: u16,
+    /// The sequence number of the message
+    pub seq: u32,
+    /// The port id of the sender, `0` for the kernel
+    pub pid: u32,
+}
+
+/// An event generated by a [`NetlinkSource`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A netlink message
+    Message {
+        /// The header of the message
+        header: MessageHeader,
+        /// The payload of the message, following its header
+        payload: Vec<u8>,
+    },
+    /// A datagram of a protocol which does not frame its messages with netlink headers
+    ///
+    /// This is notably the case of `NETLINK_KOBJECT_UEVENT`.
+    Raw(Vec<u8>),
+    /// The socket receive buffer overflowed, and some messages were lost
+    Overrun,
+}
+
+/// An event source for receiving messages from a netlink socket
+#[derive(Debug)]
+pub struct NetlinkSource {
+    socket: Generic<RawFd>,
+    protocol: SockProtocol,
+    buffer: Vec<u8>,
+}
+
+impl NetlinkSource {
+    /// Open a netlink socket for the given protocol, subscribed to the given multicast groups
+    ///
+    /// `groups` is the bitmask of groups to subscribe to, as in the `nl_groups` field of
+    /// `struct sockaddr_nl`. It can be `0` if you only want to receive answers to your
+    /// requests.
+    pub fn new(protocol: SockProtocol, groups: u32) -> io::Result<NetlinkSource> {
+        let fd = socket::socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            protocol,
+        )?;
+        if let Err(e) = socket::bind(fd, &SockAddr::new_netlink(0, groups)) {
+            let _ = close(fd);
+            return Err(e.into());
+        }
+        Ok(NetlinkSource {
+            socket: Generic::new(fd, Interest::READ, Mode::Level),
+            protocol,
+            buffer: vec![0; 32 * 1024],
+        })
+    }
+
+    /// Send a message to the kernel
+    ///
+    /// The message must include its netlink header.
+    pub fn send(&self, message: &[u8]) -> io::Result<()> {
+        socket::sendto(
+            self.socket.file,
+            message,
+            &SockAddr::new_netlink(0, 0),
+            MsgFlags::empty(),
+        )?;
+        Ok(())
+    }
+
+    fn is_framed(&self) -> bool {
+        self.protocol != SockProtocol::NetlinkKObjectUEvent
+    }
+}
+
+impl AsRawFd for NetlinkSource {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.file
+    }
+}
+
+impl Drop for NetlinkSource {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.socket.file) {
+            log::warn!("[calloop] Failed to close netlink socket: {:?}", e);
+        }
+    }
+}
+
+// Split a datagram into the netlink messages it contains
+fn parse_messages<F: FnMut(Event)>(mut data: &[u8], mut callback: F) {
+    let read_u32 = |b: &[u8]| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]);
+    let read_u16 = |b: &[u8]| u16::from_ne_bytes([b[0], b[1]]);
+    while data.len() >= HEADER_LEN {
+        let len = read_u32(&data[0..4]) as usize;
+        if len < HEADER_LEN || len > data.len() {
+            log::warn!("[calloop] Received a truncated netlink message");
+            return;
+        }
+        let header = MessageHeader {
+            message_type: read_u16(&data[4..6]),
+            flags: read_u16(&data[6..8]),
+            seq: read_u32(&data[8..12]),
+            pid: read_u32(&data[12..16]),
+        };
+        callback(Event::Message {
+            header,
+            payload: data[HEADER_LEN..len].to_vec(),
+        });
+        // messages are aligned on 4 bytes
+        let aligned = (len + 3) & !3;
+        data = &data[aligned.min(data.len())..];
+    }
+}
+
+// Receive the next datagram, growing the buffer beforehand if it does not fit
+fn recv_datagram(fd: RawFd, buffer: &mut Vec<u8>) -> nix::Result<usize> {
+    // with MSG_TRUNC, the full length of the datagram is returned even if it does not fit
+    let len = socket::recv(fd, &mut [], MsgFlags::MSG_PEEK | MsgFlags::MSG_TRUNC)?;
+    if len > buffer.len() {
+        buffer.resize(len, 0);
+    }
+    socket::recv(fd, buffer, MsgFlags::empty())
+}
+
+impl EventSource for NetlinkSource {
+    type Event = Event;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> std::io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let framed = self.is_framed();
+        let buffer = &mut self.buffer;
+        self.socket.process_events(readiness, token, |_, &mut fd| {
+            loop {
+                match recv_datagram(fd, buffer) {
+                    Ok(len) if framed => {
+                        parse_messages(&buffer[..len], |event| callback(event, &mut ()))
+                    }
+                    Ok(len) => callback(Event::Raw(buffer[..len].to_vec()), &mut ()),
+                    Err(Errno::ENOBUFS) => callback(Event::Overrun, &mut ()),
+                    Err(Errno::EAGAIN) => break,
+                    Err(Errno::EINTR) => {}
+                    Err(e) => {
+                        log::warn!("[calloop] Error reading from netlink socket: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.socket.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.socket.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+        self.socket.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_multiple_messages() {
+        let mut data = Vec::new();
+        for (message_type, payload) in &[(16u16, &b"abc"[..]), (3, &b"done"[..])] {
+            data.extend_from_slice(&((HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+            data.extend_from_slice(&message_type.to_ne_bytes());
+            data.extend_from_slice(&2u16.to_ne_bytes());
+            data.extend_from_slice(&7u32.to_ne_bytes());
+            data.extend_from_slice(&0u32.to_ne_bytes());
+            data.extend_from_slice(payload);
+            // padding
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+        }
+
+        let mut events = Vec::new();
+        parse_messages(&data, |event| events.push(event));
+        let header = |message_type| MessageHeader {
+            message_type,
+            flags: 2,
+            seq: 7,
+            pid: 0,
+        };
+        assert_eq!(
+            events,
+            &[
+                Event::Message {
+                    header: header(16),
+                    payload: b"abc".to_vec()
+                },
+                Event::Message {
+                    header: header(3),
+                    payload: b"done".to_vec()
+                },
+            ]
+        );
+    }
+
+    // Open a route socket, netlink may be forbidden in some sandboxes
+    fn open_route() -> Option<NetlinkSource> {
+        match NetlinkSource::new(SockProtocol::NetlinkRoute, 0) {
+            Ok(source) => Some(source),
+            Err(e)
+                if e.raw_os_error() == Some(nix::libc::EPERM)
+                    || e.raw_os_error() == Some(nix::libc::EACCES)
+                    || e.raw_os_error() == Some(nix::libc::EPROTONOSUPPORT) =>
+            {
+                None
+            }
+            Err(e) => panic!("Failed to open a netlink socket: {}", e),
+        }
+    }
+
+    #[test]
+    fn route_dump() {
+        let source = match open_route() {
+            Some(source) => source,
+            None => return,
+        };
+
+        // RTM_GETLINK dump request: nlmsghdr followed by an empty struct ifinfomsg
+        let mut request = Vec::new();
+        request.extend_from_slice(&32u32.to_ne_bytes());
+        request.extend_from_slice(&18u16.to_ne_bytes());
+        // NLM_F_REQUEST | NLM_F_DUMP
+        request.extend_from_slice(&0x301u16.to_ne_bytes());
+        request.extend_from_slice(&1u32.to_ne_bytes());
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        request.extend_from_slice(&[0u8; 16]);
+        source.send(&request).unwrap();
+
+        let mut event_loop = crate::EventLoop::<Vec<Event>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), events| events.push(event))
+            .unwrap();
+
+        let mut events = Vec::new();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(100)), &mut events)
+            .unwrap();
+
+        // the dump is answered with RTM_NEWLINK messages and terminated by NLMSG_DONE
+        assert!(events.iter().any(|event| match event {
+            Event::Message { header, .. } => header.message_type == 16 && header.seq == 1,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn truncated_message() {
+        let mut source = match open_route() {
+            Some(source) => source,
+            None => return,
+        };
+        // too small for the answer to the request
+        source.buffer = vec![0; HEADER_LEN];
+
+        // RTM_GETLINK dump request
+        let mut request = Vec::new();
+        request.extend_from_slice(&32u32.to_ne_bytes());
+        request.extend_from_slice(&18u16.to_ne_bytes());
+        request.extend_from_slice(&0x301u16.to_ne_bytes());
+        request.extend_from_slice(&1u32.to_ne_bytes());
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        request.extend_from_slice(&[0u8; 16]);
+        source.send(&request).unwrap();
+
+        let mut event_loop = crate::EventLoop::<Vec<Event>>::try_new().unwrap();
+        let dispatcher = crate::Dispatcher::new(source, |event, &mut (), events: &mut Vec<_>| {
+            events.push(event)
+        });
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        let mut events = Vec::new();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_millis(100)), &mut events)
+            .unwrap();
+        // the buffer was grown before receiving the answer, which is not truncated
+        assert!(dispatcher.as_source_ref().buffer.len() > HEADER_LEN);
+        assert!(events.iter().any(|event| match event {
+            Event::Message { header, payload } => header.message_type == 16 && !payload.is_empty(),
+            _ => false,
+        }));
+    }
+}
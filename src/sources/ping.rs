@@ -8,14 +8,21 @@
 //! (and is what backs the [`LoopSignal`](crate::LoopSignal)). It can also be used as a building
 //! block to construct event sources whose source of event is not file descriptor, but rather an
 //! userspace source (like an other thread).
+//!
+//! The [`CountingPingSource`], created with [`make_counting_ping`], instead delivers the number
+//! of pings coalesced into each event.
 
-use std::{os::unix::io::RawFd, sync::Arc};
+use std::{
+    os::unix::io::{AsRawFd, RawFd},
+    sync::Arc,
+};
 
 use nix::{
     fcntl::OFlag,
     unistd::{close, pipe2, read, write},
 };
 
+use super::fd_guard::FdGuard;
 use super::generic::Generic;
 use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
 
@@ -30,7 +37,7 @@ pub fn make_ping() -> std::io::Result<(Ping, PingSource)> {
         pipe: Generic::new(read, Interest::READ, Mode::Level),
     };
     let ping = Ping {
-        pipe: Arc::new(CloseOnDrop(write)),
+        pipe: Arc::new(FdGuard::new(write, "write ping")),
     };
     Ok((ping, source))
 }
@@ -61,30 +68,8 @@ impl EventSource for PingSource {
         C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
     {
         self.pipe.process_events(readiness, token, |_, &mut fd| {
-            let mut buf = [0u8; 32];
-            let mut read_something = false;
-            let mut action = PostAction::Continue;
-            loop {
-                match read(fd, &mut buf) {
-                    Ok(0) => {
-                        // The other end of the pipe was closed, mark ourselved to for removal
-                        action = PostAction::Remove;
-                        break;
-                    }
-                    Ok(_) => read_something = true,
-                    Err(e) => {
-                        let e: std::io::Error = e.into();
-                        if e.kind() == std::io::ErrorKind::WouldBlock {
-                            break;
-                        // nothing more to read
-                        } else {
-                            // propagate error
-                            return Err(e);
-                        }
-                    }
-                }
-            }
-            if read_something {
+            let (count, action) = drain(fd)?;
+            if count > 0 {
                 callback((), &mut ());
             }
             Ok(action)
@@ -112,6 +97,49 @@ impl EventSource for PingSource {
     }
 }
 
+impl PingSource {
+    // The read end of the pipe, readable while some pings are pending
+    pub(crate) fn raw_fd(&self) -> RawFd {
+        self.pipe.file
+    }
+}
+
+/// Create a new ping event source delivering the number of pings it received
+///
+/// Like [`make_ping`], you are given a [`CountingPing`] to send the pings, and a
+/// [`CountingPingSource`] to insert in your event loop.
+pub fn make_counting_ping() -> std::io::Result<(CountingPing, CountingPingSource)> {
+    let (ping, source) = make_ping()?;
+    Ok((CountingPing { ping }, CountingPingSource { source }))
+}
+
+// Read all the pending pings, returning their number
+fn drain(fd: RawFd) -> std::io::Result<(u64, PostAction)> {
+    let mut buf = [0u8; 32];
+    let mut count = 0;
+    loop {
+        match read(fd, &mut buf) {
+            Ok(0) => {
+                // The other end of the pipe was closed, mark ourselved to for removal
+                return Ok((count, PostAction::Remove));
+            }
+            Ok(n) => count += n as u64,
+            // a signal interrupted the read, the pipe may not be drained yet
+            Err(nix::errno::Errno::EINTR) => {}
+            Err(e) => {
+                let e: std::io::Error = e.into();
+                if e.kind() == std::io::ErrorKind::WouldBlock {
+                    // nothing more to read
+                    return Ok((count, PostAction::Continue));
+                } else {
+                    // propagate error
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
 impl Drop for PingSource {
     fn drop(&mut self) {
         if let Err(e) = close(self.pipe.file) {
@@ -120,31 +148,109 @@ impl Drop for PingSource {
     }
 }
 
+/// A ping event source delivering the number of pings it received
+///
+/// Each event is the number of [`Ping::ping`](Ping#method.ping) calls coalesced since the
+/// previous one. It allows a ping to stand for "N items were queued elsewhere", so that
+/// exactly N items are processed.
+///
+/// Each ping writes a byte to a pipe, and the count is the number of bytes read from it.
+/// If the pipe fills up because the source is not dispatched, which takes tens of thousands
+/// of pings, the following pings are not counted, and [`CountingPing::ping`] returns a
+/// `WouldBlock` error for them.
+///
+/// Like the [`PingSource`], it removes itself from the event loop once all [`CountingPing`]
+/// instances are dropped.
+#[derive(Debug)]
+pub struct CountingPingSource {
+    source: PingSource,
+}
+
+impl EventSource for CountingPingSource {
+    type Event = u64;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> std::io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.source
+            .pipe
+            .process_events(readiness, token, |_, &mut fd| {
+                let (count, action) = drain(fd)?;
+                if count > 0 {
+                    callback(count, &mut ());
+                }
+                Ok(action)
+            })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
 /// The Ping handle
 ///
 /// This handle can be cloned and sent accross threads. It can be used to
 /// send pings to the `PingSource`.
 #[derive(Clone, Debug)]
 pub struct Ping {
-    pipe: Arc<CloseOnDrop>,
+    pipe: Arc<FdGuard>,
 }
 
 impl Ping {
     /// Send a ping to the `PingSource`
     pub fn ping(&self) {
-        if let Err(e) = write(self.pipe.0, &[0u8]) {
+        if let Err(e) = write(self.pipe.as_raw_fd(), &[0u8]) {
             log::warn!("[calloop] Failed to write a ping: {:?}", e);
         }
     }
 }
 
-#[derive(Debug)]
-struct CloseOnDrop(RawFd);
+/// The handle of a [`CountingPingSource`]
+///
+/// Like the [`Ping`], it can be cloned and sent accross threads.
+#[derive(Clone, Debug)]
+pub struct CountingPing {
+    ping: Ping,
+}
 
-impl Drop for CloseOnDrop {
-    fn drop(&mut self) {
-        if let Err(e) = close(self.0) {
-            log::warn!("[calloop] Failed to close write ping: {:?}", e);
+impl CountingPing {
+    /// Send a ping to the `CountingPingSource`
+    ///
+    /// Fails if the ping could not be written, in which case it is not counted. In particular,
+    /// this is a `WouldBlock` error if the source received too many pings since it was last
+    /// dispatched.
+    pub fn ping(&self) -> std::io::Result<()> {
+        loop {
+            match write(self.ping.pipe.as_raw_fd(), &[0u8]) {
+                Ok(_) => return Ok(()),
+                Err(nix::errno::Errno::EINTR) => {}
+                Err(e) => return Err(e.into()),
+            }
         }
     }
 }
@@ -180,6 +286,58 @@ mod tests {
         assert!(!dispatched);
     }
 
+    #[test]
+    fn counted_pings() {
+        let mut event_loop = crate::EventLoop::<Vec<u64>>::try_new().unwrap();
+
+        let (ping, source) = make_counting_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |count, &mut (), counts| counts.push(count))
+            .unwrap();
+
+        for _ in 0..3 {
+            ping.ping().unwrap();
+        }
+        let mut counts = Vec::new();
+        event_loop
+            .dispatch(std::time::Duration::from_millis(0), &mut counts)
+            .unwrap();
+        ping.ping().unwrap();
+        event_loop
+            .dispatch(std::time::Duration::from_millis(0), &mut counts)
+            .unwrap();
+        assert_eq!(counts, &[3, 1]);
+    }
+
+    #[test]
+    fn lost_pings() {
+        let mut event_loop = crate::EventLoop::<u64>::try_new().unwrap();
+
+        let (ping, source) = make_counting_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |count, &mut (), total| *total += count)
+            .unwrap();
+
+        // fill the pipe, the pings which do not fit are reported
+        let mut sent = 0;
+        let error = loop {
+            match ping.ping() {
+                Ok(()) => sent += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+
+        let mut total = 0;
+        event_loop
+            .dispatch(std::time::Duration::from_millis(0), &mut total)
+            .unwrap();
+        assert_eq!(total, sent);
+        ping.ping().unwrap();
+    }
+
     #[test]
     fn ping_closed() {
         let mut event_loop = crate::EventLoop::<bool>::try_new().unwrap();
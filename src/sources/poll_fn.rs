@@ -0,0 +1,133 @@
+//! A source driven by a readiness predicate rather than a file descriptor
+//!
+//! Some sources of events have no file descriptor to wait on: their readiness is decided by
+//! the application, for example by checking whether a lock-free queue is empty. The
+//! [`PollFn`] source wraps such a predicate, which is checked at the start of each
+//! dispatching pass of the event loop. If it returns `true`, the callback of the source is
+//! invoked.
+//!
+//! Such a source can be inserted using
+//! [`LoopHandle::insert_poll_fn`](crate::LoopHandle#method.insert_poll_fn).
+//!
+//! ## Performance
+//!
+//! As the event loop cannot be woken up when the predicate becomes true, it stops waiting
+//! for events as long as a `PollFn` source is registered: each call to
+//! [`EventLoop::dispatch`](crate::EventLoop#method.dispatch) returns immediately, and
+//! [`EventLoop::run`](crate::EventLoop#method.run) spins, using a full CPU core. You should
+//! thus only keep such sources registered while they are expected to become ready soon, and
+//! disable them otherwise. If the producer of the events can wake the loop up, prefer a
+//! [`Ping`](crate::ping) source.
+
+use std::io;
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event source checking a readiness predicate at each dispatching pass
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct PollFn<P> {
+    predicate: P,
+    registered: bool,
+}
+
+impl<P: FnMut() -> bool> PollFn<P> {
+    /// Create a new source from a readiness predicate
+    pub fn new(predicate: P) -> PollFn<P> {
+        PollFn {
+            predicate,
+            registered: false,
+        }
+    }
+}
+
+impl<P: FnMut() -> bool> EventSource for PollFn<P> {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        _readiness: Readiness,
+        _token: Token,
+        _callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        // this source has no file descriptor and never receives events
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, _token_factory: &mut TokenFactory) -> io::Result<()> {
+        if !self.registered {
+            poll.add_busy_source();
+            self.registered = true;
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.register(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        if self.registered {
+            poll.remove_busy_source();
+            self.registered = false;
+        }
+        Ok(())
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        // a disabled source is not checked
+        if self.registered && (self.predicate)() {
+            callback((), &mut ());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use crate::EventLoop;
+
+    #[test]
+    fn predicate_checked_each_pass() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let ready = Rc::new(Cell::new(false));
+        let ready2 = ready.clone();
+
+        let token = event_loop
+            .handle()
+            .insert_poll_fn(move || ready2.get(), |count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        // the loop does not wait, even with a long timeout
+        event_loop
+            .dispatch(Duration::from_secs(10), &mut count)
+            .unwrap();
+        assert_eq!(count, 0);
+
+        ready.set(true);
+        event_loop
+            .dispatch(Duration::from_secs(10), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        // a disabled source is not checked anymore
+        event_loop.handle().disable(&token).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+}
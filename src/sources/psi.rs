@@ -0,0 +1,309 @@
+//! Event source for the pressure stall information of Linux
+//!
+//! Only available on Linux.
+//!
+//! The kernel measures how long the tasks are stalled waiting for a resource: the CPU, the
+//! memory or the IO. A [`PressureSource`] sets up a trigger on this pressure, and generates
+//! an event each time the tasks were stalled for more than a threshold over a time window.
+//! This can be used to shrink caches when the system runs low on memory, for example.
+//!
+//! The source uses the trigger files in `/proc/pressure`, and its creation fails with a
+//! `NotFound` error when the kernel does not support PSI, which requires Linux 5.2 and
+//! `CONFIG_PSI`. The kernel only accepts windows between 500ms and 10s. Setting a trigger
+//! requires the `CAP_SYS_RESOURCE` capability, except since Linux 6.5 for windows which are
+//! a multiple of 2s: the kernel rejects other triggers with an `InvalidInput` error.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::time::Duration;
+//!
+//! use calloop::psi::{PressureSource, Resource};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! // tasks stalled for 300ms over the last 2 seconds
+//! let source = PressureSource::new(
+//!     Resource::Memory,
+//!     Duration::from_millis(300),
+//!     Duration::from_secs(2),
+//! )
+//! .expect("Failed to set up the pressure trigger");
+//!
+//! handle
+//!     .insert_source(source, |(), &mut (), _| {
+//!         println!("The system is under memory pressure, shrinking the caches");
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use nix::{
+    errno::Errno,
+    sys::epoll::{self, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp},
+    unistd::close,
+};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// A resource whose pressure is monitored
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resource {
+    /// The CPU time
+    Cpu,
+    /// The memory
+    Memory,
+    /// The IO bandwidth
+    Io,
+}
+
+impl Resource {
+    fn path(self) -> &'static str {
+        match self {
+            Resource::Cpu => "/proc/pressure/cpu",
+            Resource::Memory => "/proc/pressure/memory",
+            Resource::Io => "/proc/pressure/io",
+        }
+    }
+}
+
+/// An event source generating an event when the pressure on a resource exceeds a threshold
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct PressureSource {
+    _trigger: File,
+    // the kernel signals the triggers with `POLLPRI`, which is not an interest of the event
+    // loop: the trigger is watched by an epoll instance of its own, whose
+    // readiness is the event of the trigger
+    epoll: Generic<RawFd>,
+    resource: Resource,
+}
+
+impl PressureSource {
+    /// Set up a trigger on the pressure of the given resource
+    ///
+    /// An event is generated when some tasks were stalled on the resource for more than
+    /// `threshold` of the `window`.
+    pub fn new(
+        resource: Resource,
+        threshold: Duration,
+        window: Duration,
+    ) -> io::Result<PressureSource> {
+        PressureSource::create(resource, "some", threshold, window)
+    }
+
+    /// Set up a trigger on the full pressure of the given resource
+    ///
+    /// Like [`new`](PressureSource::new), but only counts the time during which all the
+    /// non-idle tasks were stalled simultaneously. This is not supported for the CPU before
+    /// Linux 5.13.
+    pub fn new_full(
+        resource: Resource,
+        threshold: Duration,
+        window: Duration,
+    ) -> io::Result<PressureSource> {
+        PressureSource::create(resource, "full", threshold, window)
+    }
+
+    fn create(
+        resource: Resource,
+        kind: &str,
+        threshold: Duration,
+        window: Duration,
+    ) -> io::Result<PressureSource> {
+        let mut trigger = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NONBLOCK | nix::libc::O_CLOEXEC)
+            .open(resource.path())
+        {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "pressure stall information is not supported by this kernel",
+                ))
+            }
+            ret => ret?,
+        };
+        let spec = format!(
+            "{} {} {}\0",
+            kind,
+            threshold.as_micros(),
+            window.as_micros()
+        );
+        trigger.write_all(spec.as_bytes())?;
+
+        let epoll_fd = epoll::epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let mut event = EpollEvent::new(EpollFlags::EPOLLPRI, 0);
+        if let Err(e) = epoll::epoll_ctl(
+            epoll_fd,
+            EpollOp::EpollCtlAdd,
+            trigger.as_raw_fd(),
+            Some(&mut event),
+        ) {
+            let _ = close(epoll_fd);
+            return Err(e.into());
+        }
+        Ok(PressureSource {
+            _trigger: trigger,
+            epoll: Generic::new(epoll_fd, Interest::READ, Mode::Level),
+            resource,
+        })
+    }
+
+    /// The resource monitored by this source
+    pub fn resource(&self) -> Resource {
+        self.resource
+    }
+}
+
+impl Drop for PressureSource {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.epoll.file) {
+            log::warn!("[calloop] Failed to close pressure epoll instance: {:?}", e);
+        }
+    }
+}
+
+impl EventSource for PressureSource {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.epoll.process_events(readiness, token, |_, &mut fd| {
+            // polling the trigger acknowledges its event: it was already consumed when the
+            // event loop polled the epoll instance, whose readiness is the event itself. The
+            // epoll instance is only checked for the removal of the trigger, which is reported
+            // on each poll.
+            let mut events = [EpollEvent::empty()];
+            let flags = loop {
+                match epoll::epoll_wait(fd, &mut events, 0) {
+                    Ok(0) => break EpollFlags::empty(),
+                    Ok(_) => break events[0].events(),
+                    Err(Errno::EINTR) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            };
+            if flags.contains(EpollFlags::EPOLLERR) {
+                // the monitored cgroup was removed
+                return Ok(PostAction::Remove);
+            }
+            callback((), &mut ());
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.epoll.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.epoll.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.epoll.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_trigger() {
+        // the threshold cannot exceed the window
+        let ret = PressureSource::new(
+            Resource::Memory,
+            Duration::from_secs(2),
+            Duration::from_secs(1),
+        );
+        let err = ret.unwrap_err();
+        if err.kind() != io::ErrorKind::NotFound {
+            assert_eq!(err.raw_os_error(), Some(nix::libc::EINVAL));
+        }
+    }
+
+    #[test]
+    fn quiet_trigger() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let source = match PressureSource::new(
+            Resource::Memory,
+            Duration::from_millis(1900),
+            Duration::from_secs(2),
+        ) {
+            Ok(source) => source,
+            // PSI or the permission to set triggers is not available
+            Err(_) => return,
+        };
+        assert_eq!(source.resource(), Resource::Memory);
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn trigger_fires() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let source = match PressureSource::new(
+            Resource::Cpu,
+            Duration::from_millis(100),
+            Duration::from_secs(2),
+        ) {
+            Ok(source) => source,
+            // PSI or the permission to set triggers is not available
+            Err(_) => return,
+        };
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        // more busy threads than CPUs stall each other
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let threads = (0..8)
+            .map(|_| {
+                let stop = stop.clone();
+                std::thread::spawn(
+                    move || {
+                        while !stop.load(std::sync::atomic::Ordering::Relaxed) {}
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut count = 0;
+        let fired = event_loop
+            .dispatch_until(Duration::from_secs(5), &mut count, |count| *count > 0)
+            .unwrap();
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(fired);
+    }
+}
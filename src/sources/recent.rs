@@ -0,0 +1,252 @@
+//! An adapter keeping the latest events of a source for post-mortem debugging
+//!
+//! The [`RecordRecent`] adapter, created with [`record_recent()`](record_recent), keeps a
+//! copy of the last events of its wrapped source in a ring buffer, before invoking the
+//! callback it was inserted with. The [`RecentHandle`] of the adapter remains usable once
+//! it is inserted in the event loop, to dump this recent activity when something goes
+//! wrong, for example from a panic hook or an error path.
+//!
+//! ```
+//! # extern crate calloop;
+//! use calloop::{ping::make_ping, recent::record_recent};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (ping, ping_source) = make_ping().unwrap();
+//! let source = record_recent(ping_source, 16);
+//! let recent = source.handle();
+//!
+//! handle
+//!     .insert_source(source, |(), &mut (), _| println!("Pinged"))
+//!     .unwrap();
+//!
+//! // later, while investigating a failure
+//! println!("Latest events: {:?}", recent.snapshot());
+//! # }
+//! ```
+
+use std::{cell::RefCell, collections::VecDeque, fmt, io, rc::Rc};
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+struct Recorder<E> {
+    events: VecDeque<E>,
+    capacity: usize,
+}
+
+impl<E: Clone> Recorder<E> {
+    fn record(recorder: &RefCell<Recorder<E>>, event: &E) {
+        let mut recorder = recorder.borrow_mut();
+        if recorder.capacity == 0 {
+            return;
+        }
+        if recorder.events.len() == recorder.capacity {
+            recorder.events.pop_front();
+        }
+        recorder.events.push_back(event.clone());
+    }
+}
+
+/// A handle to the events recorded by a [`RecordRecent`] source
+///
+/// It can be cloned, and remains usable after the source has been inserted into the
+/// event loop, or dropped.
+pub struct RecentHandle<E> {
+    recorder: Rc<RefCell<Recorder<E>>>,
+}
+
+impl<E> Clone for RecentHandle<E> {
+    fn clone(&self) -> Self {
+        RecentHandle {
+            recorder: self.recorder.clone(),
+        }
+    }
+}
+
+impl<E> fmt::Debug for RecentHandle<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let recorder = self.recorder.borrow();
+        f.debug_struct("RecentHandle")
+            .field("events", &recorder.events.len())
+            .field("capacity", &recorder.capacity)
+            .finish()
+    }
+}
+
+impl<E: Clone> RecentHandle<E> {
+    /// A copy of the recorded events, from the oldest to the latest
+    pub fn snapshot(&self) -> Vec<E> {
+        self.recorder.borrow().events.iter().cloned().collect()
+    }
+
+    /// Forget the recorded events
+    pub fn clear(&self) {
+        self.recorder.borrow_mut().events.clear();
+    }
+}
+
+/// An event source recording the latest events of another source
+///
+/// See the [module documentation](self) for details.
+pub struct RecordRecent<S: EventSource> {
+    source: S,
+    recorder: Rc<RefCell<Recorder<S::Event>>>,
+}
+
+impl<S: EventSource + fmt::Debug> fmt::Debug for RecordRecent<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordRecent")
+            .field("source", &self.source)
+            .field("capacity", &self.recorder.borrow().capacity)
+            .finish()
+    }
+}
+
+/// Wrap a source so that its last `n` events are recorded
+pub fn record_recent<S>(source: S, n: usize) -> RecordRecent<S>
+where
+    S: EventSource,
+    S::Event: Clone,
+{
+    RecordRecent {
+        source,
+        recorder: Rc::new(RefCell::new(Recorder {
+            events: VecDeque::with_capacity(n),
+            capacity: n,
+        })),
+    }
+}
+
+impl<S> RecordRecent<S>
+where
+    S: EventSource,
+    S::Event: Clone,
+{
+    /// Retrieve a handle to the events recorded by this source
+    pub fn handle(&self) -> RecentHandle<S::Event> {
+        RecentHandle {
+            recorder: self.recorder.clone(),
+        }
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> S {
+        self.source
+    }
+}
+
+impl<S> EventSource for RecordRecent<S>
+where
+    S: EventSource,
+    S::Event: Clone,
+{
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = S::Ret;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let recorder = &self.recorder;
+        self.source.process_events(readiness, token, |event, meta| {
+            Recorder::record(recorder, &event);
+            callback(event, meta)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let recorder = &self.recorder;
+        self.source.on_dispatch_start(|event, meta| {
+            Recorder::record(recorder, &event);
+            callback(event, meta)
+        })
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end()
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        self.source.next_deadline()
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::ping::make_counting_ping;
+
+    #[test]
+    fn keeps_latest_events() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_counting_ping().unwrap();
+        let source = record_recent(source, 2);
+        let recent = source.handle();
+        event_loop
+            .handle()
+            .insert_source(source, |_, &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        for pings in 1..=3 {
+            for _ in 0..pings {
+                ping.ping().unwrap();
+            }
+            event_loop
+                .dispatch(Duration::from_millis(0), &mut count)
+                .unwrap();
+        }
+        assert_eq!(count, 3);
+        assert_eq!(recent.snapshot(), &[2, 3]);
+
+        recent.clear();
+        assert!(recent.snapshot().is_empty());
+    }
+}
@@ -0,0 +1,424 @@
+//! An adapter retrying a failing event source with an exponential backoff
+//!
+//! When an event source returns an error from its processing, the event loop stops
+//! dispatching and returns this error. For sources whose errors are often transient, like a
+//! socket which intermittently fails, the [`Retry`] adapter catches these errors instead: the
+//! wrapped source is unregistered from the event loop, and registered again after a delay
+//! which doubles with each consecutive failure, as configured by a [`RetryPolicy`].
+//!
+//! Once the wrapped source has failed `max_attempts` times in a row, the adapter gives up:
+//! the last error is given to the callback set with
+//! [`Retry::on_give_up`](Retry#method.on_give_up) and the source removes itself from the
+//! event loop, or, if no such callback was set, the error is returned by the dispatch of the
+//! event loop as usual. A successful processing resets the count of failures.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::io::Read;
+//! use std::os::unix::net::UnixStream;
+//! use std::time::Duration;
+//!
+//! use calloop::generic::Generic;
+//! use calloop::retry::{retry_with_backoff, RetryPolicy};
+//! use calloop::{Interest, Mode, PostAction};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (socket, _) = UnixStream::pair().unwrap();
+//! socket.set_nonblocking(true).unwrap();
+//! let source = Generic::new(socket, Interest::READ, Mode::Level);
+//! let policy = RetryPolicy::new(Duration::from_millis(100), Duration::from_secs(10), 5);
+//! let source = retry_with_backoff(source, policy)
+//!     .on_give_up(|e| eprintln!("Giving up on the socket: {}", e));
+//!
+//! handle.insert_source(source, |_readiness, socket, _| {
+//!     let mut buffer = [0; 1024];
+//!     // an error is retried by the adapter
+//!     let len = socket.read(&mut buffer)?;
+//!     println!("Received {} bytes", len);
+//!     Ok(PostAction::Continue)
+//! });
+//! # }
+//! ```
+
+use std::io;
+use std::time::Duration;
+
+use super::deadline::Deadline;
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// How a [`Retry`] adapter retries its source
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The delay before the first retry
+    pub base_delay: Duration,
+    /// The maximum delay between two retries
+    pub max_delay: Duration,
+    /// The number of consecutive failures after which the adapter gives up
+    pub max_attempts: u32,
+}
+
+impl RetryPolicy {
+    /// Create a new retry policy
+    pub fn new(base_delay: Duration, max_delay: Duration, max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            base_delay,
+            max_delay,
+            max_attempts,
+        }
+    }
+
+    // The delay before retrying after this number of consecutive failures
+    fn delay(&self, failures: u32) -> Duration {
+        let factor = 1u32.checked_shl(failures.saturating_sub(1)).unwrap_or(0);
+        match self.base_delay.checked_mul(factor) {
+            Some(delay) if factor > 0 => delay.min(self.max_delay),
+            _ => self.max_delay,
+        }
+    }
+}
+
+type GiveUpCallback = Box<dyn FnMut(io::Error)>;
+
+/// An event source retrying its wrapped source when it fails
+///
+/// See the [module documentation](self) for details.
+pub struct Retry<S> {
+    source: S,
+    policy: RetryPolicy,
+    deadline: Deadline,
+    failures: u32,
+    backing_off: bool,
+    source_registered: bool,
+    on_give_up: Option<GiveUpCallback>,
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for Retry<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Retry")
+            .field("source", &self.source)
+            .field("policy", &self.policy)
+            .field("failures", &self.failures)
+            .field("backing_off", &self.backing_off)
+            .finish()
+    }
+}
+
+/// Wrap a source so that it is retried with an exponential backoff when it fails
+pub fn retry_with_backoff<S: EventSource>(source: S, policy: RetryPolicy) -> Retry<S> {
+    Retry {
+        source,
+        policy,
+        deadline: Deadline::new(),
+        failures: 0,
+        backing_off: false,
+        source_registered: false,
+        on_give_up: None,
+    }
+}
+
+impl<S> Retry<S> {
+    /// Set a callback receiving the last error when the adapter gives up on its source
+    ///
+    /// The adapter then removes itself from the event loop, instead of returning the error.
+    pub fn on_give_up<F: FnMut(io::Error) + 'static>(mut self, callback: F) -> Retry<S> {
+        self.on_give_up = Some(Box::new(callback));
+        self
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// The number of consecutive failures of the wrapped source
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Whether the wrapped source is currently waiting to be retried
+    pub fn is_backing_off(&self) -> bool {
+        self.backing_off
+    }
+}
+
+impl<S: EventSource> EventSource for Retry<S> {
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = S::Ret;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.deadline.owns(token) {
+            if !self.deadline.expired() {
+                return Ok(PostAction::Continue);
+            }
+            // the backoff delay is over, register the source again
+            self.backing_off = false;
+            return Ok(PostAction::Reregister);
+        }
+        if self.backing_off {
+            return Ok(PostAction::Continue);
+        }
+
+        match self.source.process_events(readiness, token, callback) {
+            Ok(action) => {
+                self.failures = 0;
+                Ok(action)
+            }
+            Err(e) => {
+                self.failures += 1;
+                if self.failures >= self.policy.max_attempts {
+                    return match self.on_give_up {
+                        Some(ref mut on_give_up) => {
+                            on_give_up(e);
+                            Ok(PostAction::Remove)
+                        }
+                        None => Err(e),
+                    };
+                }
+                let delay = self.policy.delay(self.failures);
+                log::warn!(
+                    "[calloop] Source failed ({}), retrying in {:?}: {}",
+                    self.failures,
+                    delay,
+                    e
+                );
+                self.deadline.set(delay);
+                self.backing_off = true;
+                // the source is unregistered while backing off
+                Ok(PostAction::Reregister)
+            }
+        }
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.deadline.register(poll, token_factory);
+        if !self.backing_off {
+            if let Err(e) = self.source.register(poll, token_factory) {
+                self.deadline.unregister(poll);
+                return Err(e);
+            }
+            self.source_registered = true;
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.deadline.unregister(poll);
+        self.deadline.register(poll, token_factory);
+        match (self.backing_off, self.source_registered) {
+            (true, true) => {
+                self.source_registered = false;
+                self.source.unregister(poll)?;
+            }
+            (false, false) => {
+                self.source.register(poll, token_factory)?;
+                self.source_registered = true;
+            }
+            (false, true) => self.source.reregister(poll, token_factory)?,
+            (true, false) => {}
+        }
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.deadline.unregister(poll);
+        if self.source_registered {
+            self.source_registered = false;
+            self.source.unregister(poll)?;
+        }
+        Ok(())
+    }
+
+    fn on_dispatch_start<C>(&mut self, callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.source_registered {
+            self.source.on_dispatch_start(callback);
+        }
+    }
+
+    fn on_dispatch_end(&mut self) {
+        if self.source_registered {
+            self.source.on_dispatch_end();
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        if self.source_registered {
+            self.source.flush()
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn next_deadline(&self) -> Option<std::time::Instant> {
+        // the end of the backoff, or the deadline of the wrapped source
+        if self.backing_off {
+            self.deadline.next_deadline()
+        } else {
+            self.source.next_deadline()
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::ping::{make_ping, PingSource};
+
+    // A ping source failing a given number of times
+    struct Flaky {
+        ping: PingSource,
+        failures: u32,
+    }
+
+    impl EventSource for Flaky {
+        type Event = ();
+        type Metadata = ();
+        type Ret = ();
+
+        fn process_events<C>(
+            &mut self,
+            readiness: Readiness,
+            token: Token,
+            callback: C,
+        ) -> io::Result<PostAction>
+        where
+            C: FnMut((), &mut ()),
+        {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::Error::new(io::ErrorKind::Other, "flaky"));
+            }
+            self.ping.process_events(readiness, token, callback)
+        }
+
+        fn register(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> io::Result<()> {
+            self.ping.register(poll, factory)
+        }
+
+        fn reregister(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> io::Result<()> {
+            self.ping.reregister(poll, factory)
+        }
+
+        fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+            self.ping.unregister(poll)
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(Duration::from_millis(10), Duration::from_millis(15), 3)
+    }
+
+    #[test]
+    fn delays() {
+        let policy = RetryPolicy::new(Duration::from_millis(10), Duration::from_millis(50), 10);
+        let delays = (1..=5).map(|n| policy.delay(n)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [10, 20, 40, 50, 50]
+                .iter()
+                .map(|&ms| Duration::from_millis(ms))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(policy.delay(100), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn retried_after_failure() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        let source = retry_with_backoff(
+            Flaky {
+                ping: source,
+                failures: 1,
+            },
+            policy(),
+        );
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        ping.ping();
+        // the failure is caught, and the source is unregistered
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 0);
+
+        // wait for the retry, the ping is still pending
+        for _ in 0..10 {
+            event_loop
+                .dispatch(Duration::from_millis(20), &mut count)
+                .unwrap();
+            if count > 0 {
+                break;
+            }
+        }
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn gives_up() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        let gave_up = Rc::new(Cell::new(false));
+        let gave_up2 = gave_up.clone();
+        let source = retry_with_backoff(
+            Flaky {
+                ping: source,
+                failures: 10,
+            },
+            policy(),
+        )
+        .on_give_up(move |_| gave_up2.set(true));
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), _| {})
+            .unwrap();
+
+        ping.ping();
+        for _ in 0..20 {
+            event_loop
+                .dispatch(Duration::from_millis(20), &mut ())
+                .unwrap();
+            if gave_up.get() {
+                break;
+            }
+        }
+        assert!(gave_up.get());
+    }
+}
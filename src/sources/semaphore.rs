@@ -0,0 +1,189 @@
+//! A counting semaphore invoking the callback once per posted unit
+//!
+//! Only available on Linux.
+//!
+//! The [`semaphore()`](semaphore) function creates a [`CountingSource`] and a [`Poster`],
+//! sharing an eventfd in semaphore mode. Each [`post(n)`](Poster#method.post) adds `n` to its
+//! counter, and the source invokes its callback once per unit, decrementing the counter one
+//! by one until it reaches zero. This gives exact "N work items available, N callbacks"
+//! semantics for work queues filled from other threads.
+//!
+//! This differs from the [`ping`](crate::ping) source, whose pings are coalesced into a
+//! single event, and from its [counting](crate::ping::CountingPingSource) variant, which
+//! delivers the number of pings in one event. As the callback runs once per unit, posting a
+//! large count keeps the source busy for as many invocations during a single dispatch.
+//!
+//! The counter of an eventfd cannot exceed `2^64 - 2`: a post which would overflow it fails
+//! with a `WouldBlock` error, and posts nothing. Unlike the ping source, the source does not
+//! detect when all the [`Poster`]s are dropped, and stays in the event loop until removed.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::semaphore::semaphore;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (poster, source) = semaphore().unwrap();
+//!
+//! handle
+//!     .insert_source(source, |(), &mut (), _| {
+//!         println!("Processing one work item");
+//!     })
+//!     .unwrap();
+//!
+//! std::thread::spawn(move || {
+//!     // ... queue three work items
+//!     poster.post(3).unwrap();
+//! });
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+
+use nix::{
+    errno::Errno,
+    sys::eventfd::{eventfd, EfdFlags},
+    unistd::{read, write},
+};
+
+use super::fd_guard::FdGuard;
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// Create a new semaphore, with a counter of zero
+pub fn semaphore() -> io::Result<(Poster, CountingSource)> {
+    let fd = eventfd(
+        0,
+        EfdFlags::EFD_SEMAPHORE | EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC,
+    )?;
+    let fd = Arc::new(FdGuard::new(fd, "semaphore eventfd"));
+    Ok((
+        Poster { fd: fd.clone() },
+        CountingSource {
+            source: Generic::new(fd.as_raw_fd(), Interest::READ, Mode::Level),
+            _fd: fd,
+        },
+    ))
+}
+
+/// The handle posting units to a semaphore
+///
+/// It can be cloned and sent accross threads.
+#[derive(Clone, Debug)]
+pub struct Poster {
+    fd: Arc<FdGuard>,
+}
+
+impl Poster {
+    /// Add `n` units to the counter of the semaphore
+    ///
+    /// Fails with a `WouldBlock` error if the counter would exceed `2^64 - 2`, in which case
+    /// nothing is added.
+    pub fn post(&self, n: u64) -> io::Result<()> {
+        if n == std::u64::MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot post 2^64 - 1 units to a semaphore",
+            ));
+        }
+        loop {
+            match write(self.fd.as_raw_fd(), &n.to_ne_bytes()) {
+                Ok(_) => return Ok(()),
+                Err(Errno::EINTR) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// The event source of a semaphore
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct CountingSource {
+    source: Generic<RawFd>,
+    _fd: Arc<FdGuard>,
+}
+
+impl EventSource for CountingSource {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.source.process_events(readiness, token, |_, &mut fd| {
+            // in semaphore mode, each read decrements the counter by one
+            let mut buffer = [0u8; 8];
+            loop {
+                match read(fd, &mut buffer) {
+                    Ok(_) => callback((), &mut ()),
+                    Err(Errno::EAGAIN) => return Ok(PostAction::Continue),
+                    Err(Errno::EINTR) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn one_callback_per_unit() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let (poster, source) = semaphore().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        poster.post(2).unwrap();
+        poster.clone().post(3).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 5);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!(count, 5);
+
+        // the counter cannot overflow
+        poster.post(std::u64::MAX - 1).unwrap();
+        let err = poster.post(1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(
+            poster.post(std::u64::MAX).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! An event source routing the frames read from a file descriptor to several callbacks
+//!
+//! Some protocols multiplex several logical streams over a single file descriptor, each
+//! frame carrying the identifier of its stream. A file descriptor can only be registered
+//! once in the event loop, so these streams cannot be separate sources. The [`SharedFd`]
+//! source registers the file descriptor once, and fans the frames out to the callbacks of
+//! its logical streams instead, added with
+//! [`add_logical_stream`](SharedFd#method.add_logical_stream).
+//!
+//! The demultiplexing is provided by the user, as a function given to
+//! [`SharedFd::new`](SharedFd#method.new). It is invoked when the file descriptor is
+//! readable, and reads the next frame, returning the identifier of its stream along with its
+//! content, or `None` when no complete frame is available. It is invoked again until it
+//! returns `None`, so it must not block, and must keep the incomplete frames in its own
+//! state. If it fails, the error is returned by the source to the event loop.
+//!
+//! Each frame is given to the callback of its stream. The frames of the streams which have
+//! no callback are delivered to the callback of the source instead, along with their
+//! identifier, so that the new streams can be discovered.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::io::Read;
+//! use std::os::unix::net::UnixStream;
+//!
+//! use calloop::{shared_fd::SharedFd, Interest, Mode};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! # let (socket, _) = UnixStream::pair().unwrap();
+//! socket.set_nonblocking(true).unwrap();
+//! // frames of two bytes: the stream identifier and the payload
+//! let mut partial = Vec::new();
+//! let mut source = SharedFd::new(socket, Interest::READ, Mode::Level, move |socket| {
+//!     // the incomplete frame is kept until the rest of it is received
+//!     while partial.len() < 2 {
+//!         let mut buffer = [0u8; 2];
+//!         match socket.read(&mut buffer[partial.len()..]) {
+//!             Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+//!             Ok(len) => partial.extend_from_slice(&buffer[partial.len()..][..len]),
+//!             Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+//!             Err(e) => return Err(e),
+//!         }
+//!     }
+//!     let frame = (partial[0], partial[1]);
+//!     partial.clear();
+//!     Ok(Some(frame))
+//! });
+//! source.add_logical_stream(1, |byte| println!("Control: {}", byte));
+//! source.add_logical_stream(2, |byte| println!("Data: {}", byte));
+//!
+//! handle
+//!     .insert_source(source, |(id, _byte), &mut (), _| {
+//!         println!("Frame of the unknown stream {}", id);
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event source reading the frames of several logical streams from a file descriptor
+///
+/// See the [module documentation](self) for details.
+pub struct SharedFd<F: AsRawFd, Id, T, D> {
+    source: Generic<F>,
+    demux: D,
+    streams: HashMap<Id, Box<dyn FnMut(T)>>,
+}
+
+impl<F, Id, T, D> std::fmt::Debug for SharedFd<F, Id, T, D>
+where
+    F: AsRawFd + std::fmt::Debug,
+    Id: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedFd")
+            .field("source", &self.source)
+            .field("streams", &self.streams.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl<F, Id, T, D> SharedFd<F, Id, T, D>
+where
+    F: AsRawFd,
+    Id: Hash + Eq,
+    D: FnMut(&mut F) -> io::Result<Option<(Id, T)>>,
+{
+    /// Wrap a FD-backed object, reading its frames with the given demultiplexing function
+    pub fn new(file: F, interest: Interest, mode: Mode, demux: D) -> SharedFd<F, Id, T, D> {
+        SharedFd {
+            source: Generic::new(file, interest, mode),
+            demux,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Add a logical stream, whose frames are given to the callback
+    ///
+    /// If the stream already had a callback, it is replaced.
+    pub fn add_logical_stream<C: FnMut(T) + 'static>(&mut self, id: Id, callback: C) {
+        self.streams.insert(id, Box::new(callback));
+    }
+
+    /// Remove a logical stream, whose frames are then delivered to the callback of the source
+    ///
+    /// Returns whether the stream existed.
+    pub fn remove_logical_stream(&mut self, id: &Id) -> bool {
+        self.streams.remove(id).is_some()
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &Generic<F> {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut Generic<F> {
+        &mut self.source
+    }
+}
+
+impl<F, Id, T, D> EventSource for SharedFd<F, Id, T, D>
+where
+    F: AsRawFd,
+    Id: Hash + Eq,
+    D: FnMut(&mut F) -> io::Result<Option<(Id, T)>>,
+{
+    type Event = (Id, T);
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (demux, streams) = (&mut self.demux, &mut self.streams);
+        self.source.process_events(readiness, token, |_, file| {
+            while let Some((id, frame)) = demux(file)? {
+                match streams.get_mut(&id) {
+                    Some(stream) => stream(frame),
+                    None => callback((id, frame), &mut ()),
+                }
+            }
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Dispatcher;
+
+    #[test]
+    fn routes_frames_by_stream() {
+        let mut event_loop = crate::EventLoop::<Vec<(u8, u8)>>::try_new().unwrap();
+        let (mut peer, socket) = UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut partial = Vec::new();
+        let mut source = SharedFd::new(socket, Interest::READ, Mode::Level, move |socket| {
+            while partial.len() < 2 {
+                let mut buffer = [0u8; 2];
+                match socket.read(&mut buffer[partial.len()..]) {
+                    Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(len) => partial.extend_from_slice(&buffer[partial.len()..][..len]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+            }
+            let frame = (partial[0], partial[1]);
+            partial.clear();
+            Ok(Some(frame))
+        });
+        let routed = Rc::new(RefCell::new(Vec::new()));
+        let first = routed.clone();
+        source.add_logical_stream(1, move |byte| first.borrow_mut().push((1, byte)));
+        let second = routed.clone();
+        source.add_logical_stream(2, move |byte| second.borrow_mut().push((2, byte)));
+        let dispatcher = Dispatcher::new(source, |frame, &mut (), unknown: &mut Vec<_>| {
+            unknown.push(frame)
+        });
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        let mut unknown = Vec::new();
+        peer.write_all(&[1, 10, 2, 20, 3, 30, 1]).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut unknown)
+            .unwrap();
+        assert_eq!(*routed.borrow(), &[(1, 10), (2, 20)]);
+        assert_eq!(unknown, &[(3, 30)]);
+
+        // the incomplete frame is completed by the next read
+        peer.write_all(&[11]).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut unknown)
+            .unwrap();
+        assert_eq!(*routed.borrow(), &[(1, 10), (2, 20), (1, 11)]);
+
+        // a removed stream goes to the callback of the source
+        assert!(dispatcher.as_source_mut().remove_logical_stream(&2));
+        peer.write_all(&[2, 21]).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut unknown)
+            .unwrap();
+        assert_eq!(unknown, &[(3, 30), (2, 21)]);
+    }
+}
@@ -0,0 +1,88 @@
+//! The signal mask of a thread, shared by the signal sources watching its signals
+//!
+//! Several sources of a thread can watch the same signal, so a signal is only unblocked once
+//! no source of the thread watches it anymore. Both the [`Signals`](crate::signals::Signals)
+//! and the [`ThreadedSignals`](crate::threaded_signals::ThreadedSignals) sources count their
+//! signals here.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::os::raw::c_int;
+
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+thread_local! {
+    // the number of managed sources of this thread watching each signal
+    static WATCHERS: RefCell<HashMap<Signal, usize>> = RefCell::new(HashMap::new());
+}
+
+#[cfg(test)]
+thread_local! {
+    // whether the next unblocking fails, for the tests of the error paths
+    pub(crate) static FAIL_UNBLOCK: std::cell::Cell<bool> = std::cell::Cell::new(false);
+}
+
+// Block these signals for the thread, counting a new watcher for those not yet in `held`
+pub(crate) fn block_signals(mask: &SigSet, held: &mut SigSet) -> io::Result<()> {
+    mask.thread_block()?;
+    WATCHERS.with(|watchers| {
+        let mut watchers = watchers.borrow_mut();
+        for s in Signal::iterator() {
+            if mask.contains(s) && !held.contains(s) {
+                *watchers.entry(s).or_insert(0) += 1;
+                held.add(s);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Stop watching these signals, unblocking those no other source of the thread watches
+pub(crate) fn unblock_signals(mask: &SigSet, held: &mut SigSet) -> io::Result<()> {
+    #[cfg(test)]
+    {
+        if FAIL_UNBLOCK.with(|fail| fail.replace(false)) {
+            return Err(io::Error::from_raw_os_error(nix::libc::EINVAL));
+        }
+    }
+    let mut unblocked = SigSet::empty();
+    WATCHERS.with(|watchers| {
+        let mut watchers = watchers.borrow_mut();
+        for s in Signal::iterator() {
+            if mask.contains(s) && held.contains(s) {
+                held.remove(s);
+                let count = watchers.entry(s).or_insert(1);
+                *count -= 1;
+                if *count == 0 {
+                    watchers.remove(&s);
+                    unblocked.add(s);
+                }
+            }
+        }
+    });
+    unblocked.thread_unblock()?;
+    Ok(())
+}
+
+// Reset the disposition of the ignored signals among these ones, as ignored
+// signals are discarded and never reach the sources
+pub(crate) fn unignore(signals: &[Signal]) -> io::Result<()> {
+    let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    for &s in signals {
+        let ignored = unsafe {
+            let mut old = std::mem::MaybeUninit::<nix::libc::sigaction>::uninit();
+            nix::errno::Errno::result(nix::libc::sigaction(
+                s as c_int,
+                std::ptr::null(),
+                old.as_mut_ptr(),
+            ))?;
+            old.assume_init().sa_sigaction == nix::libc::SIG_IGN
+        };
+        if ignored {
+            // Safety: restoring the default disposition does not install any handler
+            unsafe { sigaction(s, &default) }?;
+        }
+    }
+    Ok(())
+}
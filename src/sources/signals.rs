@@ -9,115 +9,1263 @@
 //! but you are responsible for masking them on other threads if you run them. The simplest
 //! way to ensure that is to setup the signal event source before spawning any thread, as
 //! they'll inherit their parent signal mask.
+//!
+//! Several sources can watch the same signal on a thread: the source keeps track of how many
+//! of them watch each signal, and a signal is only unblocked once no source of the thread
+//! watches it anymore. This counting is done per thread, so a source should be dropped or
+//! changed on the thread which created it.
+//!
+//! A signalfd created by other code can also be adopted with
+//! [`Signals::from_signalfd`](Signals#method.from_signalfd), which leaves the management of
+//! the signal mask to the caller.
+//!
+//! By default, the signals are delivered to your callback as they are read from the signalfd.
+//! Using [`Signals::with_buffered_delivery`](Signals#method.with_buffered_delivery), all pending
+//! signals are first read into an internal queue, which is then drained into your callback. This
+//! empties the kernel buffer as quickly as possible, even if your callback is slow.
+//!
+//! The [`threaded_signals`](crate::threaded_signals) source receives the signals in a
+//! dedicated thread with `sigwaitinfo` instead, which does not need a signalfd and is not
+//! limited to Linux, at the cost of that extra thread.
+//!
+//! The set of watched signals can be changed from within the callback using a
+//! [`SignalsHandle`], see [`Signals::handle`](Signals#method.handle).
+//!
+//! A [`PosixTimer`] can deliver its expirations as signals to such a source, for the clocks
+//! that the [`Timer`](crate::timer::Timer) does not support, like the CPU time of the process.
+//!
+//! Signals you don't care about but which would otherwise terminate your program, like
+//! `SIGPIPE`, can be ignored with [`Signals::ignore`](Signals#method.ignore). A signal is
+//! either ignored or watched: an ignored signal would never reach the signalfd, so watching
+//! it resets its disposition to the default one.
+//!
+//! ## Coalescing and dropped signals
+//!
+//! The kernel keeps at most one pending instance of each standard signal: if a signal is
+//! sent several times before the source reads it, a single [`Event`] is generated.
+//! [`Event::full_info`](Event#method.full_info) then describes one of the sendings. This
+//! coalescing cannot be detected by the source, and you should not rely on receiving one
+//! event per sending. For example, a single `SIGCHLD` event can stand for the termination
+//! of several children, which should all be reaped with `waitpid`.
+//!
+//! Realtime signals are queued rather than coalesced, so sendings can fail once the queue of
+//! the process is full. They cannot be watched by this source, as they are not represented
+//! by [`Signal`], so it never loses signals this way. An application relying on realtime
+//! signals must detect the overruns on the sending side, where `sigqueue` fails with `EAGAIN`.
 
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryFrom;
 use std::io;
 use std::os::raw::c_int;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use nix::sys::signal::SigSet;
+pub use nix::sys::signal::SigHandler;
+pub use nix::sys::signal::SigSet;
 pub use nix::sys::signal::Signal;
+use nix::sys::signal::{sigaction, SaFlags, SigAction};
 pub use nix::sys::signalfd::siginfo;
-use nix::sys::signalfd::{SfdFlags, SignalFd};
+use nix::sys::signalfd::SfdFlags;
+pub use nix::sys::signalfd::SignalFd;
+use nix::unistd::{sysconf, Pid, SysconfVar};
 
 use super::generic::Generic;
-use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+use super::signal_mask::{block_signals, unblock_signals, unignore};
+use crate::{
+    BufferStats, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
 
 /// An event generated by the signal event source
 #[derive(Copy, Clone, Debug)]
 pub struct Event {
     info: siginfo,
+    received_at: Instant,
 }
 
 impl Event {
+    // The reception timestamp is the time at which the signal is read from the signalfd
+    fn new(info: siginfo) -> Event {
+        Event {
+            info,
+            received_at: Instant::now(),
+        }
+    }
+
+    /// The instant at which this signal was read from the signalfd
+    ///
+    /// The `siginfo` does not carry a reliable timestamp of the signal emission, so this is
+    /// the time at which the event loop received it. It can be used to measure the latency
+    /// between the reception of a signal and its handling.
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+
     /// Retrieve the signal number that was receive
     pub fn signal(&self) -> Signal {
         Signal::try_from(self.info.ssi_signo as c_int).unwrap()
     }
 
+    /// The information associated with this signal, with named fields
+    ///
+    /// See [`SignalInfo`] for the meaning of each field.
+    pub fn info(&self) -> SignalInfo {
+        let info = &self.info;
+        SignalInfo {
+            signal: self.signal(),
+            errno: info.ssi_errno,
+            code: info.ssi_code,
+            pid: Pid::from_raw(info.ssi_pid as nix::libc::pid_t),
+            uid: info.ssi_uid,
+            fd: info.ssi_fd,
+            timer_id: info.ssi_tid,
+            band: info.ssi_band,
+            overrun: info.ssi_overrun,
+            trap_number: info.ssi_trapno,
+            status: info.ssi_status,
+            value_int: info.ssi_int,
+            value_ptr: info.ssi_ptr,
+            user_time: info.ssi_utime,
+            system_time: info.ssi_stime,
+            address: info.ssi_addr,
+        }
+    }
+
+    /// The integer value sent along with the signal
+    ///
+    /// This is the value given to `sigqueue`, or to [`PosixTimer::new`](PosixTimer#method.new)
+    /// for the signals of a POSIX timer, which allows to tell which timer expired.
+    pub fn value_int(&self) -> i32 {
+        self.info.ssi_int
+    }
+
     /// Access the full `siginfo_t` associated with this signal event
     pub fn full_info(&self) -> siginfo {
         self.info
     }
+
+    fn is_sigchld(&self) -> bool {
+        self.info.ssi_signo as c_int == Signal::SIGCHLD as c_int
+    }
+
+    /// The pid of the child whose state changed, for a `SIGCHLD`
+    ///
+    /// The signalfd coalesces the `SIGCHLD`s received while the event loop is busy, so a
+    /// single event may stand for several children. You still need to reap the children by
+    /// calling `waitpid` with `WNOHANG` in a loop, which also clears the zombie processes.
+    pub fn child_pid(&self) -> Option<Pid> {
+        if self.is_sigchld() {
+            Some(Pid::from_raw(self.info.ssi_pid as nix::libc::pid_t))
+        } else {
+            None
+        }
+    }
+
+    /// The exit status of the child, or the signal that changed its state, for a `SIGCHLD`
+    ///
+    /// Its meaning depends on the `ssi_code` field of the [`full_info`](Event#method.full_info).
+    /// See [`child_pid`](Event#method.child_pid) for the caveats of relying on `SIGCHLD`.
+    pub fn child_status(&self) -> Option<i32> {
+        if self.is_sigchld() {
+            Some(self.info.ssi_status)
+        } else {
+            None
+        }
+    }
+
+    /// The user CPU time consumed by the child, for a `SIGCHLD`
+    pub fn child_user_time(&self) -> Option<Duration> {
+        if self.is_sigchld() {
+            clock_ticks_to_duration(self.info.ssi_utime)
+        } else {
+            None
+        }
+    }
+
+    /// The system CPU time consumed by the child, for a `SIGCHLD`
+    pub fn child_system_time(&self) -> Option<Duration> {
+        if self.is_sigchld() {
+            clock_ticks_to_duration(self.info.ssi_stime)
+        } else {
+            None
+        }
+    }
 }
 
-/// An event source for receiving Unix signals
+/// A signal event along with the control groups of its sender
+///
+/// It is generated by the [`EnrichedSignals`] source, see
+/// [`Signals::with_sender_enrichment`](Signals#method.with_sender_enrichment).
+#[derive(Clone, Debug)]
+pub struct EnrichedEvent {
+    /// The signal event
+    pub event: Event,
+    /// The control groups of the process which sent this signal
+    ///
+    /// This is the content of `/proc/<pid>/cgroup` for the sender of the signal, read when the
+    /// signal is delivered. It is `None` for the signals sent by the kernel, and if the sender
+    /// exited before the signal was delivered.
+    pub sender_cgroup: Option<String>,
+}
+
+impl EnrichedEvent {
+    fn new(event: Event) -> EnrichedEvent {
+        let sender_cgroup = if event.info.ssi_pid != 0 {
+            // the sender may have exited already
+            std::fs::read_to_string(format!("/proc/{}/cgroup", event.info.ssi_pid))
+                .ok()
+                .map(|cgroup| cgroup.trim_end().to_owned())
+        } else {
+            None
+        };
+        EnrichedEvent {
+            event,
+            sender_cgroup,
+        }
+    }
+}
+
+/// The information associated with a signal
+///
+/// This is a typed view of the `siginfo` of an [`Event`], see
+/// [`Event::info`](Event#method.info). Which fields are meaningful depends on the signal and
+/// on how it was sent, as described by `code` (the `si_code` of `sigaction(2)`); the others
+/// are zero.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SignalInfo {
+    /// The signal
+    pub signal: Signal,
+    /// An error number, generally unused on Linux
+    pub errno: i32,
+    /// Why the signal was sent, like `SI_USER` for `kill` or `CLD_EXITED` for a `SIGCHLD`
+    pub code: i32,
+    /// The pid of the sender, for signals sent by `kill` or `sigqueue`, or of the child whose
+    /// state changed, for `SIGCHLD`
+    pub pid: Pid,
+    /// The real user id of the sender, for signals sent by `kill` or `sigqueue` and `SIGCHLD`
+    pub uid: u32,
+    /// The file descriptor which became ready, for `SIGIO`
+    pub fd: i32,
+    /// The kernel id of the POSIX timer which expired, for timer signals
+    pub timer_id: u32,
+    /// The band event of the file descriptor which became ready, for `SIGIO`
+    pub band: u32,
+    /// The number of overruns of the POSIX timer which expired, for timer signals
+    pub overrun: u32,
+    /// The trap number which caused the signal, for hardware-generated signals
+    pub trap_number: u32,
+    /// The exit status of the child, or the signal which changed its state, for `SIGCHLD`
+    pub status: i32,
+    /// The integer value sent along with the signal, by `sigqueue` or a POSIX timer
+    pub value_int: i32,
+    /// The pointer value sent along with the signal, by `sigqueue` or a POSIX timer
+    pub value_ptr: u64,
+    /// The user CPU time consumed by the child, in clock ticks, for `SIGCHLD`
+    ///
+    /// See [`Event::child_user_time`](Event#method.child_user_time) for a [`Duration`].
+    pub user_time: u64,
+    /// The system CPU time consumed by the child, in clock ticks, for `SIGCHLD`
+    ///
+    /// See [`Event::child_system_time`](Event#method.child_system_time) for a [`Duration`].
+    pub system_time: u64,
+    /// The address which caused the fault, for hardware-generated signals like `SIGSEGV`
+    pub address: u64,
+}
+
+// The CPU times of the children are expressed in clock ticks
+fn clock_ticks_to_duration(ticks: u64) -> Option<Duration> {
+    match sysconf(SysconfVar::CLK_TCK) {
+        Ok(Some(rate)) if rate > 0 => {
+            let rate = rate as u64;
+            Some(
+                Duration::from_secs(ticks / rate)
+                    + Duration::from_nanos(ticks % rate * 1_000_000_000 / rate),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// The decoded fields of an [`Event`], in a form suitable for recording
+///
+/// This allows capturing a trace of the signals received in production, for example to log
+/// it, and to replay it later in tests: the events rebuilt from such a record can be given to
+/// the callback of a source. The raw `siginfo` of an event cannot be fully rebuilt, so only its
+/// meaningful fields are kept, the other fields of the rebuilt events being zero.
+///
+/// All the fields are plain public values, so that the record can be serialized by any
+/// means. With the `serde` cargo feature, it implements the `Serialize` and `Deserialize`
+/// traits of `serde`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SerializableSignalEvent {
+    /// The signal number
+    pub signal: i32,
+    /// Why the signal was sent, see [`SignalInfo::code`]
+    pub code: i32,
+    /// The pid of the sender, see [`SignalInfo::pid`]
+    pub pid: i32,
+    /// The real user id of the sender, see [`SignalInfo::uid`]
+    pub uid: u32,
+    /// The exit status of the child, see [`SignalInfo::status`]
+    pub status: i32,
+    /// The integer value sent along with the signal, see [`SignalInfo::value_int`]
+    pub value_int: i32,
+    /// When the signal was received, as the time elapsed since the Unix epoch
+    pub received_at: Duration,
+    /// The control groups of the sender, see [`EnrichedEvent::sender_cgroup`], always `None`
+    /// for the record of a plain [`Event`]
+    pub sender_cgroup: Option<String>,
+}
+
+impl From<&Event> for SerializableSignalEvent {
+    fn from(event: &Event) -> SerializableSignalEvent {
+        // an Instant has no absolute value, estimate the time of the reception from now
+        let received_at = SystemTime::now()
+            .checked_sub(event.received_at.elapsed())
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        SerializableSignalEvent {
+            signal: event.info.ssi_signo as i32,
+            code: event.info.ssi_code,
+            pid: event.info.ssi_pid as i32,
+            uid: event.info.ssi_uid,
+            status: event.info.ssi_status,
+            value_int: event.info.ssi_int,
+            received_at,
+            sender_cgroup: None,
+        }
+    }
+}
+
+impl From<&EnrichedEvent> for SerializableSignalEvent {
+    fn from(event: &EnrichedEvent) -> SerializableSignalEvent {
+        SerializableSignalEvent {
+            sender_cgroup: event.sender_cgroup.clone(),
+            ..SerializableSignalEvent::from(&event.event)
+        }
+    }
+}
+
+impl From<SerializableSignalEvent> for Event {
+    /// Rebuild an event from its record
+    ///
+    /// The instant of [`Event::received_at`](Event#method.received_at) is as far in the past
+    /// as the recorded time, or is now if the recorded time is in the future or cannot be
+    /// represented.
+    fn from(record: SerializableSignalEvent) -> Event {
+        // Safety: siginfo only contains integers, for which zero is a valid value
+        let mut info: siginfo = unsafe { std::mem::zeroed() };
+        info.ssi_signo = record.signal as u32;
+        info.ssi_code = record.code;
+        info.ssi_pid = record.pid as u32;
+        info.ssi_uid = record.uid;
+        info.ssi_status = record.status;
+        info.ssi_int = record.value_int;
+        let now = Instant::now();
+        let age = UNIX_EPOCH
+            .checked_add(record.received_at)
+            .and_then(|time| SystemTime::now().duration_since(time).ok())
+            .unwrap_or_default();
+        Event {
+            info,
+            received_at: now.checked_sub(age).unwrap_or(now),
+        }
+    }
+}
+
+/// The set of signals watched by a [`Signals`] source, in a form suitable for persistence
+///
+/// It is created by [`Signals::export_config`](Signals#method.export_config), and applied to
+/// a source with [`Signals::apply_config`](Signals#method.apply_config). Like
+/// [`SerializableSignalEvent`], it only contains plain values so that it can be serialized by
+/// any means.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SignalsConfig {
+    /// The numbers of the watched signals, in increasing order
+    pub signals: Vec<i32>,
+}
+
+/// An error of the [`Signals`] source
+///
+/// Each variant tells which operation failed, with the underlying error. It can be converted
+/// into this underlying [`io::Error`]. More variants can be added as the source gains new
+/// operations.
 #[derive(Debug)]
+#[non_exhaustive]
+pub enum SignalsError {
+    /// Changing the disposition of the signals failed
+    Disposition(io::Error),
+    /// Blocking the signals for the thread failed
+    MaskBlock(io::Error),
+    /// Unblocking the signals for the thread failed
+    MaskUnblock(io::Error),
+    /// Retrieving the signal mask of the thread failed
+    GetMask(io::Error),
+    /// Creating the signalfd failed
+    FdCreate(io::Error),
+    /// Changing the signals watched by the signalfd failed
+    SetMask(io::Error),
+    /// A [`SignalsConfig`] contains signal numbers which are not valid signals
+    InvalidConfig(io::Error),
+    /// Reading a signal from the signalfd failed
+    Read(io::Error),
+    /// Changing the flags of the signalfd failed, or is not allowed in its current state
+    FdFlags(io::Error),
+}
+
+impl SignalsError {
+    fn io_error(&self) -> &io::Error {
+        match self {
+            SignalsError::Disposition(e)
+            | SignalsError::MaskBlock(e)
+            | SignalsError::MaskUnblock(e)
+            | SignalsError::GetMask(e)
+            | SignalsError::FdCreate(e)
+            | SignalsError::SetMask(e)
+            | SignalsError::InvalidConfig(e)
+            | SignalsError::Read(e)
+            | SignalsError::FdFlags(e) => e,
+        }
+    }
+}
+
+impl std::fmt::Display for SignalsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operation = match self {
+            SignalsError::Disposition(_) => "change the signal disposition",
+            SignalsError::MaskBlock(_) => "block the signals",
+            SignalsError::MaskUnblock(_) => "unblock the signals",
+            SignalsError::GetMask(_) => "retrieve the signal mask",
+            SignalsError::FdCreate(_) => "create the signalfd",
+            SignalsError::SetMask(_) => "set the signalfd mask",
+            SignalsError::InvalidConfig(_) => "decode the signals configuration",
+            SignalsError::Read(_) => "read the signalfd",
+            SignalsError::FdFlags(_) => "change the flags of the signalfd",
+        };
+        write!(f, "failed to {}: {}", operation, self.io_error())
+    }
+}
+
+impl std::error::Error for SignalsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<SignalsError> for io::Error {
+    fn from(e: SignalsError) -> io::Error {
+        match e {
+            SignalsError::Disposition(e)
+            | SignalsError::MaskBlock(e)
+            | SignalsError::MaskUnblock(e)
+            | SignalsError::GetMask(e)
+            | SignalsError::FdCreate(e)
+            | SignalsError::SetMask(e)
+            | SignalsError::InvalidConfig(e)
+            | SignalsError::Read(e)
+            | SignalsError::FdFlags(e) => e,
+        }
+    }
+}
+
+/// An event source for receiving Unix signals
 pub struct Signals {
     sfd: Generic<SignalFd>,
     mask: SigSet,
+    buffered: Option<BufferedDelivery>,
+    pending_changes: Arc<Mutex<Vec<MaskChange>>>,
+    counts: HashMap<Signal, u64>,
+    one_per_pass: bool,
+    // whether the source blocks and unblocks its signals
+    managed: bool,
+    // the signals this source counts as watched in the registry of the thread
+    held: SigSet,
+    // a replaced signalfd, kept open until the new one is registered in its place
+    stale_fd: Option<SignalFd>,
+    // whether the signalfd was made blocking, see `set_nonblocking`
+    blocking: bool,
+    // whether an unmanaged source unblocks its signals when dropped
+    restore_on_drop: bool,
+    // the comparator sorting the signals read during a dispatch
+    order: Option<fn(&Event, &Event) -> std::cmp::Ordering>,
+    // the hook receiving the errors of the drop, which are logged otherwise
+    drop_error: Option<DropErrorHook>,
+}
+
+type DropErrorHook = Box<dyn Fn(SignalsError) + Send>;
+
+impl std::fmt::Debug for Signals {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Signals")
+            .field("sfd", &self.sfd)
+            .field("mask", &self.mask)
+            .field("buffered", &self.buffered)
+            .field("counts", &self.counts)
+            .field("one_per_pass", &self.one_per_pass)
+            .field("managed", &self.managed)
+            .field("blocking", &self.blocking)
+            .field("restore_on_drop", &self.restore_on_drop)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+enum MaskChange {
+    Add(Vec<Signal>),
+    Remove(Vec<Signal>),
+    Set(Vec<Signal>),
+}
+
+#[derive(Debug)]
+struct BufferedDelivery {
+    queue: VecDeque<Event>,
+    max_len: usize,
+    received: u64,
+    delivered: u64,
+    dropped: u64,
 }
 
 impl Signals {
     /// Create a new signal event source listening on the specified list of signals
-    pub fn new(signals: &[Signal]) -> io::Result<Signals> {
+    pub fn new(signals: &[Signal]) -> Result<Signals, SignalsError> {
+        Signals::create(signals, true)
+    }
+
+    /// Create a new signal event source which never changes the signal mask of the thread
+    ///
+    /// This is meant for applications where the signal mask is managed by someone else, for
+    /// example a runtime which blocks all signals centrally. The source only creates its
+    /// signalfd: neither its creation, its drop, nor the changes of its watched signals
+    /// block or unblock any signal.
+    ///
+    /// The caller is responsible for blocking the watched signals, on every thread of the
+    /// process, for as long as the source exists. A signalfd only receives blocked signals:
+    /// the signals that are not blocked are still handled by their disposition, which by
+    /// default terminates the program.
+    pub fn unmanaged(signals: &[Signal]) -> Result<Signals, SignalsError> {
+        Signals::create(signals, false)
+    }
+
+    fn create(signals: &[Signal], managed: bool) -> Result<Signals, SignalsError> {
         let mut mask = SigSet::empty();
         for &s in signals {
             mask.add(s);
         }
 
+        unignore(signals).map_err(SignalsError::Disposition)?;
         // Mask the signals for this thread
-        mask.thread_block()?;
+        let mut held = SigSet::empty();
+        if managed {
+            block_signals(&mask, &mut held).map_err(SignalsError::MaskBlock)?;
+        }
         // Create the SignalFd
-        let sfd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)?;
+        let sfd = match SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+        {
+            Ok(sfd) => sfd,
+            Err(e) => {
+                let _ = unblock_signals(&mask, &mut held);
+                return Err(SignalsError::FdCreate(e.into()));
+            }
+        };
 
         Ok(Signals {
             sfd: Generic::new(sfd, Interest::READ, Mode::Level),
             mask,
+            buffered: None,
+            pending_changes: Arc::new(Mutex::new(Vec::new())),
+            counts: HashMap::new(),
+            one_per_pass: false,
+            managed,
+            held,
+            stale_fd: None,
+            blocking: false,
+            restore_on_drop: false,
+            order: None,
+            drop_error: None,
         })
     }
 
+    /// Create a signal event source reading an existing signalfd
+    ///
+    /// This adopts a signalfd created by other code, for example with specific flags or by a
+    /// library, rather than creating a new one. `mask` must be the set of signals the
+    /// signalfd was created with, as it cannot be retrieved from the signalfd itself.
+    ///
+    /// Like an [unmanaged](Signals#method.unmanaged) source, the adopted source never changes
+    /// the signal mask of the thread: the caller is responsible for blocking the watched
+    /// signals. The changes of the watched signals, with
+    /// [`add_signals`](Signals#method.add_signals) or the [`SignalsHandle`], only apply to the
+    /// mask of the adopted signalfd. Use
+    /// [`restore_mask_on_drop`](Signals#method.restore_mask_on_drop) to have the source unblock
+    /// its signals when it is dropped.
+    ///
+    /// Fails with [`SignalsError::FdFlags`] if the flags of the signalfd cannot be retrieved.
+    /// A blocking signalfd is adopted as such, see
+    /// [`set_nonblocking`](Signals#method.set_nonblocking).
+    pub fn from_signalfd(sfd: SignalFd, mask: SigSet) -> Result<Signals, SignalsError> {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+        use std::os::unix::io::AsRawFd;
+
+        let flags = fcntl(sfd.as_raw_fd(), FcntlArg::F_GETFL)
+            .map_err(|e| SignalsError::FdFlags(e.into()))?;
+        let flags = OFlag::from_bits_truncate(flags);
+        Ok(Signals {
+            sfd: Generic::new(sfd, Interest::READ, Mode::Level),
+            mask,
+            buffered: None,
+            pending_changes: Arc::new(Mutex::new(Vec::new())),
+            counts: HashMap::new(),
+            one_per_pass: false,
+            managed: false,
+            held: SigSet::empty(),
+            stale_fd: None,
+            blocking: !flags.contains(OFlag::O_NONBLOCK),
+            restore_on_drop: false,
+            order: None,
+            drop_error: None,
+        })
+    }
+
+    /// Set whether this source unblocks its signals for the thread when dropped
+    ///
+    /// This only applies to the sources which do not manage the signal mask, created with
+    /// [`unmanaged`](Signals#method.unmanaged) or
+    /// [`from_signalfd`](Signals#method.from_signalfd), which by default leave it untouched.
+    /// The signals watched by the source when it is dropped are then unblocked for the thread
+    /// dropping it, even if other sources still watch them.
+    pub fn restore_mask_on_drop(mut self, restore: bool) -> Signals {
+        self.restore_on_drop = restore;
+        self
+    }
+
+    /// Set how the errors occuring when this source is dropped are reported
+    ///
+    /// Dropping the source unblocks its signals, which can fail. As a drop cannot return
+    /// errors, they are logged as warnings by default. With a hook, they are given to it
+    /// instead, so that a daemon can route them to its own reporting, or a test assert them.
+    /// Passing `None` restores the default logging. Use [`abandon`](Signals#method.abandon)
+    /// to get the error as a return value instead.
+    ///
+    /// The hook must be `Send`, as the source can be moved to another thread with
+    /// [`detach_from_thread`](Signals#method.detach_from_thread).
+    pub fn set_drop_error_policy(&mut self, policy: Option<DropErrorHook>) {
+        self.drop_error = policy;
+    }
+
+    /// Create a new signal event source reading a single signal per dispatching pass
+    ///
+    /// The signalfd is level-triggered, so as long as some signals are pending the event
+    /// loop is woken up again and the source reads the next one. This naturally interleaves
+    /// the processing of many signals with the other sources of the loop, at the cost of
+    /// more polling round-trips.
+    ///
+    /// This mode is not compatible with the
+    /// [buffered delivery](Signals#method.with_buffered_delivery), which always drains the
+    /// signalfd. Enabling it on this source disables the single read per pass.
+    pub fn one_per_pass(signals: &[Signal]) -> Result<Signals, SignalsError> {
+        let mut source = Signals::new(signals)?;
+        source.one_per_pass = true;
+        Ok(source)
+    }
+
+    /// Get a handle to change the watched signals from within the callback
+    ///
+    /// See [`SignalsHandle`] for details.
+    pub fn handle(&self) -> SignalsHandle {
+        SignalsHandle {
+            pending_changes: self.pending_changes.clone(),
+        }
+    }
+
+    fn apply_pending_changes(&mut self) {
+        let changes = std::mem::take(&mut *self.pending_changes.lock().unwrap());
+        for change in changes {
+            let ret = match change {
+                MaskChange::Add(signals) => self.add_signals(&signals),
+                MaskChange::Remove(signals) => self.remove_signals(&signals),
+                MaskChange::Set(signals) => self.set_signals(&signals),
+            };
+            if let Err(e) = ret {
+                log::warn!("[calloop] Failed to change the watched signals: {:?}", e);
+            }
+        }
+    }
+
+    /// Deliver the signals through a bounded internal queue
+    ///
+    /// When the signalfd becomes readable, all pending signals are read into a queue before
+    /// your callback is invoked for each of them. If more than `max_len` signals are read
+    /// during a single dispatch, the oldest ones are dropped. The number of dropped signals
+    /// can be retrieved with [`dropped_events`](Signals#method.dropped_events).
+    pub fn with_buffered_delivery(mut self, max_len: usize) -> Signals {
+        self.one_per_pass = false;
+        self.buffered = Some(BufferedDelivery {
+            queue: VecDeque::with_capacity(max_len),
+            max_len,
+            received: 0,
+            delivered: 0,
+            dropped: 0,
+        });
+        self
+    }
+
+    /// Deliver the signals read during a dispatch in the order given by a comparator
+    ///
+    /// By default the signals are delivered in the order the kernel queued them, which gives
+    /// precedence to the lowest standard signals, then to the real-time signals in the order
+    /// they were sent. With this option, all the pending signals are read before your
+    /// callback is invoked for each of them, sorted with `cmp`: for example
+    /// `|a, b| a.signal().cmp(&b.signal())` delivers them by increasing signal number. The
+    /// sort is stable, so equivalent signals keep the order they were queued in. The
+    /// comparator is a plain function, or a closure which does not capture anything.
+    ///
+    /// Reading all the signals first slightly delays the first callback under bursts. This
+    /// disables the delivery of [one signal per pass](Signals#method.one_per_pass), and
+    /// applies to the queue of the [buffered delivery](Signals#method.with_buffered_delivery)
+    /// when it is also enabled.
+    pub fn with_ordered_delivery(
+        mut self,
+        cmp: fn(&Event, &Event) -> std::cmp::Ordering,
+    ) -> Signals {
+        self.one_per_pass = false;
+        self.order = Some(cmp);
+        self
+    }
+
+    /// Record the control groups of the sender of each signal along with its event
+    ///
+    /// For each signal sent by a process, the returned source reads the `/proc/<pid>/cgroup`
+    /// file of the sender, and delivers its content along with the event, see
+    /// [`EnrichedEvent::sender_cgroup`]. This allows correlating the signals with the
+    /// containers they come from. This is a best-effort lookup, which fails if the sender
+    /// already exited, and costs a few system calls per signal, so the plain source does not
+    /// do it.
+    pub fn with_sender_enrichment(self) -> EnrichedSignals {
+        EnrichedSignals { signals: self }
+    }
+
+    /// The number of signals dropped by the buffered delivery since the creation of the source
+    ///
+    /// This is always `0` if the buffered delivery is not enabled.
+    pub fn dropped_events(&self) -> u64 {
+        self.buffered.as_ref().map(|b| b.dropped).unwrap_or(0)
+    }
+
+    /// The statistics of the queue of the buffered delivery
+    ///
+    /// The signals read from the signalfd are counted as sent. They are all zero if the
+    /// buffered delivery is not enabled.
+    pub fn stats(&self) -> BufferStats {
+        self.buffered
+            .as_ref()
+            .map(|b| BufferStats {
+                sent: b.received,
+                delivered: b.delivered,
+                dropped: b.dropped,
+                occupancy: b.queue.len() as u64,
+            })
+            .unwrap_or_default()
+    }
+
+    /// The number of times each signal has been delivered to the callback
+    ///
+    /// The counts are kept as long as the signals are watched by the source: removing a
+    /// signal from the source resets its count.
+    pub fn counts(&self) -> HashMap<Signal, u64> {
+        self.counts.clone()
+    }
+
+    /// Reset the delivery counts of all signals
+    pub fn reset_counts(&mut self) {
+        self.counts.clear();
+    }
+
+    /// Recreate the signalfd after a `fork()`
+    ///
+    /// A child process created with `fork()` inherits the signalfd of its parent and its
+    /// signal mask. The signalfd is closed on `exec()`, but a child that does not exec keeps
+    /// sharing it with its parent, and both could read the same signals. Calling this method
+    /// in the child gives the source its own signalfd, and blocks the watched signals again
+    /// for the calling thread, which is the only thread of the child.
+    ///
+    /// The event loop of the parent shares its polling system with the child as well, so the
+    /// child should not keep using it: get the source back from its [`Dispatcher`] or create
+    /// it before inserting it, call this method, and insert the source into a new event loop.
+    /// If the source is inserted in an event loop when this method is called, the new
+    /// signalfd is registered in place of the old one during the next dispatch of this loop.
+    /// Typically, this is done right after `fork()` returned in the child, like an
+    /// `atfork` child handler would.
+    ///
+    /// If the child does not want to watch any signal, use
+    /// [`abandon`](Signals#method.abandon) instead.
+    ///
+    /// [`Dispatcher`]: crate::Dispatcher
+    pub fn reinit_after_fork(&mut self) -> Result<(), SignalsError> {
+        self.block(self.mask)?;
+        let mut flags = SfdFlags::SFD_CLOEXEC;
+        flags.set(SfdFlags::SFD_NONBLOCK, !self.blocking);
+        let sfd = SignalFd::with_flags(&self.mask, flags)
+            .map_err(|e| SignalsError::FdCreate(e.into()))?;
+        // closing the inherited fd does not affect the parent
+        self.replace_fd(sfd);
+        // these signals were received by the parent
+        if let Some(buffered) = &mut self.buffered {
+            buffered.queue.clear();
+        }
+        Ok(())
+    }
+
+    /// Stop watching the signals, closing the signalfd and unblocking them
+    ///
+    /// This is equivalent to dropping the source, but reports the failure to unblock the
+    /// signals instead of logging it. It is notably meant for a child process created with
+    /// `fork()` that does not want to handle the signals of its parent, see
+    /// [`reinit_after_fork`](Signals#method.reinit_after_fork).
+    pub fn abandon(mut self) -> Result<(), SignalsError> {
+        // the drop of the source now has no signal to unblock, and closes the signalfd
+        self.release()
+    }
+
+    /// Prepare this source to be moved to another thread
+    ///
+    /// The signals of a source are blocked for the thread which created it, and the other
+    /// threads may receive them with their default disposition. Moving the source to another
+    /// thread thus requires unblocking its signals on the current thread, and blocking them on
+    /// the new one. This method does the first part: it unblocks the signals, unless another
+    /// source of the current thread still watches them, and returns a [`DetachedSignals`]
+    /// which can be sent to the new thread. There,
+    /// [`attach_to_current_thread`](DetachedSignals#method.attach_to_current_thread) blocks
+    /// the signals again and gives the source back.
+    ///
+    /// No watched signal should be sent to the process during the handoff, while the signals
+    /// are blocked by neither thread: it would be handled by its disposition, which kills the
+    /// process for most signals. The masks of an [unmanaged](Signals#method.unmanaged) source
+    /// are not changed, and remain the responsibility of the caller. The source must not be
+    /// inserted in an event loop.
+    pub fn detach_from_thread(mut self) -> DetachedSignals {
+        let mask = self.mask;
+        if let Err(e) = self.unblock(mask) {
+            log::warn!("[calloop] Failed to unmask signals: {:?}", e);
+        }
+        DetachedSignals {
+            signals: Box::new(self),
+        }
+    }
+
+    // Stop watching the signals, unblocking them as configured
+    fn release(&mut self) -> Result<(), SignalsError> {
+        let mask = std::mem::replace(&mut self.mask, SigSet::empty());
+        if !self.managed && self.restore_on_drop {
+            mask.thread_unblock()
+                .map_err(|e| SignalsError::MaskUnblock(e.into()))?;
+        }
+        self.unblock(mask)
+    }
+
+    // Replace the signalfd, requesting the loop to reregister the source
+    fn replace_fd(&mut self, sfd: SignalFd) {
+        let old = self.sfd.replace_file(sfd);
+        if self.sfd.is_registered() {
+            // the old fd is still registered, it is closed once the source is reregistered
+            if self.stale_fd.is_none() {
+                self.stale_fd = Some(old);
+            }
+        }
+    }
+
+    fn block(&mut self, mask: SigSet) -> Result<(), SignalsError> {
+        if self.managed {
+            block_signals(&mask, &mut self.held).map_err(SignalsError::MaskBlock)?;
+        }
+        Ok(())
+    }
+
+    fn unblock(&mut self, mask: SigSet) -> Result<(), SignalsError> {
+        if self.managed {
+            unblock_signals(&mask, &mut self.held).map_err(SignalsError::MaskUnblock)?;
+        }
+        Ok(())
+    }
+
     /// Add a list of signals to the signals source
     ///
+    /// Returns the resulting set of signals blocked by the thread, see
+    /// [`blocked_signals`](Signals#method.blocked_signals).
+    ///
     /// If this function returns an error, the signal mask of the thread may
     /// have still been changed.
-    pub fn add_signals(&mut self, signals: &[Signal]) -> io::Result<()> {
+    pub fn add_signals(&mut self, signals: &[Signal]) -> Result<SigSet, SignalsError> {
+        unignore(signals).map_err(SignalsError::Disposition)?;
         for &s in signals {
             self.mask.add(s);
         }
-        self.mask.thread_block()?;
-        self.sfd.file.set_mask(&self.mask)?;
-        Ok(())
+        self.block(self.mask)?;
+        self.sfd
+            .file
+            .set_mask(&self.mask)
+            .map_err(|e| SignalsError::SetMask(e.into()))?;
+        self.blocked_signals().map_err(SignalsError::GetMask)
     }
 
     /// Remove a list of signals from the signals source
     ///
+    /// Returns the resulting set of signals blocked by the thread, see
+    /// [`blocked_signals`](Signals#method.blocked_signals).
+    ///
+    /// The removed signals remain blocked if another source of the thread still watches them.
+    ///
     /// If this function returns an error, the signal mask of the thread may
     /// have still been changed.
-    pub fn remove_signals(&mut self, signals: &[Signal]) -> io::Result<()> {
+    pub fn remove_signals(&mut self, signals: &[Signal]) -> Result<SigSet, SignalsError> {
         let mut removed = SigSet::empty();
         for &s in signals {
             self.mask.remove(s);
+            self.counts.remove(&s);
             removed.add(s);
         }
-        removed.thread_unblock()?;
-        self.sfd.file.set_mask(&self.mask)?;
-        Ok(())
+        self.unblock(removed)?;
+        self.sfd
+            .file
+            .set_mask(&self.mask)
+            .map_err(|e| SignalsError::SetMask(e.into()))?;
+        self.blocked_signals().map_err(SignalsError::GetMask)
     }
 
     /// Replace the list of signals of the source
     ///
+    /// Returns the resulting set of signals blocked by the thread, see
+    /// [`blocked_signals`](Signals#method.blocked_signals).
+    ///
     /// If this function returns an error, the signal mask of the thread may
     /// have still been changed.
-    pub fn set_signals(&mut self, signals: &[Signal]) -> io::Result<()> {
+    pub fn set_signals(&mut self, signals: &[Signal]) -> Result<SigSet, SignalsError> {
         let mut new_mask = SigSet::empty();
         for &s in signals {
             new_mask.add(s);
         }
 
-        self.mask.thread_unblock()?;
-        new_mask.thread_block()?;
-        self.sfd.file.set_mask(&new_mask)?;
+        unignore(signals).map_err(SignalsError::Disposition)?;
+        // the signals watched before and after are not unblocked in between
+        self.block(new_mask)?;
+        let mut removed = self.mask;
+        for s in Signal::iterator() {
+            if new_mask.contains(s) {
+                removed.remove(s);
+            }
+        }
+        self.unblock(removed)?;
+        self.sfd
+            .file
+            .set_mask(&new_mask)
+            .map_err(|e| SignalsError::SetMask(e.into()))?;
         self.mask = new_mask;
+        self.counts.retain(|&s, _| new_mask.contains(s));
+
+        self.blocked_signals().map_err(SignalsError::GetMask)
+    }
+
+    /// The set of signals watched by this source
+    pub fn signals(&self) -> SigSet {
+        self.mask
+    }
+
+    /// Export the set of signals watched by this source
+    pub fn export_config(&self) -> SignalsConfig {
+        SignalsConfig {
+            signals: Signal::iterator()
+                .filter(|&s| self.mask.contains(s))
+                .map(|s| s as i32)
+                .collect(),
+        }
+    }
+
+    /// Watch the set of signals of a configuration, replacing the current ones
+    ///
+    /// This is [`set_signals`](Signals#method.set_signals) with the decoded signals. If some
+    /// numbers of the configuration are not valid signals, a
+    /// [`SignalsError::InvalidConfig`] error listing them is returned and the source is left
+    /// unchanged.
+    pub fn apply_config(&mut self, config: &SignalsConfig) -> Result<SigSet, SignalsError> {
+        let mut signals = Vec::with_capacity(config.signals.len());
+        let mut invalid = Vec::new();
+        for &number in &config.signals {
+            match Signal::try_from(number) {
+                Ok(s) => signals.push(s),
+                Err(_) => invalid.push(number.to_string()),
+            }
+        }
+        if !invalid.is_empty() {
+            return Err(SignalsError::InvalidConfig(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid signal numbers: {}", invalid.join(", ")),
+            )));
+        }
+        self.set_signals(&signals)
+    }
+
+    /// Block until one of the watched signals is received
+    ///
+    /// This waits on the signalfd outside of any event loop, and returns the first signal
+    /// read from it. It is meant for simple sequencing, like waiting for `SIGCONT` before
+    /// starting the actual work of the program.
+    ///
+    /// It must not be used while the source is inserted in an event loop which is being
+    /// dispatched from another thread, as both would compete for the same signals. The
+    /// signals received this way are not taken into account by the
+    /// [`counts`](Signals#method.counts). Fails with [`SignalsError::Read`] if the signalfd
+    /// cannot be polled or read.
+    pub fn wait(&mut self) -> Result<Event, SignalsError> {
+        use nix::poll::{poll, PollFd, PollFlags};
+        use std::os::unix::io::AsRawFd;
+
+        loop {
+            let mut fds = [PollFd::new(self.sfd.file.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, -1) {
+                Ok(_) | Err(nix::errno::Errno::EINTR) => {}
+                Err(e) => return Err(SignalsError::Read(e.into())),
+            }
+            // another reader may have taken the signal in the meantime
+            let read = self.sfd.file.read_signal();
+            if let Some(info) = read.map_err(|e| SignalsError::Read(e.into()))? {
+                return Ok(Event::new(info));
+            }
+        }
+    }
 
+    /// Set whether the signalfd is in non-blocking mode
+    ///
+    /// The signalfd is non-blocking by default, which the event loop requires: the source
+    /// reads it until no signal is left, and a blocking read would stall the whole loop.
+    /// Making it blocking is thus only allowed while the source is not registered in an event
+    /// loop, that is before it is inserted, or while it is disabled or after it was removed,
+    /// and returns a [`SignalsError::FdFlags`] error otherwise. A blocking source cannot be
+    /// registered either, so it must be made non-blocking again before it is inserted or
+    /// enabled.
+    ///
+    /// A blocking signalfd can be read by [`wait`](Signals#method.wait) or by another program
+    /// sharing it, to drive the source outside of an event loop during some phase of the
+    /// program.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), SignalsError> {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+        use std::os::unix::io::AsRawFd;
+
+        if !nonblocking && self.sfd.is_registered() {
+            return Err(SignalsError::FdFlags(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot make the signalfd of a registered source blocking",
+            )));
+        }
+        let fd = self.sfd.file.as_raw_fd();
+        let flags = fcntl(fd, FcntlArg::F_GETFL).map_err(|e| SignalsError::FdFlags(e.into()))?;
+        let mut flags = OFlag::from_bits_truncate(flags);
+        flags.set(OFlag::O_NONBLOCK, nonblocking);
+        fcntl(fd, FcntlArg::F_SETFL(flags)).map_err(|e| SignalsError::FdFlags(e.into()))?;
+        self.blocking = !nonblocking;
+        Ok(())
+    }
+
+    /// Whether the signalfd is in non-blocking mode
+    ///
+    /// See [`set_nonblocking`](Signals#method.set_nonblocking).
+    pub fn is_nonblocking(&self) -> bool {
+        !self.blocking
+    }
+
+    /// The set of signals currently blocked by the thread
+    ///
+    /// This includes the signals watched by this source, but also the signals that were
+    /// blocked independently of it, for example before the source was created or by another
+    /// source.
+    pub fn blocked_signals(&self) -> io::Result<SigSet> {
+        Ok(SigSet::thread_get_mask()?)
+    }
+
+    /// Ignore a list of signals
+    ///
+    /// This sets the disposition of these signals to `SIG_IGN` for the whole process, so
+    /// that they are discarded rather than running their default action. This is typically
+    /// used for `SIGPIPE`, so that writing to a closed socket returns `EPIPE` instead of
+    /// killing the process.
+    ///
+    /// The ignored signals are not delivered through the event loop. Watching one of them
+    /// afterwards with a [`Signals`] source resets its disposition to the default one. Fails
+    /// with [`SignalsError::Disposition`] if the disposition of a signal cannot be changed.
+    pub fn ignore(signals: &[Signal]) -> Result<(), SignalsError> {
+        let action = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        for &s in signals {
+            // Safety: ignoring a signal does not install any handler
+            unsafe { sigaction(s, &action) }.map_err(|e| SignalsError::Disposition(e.into()))?;
+        }
         Ok(())
     }
+
+    /// Set the disposition of the fatal signals this source does not watch
+    ///
+    /// This is a safety net during development: a stray signal, like a `SIGUSR1` sent to the
+    /// wrong process, is ignored with [`SigHandler::SigIgn`], or reported with the
+    /// [`report_unwatched_signal`] handler, rather than silently killing the program. Unlike
+    /// watching a signal, this does not deliver anything to the event loop. Returns the set of
+    /// signals whose disposition was changed, or a [`SignalsError::Disposition`] error if one
+    /// of them could not be changed.
+    ///
+    /// This changes the disposition for the whole process, and only applies to the signals
+    /// which are not watched by this source when it is called, and whose default action is to
+    /// terminate the process: `SIGHUP`, `SIGINT`, `SIGQUIT`, `SIGPIPE`, `SIGALRM`, `SIGTERM`,
+    /// `SIGUSR1`, `SIGUSR2`, `SIGSTKFLT`, `SIGXCPU`, `SIGXFSZ`, `SIGVTALRM`, `SIGPROF`, `SIGIO`
+    /// and `SIGPWR`. The signals generated by faults of the program, like `SIGSEGV`, as well as
+    /// `SIGABRT`, keep their disposition, as ignoring them would hide real crashes. `SIGKILL`
+    /// and `SIGSTOP` cannot be caught nor ignored at all. Pass [`SigHandler::SigDfl`] to
+    /// restore the default dispositions.
+    ///
+    /// A [`SigHandler::Handler`] runs asynchronously, interrupting any code of the thread
+    /// receiving the signal: it must only call async-signal-safe functions, as listed in
+    /// `signal-safety(7)`. In particular it must not allocate, lock a mutex, or use `println!`
+    /// or the `log` macros, which could deadlock or corrupt the state of the interrupted code.
+    /// The handler is installed with `SA_RESTART`, so that the interrupted system calls are
+    /// restarted rather than failing with `EINTR`.
+    pub fn set_unwatched_default(&self, handler: SigHandler) -> Result<SigSet, SignalsError> {
+        let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+        let mut changed = SigSet::empty();
+        for &s in FATAL_SIGNALS {
+            if !self.mask.contains(s) {
+                // Safety: the caller is responsible for the safety of its handler
+                unsafe { sigaction(s, &action) }
+                    .map_err(|e| SignalsError::Disposition(e.into()))?;
+                changed.add(s);
+            }
+        }
+        Ok(changed)
+    }
+}
+
+// The signals terminating the process by default which are not generated by faults
+const FATAL_SIGNALS: &[Signal] = &[
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGPIPE,
+    Signal::SIGALRM,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGSTKFLT,
+    Signal::SIGXCPU,
+    Signal::SIGXFSZ,
+    Signal::SIGVTALRM,
+    Signal::SIGPROF,
+    Signal::SIGIO,
+    Signal::SIGPWR,
+];
+
+/// A signal handler reporting the signals it receives on the standard error
+///
+/// It writes `calloop: received unwatched signal <number>` to the standard error, and is
+/// meant to be installed with [`Signals::set_unwatched_default`](Signals#method.set_unwatched_default)
+/// as `SigHandler::Handler(report_unwatched_signal)`. It only uses `write`, which is
+/// async-signal-safe.
+pub extern "C" fn report_unwatched_signal(signal: c_int) {
+    const PREFIX: &[u8] = b"calloop: received unwatched signal ";
+    let mut message = [0u8; 48];
+    message[..PREFIX.len()].copy_from_slice(PREFIX);
+    let mut len = PREFIX.len();
+    // format the number without allocating
+    let mut digits = [0u8; 10];
+    let mut n = signal.max(0) as u32;
+    let mut count = 0;
+    loop {
+        digits[count] = b'0' + (n % 10) as u8;
+        count += 1;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    for &digit in digits[..count].iter().rev() {
+        message[len] = digit;
+        len += 1;
+    }
+    message[len] = b'\n';
+    len += 1;
+    // Safety: write is async-signal-safe, and the errno of the interrupted code is preserved
+    unsafe {
+        let errno = *nix::libc::__errno_location();
+        nix::libc::write(2, message.as_ptr() as *const _, len);
+        *nix::libc::__errno_location() = errno;
+    }
+}
+
+/// A handle to change the signals watched by a [`Signals`] source
+///
+/// The [`Signals`] source is owned by the event loop while it is inserted, and its
+/// callback does not have access to it. This handle can be cloned and captured into
+/// the callback to change the watched signals from there.
+///
+/// The changes are not applied right away, but once the source has finished
+/// processing its pending signals, and in any case before the event loop waits for
+/// new events. Errors occuring while applying them are logged.
+#[derive(Clone, Debug)]
+pub struct SignalsHandle {
+    pending_changes: Arc<Mutex<Vec<MaskChange>>>,
+}
+
+impl SignalsHandle {
+    /// Add a list of signals to the signals source
+    pub fn add_signals(&self, signals: &[Signal]) {
+        self.push(MaskChange::Add(signals.to_vec()));
+    }
+
+    /// Remove a list of signals from the signals source
+    pub fn remove_signals(&self, signals: &[Signal]) {
+        self.push(MaskChange::Remove(signals.to_vec()));
+    }
+
+    /// Replace the list of signals of the source
+    pub fn set_signals(&self, signals: &[Signal]) {
+        self.push(MaskChange::Set(signals.to_vec()));
+    }
+
+    fn push(&self, change: MaskChange) {
+        self.pending_changes.lock().unwrap().push(change);
+    }
+}
+
+/// A [`Signals`] source moving between threads
+///
+/// It is created by [`Signals::detach_from_thread`], and its signals are not blocked by any
+/// thread. It can be sent to another thread, and turned back into a source there with
+/// [`attach_to_current_thread`](DetachedSignals#method.attach_to_current_thread).
+#[derive(Debug)]
+pub struct DetachedSignals {
+    signals: Box<Signals>,
+}
+
+impl DetachedSignals {
+    /// Block the signals on the current thread, getting the source back
+    ///
+    /// If blocking the signals fails, the detached source is given back along with the error,
+    /// so that the handoff can be retried or the source dropped.
+    pub fn attach_to_current_thread(mut self) -> Result<Signals, (DetachedSignals, SignalsError)> {
+        let mask = self.signals.mask;
+        match self.signals.block(mask) {
+            Ok(()) => Ok(*self.signals),
+            Err(e) => Err((self, e)),
+        }
+    }
+
+    /// The signals watched by the detached source
+    pub fn signals(&self) -> SigSet {
+        self.signals.mask
+    }
 }
 
 impl Drop for Signals {
     fn drop(&mut self) {
-        // we cannot handle error here
-        if let Err(e) = self.mask.thread_unblock() {
-            log::warn!("[calloop] Failed to unmask signals: {:?}", e);
+        // we cannot return the error here
+        if let Err(e) = self.release() {
+            match &self.drop_error {
+                Some(hook) => hook(e),
+                None => log::warn!("[calloop] Failed to unmask signals: {:?}", e),
+            }
         }
     }
 }
@@ -136,10 +1284,66 @@ impl EventSource for Signals {
     where
         C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
     {
-        self.sfd.process_events(readiness, token, |_, sfd| {
+        let buffered = &mut self.buffered;
+        let order = self.order;
+        let one_per_pass = self.one_per_pass;
+        let counts = &mut self.counts;
+        let mut callback = |event: Event, meta: &mut ()| {
+            *counts.entry(event.signal()).or_insert(0) += 1;
+            callback(event, meta)
+        };
+        let ret = self.sfd.process_events(readiness, token, |_, sfd| {
+            if let Some(buffered) = buffered {
+                let mut dropped = 0;
+                loop {
+                    match sfd.read_signal() {
+                        Ok(Some(info)) => {
+                            if buffered.queue.len() >= buffered.max_len {
+                                buffered.queue.pop_front();
+                                dropped += 1;
+                            }
+                            buffered.queue.push_back(Event::new(info));
+                            buffered.received += 1;
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            log::warn!("[callop] Error reading from signalfd: {}", e);
+                            return Err(e.into());
+                        }
+                    }
+                }
+                if dropped > 0 {
+                    log::warn!("[calloop] Signal queue full, dropped {} signals", dropped);
+                    buffered.dropped += dropped;
+                }
+                if let Some(cmp) = order {
+                    let mut events: Vec<_> = buffered.queue.drain(..).collect();
+                    events.sort_by(cmp);
+                    buffered.queue.extend(events);
+                }
+                while let Some(event) = buffered.queue.pop_front() {
+                    buffered.delivered += 1;
+                    callback(event, &mut ());
+                }
+                return Ok(PostAction::Continue);
+            }
+            if one_per_pass {
+                // on a spurious wakeup, there may be no signal to read
+                match sfd.read_signal() {
+                    Ok(Some(info)) => callback(Event::new(info), &mut ()),
+                    Ok(None) => {}
+                    Err(e) => {
+                        log::warn!("[callop] Error reading from signalfd: {}", e);
+                        return Err(e.into());
+                    }
+                }
+                return Ok(PostAction::Continue);
+            }
+            let mut sorted = Vec::new();
             loop {
                 match sfd.read_signal() {
-                    Ok(Some(info)) => callback(Event { info }, &mut ()),
+                    Ok(Some(info)) if order.is_some() => sorted.push(Event::new(info)),
+                    Ok(Some(info)) => callback(Event::new(info), &mut ()),
                     Ok(None) => break,
                     Err(e) => {
                         log::warn!("[callop] Error reading from signalfd: {}", e);
@@ -147,8 +1351,17 @@ impl EventSource for Signals {
                     }
                 }
             }
+            if let Some(cmp) = order {
+                sorted.sort_by(cmp);
+                for event in sorted {
+                    callback(event, &mut ());
+                }
+            }
             Ok(PostAction::Continue)
-        })
+        });
+        // apply the changes requested from the callback now that the signalfd is drained
+        self.apply_pending_changes();
+        ret
     }
 
     fn register(
@@ -156,7 +1369,15 @@ impl EventSource for Signals {
         poll: &mut Poll,
         token_factory: &mut TokenFactory,
     ) -> std::io::Result<()> {
-        self.sfd.register(poll, token_factory)
+        if self.blocking {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot register a Signals source with a blocking signalfd",
+            ));
+        }
+        self.sfd.register(poll, token_factory)?;
+        self.stale_fd = None;
+        Ok(())
     }
 
     fn reregister(
@@ -164,10 +1385,271 @@ impl EventSource for Signals {
         poll: &mut Poll,
         token_factory: &mut TokenFactory,
     ) -> std::io::Result<()> {
-        self.sfd.reregister(poll, token_factory)
+        self.sfd.reregister(poll, token_factory)?;
+        self.stale_fd = None;
+        Ok(())
     }
 
     fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
-        self.sfd.unregister(poll)
+        self.sfd.unregister(poll)?;
+        self.stale_fd = None;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        // the signalfd was replaced while registered, swap the registrations
+        if self.stale_fd.is_some() {
+            Ok(PostAction::Reregister)
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn on_dispatch_start<C>(&mut self, _callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        // apply the changes requested since the last dispatch, before the loop polls
+        self.apply_pending_changes();
+    }
+}
+
+/// A [`Signals`] source delivering the control groups of the sender along with each signal
+///
+/// It is created by [`Signals::with_sender_enrichment`](Signals#method.with_sender_enrichment),
+/// and otherwise behaves like the wrapped source.
+#[derive(Debug)]
+pub struct EnrichedSignals {
+    signals: Signals,
+}
+
+impl EnrichedSignals {
+    /// Access the wrapped source
+    pub fn source(&self) -> &Signals {
+        &self.signals
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut Signals {
+        &mut self.signals
+    }
+
+    /// Unwrap the source, which then stops looking up the senders
+    pub fn into_source(self) -> Signals {
+        self.signals
+    }
+
+    /// Block until one of the watched signals is received
+    ///
+    /// See [`Signals::wait`](Signals#method.wait).
+    pub fn wait(&mut self) -> Result<EnrichedEvent, SignalsError> {
+        self.signals.wait().map(EnrichedEvent::new)
+    }
+}
+
+impl EventSource for EnrichedSignals {
+    type Event = EnrichedEvent;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> std::io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.signals
+            .process_events(readiness, token, |event, meta| {
+                callback(EnrichedEvent::new(event), meta)
+            })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.signals.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.signals.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+        self.signals.unregister(poll)
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.signals.flush()
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.signals
+            .on_dispatch_start(|event, meta| callback(EnrichedEvent::new(event), meta))
+    }
+}
+
+/// A POSIX per-process timer, delivering its expirations as a signal
+///
+/// The timer is created with `timer_create`, and sends the given signal each time it
+/// expires, with the given value. Watch this signal with a [`Signals`] source, and tell which
+/// timer expired using [`Event::value_int`](Event#method.value_int):
+///
+/// ```no_run
+/// # extern crate calloop;
+/// use std::time::Duration;
+///
+/// use calloop::signals::{PosixTimer, Signal, Signals};
+///
+/// # fn main() {
+/// # let mut event_loop = calloop::EventLoop::<()>::try_new()
+/// #                .expect("Failed to initialize the event loop!");
+/// # let handle = event_loop.handle();
+/// const CPU_TIMER: i32 = 1;
+///
+/// let signals = Signals::new(&[Signal::SIGALRM]).unwrap();
+/// let timer = PosixTimer::with_clock(
+///     nix::time::ClockId::CLOCK_PROCESS_CPUTIME_ID,
+///     Signal::SIGALRM,
+///     CPU_TIMER,
+/// )
+/// .unwrap();
+/// timer.set(Duration::from_secs(1), Some(Duration::from_secs(1))).unwrap();
+///
+/// handle
+///     .insert_source(signals, |event, &mut (), _| {
+///         if event.value_int() == CPU_TIMER {
+///             println!("One more second of CPU time consumed");
+///         }
+///     })
+///     .unwrap();
+/// # }
+/// ```
+///
+/// As a standard signal is only pending once, the expirations of several timers sharing a
+/// signal which occur while the source is busy are coalesced into one event, which only
+/// carries the value of one of them. Use different signals for timers which can expire at the
+/// same time, and [`overrun`](PosixTimer#method.overrun) to know how many expirations of a
+/// timer were missed.
+///
+/// The timer is deleted when dropped.
+#[derive(Debug)]
+pub struct PosixTimer {
+    id: nix::libc::timer_t,
+}
+
+// The id of a timer is valid for the whole process
+unsafe impl Send for PosixTimer {}
+
+impl PosixTimer {
+    /// Create a new timer on the monotonic clock
+    ///
+    /// The timer is created disarmed, see [`set`](PosixTimer#method.set).
+    pub fn new(signal: Signal, value: i32) -> io::Result<PosixTimer> {
+        PosixTimer::with_clock(nix::time::ClockId::CLOCK_MONOTONIC, signal, value)
+    }
+
+    /// Create a new timer on the given clock
+    pub fn with_clock(
+        clock: nix::time::ClockId,
+        signal: Signal,
+        value: i32,
+    ) -> io::Result<PosixTimer> {
+        let mut id = std::mem::MaybeUninit::<nix::libc::timer_t>::uninit();
+        unsafe {
+            let mut event: nix::libc::sigevent = std::mem::zeroed();
+            event.sigev_notify = nix::libc::SIGEV_SIGNAL;
+            event.sigev_signo = signal as c_int;
+            // sival_int is the integer member of the sigval union
+            std::ptr::write(
+                &mut event.sigev_value as *mut nix::libc::sigval as *mut c_int,
+                value,
+            );
+            nix::errno::Errno::result(nix::libc::timer_create(
+                clock.as_raw(),
+                &mut event,
+                id.as_mut_ptr(),
+            ))?;
+            Ok(PosixTimer {
+                id: id.assume_init(),
+            })
+        }
+    }
+
+    /// Arm the timer to first expire after `delay`, and then every `interval` if given
+    ///
+    /// This replaces the previous setting of the timer. A zero `delay` disarms it.
+    pub fn set(&self, delay: Duration, interval: Option<Duration>) -> io::Result<()> {
+        let timespec = |d: Duration| nix::libc::timespec {
+            tv_sec: d.as_secs() as nix::libc::time_t,
+            tv_nsec: d.subsec_nanos() as _,
+        };
+        let spec = nix::libc::itimerspec {
+            it_interval: timespec(interval.unwrap_or_default()),
+            it_value: timespec(delay),
+        };
+        nix::errno::Errno::result(unsafe {
+            nix::libc::timer_settime(self.id, 0, &spec, std::ptr::null_mut())
+        })?;
+        Ok(())
+    }
+
+    /// Disarm the timer
+    pub fn disarm(&self) -> io::Result<()> {
+        self.set(Duration::from_secs(0), None)
+    }
+
+    /// The number of expirations missed when the last signal of this timer was delivered
+    pub fn overrun(&self) -> io::Result<u32> {
+        let overrun = nix::errno::Errno::result(unsafe { nix::libc::timer_getoverrun(self.id) })?;
+        Ok(overrun as u32)
+    }
+}
+
+impl Drop for PosixTimer {
+    fn drop(&mut self) {
+        if unsafe { nix::libc::timer_delete(self.id) } != 0 {
+            log::warn!(
+                "[calloop] Failed to delete POSIX timer: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::sources::signal_mask::FAIL_UNBLOCK;
+
+    #[test]
+    fn drop_error_hook() {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let reported = errors.clone();
+        let mut signals = Signals::new(&[Signal::SIGUSR2]).unwrap();
+        signals.set_drop_error_policy(Some(Box::new(move |e| reported.lock().unwrap().push(e))));
+        assert!(format!("{:?}", signals).starts_with("Signals {"));
+
+        FAIL_UNBLOCK.with(|fail| fail.set(true));
+        drop(signals);
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        match &errors[0] {
+            SignalsError::MaskUnblock(e) => assert_eq!(e.raw_os_error(), Some(nix::libc::EINVAL)),
+            e => panic!("Unexpected error: {:?}", e),
+        }
     }
 }
@@ -0,0 +1,230 @@
+//! An adapter handling the readability and the writability of a file descriptor separately
+//!
+//! A socket registered for both reading and writing is usually handled by a single callback
+//! branching on the [`Readiness`](crate::Readiness) it is given. The [`SplitRw`] adapter,
+//! created with [`split_rw()`](split_rw), wraps such a [`Generic`] source with two handlers
+//! instead: the first one is invoked when the file descriptor is readable, the second one
+//! when it is writable, keeping the reading and writing state machines apart.
+//!
+//! When both readiness bits are set in the same pass, the read handler is invoked first, and
+//! then the write handler. An error readiness is given to the read handler, whose read then
+//! reports the error. If the read handler fails, disables or removes the source, the write
+//! handler is not invoked in this pass. Otherwise the [`PostAction`]s of both handlers are
+//! combined like the event loop combines the actions requested by a source during a pass: the
+//! strongest one wins, a removal over a disabling, over a re-registration or a change of
+//! interest, the one of the read handler winning between the last two.
+//!
+//! The handlers are given the wrapped object. After each of them, the callback of the source
+//! is invoked with the [`Side`] which was handled, so that it can update the shared data.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::os::unix::net::UnixStream;
+//!
+//! use calloop::{generic::Generic, split::split_rw, Interest, Mode, PostAction};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! # let (socket, _) = UnixStream::pair().unwrap();
+//! let source = split_rw(
+//!     Generic::new(socket, Interest::BOTH, Mode::Level),
+//!     |socket| {
+//!         // ... read the incoming data
+//!         Ok(PostAction::Continue)
+//!     },
+//!     |socket| {
+//!         // ... write the outgoing data
+//!         Ok(PostAction::Continue)
+//!     },
+//! );
+//!
+//! handle
+//!     .insert_source(source, |side, &mut (), _| {
+//!         println!("Handled the {:?} side", side);
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use super::generic::Generic;
+use crate::loop_logic::action_rank;
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// The side of a file descriptor which was handled by a [`SplitRw`] source
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Side {
+    /// The read handler was invoked
+    Read,
+    /// The write handler was invoked
+    Write,
+}
+
+/// An event source handling the readability and the writability of its file descriptor with
+/// two handlers
+///
+/// See the [module documentation](self) for details.
+pub struct SplitRw<F: AsRawFd, R, W> {
+    source: Generic<F>,
+    on_read: R,
+    on_write: W,
+}
+
+impl<F: AsRawFd + std::fmt::Debug, R, W> std::fmt::Debug for SplitRw<F, R, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SplitRw")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+/// Handle the readability and the writability of a source with separate handlers
+///
+/// The source must be registered with the interests the handlers need, usually
+/// [`Interest::BOTH`](crate::Interest::BOTH).
+pub fn split_rw<F, R, W>(source: Generic<F>, on_read: R, on_write: W) -> SplitRw<F, R, W>
+where
+    F: AsRawFd,
+    R: FnMut(&mut F) -> io::Result<PostAction>,
+    W: FnMut(&mut F) -> io::Result<PostAction>,
+{
+    SplitRw {
+        source,
+        on_read,
+        on_write,
+    }
+}
+
+impl<F: AsRawFd, R, W> SplitRw<F, R, W> {
+    /// Access the wrapped source
+    pub fn source(&self) -> &Generic<F> {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut Generic<F> {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> Generic<F> {
+        self.source
+    }
+}
+
+// The strongest of two actions, the first one winning a tie
+fn combine(first: PostAction, second: PostAction) -> PostAction {
+    if action_rank(&second) > action_rank(&first) {
+        second
+    } else {
+        first
+    }
+}
+
+impl<F, R, W> EventSource for SplitRw<F, R, W>
+where
+    F: AsRawFd,
+    R: FnMut(&mut F) -> io::Result<PostAction>,
+    W: FnMut(&mut F) -> io::Result<PostAction>,
+{
+    type Event = Side;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (on_read, on_write) = (&mut self.on_read, &mut self.on_write);
+        self.source
+            .process_events(readiness, token, |readiness, file| {
+                let mut action = PostAction::Continue;
+                if readiness.readable || readiness.error {
+                    action = on_read(file)?;
+                    callback(Side::Read, &mut ());
+                    if let PostAction::Disable | PostAction::Remove = action {
+                        return Ok(action);
+                    }
+                }
+                if readiness.writable {
+                    action = combine(action, on_write(file)?);
+                    callback(Side::Write, &mut ());
+                }
+                Ok(action)
+            })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{Interest, Mode};
+
+    #[test]
+    fn read_then_write() {
+        let mut event_loop = crate::EventLoop::<Vec<Side>>::try_new().unwrap();
+        let (mut peer, socket) = UnixStream::pair().unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let source = split_rw(
+            Generic::new(socket, Interest::BOTH, Mode::Level),
+            |socket| {
+                let mut buffer = [0u8; 4];
+                socket.read_exact(&mut buffer)?;
+                Ok(PostAction::Continue)
+            },
+            |socket| {
+                socket.write_all(b"pong")?;
+                // nothing left to write
+                Ok(PostAction::SetInterest(Interest::READ))
+            },
+        );
+        event_loop
+            .handle()
+            .insert_source(source, |side, &mut (), sides| sides.push(side))
+            .unwrap();
+
+        // both sides are ready, the read is handled first
+        peer.write_all(b"ping").unwrap();
+        let mut sides = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut sides)
+            .unwrap();
+        assert_eq!(sides, &[Side::Read, Side::Write]);
+        let mut buffer = [0u8; 4];
+        peer.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"pong");
+
+        // the write handler changed the interest
+        peer.write_all(b"ping").unwrap();
+        sides.clear();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut sides)
+            .unwrap();
+        assert_eq!(sides, &[Side::Read]);
+    }
+}
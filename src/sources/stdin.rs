@@ -0,0 +1,280 @@
+//! Event source for reading the standard input
+//!
+//! The [`StdinSource`] makes the standard input non-blocking and inserts it into the event
+//! loop. It can work in two modes:
+//!
+//! - [`InputMode::Cooked`]: the input is buffered until a newline is received, and complete
+//!   lines are delivered as [`Event::Line`]
+//! - [`InputMode::Raw`]: the terminal is switched to non-canonical mode without echo, and
+//!   bytes are delivered as [`Event::Input`] as soon as they are typed
+//!
+//! When the end of the input is reached (for example when Ctrl-D is pressed in a terminal in
+//! cooked mode), an [`Event::Eof`] is generated and the source removes itself from the event
+//! loop. The original terminal settings and file descriptor flags are restored when the
+//! source is dropped.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::stdin::{Event, InputMode, StdinSource};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let source = StdinSource::new(InputMode::Cooked).expect("Failed to setup stdin");
+//!
+//! handle
+//!     .insert_source(source, |event, &mut (), _| match event {
+//!         Event::Line(line) => println!("Read: {}", line),
+//!         Event::Input(_) => unreachable!(),
+//!         Event::Eof => println!("Done"),
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    sys::termios::{self, LocalFlags, SetArg, SpecialCharacterIndices, Termios},
+    unistd::read,
+};
+
+use super::generic::Generic;
+use super::lines::split_lines;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// How the standard input is read
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InputMode {
+    /// Deliver complete lines
+    Cooked,
+    /// Deliver bytes as soon as they are available
+    ///
+    /// If the standard input is a terminal, its canonical mode and echo are disabled,
+    /// so that keypresses are received immediately. The keys generating signals, like
+    /// Ctrl-C, keep working, but Ctrl-D is delivered as a byte instead of closing the input.
+    Raw,
+}
+
+/// An event generated by the [`StdinSource`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A line of input, without its trailing newline, in cooked mode
+    ///
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`. If the input ends without a final
+    /// newline, the last incomplete line is delivered before [`Event::Eof`].
+    Line(String),
+    /// Some bytes of input, in raw mode
+    Input(Vec<u8>),
+    /// The end of the input was reached
+    ///
+    /// This is the last event of the source, which then removes itself from the event loop.
+    Eof,
+}
+
+/// An event source reading the standard input
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct StdinSource {
+    fd: Generic<RawFd>,
+    mode: InputMode,
+    line: Vec<u8>,
+    flags: OFlag,
+    termios: Option<Termios>,
+}
+
+impl StdinSource {
+    /// Setup the standard input for the given mode
+    pub fn new(mode: InputMode) -> io::Result<StdinSource> {
+        StdinSource::from_fd(0, mode)
+    }
+
+    fn from_fd(fd: RawFd, mode: InputMode) -> io::Result<StdinSource> {
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        // only adjust the terminal when there is one
+        let termios = match (mode, termios::tcgetattr(fd)) {
+            (InputMode::Raw, Ok(original)) => {
+                let mut raw = original.clone();
+                raw.local_flags
+                    .remove(LocalFlags::ICANON | LocalFlags::ECHO);
+                raw.control_chars[SpecialCharacterIndices::VMIN as usize] = 1;
+                raw.control_chars[SpecialCharacterIndices::VTIME as usize] = 0;
+                termios::tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+                Some(original)
+            }
+            _ => None,
+        };
+        let source = StdinSource {
+            fd: Generic::new(fd, Interest::READ, Mode::Level),
+            mode,
+            line: Vec::new(),
+            flags,
+            termios,
+        };
+        // on failure, the drop of the source restores the terminal
+        fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        Ok(source)
+    }
+
+    /// The mode of this source
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+}
+
+impl Drop for StdinSource {
+    fn drop(&mut self) {
+        if let Err(e) = fcntl(self.fd.file, FcntlArg::F_SETFL(self.flags)) {
+            log::warn!("[calloop] Failed to restore stdin flags: {:?}", e);
+        }
+        if let Some(ref original) = self.termios {
+            if let Err(e) = termios::tcsetattr(self.fd.file, SetArg::TCSANOW, original) {
+                log::warn!("[calloop] Failed to restore terminal settings: {:?}", e);
+            }
+        }
+    }
+}
+
+impl EventSource for StdinSource {
+    type Event = Event;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mode = self.mode;
+        let line = &mut self.line;
+        self.fd.process_events(readiness, token, |_, &mut fd| {
+            let mut buffer = [0u8; 1024];
+            loop {
+                match read(fd, &mut buffer) {
+                    Ok(0) => {
+                        if !line.is_empty() {
+                            let last = std::mem::take(line);
+                            callback(
+                                Event::Line(String::from_utf8_lossy(&last).into_owned()),
+                                &mut (),
+                            );
+                        }
+                        callback(Event::Eof, &mut ());
+                        return Ok(PostAction::Remove);
+                    }
+                    Ok(len) if mode == InputMode::Raw => {
+                        callback(Event::Input(buffer[..len].to_vec()), &mut ())
+                    }
+                    Ok(len) => {
+                        line.extend_from_slice(&buffer[..len]);
+                        split_lines(line, |line| {
+                            let line = String::from_utf8_lossy(&line).into_owned();
+                            callback(Event::Line(line), &mut ())
+                        });
+                    }
+                    Err(Errno::EAGAIN) => return Ok(PostAction::Continue),
+                    Err(Errno::EINTR) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.fd.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.fd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.fd.unregister(poll)
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.line.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nix::unistd::{close, pipe, write};
+
+    use super::*;
+
+    #[test]
+    fn cooked_lines_and_eof() {
+        let mut event_loop = crate::EventLoop::<Vec<Event>>::try_new().unwrap();
+        let (read_fd, write_fd) = pipe().unwrap();
+        let source = StdinSource::from_fd(read_fd, InputMode::Cooked).unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), events| events.push(event))
+            .unwrap();
+
+        write(write_fd, b"hello\nwor").unwrap();
+        let mut events = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut events)
+            .unwrap();
+        assert_eq!(events, &[Event::Line("hello".into())]);
+
+        write(write_fd, b"ld\nend").unwrap();
+        close(write_fd).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut events)
+            .unwrap();
+        assert_eq!(
+            events,
+            &[
+                Event::Line("hello".into()),
+                Event::Line("world".into()),
+                Event::Line("end".into()),
+                Event::Eof,
+            ]
+        );
+
+        // the source removed itself, and dropping it restored the flags
+        let flags = OFlag::from_bits_truncate(fcntl(read_fd, FcntlArg::F_GETFL).unwrap());
+        assert!(!flags.contains(OFlag::O_NONBLOCK));
+        close(read_fd).unwrap();
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let mut event_loop = crate::EventLoop::<Vec<Event>>::try_new().unwrap();
+        let (read_fd, write_fd) = pipe().unwrap();
+        // a pipe is not a terminal, the source still delivers bytes immediately
+        let source = StdinSource::from_fd(read_fd, InputMode::Raw).unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), events| events.push(event))
+            .unwrap();
+
+        write(write_fd, b"ab").unwrap();
+        let mut events = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut events)
+            .unwrap();
+        assert_eq!(events, &[Event::Input(b"ab".to_vec())]);
+
+        close(write_fd).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut events)
+            .unwrap();
+        assert_eq!(events, &[Event::Input(b"ab".to_vec()), Event::Eof]);
+        close(read_fd).unwrap();
+    }
+}
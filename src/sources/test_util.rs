@@ -0,0 +1,436 @@
+//! Event sources for testing the event loop
+//!
+//! Only available with the `test-util` cargo feature.
+//!
+//! These sources do not perform any real IO, and are meant to write deterministic tests of
+//! the insertion, removal and dispatching of sources:
+//!
+//! - [`NullSource`] never generates any event
+//! - [`ManualSource`] generates an event when you trigger it, on Linux
+//!
+//! The [`TestClock`] can be given to a [`Timer`](crate::timer::Timer) to fire its timeouts
+//! without waiting for them in real time.
+//!
+//! The events of any source can also be gathered into a list owned by the test, using the
+//! callback returned by [`collector`] and
+//! [`EventLoop::dispatch_until_quiescent`](crate::EventLoop#method.dispatch_until_quiescent).
+//!
+//! ```
+//! # extern crate calloop;
+//! use calloop::test_util::ManualSource;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<bool>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let source = ManualSource::new().unwrap();
+//! let trigger = source.trigger_handle();
+//!
+//! handle
+//!     .insert_source(source, |_count, &mut (), dispatched| *dispatched = true)
+//!     .unwrap();
+//!
+//! trigger.trigger();
+//! let mut dispatched = false;
+//! event_loop.dispatch(std::time::Duration::from_millis(0), &mut dispatched).unwrap();
+//! assert!(dispatched);
+//! # }
+//! ```
+
+use std::cell::RefCell;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use nix::{
+    sys::eventfd::{eventfd, EfdFlags},
+    unistd::{read, write},
+};
+
+#[cfg(target_os = "linux")]
+use super::fd_guard::FdGuard;
+#[cfg(target_os = "linux")]
+use super::generic::Generic;
+use super::timer::{Clock, TimerWaker};
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+#[cfg(target_os = "linux")]
+use crate::{Interest, Mode};
+
+/// Create a callback pushing the events of a source into a shared list
+///
+/// The returned callback can be given to
+/// [`LoopHandle::insert_source`](crate::LoopHandle#method.insert_source) for any source
+/// whose callbacks return `()`. The test keeps a clone of `events` to check what was
+/// received. For example, with a [signals](crate::signals) source:
+///
+/// ```no_run
+/// # extern crate calloop;
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// use calloop::signals::{Signal, Signals};
+/// use calloop::test_util::collector;
+///
+/// # fn main() {
+/// let mut event_loop = calloop::EventLoop::<()>::try_new().unwrap();
+/// let events = Rc::new(RefCell::new(Vec::new()));
+/// event_loop
+///     .handle()
+///     .insert_source(Signals::new(&[Signal::SIGUSR1]).unwrap(), collector(events.clone()))
+///     .unwrap();
+///
+/// nix::sys::signal::kill(nix::unistd::Pid::this(), Signal::SIGUSR1).unwrap();
+/// event_loop.dispatch_until_quiescent(&mut ()).unwrap();
+///
+/// let events = events.borrow();
+/// assert_eq!(events.len(), 1);
+/// assert_eq!(events[0].signal(), Signal::SIGUSR1);
+/// # }
+/// ```
+pub fn collector<E, M, D>(events: Rc<RefCell<Vec<E>>>) -> impl FnMut(E, &mut M, &mut D) {
+    move |event, _, _| events.borrow_mut().push(event)
+}
+
+/// A clock which is advanced manually
+///
+/// Give it to [`Timer::with_clock`](crate::timer::Timer#method.with_clock) to create a
+/// timer whose timeouts only fire when this clock is [advanced](TestClock#method.advance)
+/// past their deadline. The timer is then woken up right away, so that the expired timeouts
+/// are dispatched by the next dispatch of the event loop, without waiting:
+///
+/// ```
+/// # extern crate calloop;
+/// use std::time::Duration;
+///
+/// use calloop::{test_util::TestClock, timer::Timer};
+///
+/// # fn main() {
+/// let mut event_loop = calloop::EventLoop::<bool>::try_new().unwrap();
+/// let clock = TestClock::new();
+/// let timer = Timer::with_clock(clock.clone()).unwrap();
+/// timer.handle().add_timeout(Duration::from_secs(60), ());
+/// event_loop
+///     .handle()
+///     .insert_source(timer, |(), _, fired| *fired = true)
+///     .unwrap();
+///
+/// let mut fired = false;
+/// clock.advance(Duration::from_secs(60));
+/// event_loop.dispatch(Duration::from_millis(0), &mut fired).unwrap();
+/// assert!(fired);
+/// # }
+/// ```
+///
+/// The clones of a test clock share the same time.
+#[derive(Clone, Debug)]
+pub struct TestClock {
+    inner: Arc<Mutex<TestClockInner>>,
+}
+
+#[derive(Debug)]
+struct TestClockInner {
+    now: Instant,
+    wakers: Vec<TimerWaker>,
+}
+
+impl TestClock {
+    /// Create a new test clock, starting at the current time
+    pub fn new() -> TestClock {
+        TestClock {
+            inner: Arc::new(Mutex::new(TestClockInner {
+                now: Instant::now(),
+                wakers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Advance the time of this clock, waking up the timers using it
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now += duration;
+        // forget the timers which were dropped
+        inner.wakers.retain(|waker| waker.wake());
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> TestClock {
+        TestClock::new()
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    fn wake_on_change(&self, waker: TimerWaker) -> bool {
+        self.inner.lock().unwrap().wakers.push(waker);
+        true
+    }
+}
+
+/// An event source that never generates any event
+///
+/// It does not register anything into the poll instance of the event loop, so it never
+/// wakes the loop up.
+#[derive(Debug, Default)]
+pub struct NullSource;
+
+impl NullSource {
+    /// Create a new null source
+    pub fn new() -> NullSource {
+        NullSource
+    }
+}
+
+impl EventSource for NullSource {
+    type Event = ();
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        _readiness: Readiness,
+        _token: Token,
+        _callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, _poll: &mut Poll, _token_factory: &mut TokenFactory) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        _poll: &mut Poll,
+        _token_factory: &mut TokenFactory,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn unregister(&mut self, _poll: &mut Poll) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An event source that generates events when it is triggered
+///
+/// It is backed by an eventfd, so it is only available on Linux. Each call to
+/// [`trigger`](ManualSource#method.trigger) makes the event loop dispatch the source on its
+/// next poll. The triggers received between two dispatches are coalesced into a single
+/// event, holding their number.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct ManualSource {
+    fd: Arc<FdGuard>,
+    inner: Generic<RawFd>,
+}
+
+#[cfg(target_os = "linux")]
+impl ManualSource {
+    /// Create a new manual source
+    pub fn new() -> io::Result<ManualSource> {
+        let fd = eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)?;
+        Ok(ManualSource {
+            fd: Arc::new(FdGuard::new(fd, "eventfd")),
+            inner: Generic::new(fd, Interest::READ, Mode::Level),
+        })
+    }
+
+    /// Trigger the source, it'll be dispatched by the next poll of the event loop
+    pub fn trigger(&self) {
+        trigger(self.fd.as_raw_fd())
+    }
+
+    /// Get a handle that can trigger this source once it is inserted in the event loop
+    pub fn trigger_handle(&self) -> ManualTrigger {
+        ManualTrigger {
+            fd: self.fd.clone(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn trigger(fd: RawFd) {
+    if let Err(e) = write(fd, &1u64.to_ne_bytes()) {
+        log::warn!("[calloop] Failed to trigger manual source: {:?}", e);
+    }
+}
+
+/// A handle to trigger a [`ManualSource`]
+///
+/// It can be cloned and sent across threads.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug)]
+pub struct ManualTrigger {
+    fd: Arc<FdGuard>,
+}
+
+#[cfg(target_os = "linux")]
+impl ManualTrigger {
+    /// Trigger the source, it'll be dispatched by the next poll of the event loop
+    pub fn trigger(&self) {
+        trigger(self.fd.as_raw_fd())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl EventSource for ManualSource {
+    type Event = u64;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        self.inner.process_events(readiness, token, |_, &mut fd| {
+            let mut buf = [0u8; 8];
+            match read(fd, &mut buf) {
+                Ok(_) => callback(u64::from_ne_bytes(buf), &mut ()),
+                // spurious wakeup, the counter was already read
+                Err(nix::errno::Errno::EAGAIN) => {}
+                Err(e) => return Err(e.into()),
+            }
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.inner.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.inner.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.inner.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn null_source_never_dispatched() {
+        let mut event_loop = crate::EventLoop::<bool>::try_new().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(NullSource::new(), |(), &mut (), dispatched| {
+                *dispatched = true
+            })
+            .unwrap();
+
+        let mut dispatched = false;
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut dispatched)
+            .unwrap();
+        assert!(!dispatched);
+
+        event_loop.handle().remove(token);
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut dispatched)
+            .unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn manual_source_coalesces_triggers() {
+        let mut event_loop = crate::EventLoop::<Vec<u64>>::try_new().unwrap();
+        let source = ManualSource::new().unwrap();
+        let trigger = source.trigger_handle();
+        event_loop
+            .handle()
+            .insert_source(source, |count, &mut (), counts| counts.push(count))
+            .unwrap();
+
+        let mut counts = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut counts)
+            .unwrap();
+        assert!(counts.is_empty());
+
+        trigger.trigger();
+        trigger.trigger();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut counts)
+            .unwrap();
+        assert_eq!(counts, &[2]);
+
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut counts)
+            .unwrap();
+        assert_eq!(counts, &[2]);
+    }
+
+    #[test]
+    fn timer_with_test_clock() {
+        let mut event_loop = crate::EventLoop::<Vec<u32>>::try_new().unwrap();
+        let clock = TestClock::new();
+        let timer = crate::timer::Timer::with_clock(clock.clone()).unwrap();
+        let handle = timer.handle();
+        event_loop
+            .handle()
+            .insert_source(timer, |id, _, fired| fired.push(id))
+            .unwrap();
+
+        handle.add_timeout(Duration::from_secs(10), 1);
+        handle.add_timeout(Duration::from_secs(20), 2);
+
+        let mut fired = Vec::new();
+        clock.advance(Duration::from_secs(5));
+        event_loop.dispatch_until_quiescent(&mut fired).unwrap();
+        assert!(fired.is_empty());
+
+        clock.advance(Duration::from_secs(5));
+        event_loop.dispatch_until_quiescent(&mut fired).unwrap();
+        assert_eq!(fired, &[1]);
+
+        clock.advance(Duration::from_secs(60));
+        event_loop.dispatch_until_quiescent(&mut fired).unwrap();
+        assert_eq!(fired, &[1, 2]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn collect_until_quiescent() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let first = ManualSource::new().unwrap();
+        let second = ManualSource::new().unwrap();
+        first.trigger();
+        second.trigger();
+        second.trigger();
+        event_loop
+            .handle()
+            .insert_source(first, collector(events.clone()))
+            .unwrap();
+        event_loop
+            .handle()
+            .insert_source(second, collector(events.clone()))
+            .unwrap();
+
+        event_loop.dispatch_until_quiescent(&mut ()).unwrap();
+        let mut events = events.borrow().clone();
+        events.sort_unstable();
+        assert_eq!(events, &[1, 2]);
+    }
+}
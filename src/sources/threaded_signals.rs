@@ -0,0 +1,301 @@
+//! Event source receiving Unix signals in a dedicated thread
+//!
+//! Unlike the [`signals`](crate::signals) source, which reads a Linux signalfd, the
+//! [`ThreadedSignals`] source only relies on POSIX functions, and is available on every
+//! platform supported by calloop. It blocks its signals for the calling thread, then spawns a
+//! thread which inherits this signal mask and waits for the signals with `sigwaitinfo`, and
+//! forwards them to the event loop through a [channel](crate::channel). This costs an extra
+//! thread and a wakeup of the loop per signal.
+//!
+//! As for a [`signals`](crate::signals) source, the signals must be blocked on the other
+//! threads of the process too, which is simplest by creating the source before spawning any
+//! thread. A signal watched by several sources is received by only one of them, and is only
+//! unblocked once no source of the thread watches it anymore.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use calloop::threaded_signals::{Signal, ThreadedSignals};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let signals = ThreadedSignals::new(&[Signal::SIGUSR1])
+//!     .expect("Failed to watch the signals");
+//!
+//! handle
+//!     .insert_source(signals, |event, &mut (), _| {
+//!         println!("Received {:?} from {:?}", event.signal(), event.pid());
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::os::raw::c_int;
+use std::os::unix::thread::JoinHandleExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+pub use nix::sys::signal::{SigSet, Signal};
+use nix::unistd::Pid;
+
+use super::channel::{channel, Channel, Event as ChannelEvent, Sender};
+use super::signal_mask::{block_signals, unblock_signals, unignore};
+use super::timer::ThreadConfig;
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// A signal received by a [`ThreadedSignals`] source
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    signal: Signal,
+    pid: Option<Pid>,
+    uid: Option<u32>,
+    received_at: Instant,
+}
+
+impl Event {
+    /// The signal which was received
+    pub fn signal(&self) -> Signal {
+        self.signal
+    }
+
+    /// The pid carried by the signal
+    ///
+    /// This is the pid of the sending process for the signals sent with `kill` or `sigqueue`,
+    /// and the pid of the child whose state changed for a `SIGCHLD`. It is `None` if the
+    /// signal carries no pid, and on the platforms whose `siginfo_t` does not expose it, that
+    /// is NetBSD and OpenBSD.
+    pub fn pid(&self) -> Option<Pid> {
+        self.pid
+    }
+
+    /// The real user id carried by the signal
+    ///
+    /// Like the [`pid`](Event#method.pid), it is only meaningful for the signals sent by a
+    /// process, and is `None` on NetBSD and OpenBSD.
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// The instant at which the thread of the source received this signal
+    pub fn received_at(&self) -> Instant {
+        self.received_at
+    }
+}
+
+/// An event source receiving Unix signals in a dedicated thread
+///
+/// See the [module documentation](self) for details.
+///
+/// When the source is dropped, its thread is woken up with one of the watched signals, sent
+/// to this thread only, and joined. The signals are then unblocked.
+#[derive(Debug)]
+pub struct ThreadedSignals {
+    channel: Channel<Event>,
+    mask: SigSet,
+    held: SigSet,
+    counts: HashMap<Signal, u64>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadedSignals {
+    /// Create a source listening on the specified list of signals
+    pub fn new(signals: &[Signal]) -> io::Result<ThreadedSignals> {
+        ThreadedSignals::with_thread_config(signals, &ThreadConfig::new())
+    }
+
+    /// Create a source listening on the specified list of signals, whose thread is
+    /// configured by `config`
+    ///
+    /// See [`ThreadConfig`] for details, the thread is named after the `signals` role.
+    pub fn with_thread_config(
+        signals: &[Signal],
+        config: &ThreadConfig,
+    ) -> io::Result<ThreadedSignals> {
+        let mut mask = SigSet::empty();
+        for &s in signals {
+            mask.add(s);
+        }
+
+        unignore(signals)?;
+        // the thread inherits the signal mask of this thread
+        let mut held = SigSet::empty();
+        block_signals(&mask, &mut held)?;
+        let (sender, channel) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = if signals.is_empty() {
+            // there is no signal to wait for, nor to stop the thread with
+            None
+        } else {
+            let thread_stop = stop.clone();
+            match config.spawn("signals", move || wait_signals(mask, &thread_stop, &sender)) {
+                Ok(thread) => Some(thread),
+                Err(e) => {
+                    let _ = unblock_signals(&mask, &mut held);
+                    return Err(e);
+                }
+            }
+        };
+        Ok(ThreadedSignals {
+            channel,
+            mask,
+            held,
+            counts: HashMap::new(),
+            stop,
+            thread,
+        })
+    }
+
+    /// The set of signals watched by this source
+    pub fn signals(&self) -> SigSet {
+        self.mask
+    }
+
+    /// The number of times each signal has been delivered to the callback
+    pub fn counts(&self) -> HashMap<Signal, u64> {
+        self.counts.clone()
+    }
+}
+
+// Forward the signals received by the thread to the source, until it is dropped
+fn wait_signals(mask: SigSet, stop: &AtomicBool, sender: &Sender<Event>) {
+    loop {
+        let ret = wait_signal(&mask);
+        if stop.load(Ordering::Acquire) {
+            return;
+        }
+        let event = match ret {
+            Ok(event) => event,
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => {
+                log::warn!("[calloop] Error waiting for signals: {}", e);
+                return;
+            }
+        };
+        if sender.send(event).is_err() {
+            return;
+        }
+    }
+}
+
+// Wait for one of the signals of the mask
+#[cfg(not(target_os = "openbsd"))]
+fn wait_signal(mask: &SigSet) -> nix::Result<Event> {
+    use std::convert::TryFrom;
+
+    let mut info = std::mem::MaybeUninit::<nix::libc::siginfo_t>::uninit();
+    let ret = unsafe { nix::libc::sigwaitinfo(mask.as_ref(), info.as_mut_ptr()) };
+    nix::errno::Errno::result(ret)?;
+    // Safety: sigwaitinfo succeeded and filled the siginfo
+    let info = unsafe { info.assume_init() };
+    let (pid, uid) = sender(&info);
+    Ok(Event {
+        signal: Signal::try_from(info.si_signo)?,
+        pid,
+        uid,
+        received_at: Instant::now(),
+    })
+}
+
+// OpenBSD has no sigwaitinfo, the signal carries no information there
+#[cfg(target_os = "openbsd")]
+fn wait_signal(mask: &SigSet) -> nix::Result<Event> {
+    Ok(Event {
+        signal: mask.wait()?,
+        pid: None,
+        uid: None,
+        received_at: Instant::now(),
+    })
+}
+
+// The pid and uid carried by a siginfo_t, on the platforms exposing them
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+))]
+fn sender(info: &nix::libc::siginfo_t) -> (Option<Pid>, Option<u32>) {
+    // Safety: the pid and the uid are read at the same place for all the signals which
+    // carry them, and are zero for the signals sent by the kernel
+    let (pid, uid) = unsafe { (info.si_pid(), info.si_uid()) };
+    if pid == 0 {
+        (None, None)
+    } else {
+        (Some(Pid::from_raw(pid)), Some(uid))
+    }
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+)))]
+fn sender(_info: &nix::libc::siginfo_t) -> (Option<Pid>, Option<u32>) {
+    (None, None)
+}
+
+impl Drop for ThreadedSignals {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stop.store(true, Ordering::Release);
+            // the signal stays pending for the thread if it is not waiting yet
+            if let Some(sentinel) = Signal::iterator().find(|&s| self.mask.contains(s)) {
+                unsafe { nix::libc::pthread_kill(thread.as_pthread_t(), sentinel as c_int) };
+            }
+            if thread.join().is_err() {
+                log::warn!("[calloop] The signal thread panicked");
+            }
+        }
+        if let Err(e) = unblock_signals(&self.mask, &mut self.held) {
+            log::warn!("[calloop] Failed to unmask signals: {:?}", e);
+        }
+    }
+}
+
+impl EventSource for ThreadedSignals {
+    type Event = Event;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let counts = &mut self.counts;
+        self.channel
+            .process_events(readiness, token, |event, &mut ()| match event {
+                ChannelEvent::Msg(event) => {
+                    *counts.entry(event.signal()).or_insert(0) += 1;
+                    callback(event, &mut ())
+                }
+                ChannelEvent::Closed => {
+                    log::warn!("[calloop] The signal thread exited, no more signals are received")
+                }
+            })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.channel.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.channel.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.channel.unregister(poll)
+    }
+}
@@ -0,0 +1,171 @@
+//! A periodic source driven by the timeout of the event loop
+//!
+//! The [`TickSource`] generates an event approximately every period, without using any file
+//! descriptor: the event loop bounds its waits by the
+//! [deadline](crate::EventSource#method.next_deadline) of the next tick, and processes the
+//! source once this deadline is reached. It thus behaves the same on every platform, and does
+//! not need the thread and the wakeup file descriptor of the [`Timer`](crate::timer::Timer).
+//!
+//! Its accuracy is bounded by the granularity of the polling system, which counts its timeout
+//! in milliseconds: the loop wakes up at the millisecond following the deadline. A tick can
+//! also be late if other callbacks of the loop run for long. The ticks are scheduled a whole
+//! number of periods after the insertion of the source, so a late tick does not shift the
+//! following ones, and the ticks missed while the loop was busy for more than a period are
+//! skipped rather than delivered in a burst.
+//!
+//! The event of the source is the deadline of the tick.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::time::Duration;
+//!
+//! use calloop::tick::TickSource;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! handle
+//!     .insert_source(TickSource::new(Duration::from_secs(1)), |deadline, &mut (), _| {
+//!         println!("Tick, {:?} late", deadline.elapsed());
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event source generating an event every period
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct TickSource {
+    period: Duration,
+    next: Option<Instant>,
+    token: Option<Token>,
+}
+
+impl TickSource {
+    /// Create a source ticking every `period`
+    ///
+    /// The first tick happens one period after the source is inserted in an event loop.
+    pub fn new(period: Duration) -> TickSource {
+        TickSource {
+            period,
+            next: None,
+            token: None,
+        }
+    }
+
+    /// The period of the ticks
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// The deadline of the next tick, if the source is registered
+    pub fn next_tick(&self) -> Option<Instant> {
+        self.token.and(self.next)
+    }
+}
+
+impl EventSource for TickSource {
+    type Event = Instant;
+    type Metadata = ();
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        _readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.token != Some(token) {
+            return Ok(PostAction::Continue);
+        }
+        let now = Instant::now();
+        if let Some(deadline) = self.next.filter(|&deadline| deadline <= now) {
+            // skip the ticks missed while the loop was busy
+            let mut next = deadline + self.period;
+            while next <= now && self.period > Duration::from_millis(0) {
+                next += self.period;
+            }
+            self.next = Some(next.max(now));
+            callback(deadline, &mut ());
+        }
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        let token = token_factory.token();
+        poll.add_deadline_source(token);
+        self.token = Some(token);
+        if self.next.is_none() {
+            self.next = Some(Instant::now() + self.period);
+        }
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.unregister(poll)?;
+        self.register(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        if let Some(token) = self.token.take() {
+            poll.remove_deadline_source(token);
+        }
+        Ok(())
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.next_tick()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks() {
+        let mut event_loop = crate::EventLoop::<Vec<Instant>>::try_new().unwrap();
+        let start = Instant::now();
+        let token = event_loop
+            .handle()
+            .insert_source(
+                TickSource::new(Duration::from_millis(20)),
+                |deadline, &mut (), ticks| ticks.push(deadline),
+            )
+            .unwrap();
+
+        // the loop wakes up for the tick, despite the long timeout
+        let mut ticks = Vec::new();
+        while ticks.is_empty() {
+            event_loop
+                .dispatch(Duration::from_secs(10), &mut ticks)
+                .unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(ticks[0] <= Instant::now());
+
+        // the next tick is a whole number of periods after the previous one
+        let next = event_loop.next_deadline().unwrap();
+        assert!(next > ticks[0]);
+        assert_eq!((next - ticks[0]).as_nanos() % 20_000_000, 0);
+
+        // a disabled source does not wake up the loop
+        event_loop.handle().disable(&token).unwrap();
+        assert_eq!(event_loop.next_deadline(), None);
+        event_loop
+            .dispatch(Duration::from_millis(40), &mut ticks)
+            .unwrap();
+        assert_eq!(ticks.len(), 1);
+    }
+}
@@ -0,0 +1,523 @@
+//! Adapters giving up on file descriptors and sources which stay idle for too long
+//!
+//! The [`WithTimeout`] adapter, created with [`with_timeout()`](with_timeout), wraps a
+//! [`Generic`] source and delivers either its readiness, as [`Event::Ready`], or an
+//! [`Event::Timeout`] once the timeout elapsed without the file descriptor becoming ready.
+//! Each readiness restarts the timeout, as does each timeout: as long as the file descriptor
+//! stays idle, an [`Event::Timeout`] is delivered after each period. This is typically used
+//! to close idle connections, by removing the source on timeout.
+//!
+//! If the file descriptor becomes ready while the timeout elapses, during the same dispatch
+//! of the event loop, only the readiness is delivered and the timeout is restarted.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::io::Read;
+//! use std::os::unix::net::UnixStream;
+//! use std::time::Duration;
+//!
+//! use calloop::generic::Generic;
+//! use calloop::timeout::{with_timeout, Event};
+//! use calloop::{Interest, Mode, PostAction};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let (connection, _) = UnixStream::pair().unwrap();
+//! connection.set_nonblocking(true).unwrap();
+//! let source = Generic::new(connection, Interest::READ, Mode::Level);
+//!
+//! handle
+//!     .insert_source(
+//!         with_timeout(source, Duration::from_secs(30)),
+//!         |event, connection, _| match event {
+//!             Event::Ready(_) => {
+//!                 let mut buffer = [0; 1024];
+//!                 let len = connection.read(&mut buffer)?;
+//!                 println!("Received {} bytes", len);
+//!                 Ok(PostAction::Continue)
+//!             }
+//!             Event::Timeout => {
+//!                 println!("Closing the idle connection");
+//!                 Ok(PostAction::Remove)
+//!             }
+//!         },
+//!     )
+//!     .unwrap();
+//! # }
+//! ```
+//!
+//! ## Inactivity of any source
+//!
+//! The [`InactivityTimeout`] adapter, created with
+//! [`inactivity_timeout()`](inactivity_timeout), applies to any event source: when its
+//! wrapped source does not generate any event for the given duration, it invokes its
+//! [hook](InactivityTimeout#method.on_inactive) and [disables](OnInactivity::Disable) or
+//! [removes](OnInactivity::Remove) itself. Each event of the source restarts the timeout, and
+//! enabling a disabled source starts it again.
+//!
+//! The timeouts of both adapters are driven by the timeout of the event loop, like the
+//! [`TickSource`](crate::tick::TickSource), without any thread: they are checked when the
+//! event loop wakes up, so they elapse with the resolution of the polling system, and late if
+//! the other sources of the loop take long to dispatch.
+
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use super::deadline::Deadline;
+use super::generic::Generic;
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event generated by the [`WithTimeout`] adapter
+#[derive(Copy, Clone, Debug)]
+pub enum Event {
+    /// The file descriptor is ready
+    Ready(Readiness),
+    /// The timeout elapsed without the file descriptor becoming ready
+    Timeout,
+}
+
+/// An event source delivering the readiness of a file descriptor or a timeout
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct WithTimeout<F: AsRawFd> {
+    source: Generic<F>,
+    timeout: Duration,
+    deadline: Deadline,
+}
+
+/// Wrap a source so that it delivers a timeout when it stays idle for the given duration
+///
+/// The timeout starts once the source is inserted in an event loop.
+pub fn with_timeout<F: AsRawFd>(source: Generic<F>, timeout: Duration) -> WithTimeout<F> {
+    WithTimeout {
+        source,
+        timeout,
+        deadline: Deadline::new(),
+    }
+}
+
+impl<F: AsRawFd> WithTimeout<F> {
+    /// Access the wrapped source
+    pub fn source(&self) -> &Generic<F> {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut Generic<F> {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> Generic<F> {
+        self.source
+    }
+
+    /// The duration after which an idle source times out
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Change the duration after which an idle source times out
+    ///
+    /// The new duration is used from the next time the timeout is restarted.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn restart(&mut self) {
+        self.deadline.set(self.timeout);
+    }
+
+    // Whether the file descriptor is ready, without waiting for the polling system
+    fn is_ready(&self) -> io::Result<bool> {
+        use nix::poll::{poll, PollFd, PollFlags};
+
+        let mut flags = PollFlags::empty();
+        flags.set(PollFlags::POLLIN, self.source.interest.readable);
+        flags.set(PollFlags::POLLOUT, self.source.interest.writable);
+        let mut fds = [PollFd::new(self.source.file.as_raw_fd(), flags)];
+        loop {
+            match poll(&mut fds, 0) {
+                Ok(n) => return Ok(n > 0),
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl<F: AsRawFd> EventSource for WithTimeout<F> {
+    type Event = Event;
+    type Metadata = F;
+    type Ret = io::Result<PostAction>;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.deadline.owns(token) {
+            if !self.deadline.expired() {
+                return Ok(PostAction::Continue);
+            }
+            self.restart();
+            if self.is_ready()? {
+                // the readiness is delivered instead, during this dispatch or the next one
+                return Ok(PostAction::Continue);
+            }
+            return match callback(Event::Timeout, &mut self.source.file)? {
+                PostAction::SetInterest(interest) => {
+                    self.source.interest = interest;
+                    Ok(PostAction::Reregister)
+                }
+                action => Ok(action),
+            };
+        }
+
+        let mut ready = false;
+        let action = self
+            .source
+            .process_events(readiness, token, |readiness, file| {
+                ready = true;
+                callback(Event::Ready(readiness), file)
+            })?;
+        if ready {
+            self.restart();
+        }
+        Ok(action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)?;
+        self.deadline.register(poll, token_factory);
+        self.restart();
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)?;
+        self.deadline.unregister(poll);
+        self.deadline.register(poll, token_factory);
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        // a disabled source does not time out
+        self.deadline.cancel();
+        self.deadline.unregister(poll);
+        self.source.unregister(poll)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.deadline.next_deadline()
+    }
+}
+
+/// What the [`InactivityTimeout`] adapter does when its source stays inactive
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnInactivity {
+    /// Disable the source, which can be enabled again with the [`LoopHandle`](crate::LoopHandle)
+    Disable,
+    /// Remove the source from the event loop
+    Remove,
+}
+
+/// An event source disabling or removing itself when its wrapped source stays inactive
+///
+/// See the [module documentation](self) for details.
+pub struct InactivityTimeout<S> {
+    source: S,
+    timeout: Duration,
+    deadline: Deadline,
+    action: OnInactivity,
+    hook: Option<Box<dyn FnMut()>>,
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for InactivityTimeout<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InactivityTimeout")
+            .field("source", &self.source)
+            .field("timeout", &self.timeout)
+            .field("action", &self.action)
+            .finish()
+    }
+}
+
+/// Wrap a source so that it applies `action` when it stays inactive for the given duration
+///
+/// The timeout starts once the source is inserted in an event loop.
+pub fn inactivity_timeout<S: EventSource>(
+    source: S,
+    timeout: Duration,
+    action: OnInactivity,
+) -> InactivityTimeout<S> {
+    InactivityTimeout {
+        source,
+        timeout,
+        deadline: Deadline::new(),
+        action,
+        hook: None,
+    }
+}
+
+impl<S: EventSource> InactivityTimeout<S> {
+    /// Set a hook invoked when the source times out, before it is disabled or removed
+    pub fn on_inactive<F: FnMut() + 'static>(mut self, hook: F) -> InactivityTimeout<S> {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Access the wrapped source
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Mutably access the wrapped source
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Unwrap the adapter, retrieving the wrapped source
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    /// The duration after which an inactive source times out
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Change the duration after which an inactive source times out
+    ///
+    /// The new duration is used from the next time the timeout is restarted.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn restart(&mut self) {
+        self.deadline.set(self.timeout);
+    }
+}
+
+impl<S: EventSource> EventSource for InactivityTimeout<S> {
+    type Event = S::Event;
+    type Metadata = S::Metadata;
+    type Ret = S::Ret;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.deadline.owns(token) {
+            if !self.deadline.expired() {
+                return Ok(PostAction::Continue);
+            }
+            if let Some(hook) = self.hook.as_mut() {
+                hook();
+            }
+            return Ok(match self.action {
+                OnInactivity::Disable => PostAction::Disable,
+                OnInactivity::Remove => PostAction::Remove,
+            });
+        }
+
+        let mut active = false;
+        let action = self
+            .source
+            .process_events(readiness, token, |event, meta| {
+                active = true;
+                callback(event, meta)
+            })?;
+        if active {
+            self.restart();
+        }
+        Ok(action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.register(poll, token_factory)?;
+        self.deadline.register(poll, token_factory);
+        self.restart();
+        Ok(())
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.source.reregister(poll, token_factory)?;
+        self.deadline.unregister(poll);
+        self.deadline.register(poll, token_factory);
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        // a disabled source does not time out
+        self.deadline.cancel();
+        self.deadline.unregister(poll);
+        self.source.unregister(poll)
+    }
+
+    fn on_dispatch_start<C>(&mut self, mut callback: C)
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mut active = false;
+        self.source.on_dispatch_start(|event, meta| {
+            active = true;
+            callback(event, meta)
+        });
+        if active {
+            self.restart();
+        }
+    }
+
+    fn on_dispatch_end(&mut self) {
+        self.source.on_dispatch_end()
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        self.source.flush()
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        match (self.deadline.next_deadline(), self.source.next_deadline()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn on_shutdown(&mut self) {
+        self.source.on_shutdown()
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.source.buffered_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+    use crate::{Interest, Mode};
+
+    #[derive(Debug, PartialEq)]
+    enum Seen {
+        Ready(usize),
+        Timeout,
+    }
+
+    fn insert(event_loop: &crate::EventLoop<Vec<Seen>>, stream: UnixStream, timeout: Duration) {
+        stream.set_nonblocking(true).unwrap();
+        let source = Generic::new(stream, Interest::READ, Mode::Level);
+        event_loop
+            .handle()
+            .insert_source(with_timeout(source, timeout), |event, stream, seen| {
+                match event {
+                    Event::Ready(_) => {
+                        let mut buffer = [0; 16];
+                        seen.push(Seen::Ready(stream.read(&mut buffer)?));
+                    }
+                    Event::Timeout => seen.push(Seen::Timeout),
+                }
+                Ok(PostAction::Continue)
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let mut event_loop = crate::EventLoop::<Vec<Seen>>::try_new().unwrap();
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        insert(&event_loop, stream, Duration::from_millis(20));
+
+        let mut seen = Vec::new();
+        peer.write_all(b"abc").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert_eq!(seen, &[Seen::Ready(3)]);
+
+        while seen.len() < 2 {
+            event_loop
+                .dispatch(Duration::from_millis(50), &mut seen)
+                .unwrap();
+        }
+        assert_eq!(seen, &[Seen::Ready(3), Seen::Timeout]);
+    }
+
+    #[test]
+    fn readiness_wins_over_timeout() {
+        let mut event_loop = crate::EventLoop::<Vec<Seen>>::try_new().unwrap();
+        let (stream, mut peer) = UnixStream::pair().unwrap();
+        insert(&event_loop, stream, Duration::from_millis(20));
+
+        // both the data and the timeout are pending once the loop is dispatched
+        peer.write_all(b"abc").unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        let mut seen = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert_eq!(seen, &[Seen::Ready(3)]);
+    }
+
+    #[test]
+    fn inactive_source_is_disabled() {
+        use crate::ping::make_ping;
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+        let (ping, source) = make_ping().unwrap();
+        let timeouts = Rc::new(Cell::new(0));
+        let hook_timeouts = timeouts.clone();
+        let source = inactivity_timeout(source, Duration::from_millis(20), OnInactivity::Disable)
+            .on_inactive(move || hook_timeouts.set(hook_timeouts.get() + 1));
+        let token = event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        let mut count = 0;
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!((count, timeouts.get()), (1, 0));
+
+        while timeouts.get() == 0 {
+            event_loop
+                .dispatch(Duration::from_millis(50), &mut count)
+                .unwrap();
+        }
+        // the disabled source does not receive its events anymore
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!((count, timeouts.get()), (1, 1));
+
+        event_loop.handle().enable(&token).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut count)
+            .unwrap();
+        assert_eq!((count, timeouts.get()), (2, 1));
+    }
+}
@@ -6,17 +6,26 @@
 //! The [`Timer<T>`](Timer) event source provides an handle [`TimerHandle<T>`](TimerHandle), which
 //! is used to set or cancel timeouts. This handle is cloneable and can be sent accross threads
 //! if `T: Send`, allowing you to setup timeouts from any point of your program.
+//!
+//! The deadlines of the timeouts are computed from a [`Clock`], which is by default the
+//! [`MonotonicClock`] of the system. Another clock can be injected when creating the timer
+//! with [`Timer::with_clock`](Timer#method.with_clock), for example the
+//! [`TestClock`](crate::test_util::TestClock) of the `test-util` cargo feature, which lets
+//! tests advance the time manually rather than sleeping.
 
 use std::cell::RefCell;
 use std::collections::BinaryHeap;
 use std::io;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+    Arc, Mutex, Weak,
 };
 use std::time::{Duration, Instant};
 
-use super::ping::{make_ping, PingSource};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use nix::sched::CpuSet;
+
+use super::ping::{make_ping, Ping, PingSource};
 use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
 
 /// A Timer event source
@@ -27,16 +36,58 @@ use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
 #[derive(Debug)]
 pub struct Timer<T> {
     inner: Arc<Mutex<TimerInner<T>>>,
+    // kept alive for the clock, if it wakes the timer itself
+    _waker: Option<Arc<Ping>>,
     source: TimerSource,
 }
 
 impl<T> Timer<T> {
     /// Create a new timer
     pub fn new() -> std::io::Result<Timer<T>> {
-        let (scheduler, source) = TimerScheduler::new()?;
-        let inner = TimerInner::new(scheduler);
+        Timer::with_clock(MonotonicClock)
+    }
+
+    /// Create a new timer computing its deadlines from the given clock
+    ///
+    /// Unless the clock [wakes the timer](Clock#method.wake_on_change) itself, the timer
+    /// waits for its deadlines in real time, so the clock should advance at the same pace as
+    /// the [`MonotonicClock`].
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> std::io::Result<Timer<T>> {
+        Timer::with_clock_and_thread_config(clock, &ThreadConfig::new())
+    }
+
+    /// Create a new timer whose scheduling thread is configured by `config`
+    ///
+    /// See [`ThreadConfig`] for details.
+    pub fn with_thread_config(config: &ThreadConfig) -> std::io::Result<Timer<T>> {
+        Timer::with_clock_and_thread_config(MonotonicClock, config)
+    }
+
+    /// Create a new timer computing its deadlines from the given clock, and whose scheduling
+    /// thread is configured by `config`
+    ///
+    /// This combines [`with_clock`](Timer#method.with_clock) and
+    /// [`with_thread_config`](Timer#method.with_thread_config). The configuration is unused
+    /// if the clock [wakes the timer](Clock#method.wake_on_change) itself, as no thread is
+    /// spawned then.
+    pub fn with_clock_and_thread_config<C: Clock + 'static>(
+        clock: C,
+        config: &ThreadConfig,
+    ) -> std::io::Result<Timer<T>> {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        let (ping, source) = make_ping()?;
+        let ping = Arc::new(ping);
+        let (scheduler, waker) = if clock.wake_on_change(TimerWaker {
+            ping: Arc::downgrade(&ping),
+        }) {
+            (None, Some(ping))
+        } else {
+            let scheduler = TimerScheduler::new((*ping).clone(), clock.clone(), config)?;
+            (Some(scheduler), None)
+        };
         Ok(Timer {
-            inner: Arc::new(Mutex::new(inner)),
+            inner: Arc::new(Mutex::new(TimerInner::new(scheduler, clock))),
+            _waker: waker,
             source,
         })
     }
@@ -68,6 +119,139 @@ impl<T> Clone for TimerHandle<T> {
     }
 }
 
+/// The configuration of the helper threads spawned by some sources
+///
+/// The event loop itself does not spawn any thread, but a few sources do. For profiling or
+/// locality, their threads can be given a name and, on Linux and Android, be pinned to some
+/// CPUs. The same configuration can be given to all of them. The sources honoring it are:
+///
+/// - the [`Timer`], unless its clock wakes it up by itself, which waits for its deadlines in
+///   a thread, see [`Timer::with_thread_config`](Timer#method.with_thread_config) and
+///   [`Timer::with_clock_and_thread_config`](Timer#method.with_clock_and_thread_config);
+/// - the [`ChannelWithTick`](crate::channel::ChannelWithTick), whose ticks are driven by a
+///   timer, see
+///   [`channel_with_tick_and_thread_config`](crate::channel::channel_with_tick_and_thread_config);
+/// - the [`ThreadedSignals`](crate::threaded_signals::ThreadedSignals), which waits for its
+///   signals in a thread, see
+///   [`ThreadedSignals::with_thread_config`](crate::threaded_signals::ThreadedSignals#method.with_thread_config).
+///
+/// The other sources do not spawn any thread.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadConfig {
+    name_prefix: Option<String>,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    affinity: Option<CpuSet>,
+}
+
+impl ThreadConfig {
+    /// The default configuration, naming the threads `calloop` followed by their role, like
+    /// `calloop timer`
+    pub fn new() -> ThreadConfig {
+        ThreadConfig::default()
+    }
+
+    /// Name the threads with this prefix, followed by their role, like ` timer`
+    pub fn name_prefix<S: Into<String>>(mut self, prefix: S) -> ThreadConfig {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Restrict the threads to run on these CPUs, using `sched_setaffinity`
+    ///
+    /// This is only available on Linux and Android.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn affinity(mut self, cpus: CpuSet) -> ThreadConfig {
+        self.affinity = Some(cpus);
+        self
+    }
+
+    // Spawn a thread with this configuration, failing if it could not be applied
+    pub(crate) fn spawn<F>(&self, role: &str, f: F) -> io::Result<std::thread::JoinHandle<()>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let name = match self.name_prefix {
+            Some(ref prefix) => format!("{} {}", prefix, role),
+            None => format!("calloop {}", role),
+        };
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let affinity = self.affinity;
+        let (started, start_result) = std::sync::mpsc::channel::<nix::Result<()>>();
+
+        let thread = std::thread::Builder::new().name(name).spawn(move || {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            {
+                if let Some(ref cpus) = affinity {
+                    let result = nix::sched::sched_setaffinity(nix::unistd::Pid::from_raw(0), cpus);
+                    if let Err(e) = result {
+                        let _ = started.send(Err(e));
+                        return;
+                    }
+                }
+            }
+            let _ = started.send(Ok(()));
+            f()
+        })?;
+        if let Ok(Err(e)) = start_result.recv() {
+            return Err(e.into());
+        }
+        Ok(thread)
+    }
+}
+
+/// A source of time for the [`Timer`]
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// Take over the wakeups of a timer using this clock
+    ///
+    /// This is called once when a [`Timer`] is created with this clock. A clock which does
+    /// not follow the real time, like a clock advanced manually, can return `true` and keep
+    /// the `waker`: it must then call [`TimerWaker::wake`] whenever its time changes, and
+    /// the timer does not wait for its deadlines by itself.
+    ///
+    /// The default implementation returns `false`.
+    fn wake_on_change(&self, waker: TimerWaker) -> bool {
+        let _ = waker;
+        false
+    }
+}
+
+/// The monotonic clock of the system, based on [`Instant::now`]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MonotonicClock;
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A handle to wake a [`Timer`] up, given to its [`Clock`]
+///
+/// See [`Clock::wake_on_change`](Clock#method.wake_on_change).
+#[derive(Clone, Debug)]
+pub struct TimerWaker {
+    ping: Weak<Ping>,
+}
+
+impl TimerWaker {
+    /// Wake the timer up, so that it fires its expired timeouts
+    ///
+    /// Returns `false` if the timer does not exist anymore, in which case this waker can be
+    /// discarded.
+    pub fn wake(&self) -> bool {
+        match self.ping.upgrade() {
+            Some(ping) => {
+                ping.ping();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 /// An itentifier to cancel a timeout if necessary
 #[derive(Debug)]
 pub struct Timeout {
@@ -82,16 +266,20 @@ impl<T> TimerHandle<T> {
     /// The returned `Timeout` can be used to cancel it. You can drop it if you don't
     /// plan to cancel this timeout.
     pub fn add_timeout(&self, delay_from_now: Duration, data: T) -> Timeout {
-        self.inner
-            .lock()
-            .unwrap()
-            .insert(Instant::now() + delay_from_now, data)
+        let mut inner = self.inner.lock().unwrap();
+        let deadline = inner.clock.now() + delay_from_now;
+        inner.insert(deadline, data)
     }
 
     /// Cancel a previsouly set timeout and retrieve the associated data
     ///
     /// This method returns `None` if the timeout does not exist (it has already fired
     /// or has already been cancelled).
+    ///
+    /// The expired timeouts are not collected before their callbacks are invoked, but
+    /// fired one after the other: a timeout cancelled from the callback of another
+    /// timeout, with the handle it receives, does not fire even if it expired during
+    /// the same batch.
     pub fn cancel_timeout(&self, timeout: &Timeout) -> Option<T> {
         self.inner.lock().unwrap().cancel(timeout)
     }
@@ -158,6 +346,10 @@ impl<T> EventSource for Timer<T> {
     fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
         self.source.unregister(poll)
     }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.inner.lock().unwrap().next_deadline()
+    }
 }
 
 /*
@@ -174,15 +366,18 @@ struct TimeoutData<T> {
 #[derive(Debug)]
 struct TimerInner<T> {
     heap: BinaryHeap<TimeoutData<T>>,
-    scheduler: TimerScheduler,
+    // none if the clock wakes the timer itself
+    scheduler: Option<TimerScheduler>,
+    clock: Arc<dyn Clock>,
     counter: u32,
 }
 
 impl<T> TimerInner<T> {
-    fn new(scheduler: TimerScheduler) -> TimerInner<T> {
+    fn new(scheduler: Option<TimerScheduler>, clock: Arc<dyn Clock>) -> TimerInner<T> {
         TimerInner {
             heap: BinaryHeap::new(),
             scheduler,
+            clock,
             counter: 0,
         }
     }
@@ -218,7 +413,7 @@ impl<T> TimerInner<T> {
     }
 
     fn next_expired(&mut self) -> Option<T> {
-        let now = Instant::now();
+        let now = self.clock.now();
         loop {
             // check if there is an expired item
             if let Some(data) = self.heap.peek() {
@@ -240,11 +435,24 @@ impl<T> TimerInner<T> {
         }
     }
 
+    fn next_deadline(&self) -> Option<Instant> {
+        // the cancelled timeouts are still in the heap
+        self.heap
+            .iter()
+            .filter(|data| data.data.borrow().is_some())
+            .map(|data| data.deadline)
+            .min()
+    }
+
     fn reschedule(&mut self) {
+        let scheduler = match self.scheduler {
+            Some(ref mut scheduler) => scheduler,
+            None => return,
+        };
         if let Some(next_deadline) = self.heap.peek().map(|data| data.deadline) {
-            self.scheduler.reschedule(next_deadline);
+            scheduler.reschedule(next_deadline);
         } else {
-            self.scheduler.deschedule();
+            scheduler.deschedule();
         }
     }
 }
@@ -282,24 +490,24 @@ impl<T> std::cmp::Eq for TimeoutData<T> {}
 struct TimerScheduler {
     current_deadline: Arc<Mutex<Option<Instant>>>,
     kill_switch: Arc<AtomicBool>,
+    clock: Arc<dyn Clock>,
     thread: std::thread::JoinHandle<()>,
 }
 
 type TimerSource = PingSource;
 
 impl TimerScheduler {
-    fn new() -> io::Result<(TimerScheduler, TimerSource)> {
+    fn new(ping: Ping, clock: Arc<dyn Clock>, config: &ThreadConfig) -> io::Result<TimerScheduler> {
         let current_deadline = Arc::new(Mutex::new(None::<Instant>));
         let thread_deadline = current_deadline.clone();
 
         let kill_switch = Arc::new(AtomicBool::new(false));
         let thread_kill = kill_switch.clone();
 
-        let (ping, ping_source) = make_ping()?;
+        let thread_clock = clock.clone();
 
-        let thread = std::thread::Builder::new()
-            .name("calloop timer".into())
-            .spawn(move || loop {
+        let thread = config.spawn("timer", move || {
+            loop {
                 // stop if requested
                 if thread_kill.load(Ordering::Acquire) {
                     return;
@@ -311,7 +519,7 @@ impl TimerScheduler {
                     *guard
                 };
                 if let Some(deadline) = opt_deadline {
-                    if let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                    if let Some(remaining) = deadline.checked_duration_since(thread_clock.now()) {
                         // it is not yet expired, go to sleep until it
                         std::thread::park_timeout(remaining);
                     } else {
@@ -323,20 +531,21 @@ impl TimerScheduler {
                     // there is none, got to sleep
                     std::thread::park();
                 }
-            })?;
+            }
+        })?;
 
-        let scheduler = TimerScheduler {
+        Ok(TimerScheduler {
             current_deadline,
             kill_switch,
+            clock,
             thread,
-        };
-        Ok((scheduler, ping_source))
+        })
     }
 
     fn reschedule(&mut self, new_deadline: Instant) {
         let mut deadline_guard = self.current_deadline.lock().unwrap();
         if let Some(current_deadline) = *deadline_guard {
-            if new_deadline < current_deadline || current_deadline <= Instant::now() {
+            if new_deadline < current_deadline || current_deadline <= self.clock.now() {
                 *deadline_guard = Some(new_deadline);
                 self.thread.thread().unpark();
             }
@@ -400,6 +609,58 @@ mod tests {
         assert!(fired);
     }
 
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn thread_config() {
+        use nix::sched::sched_getaffinity;
+        use nix::unistd::Pid;
+
+        // pin the thread to one of the CPUs this test is allowed to run on
+        let allowed = sched_getaffinity(Pid::from_raw(0)).unwrap();
+        let cpu = (0..CpuSet::count())
+            .find(|&cpu| allowed.is_set(cpu).unwrap())
+            .unwrap();
+        let mut cpus = CpuSet::new();
+        cpus.set(cpu).unwrap();
+        let config = ThreadConfig::new().name_prefix("configured").affinity(cpus);
+        let _timer = Timer::<()>::with_thread_config(&config).unwrap();
+        assert_eq!(
+            sched_getaffinity(timer_thread("configured time")).unwrap(),
+            cpus
+        );
+
+        // a clock which does not wake the timer itself, but is not the default one
+        #[derive(Debug)]
+        struct OtherClock;
+        impl Clock for OtherClock {
+            fn now(&self) -> Instant {
+                Instant::now()
+            }
+        }
+        let config = ThreadConfig::new().name_prefix("clocked").affinity(cpus);
+        let _timer = Timer::<()>::with_clock_and_thread_config(OtherClock, &config).unwrap();
+        assert_eq!(
+            sched_getaffinity(timer_thread("clocked timer")).unwrap(),
+            cpus
+        );
+    }
+
+    // Find the thread with this name among the threads of the process
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn timer_thread(name: &str) -> nix::unistd::Pid {
+        std::fs::read_dir("/proc/self/task")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|task| {
+                let comm = std::fs::read_to_string(task.join("comm")).unwrap();
+                // the kernel truncates the thread names to 15 bytes
+                comm.trim_end() == name
+            })
+            .and_then(|task| task.file_name()?.to_str()?.parse().ok())
+            .map(nix::unistd::Pid::from_raw)
+            .expect("The timer thread was not found")
+    }
+
     #[test]
     fn multi_timout_order() {
         let mut event_loop = crate::EventLoop::try_new().unwrap();
@@ -494,6 +755,55 @@ mod tests {
         assert_eq!(&fired, &[2]);
     }
 
+    #[test]
+    fn next_deadline() {
+        let event_loop = crate::EventLoop::<()>::try_new().unwrap();
+        assert_eq!(event_loop.next_deadline(), None);
+
+        let timer = Timer::<()>::new().unwrap();
+        let timers = timer.handle();
+        event_loop
+            .handle()
+            .insert_source(timer, |(), _, _| {})
+            .unwrap();
+        assert_eq!(event_loop.next_deadline(), None);
+
+        let before = Instant::now();
+        let sooner = timers.add_timeout(Duration::from_secs(10), ());
+        timers.add_timeout(Duration::from_secs(20), ());
+        let deadline = event_loop.next_deadline().unwrap();
+        assert!(deadline >= before + Duration::from_secs(10));
+        assert!(deadline <= Instant::now() + Duration::from_secs(10));
+
+        // cancelled timeouts are not taken into account
+        timers.cancel_timeout(&sooner);
+        assert!(event_loop.next_deadline().unwrap() >= before + Duration::from_secs(20));
+    }
+
+    #[test]
+    fn time_to_next_event() {
+        let event_loop = crate::EventLoop::<()>::try_new().unwrap();
+        assert_eq!(event_loop.time_to_next_event(), None);
+
+        let timer = Timer::<()>::new().unwrap();
+        let timers = timer.handle();
+        event_loop
+            .handle()
+            .insert_source(timer, |(), _, _| {})
+            .unwrap();
+        timers.add_timeout(Duration::from_secs(10), ());
+        let left = event_loop.time_to_next_event().unwrap();
+        assert!(left <= Duration::from_secs(10));
+        assert!(left > Duration::from_secs(5));
+
+        // an idle callback is run without waiting
+        event_loop.handle().insert_idle(|_| {});
+        assert_eq!(
+            event_loop.time_to_next_event(),
+            Some(Duration::from_millis(0))
+        );
+    }
+
     #[test]
     fn timeout_cancel_early() {
         // Cancelling an earlier timeout should not prevent later ones from running
@@ -532,4 +842,33 @@ mod tests {
         // second dispatch gets the second timeout
         assert_eq!(timeout_count, 1);
     }
+
+    #[test]
+    fn cancel_in_same_batch() {
+        let mut event_loop = crate::EventLoop::<(Vec<u32>, Option<Timeout>)>::try_new().unwrap();
+        let timer = Timer::<u32>::new().unwrap();
+        let timer_handle = timer.handle();
+        event_loop
+            .handle()
+            .insert_source(timer, |val, handle, (fired, sibling)| {
+                fired.push(val);
+                if let Some(sibling) = sibling.take() {
+                    assert_eq!(handle.cancel_timeout(&sibling), Some(2));
+                }
+            })
+            .unwrap();
+
+        timer_handle.add_timeout(Duration::from_millis(10), 1);
+        let sibling = timer_handle.add_timeout(Duration::from_millis(11), 2);
+        // both timeouts expire before the loop is dispatched
+        std::thread::sleep(Duration::from_millis(30));
+        let mut data = (Vec::new(), Some(sibling));
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut data)
+            .unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut data)
+            .unwrap();
+        assert_eq!(data.0, &[1]);
+    }
 }
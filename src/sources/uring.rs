@@ -0,0 +1,661 @@
+//! An event source for io_uring completions
+//!
+//! Only available on Linux, with the `uring` cargo feature.
+//!
+//! The [`UringSource`] owns an io_uring instance, whose completions are signaled through an
+//! eventfd registered in the event loop. Operations are submitted through an [`UringHandle`],
+//! each tagged with a `user_data` of your choosing, and the source delivers a [`Completion`]
+//! carrying this `user_data` and the result of the operation once it is done.
+//!
+//! The buffers of the read and write operations are moved into the source for as long as the
+//! kernel uses them, and given back with their completion: a read buffer is truncated to the
+//! number of bytes read. The file descriptors are not owned by the source, and must be kept
+//! open until the operations using them are completed.
+//!
+//! The source requires Linux 5.6 or later; its creation fails on older kernels, or when
+//! io_uring is disabled on the system.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::fs::File;
+//! use std::os::unix::io::AsRawFd;
+//!
+//! use calloop::uring::UringSource;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let file = File::open("/etc/hostname").unwrap();
+//! let source = UringSource::new(32).expect("io_uring is not available");
+//! source
+//!     .handle()
+//!     .read(file.as_raw_fd(), vec![0; 1024], 0, 1)
+//!     .unwrap();
+//!
+//! handle
+//!     .insert_source(source, move |completion, _uring, _| {
+//!         // the file must be kept open until the read is completed
+//!         let _ = &file;
+//!         match completion.result {
+//!             Ok(len) => println!("Read {} bytes", len),
+//!             Err(e) => println!("Failed to read: {}", e),
+//!         }
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::eventfd::{eventfd, EfdFlags};
+use nix::unistd::{close, read};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+// The definitions of linux/io_uring.h used by the source
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+// introduced with Linux 5.6, along with the read and write operations
+const IORING_FEAT_RW_CUR_POS: u32 = 1 << 3;
+const IORING_REGISTER_EVENTFD: std::os::raw::c_uint = 4;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// The completion of an operation submitted to an [`UringSource`]
+#[derive(Debug)]
+pub struct Completion {
+    /// The `user_data` given when submitting the operation
+    pub user_data: u64,
+    /// The number of bytes read or written, or the error of the operation
+    pub result: io::Result<usize>,
+    /// The buffer of a read or write operation
+    ///
+    /// The buffer of a successful read is truncated to the number of bytes read.
+    pub buffer: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    user_data: u64,
+    buffer: Option<Vec<u8>>,
+    truncate: bool,
+}
+
+// A memory mapping of the rings shared with the kernel
+#[derive(Debug)]
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    // A pointer at the given offset of the mapping
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        (self.ptr as *mut u8).add(offset as usize) as *mut T
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Ring {
+    fd: RawFd,
+    eventfd: RawFd,
+    // the CQ ring shares the mapping of the SQ ring
+    _rings: Mapping,
+    sqes: Mapping,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cq_entries: u32,
+    cqes: *const Cqe,
+    pending: HashMap<u64, Pending>,
+    next_id: u64,
+}
+
+// The pointers of the ring are only accessed behind its mutex
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Ring> {
+        let mut params = Params::default();
+        let fd = Errno::result(unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        })? as RawFd;
+        let required = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_RW_CUR_POS;
+        if params.features & required != required {
+            let _ = close(fd);
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "io_uring requires Linux 5.6 or later",
+            ));
+        }
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len =
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>();
+        let mappings = Mapping::new(fd, sq_len.max(cq_len), IORING_OFF_SQ_RING).and_then(|rings| {
+            let sqes_len = params.sq_entries as usize * std::mem::size_of::<Sqe>();
+            Ok((rings, Mapping::new(fd, sqes_len, IORING_OFF_SQES)?))
+        });
+        let (rings, sqes) = match mappings {
+            Ok(mappings) => mappings,
+            Err(e) => {
+                let _ = close(fd);
+                return Err(e);
+            }
+        };
+        let eventfd = match eventfd(0, EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC) {
+            Ok(eventfd) => eventfd,
+            Err(e) => {
+                let _ = close(fd);
+                return Err(e.into());
+            }
+        };
+
+        let ring = unsafe {
+            Ring {
+                fd,
+                eventfd,
+                sq_head: rings.at(params.sq_off.head),
+                sq_tail: rings.at(params.sq_off.tail),
+                sq_mask: *rings.at::<u32>(params.sq_off.ring_mask),
+                sq_entries: params.sq_entries,
+                sq_array: rings.at(params.sq_off.array),
+                cq_head: rings.at(params.cq_off.head),
+                cq_tail: rings.at(params.cq_off.tail),
+                cq_mask: *rings.at::<u32>(params.cq_off.ring_mask),
+                cq_entries: params.cq_entries,
+                cqes: rings.at(params.cq_off.cqes),
+                _rings: rings,
+                sqes,
+                pending: HashMap::new(),
+                next_id: 0,
+            }
+        };
+        // from now on, the drop of the ring closes the file descriptors
+        Errno::result(unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                ring.fd,
+                IORING_REGISTER_EVENTFD,
+                &ring.eventfd as *const RawFd,
+                1,
+            )
+        })?;
+        Ok(ring)
+    }
+
+    fn submit(&mut self, mut sqe: Sqe, pending: Pending) -> io::Result<()> {
+        // the completion queue must not overflow
+        if self.pending.len() >= self.cq_entries as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "Too many operations in flight",
+            ));
+        }
+        let id = self.next_id;
+        sqe.user_data = id;
+        unsafe {
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            if tail.wrapping_sub((*self.sq_head).load(Ordering::Acquire)) >= self.sq_entries {
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "The submission queue is full",
+                ));
+            }
+            let index = tail & self.sq_mask;
+            *(self.sqes.ptr as *mut Sqe).add(index as usize) = sqe;
+            *self.sq_array.add(index as usize) = index;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+
+            loop {
+                let ret = libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd,
+                    1,
+                    0,
+                    0,
+                    std::ptr::null::<libc::sigset_t>(),
+                    0,
+                );
+                match Errno::result(ret) {
+                    Ok(0) => {
+                        // the kernel did not consume the entry, no completion will come for it
+                        (*self.sq_tail).store(tail, Ordering::Release);
+                        return Err(io::Error::new(
+                            io::ErrorKind::Other,
+                            "The operation was not submitted",
+                        ));
+                    }
+                    Ok(_) => break,
+                    Err(Errno::EINTR) => {}
+                    Err(e) => {
+                        // nothing was submitted, the kernel only reads the queue when entered
+                        (*self.sq_tail).store(tail, Ordering::Release);
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.insert(id, pending);
+        Ok(())
+    }
+
+    fn reap(&mut self) -> Vec<Completion> {
+        let mut completions = Vec::new();
+        unsafe {
+            let mut head = (*self.cq_head).load(Ordering::Relaxed);
+            let tail = (*self.cq_tail).load(Ordering::Acquire);
+            while head != tail {
+                let cqe = *self.cqes.add((head & self.cq_mask) as usize);
+                head = head.wrapping_add(1);
+                let pending = match self.pending.remove(&cqe.user_data) {
+                    Some(pending) => pending,
+                    None => continue,
+                };
+                let result = if cqe.res < 0 {
+                    Err(io::Error::from_raw_os_error(-cqe.res))
+                } else {
+                    Ok(cqe.res as usize)
+                };
+                let mut buffer = pending.buffer;
+                if let (Ok(len), Some(buffer), true) = (&result, &mut buffer, pending.truncate) {
+                    buffer.truncate(*len);
+                }
+                completions.push(Completion {
+                    user_data: pending.user_data,
+                    result,
+                    buffer,
+                });
+            }
+            (*self.cq_head).store(head, Ordering::Release);
+        }
+        completions
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // the kernel may still write into the buffers of the operations in flight
+        for (_, pending) in self.pending.drain() {
+            std::mem::forget(pending.buffer);
+        }
+        let _ = close(self.eventfd);
+        let _ = close(self.fd);
+    }
+}
+
+/// An event source delivering the completions of an io_uring instance
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct UringSource {
+    ring: Arc<Mutex<Ring>>,
+    eventfd: Generic<RawFd>,
+}
+
+impl UringSource {
+    /// Create a new io_uring instance with room for `entries` submissions
+    ///
+    /// The kernel rounds `entries` up to a power of two. At most twice as many operations
+    /// can be in flight at once.
+    pub fn new(entries: u32) -> io::Result<UringSource> {
+        let ring = Ring::new(entries)?;
+        let eventfd = ring.eventfd;
+        Ok(UringSource {
+            ring: Arc::new(Mutex::new(ring)),
+            eventfd: Generic::new(eventfd, Interest::READ, Mode::Level),
+        })
+    }
+
+    /// Get a handle to submit operations
+    pub fn handle(&self) -> UringHandle {
+        UringHandle {
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+/// A handle to submit operations to an [`UringSource`]
+///
+/// It can be cloned and sent across threads. The operations are submitted right away, and
+/// fail with a `WouldBlock` error when too many of them are already in flight.
+#[derive(Clone, Debug)]
+pub struct UringHandle {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl UringHandle {
+    /// Read from `fd` at `offset` into `buffer`, up to its length
+    ///
+    /// An `offset` of `u64::MAX` reads from the current position of the file.
+    /// Fails with an `InvalidInput` error if the buffer is larger than 4 GiB.
+    pub fn read(
+        &self,
+        fd: RawFd,
+        mut buffer: Vec<u8>,
+        offset: u64,
+        user_data: u64,
+    ) -> io::Result<()> {
+        let sqe = Sqe {
+            opcode: IORING_OP_READ,
+            fd,
+            off: offset,
+            addr: buffer.as_mut_ptr() as u64,
+            len: sqe_len(buffer.len())?,
+            ..Sqe::default()
+        };
+        self.submit(sqe, user_data, Some(buffer), true)
+    }
+
+    /// Write `buffer` to `fd` at `offset`
+    ///
+    /// An `offset` of `u64::MAX` writes at the current position of the file.
+    /// Fails with an `InvalidInput` error if the buffer is larger than 4 GiB.
+    pub fn write(&self, fd: RawFd, buffer: Vec<u8>, offset: u64, user_data: u64) -> io::Result<()> {
+        let sqe = Sqe {
+            opcode: IORING_OP_WRITE,
+            fd,
+            off: offset,
+            addr: buffer.as_ptr() as u64,
+            len: sqe_len(buffer.len())?,
+            ..Sqe::default()
+        };
+        self.submit(sqe, user_data, Some(buffer), false)
+    }
+
+    /// Flush the data and metadata of `fd` to the storage
+    pub fn fsync(&self, fd: RawFd, user_data: u64) -> io::Result<()> {
+        let sqe = Sqe {
+            opcode: IORING_OP_FSYNC,
+            fd,
+            ..Sqe::default()
+        };
+        self.submit(sqe, user_data, None, false)
+    }
+
+    /// The number of operations in flight
+    pub fn in_flight(&self) -> usize {
+        self.ring.lock().unwrap().pending.len()
+    }
+
+    fn submit(
+        &self,
+        sqe: Sqe,
+        user_data: u64,
+        buffer: Option<Vec<u8>>,
+        truncate: bool,
+    ) -> io::Result<()> {
+        // the heap memory of the buffer does not move when the vector is moved
+        self.ring.lock().unwrap().submit(
+            sqe,
+            Pending {
+                user_data,
+                buffer,
+                truncate,
+            },
+        )
+    }
+}
+
+// The length of a buffer, as stored in a submission entry
+fn sqe_len(len: usize) -> io::Result<u32> {
+    u32::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The buffer is too large for an io_uring operation",
+        )
+    })
+}
+
+impl EventSource for UringSource {
+    type Event = Completion;
+    type Metadata = UringHandle;
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let ring = &self.ring;
+        self.eventfd
+            .process_events(readiness, token, |_, &mut eventfd| {
+                let mut counter = [0u8; 8];
+                match read(eventfd, &mut counter) {
+                    Ok(_) | Err(Errno::EAGAIN) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                // the callback may submit new operations, it is run without holding the lock
+                let completions = ring.lock().unwrap().reap();
+                let mut handle = UringHandle { ring: ring.clone() };
+                for completion in completions {
+                    callback(completion, &mut handle);
+                }
+                Ok(PostAction::Continue)
+            })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.eventfd.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.eventfd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.eventfd.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn dispatch_all(
+        event_loop: &mut crate::EventLoop<Vec<Completion>>,
+        source: &UringHandle,
+        completions: &mut Vec<Completion>,
+    ) {
+        while source.in_flight() > 0 {
+            event_loop
+                .dispatch(Duration::from_millis(100), completions)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn write_and_read_file() {
+        let mut event_loop = crate::EventLoop::<Vec<Completion>>::try_new().unwrap();
+        let source = UringSource::new(8).unwrap();
+        let uring = source.handle();
+        event_loop
+            .handle()
+            .insert_source(source, |completion, _, completions| {
+                completions.push(completion)
+            })
+            .unwrap();
+
+        let path = std::env::temp_dir().join(format!("calloop-uring-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let fd = file.as_raw_fd();
+
+        let mut completions = Vec::new();
+        uring.write(fd, b"hello uring".to_vec(), 0, 1).unwrap();
+        dispatch_all(&mut event_loop, &uring, &mut completions);
+        uring.fsync(fd, 2).unwrap();
+        uring.read(fd, vec![0; 64], 6, 3).unwrap();
+        dispatch_all(&mut event_loop, &uring, &mut completions);
+
+        completions.sort_by_key(|completion| completion.user_data);
+        assert_eq!(completions.len(), 3);
+        assert_eq!(completions[0].result.as_ref().unwrap(), &11);
+        assert_eq!(completions[0].buffer.as_ref().unwrap(), b"hello uring");
+        assert!(completions[1].result.is_ok());
+        assert!(completions[1].buffer.is_none());
+        assert_eq!(completions[2].result.as_ref().unwrap(), &5);
+        assert_eq!(completions[2].buffer.as_ref().unwrap(), b"uring");
+    }
+
+    #[test]
+    fn failed_operation() {
+        let mut event_loop = crate::EventLoop::<Vec<Completion>>::try_new().unwrap();
+        let source = UringSource::new(8).unwrap();
+        let uring = source.handle();
+        event_loop
+            .handle()
+            .insert_source(source, |completion, _, completions| {
+                completions.push(completion)
+            })
+            .unwrap();
+
+        let mut completions = Vec::new();
+        uring.read(-1, vec![0; 8], 0, 42).unwrap();
+        dispatch_all(&mut event_loop, &uring, &mut completions);
+
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].user_data, 42);
+        let err = completions[0].result.as_ref().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+        // the buffer is given back untouched
+        assert_eq!(completions[0].buffer.as_ref().unwrap().len(), 8);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn oversized_buffer() {
+        assert_eq!(sqe_len(u32::MAX as usize).unwrap(), u32::MAX);
+        let err = sqe_len(u32::MAX as usize + 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
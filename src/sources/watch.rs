@@ -0,0 +1,185 @@
+//! A shared state whose changes are notified to the event loop
+//!
+//! Create a watch using [`watch()`](watch) from some state shared behind an
+//! `Arc<Mutex<T>>`. It returns a [`Notifier`] that can be cloned and sent accross threads,
+//! and a [`WatchSource`] that can be inserted into an [`EventLoop`](crate::EventLoop).
+//!
+//! After changing the state, a thread calls [`Notifier::notify`] to wake the event loop,
+//! which then invokes the callback of the source with the locked state as its metadata.
+//! Notifications sent before the event loop had time to process them are coalesced: the
+//! callback is invoked once and sees the latest state.
+//!
+//! The state stays locked while the callback runs, so the threads trying to change it
+//! meanwhile are blocked. The callback should thus be kept short.
+//!
+//! ```
+//! # extern crate calloop;
+//! use std::sync::{Arc, Mutex};
+//!
+//! use calloop::watch::watch;
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! let progress = Arc::new(Mutex::new(0u32));
+//! let (notifier, source) = watch(progress.clone()).unwrap();
+//!
+//! handle
+//!     .insert_source(source, |(), progress, _| println!("Progress: {}%", progress))
+//!     .unwrap();
+//!
+//! std::thread::spawn(move || {
+//!     for _ in 0..100 {
+//!         notifier.update(|progress| *progress += 1);
+//!     }
+//! });
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
+
+use super::ping::{make_ping, Ping, PingSource};
+
+/// Create a watch over some shared state
+///
+/// The returned [`Notifier`] is used to notify the changes of the state to the
+/// [`WatchSource`].
+pub fn watch<T>(state: Arc<Mutex<T>>) -> std::io::Result<(Notifier<T>, WatchSource<T>)> {
+    let (ping, source) = make_ping()?;
+    Ok((
+        Notifier {
+            state: state.clone(),
+            ping,
+        },
+        WatchSource { state, source },
+    ))
+}
+
+fn lock<T>(state: &Mutex<T>) -> MutexGuard<'_, T> {
+    // a thread panicking while changing the state does not make it unusable
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The notifying end of a watch
+///
+/// It can be cloned and sent accross threads (if `T` is `Send`). Once all the notifiers
+/// are dropped, the [`WatchSource`] removes itself from the event loop.
+#[derive(Debug)]
+pub struct Notifier<T> {
+    state: Arc<Mutex<T>>,
+    ping: Ping,
+}
+
+impl<T> Clone for Notifier<T> {
+    fn clone(&self) -> Notifier<T> {
+        Notifier {
+            state: self.state.clone(),
+            ping: self.ping.clone(),
+        }
+    }
+}
+
+impl<T> Notifier<T> {
+    /// Notify the event loop that the state changed
+    pub fn notify(&self) {
+        self.ping.ping();
+    }
+
+    /// Change the state and notify the event loop
+    pub fn update<F: FnOnce(&mut T)>(&self, f: F) {
+        f(&mut lock(&self.state));
+        self.notify();
+    }
+}
+
+/// The receiving end of a watch
+///
+/// It generates a `()` event whenever it was notified, and gives the locked state to the
+/// callback as its metadata.
+#[derive(Debug)]
+pub struct WatchSource<T> {
+    state: Arc<Mutex<T>>,
+    source: PingSource,
+}
+
+impl<T> EventSource for WatchSource<T> {
+    type Event = ();
+    type Metadata = T;
+    type Ret = ();
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> std::io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let state = &self.state;
+        self.source.process_events(readiness, token, |(), &mut ()| {
+            callback((), &mut lock(state))
+        })
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.source.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.source.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+        self.source.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn coalesced_notifications() {
+        let mut event_loop = crate::EventLoop::<Vec<u32>>::try_new().unwrap();
+        let state = Arc::new(Mutex::new(0u32));
+        let (notifier, source) = watch(state).unwrap();
+
+        event_loop
+            .handle()
+            .insert_source(source, |(), state, seen| seen.push(*state))
+            .unwrap();
+
+        let mut seen = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        assert!(seen.is_empty());
+
+        std::thread::spawn(move || {
+            notifier.update(|state| *state = 1);
+            notifier.update(|state| *state = 2);
+        })
+        .join()
+        .unwrap();
+
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut seen)
+            .unwrap();
+        // a single callback with the latest state
+        assert_eq!(seen, &[2]);
+    }
+}
@@ -0,0 +1,304 @@
+//! Hooks integrating the connection of a Wayland client into an event loop
+//!
+//! A Wayland client reads its protocol events from the file descriptor of its connection, and
+//! must flush its outgoing requests before blocking. The reading follows a precise sequence,
+//! which the [`WaylandSource`] maps to the phases of a dispatching pass of the event loop:
+//!
+//! - when the pass starts, before the loop polls, the source announces its intent to read
+//!   with `prepare_read`. If events are already queued, this fails: the callback is invoked
+//!   to dispatch them, and the source tries again. The outgoing requests are then flushed,
+//!   so that the server can answer them while the loop waits.
+//! - if the file descriptor is readable, the source reads the events with `read_events`, and
+//!   invokes the callback to dispatch them.
+//! - otherwise, the read is cancelled with `cancel_read` once the events of the pass are
+//!   processed.
+//! - at the end of the pass, the requests sent by the callbacks are
+//!   [flushed](crate::EventSource#method.flush) as well.
+//!
+//! This module does not depend on any Wayland library. The connection is abstracted by the
+//! [`Connection`] trait, whose methods map to the functions of `libwayland-client` of the
+//! same names, or to their equivalent in the Rust bindings. The callback of the source is
+//! given the connection, and dispatches the pending events with the state of the program,
+//! typically with `wl_display_dispatch_pending`.
+//!
+//! A flush failing because the socket is full is retried at the next flush. The other errors
+//! of the flush are returned by the source at the end of the pass, or logged when they happen
+//! before polling.
+//!
+//! ```no_run
+//! # extern crate calloop;
+//! use std::io;
+//! use std::os::unix::io::{AsRawFd, RawFd};
+//!
+//! use calloop::wayland::{Connection, WaylandSource};
+//!
+//! // the connection of the Wayland library used by the program
+//! struct Display {
+//!     // ...
+//! #   fd: RawFd,
+//! }
+//!
+//! impl AsRawFd for Display {
+//!     fn as_raw_fd(&self) -> RawFd {
+//!         // wl_display_get_fd
+//! #       self.fd
+//!     }
+//! }
+//!
+//! impl Connection for Display {
+//!     fn prepare_read(&mut self) -> bool {
+//!         // wl_display_prepare_read() == 0
+//! #       true
+//!     }
+//!     fn read_events(&mut self) -> io::Result<()> {
+//!         // wl_display_read_events
+//! #       Ok(())
+//!     }
+//!     fn cancel_read(&mut self) {
+//!         // wl_display_cancel_read
+//!     }
+//!     fn flush(&mut self) -> io::Result<()> {
+//!         // wl_display_flush
+//! #       Ok(())
+//!     }
+//! }
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! # let display = Display { fd: 0 };
+//! handle
+//!     .insert_source(WaylandSource::new(display), |(), display, _state| {
+//!         // wl_display_dispatch_pending, with the state of the program
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// The connection of a Wayland client, as needed by a [`WaylandSource`]
+///
+/// Its file descriptor is the one of the connection to the server.
+pub trait Connection: AsRawFd {
+    /// Announce the intent to read the events from the file descriptor
+    ///
+    /// Returns `false` if some events are already queued, which must be dispatched first.
+    fn prepare_read(&mut self) -> bool;
+
+    /// Read the events from the file descriptor, after a successful `prepare_read`
+    fn read_events(&mut self) -> io::Result<()>;
+
+    /// Cancel the read announced by a successful `prepare_read`
+    fn cancel_read(&mut self);
+
+    /// Send the outgoing requests to the server
+    ///
+    /// Fails with a `WouldBlock` error if the socket is full.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// An event source reading the events of a Wayland connection
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct WaylandSource<C: Connection> {
+    connection: C,
+    fd: Generic<RawFd>,
+    // whether a read was announced and must be completed or cancelled
+    reading: bool,
+}
+
+impl<C: Connection> WaylandSource<C> {
+    /// Read the events of this connection
+    pub fn new(connection: C) -> WaylandSource<C> {
+        WaylandSource {
+            fd: Generic::new(connection.as_raw_fd(), Interest::READ, Mode::Level),
+            connection,
+            reading: false,
+        }
+    }
+
+    /// Access the connection
+    pub fn connection(&self) -> &C {
+        &self.connection
+    }
+
+    /// Mutably access the connection
+    pub fn connection_mut(&mut self) -> &mut C {
+        &mut self.connection
+    }
+}
+
+// Flush the outgoing requests, a full socket being retried later
+fn flush<C: Connection>(connection: &mut C) -> io::Result<()> {
+    match connection.flush() {
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+        ret => ret,
+    }
+}
+
+impl<C: Connection> EventSource for WaylandSource<C> {
+    type Event = ();
+    type Metadata = C;
+    type Ret = ();
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> io::Result<PostAction>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let (connection, reading) = (&mut self.connection, &mut self.reading);
+        self.fd.process_events(readiness, token, |_, _| {
+            if std::mem::replace(reading, false) {
+                connection.read_events()?;
+            }
+            callback((), connection);
+            Ok(PostAction::Continue)
+        })
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.fd.register(poll, token_factory)
+    }
+
+    fn reregister(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> io::Result<()> {
+        self.fd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> io::Result<()> {
+        self.fd.unregister(poll)
+    }
+
+    fn on_dispatch_start<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if !self.fd.is_registered() || self.reading {
+            return;
+        }
+        while !self.connection.prepare_read() {
+            // dispatch the queued events, which could be followed by more
+            callback((), &mut self.connection);
+        }
+        self.reading = true;
+        if let Err(e) = flush(&mut self.connection) {
+            log::warn!("[calloop] Failed to flush the Wayland connection: {:?}", e);
+        }
+    }
+
+    fn on_dispatch_end(&mut self) {
+        if std::mem::replace(&mut self.reading, false) {
+            // the connection was not readable during this pass
+            self.connection.cancel_read();
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        flush(&mut self.connection)?;
+        Ok(PostAction::Continue)
+    }
+}
+
+impl<C: Connection> Drop for WaylandSource<C> {
+    fn drop(&mut self) {
+        if self.reading {
+            self.connection.cancel_read();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::*;
+
+    // A connection recording the calls of the source
+    struct Mock {
+        socket: UnixStream,
+        queued: u32,
+        calls: Rc<RefCell<Vec<&'static str>>>,
+    }
+
+    impl AsRawFd for Mock {
+        fn as_raw_fd(&self) -> RawFd {
+            self.socket.as_raw_fd()
+        }
+    }
+
+    impl Connection for Mock {
+        fn prepare_read(&mut self) -> bool {
+            self.calls.borrow_mut().push("prepare_read");
+            self.queued == 0
+        }
+
+        fn read_events(&mut self) -> io::Result<()> {
+            self.calls.borrow_mut().push("read_events");
+            let mut buffer = [0u8; 16];
+            self.queued += self.socket.read(&mut buffer)? as u32;
+            Ok(())
+        }
+
+        fn cancel_read(&mut self) {
+            self.calls.borrow_mut().push("cancel_read");
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.calls.borrow_mut().push("flush");
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_sequence() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+        let (mut server, socket) = UnixStream::pair().unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let mock = Mock {
+            socket,
+            queued: 0,
+            calls: calls.clone(),
+        };
+        event_loop
+            .handle()
+            .insert_source(WaylandSource::new(mock), |(), mock, &mut ()| {
+                mock.calls.borrow_mut().push("dispatch");
+                mock.queued = 0;
+            })
+            .unwrap();
+
+        // nothing to read, the read is cancelled
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            &["prepare_read", "flush", "cancel_read", "flush"]
+        );
+
+        // the events are read once the connection is readable
+        calls.borrow_mut().clear();
+        server.write_all(&[1]).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+        assert_eq!(
+            *calls.borrow(),
+            &["prepare_read", "flush", "read_events", "dispatch", "flush"]
+        );
+    }
+}
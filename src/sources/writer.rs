@@ -0,0 +1,301 @@
+//! A buffered writer event source
+//!
+//! The [`BufferedWriter`] wraps a FD-backed IO object, like a socket, and manages a buffer of
+//! outgoing data for it. Data queued for writing (either from the callback or through
+//! [`Dispatcher::as_source_mut`](crate::Dispatcher#method.as_source_mut)) is written during the
+//! [flush phase](crate::EventSource#method.flush) of the event loop, at the end of each
+//! dispatching pass. If the object cannot accept all the data right away, the source
+//! registers for writability and continues writing as the object becomes writable, then
+//! drops its writable interest once its buffer is empty.
+//!
+//! The events generated by this source are the read [`Readiness`](crate::Readiness) of the
+//! object, and your callback is given access to it through a [`WriteBuffer`], allowing you
+//! to queue responses.
+//!
+//! ```
+//! # extern crate calloop;
+//! use std::io::Read;
+//! use std::os::unix::net::UnixStream;
+//!
+//! use calloop::{writer::BufferedWriter, PostAction};
+//!
+//! # fn main() {
+//! # let mut event_loop = calloop::EventLoop::<()>::try_new()
+//! #                .expect("Failed to initialize the event loop!");
+//! # let handle = event_loop.handle();
+//! # let (stream, _other) = UnixStream::pair().unwrap();
+//! handle.insert_source(
+//!     BufferedWriter::new(stream),
+//!     |_readiness, buffer, _shared_data| {
+//!         let mut request = [0u8; 32];
+//!         let len = buffer.get_mut().read(&mut request)?;
+//!         // echo the request back, it'll be written during the flush phase
+//!         buffer.queue(&request[..len]);
+//!         Ok(PostAction::Continue)
+//!     }
+//! );
+//! # }
+//! ```
+
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An IO object with its buffer of outgoing data
+#[derive(Debug)]
+pub struct WriteBuffer<F> {
+    file: F,
+    buffer: Vec<u8>,
+}
+
+impl<F: Write> WriteBuffer<F> {
+    /// Access the underlying IO object
+    pub fn get_ref(&self) -> &F {
+        &self.file
+    }
+
+    /// Mutably access the underlying IO object
+    ///
+    /// Writing directly to the object bypasses the buffer, possibly interleaving with data
+    /// that was queued before.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.file
+    }
+
+    /// Queue some data to be written
+    pub fn queue(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The number of bytes waiting to be written
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Write as much of the buffer as possible
+    fn try_flush(&mut self) -> io::Result<()> {
+        while !self.buffer.is_empty() {
+            match self.file.write(&self.buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write buffered data",
+                    ))
+                }
+                Ok(n) => {
+                    self.buffer.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F: AsRawFd> AsRawFd for WriteBuffer<F> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// An event source writing buffered data to a FD-backed IO object
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct BufferedWriter<F: AsRawFd + Write> {
+    inner: Generic<WriteBuffer<F>>,
+}
+
+impl<F: AsRawFd + Write> BufferedWriter<F> {
+    /// Wrap an IO object, monitoring it for readability
+    ///
+    /// The object must be in non-blocking mode.
+    pub fn new(file: F) -> BufferedWriter<F> {
+        BufferedWriter {
+            inner: Generic::new(
+                WriteBuffer {
+                    file,
+                    buffer: Vec::new(),
+                },
+                Interest::READ,
+                Mode::Level,
+            ),
+        }
+    }
+
+    /// Access the buffer of the source
+    pub fn buffer(&mut self) -> &mut WriteBuffer<F> {
+        &mut self.inner.file
+    }
+
+    /// Unwrap the source to retrieve the underlying IO object
+    ///
+    /// Any data that has not yet been written is discarded.
+    pub fn unwrap(self) -> F {
+        self.inner.unwrap().file
+    }
+
+    // Update the writable interest depending on the state of the buffer, returning
+    // whether it changed
+    fn update_interest(&mut self) -> bool {
+        let writable = self.inner.file.pending() > 0;
+        if self.inner.interest.writable == writable {
+            return false;
+        }
+        self.inner.interest.writable = writable;
+        true
+    }
+}
+
+impl<F: AsRawFd + Write> EventSource for BufferedWriter<F> {
+    type Event = Readiness;
+    type Metadata = WriteBuffer<F>;
+    type Ret = io::Result<PostAction>;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> std::io::Result<PostAction>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let action = self
+            .inner
+            .process_events(readiness, token, |readiness, buffer| {
+                if readiness.writable {
+                    buffer.try_flush()?;
+                }
+                if readiness.readable || readiness.error {
+                    callback(readiness, buffer)
+                } else {
+                    Ok(PostAction::Continue)
+                }
+            })?;
+        match action {
+            PostAction::Continue if self.update_interest() => Ok(PostAction::Reregister),
+            action => Ok(action),
+        }
+    }
+
+    fn register(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.update_interest();
+        self.inner.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> std::io::Result<()> {
+        self.update_interest();
+        self.inner.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> std::io::Result<()> {
+        self.inner.unregister(poll)
+    }
+
+    fn flush(&mut self) -> io::Result<PostAction> {
+        if !self.inner.is_registered() {
+            // the source is disabled, wait for it to be enabled again
+            return Ok(PostAction::Continue);
+        }
+        self.inner.file.try_flush()?;
+        if self.update_interest() {
+            Ok(PostAction::Reregister)
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.inner.file.pending()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Dispatcher;
+
+    #[test]
+    fn echo() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        rx.set_nonblocking(true).unwrap();
+
+        event_loop
+            .handle()
+            .insert_source(BufferedWriter::new(rx), |_, buffer, _| {
+                let mut request = [0u8; 32];
+                let len = buffer.get_mut().read(&mut request)?;
+                buffer.queue(&request[..len]);
+                Ok(PostAction::Continue)
+            })
+            .unwrap();
+
+        tx.write_all(b"ping").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(0), &mut ())
+            .unwrap();
+
+        // the answer was written during the flush phase of this same dispatch
+        tx.set_nonblocking(true).unwrap();
+        let mut answer = [0u8; 32];
+        let len = tx.read(&mut answer).unwrap();
+        assert_eq!(&answer[..len], b"ping");
+    }
+
+    #[test]
+    fn write_larger_than_socket_buffer() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+        rx.set_nonblocking(true).unwrap();
+        tx.set_nonblocking(true).unwrap();
+
+        let dispatcher =
+            Dispatcher::new(BufferedWriter::new(rx), |_, _, _| Ok(PostAction::Continue));
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        let data = vec![42u8; 4 * 1024 * 1024];
+        dispatcher.as_source_mut().buffer().queue(&data);
+
+        // the socket cannot accept all the data at once, the source keeps writing
+        // as the peer reads
+        let mut received = Vec::new();
+        let mut chunk = vec![0u8; 64 * 1024];
+        while received.len() < data.len() {
+            event_loop
+                .dispatch(Duration::from_millis(10), &mut ())
+                .unwrap();
+            loop {
+                match tx.read(&mut chunk) {
+                    Ok(n) => received.extend_from_slice(&chunk[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        }
+        assert_eq!(received, data);
+        assert_eq!(dispatcher.as_source_mut().buffer().pending(), 0);
+    }
+}
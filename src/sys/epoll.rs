@@ -1,6 +1,6 @@
 use std::{io, os::unix::io::RawFd};
 
-use super::{Interest, Mode, PollEvent, Readiness, Token};
+use super::{Interest, Mode, PollEvent, Readiness, Token, EVENTS_CAPACITY};
 
 use nix::sys::epoll;
 
@@ -38,11 +38,15 @@ impl Epoll {
         Ok(Epoll { epoll_fd })
     }
 
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.epoll_fd
+    }
+
     pub(crate) fn poll(
         &mut self,
         timeout: Option<std::time::Duration>,
     ) -> io::Result<Vec<PollEvent>> {
-        let mut buffer = [epoll::EpollEvent::empty(); 32];
+        let mut buffer = [epoll::EpollEvent::empty(); EVENTS_CAPACITY];
         let timeout = timeout.map(|d| d.as_millis() as isize).unwrap_or(-1);
         let n_ready = epoll::epoll_wait(self.epoll_fd, &mut buffer, timeout)?;
         let events = buffer
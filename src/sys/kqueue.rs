@@ -2,7 +2,7 @@ use std::{io, os::unix::io::RawFd};
 
 use nix::sys::event::{kevent, kevent_ts, kqueue, EventFilter, EventFlag, FilterFlag, KEvent};
 
-use super::{Interest, Mode, PollEvent, Readiness, Token};
+use super::{Interest, Mode, PollEvent, Readiness, Token, EVENTS_CAPACITY};
 
 pub struct Kqueue {
     kq: RawFd,
@@ -22,6 +22,10 @@ impl Kqueue {
         Ok(Kqueue { kq })
     }
 
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.kq
+    }
+
     pub(crate) fn poll(
         &mut self,
         timeout: Option<std::time::Duration>,
@@ -33,7 +37,7 @@ impl Kqueue {
             FilterFlag::empty(),
             0,
             0,
-        ); 32];
+        ); EVENTS_CAPACITY];
 
         let nevents = match timeout {
             None => kevent_ts(self.kq, &[], &mut buffer, None),
@@ -1,4 +1,4 @@
-use std::{io, os::unix::io::RawFd};
+use std::{collections::HashMap, io, os::unix::io::RawFd};
 
 use crate::loop_logic::CalloopKey;
 
@@ -23,6 +23,9 @@ mod kqueue;
 use kqueue::Kqueue as Poller;
 use slotmap::Key;
 
+// the number of events the polling system returns at most at once
+pub(crate) const EVENTS_CAPACITY: usize = 32;
+
 /// Possible modes for registering a file descriptor
 #[derive(Copy, Clone, Debug)]
 pub enum Mode {
@@ -106,6 +109,21 @@ pub(crate) struct PollEvent {
     pub(crate) token: Token,
 }
 
+/// A file descriptor as currently registered in the polling system
+///
+/// This is the interest and mode the source last passed to [`Poll::register`] or
+/// [`Poll::reregister`], see
+/// [`EventLoop::effective_registrations`](crate::EventLoop#method.effective_registrations).
+#[derive(Copy, Clone, Debug)]
+pub struct Registration {
+    /// The registered file descriptor
+    pub fd: RawFd,
+    /// The interest it is registered with
+    pub interest: Interest,
+    /// The mode it is registered with
+    pub mode: Mode,
+}
+
 /// Factory for creating tokens in your registrations
 ///
 /// When composing event sources, each sub-source needs to
@@ -141,7 +159,12 @@ impl TokenFactory {
 /// to identify which sub-source produced them.
 ///
 /// You should forward it to the [`Poll`] when registering your file descriptors.
-#[derive(Clone, Copy, Debug, PartialEq)]
+///
+/// Tokens are always assigned by the event loop, from the slot of the source they belong
+/// to: the loop uses them to find the source to dispatch an event to, so they cannot be
+/// chosen freely. They are private to the [`Poll`] of the loop and cannot collide with the
+/// registrations of another polling layer, which should use its own [`Poll`] instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Token {
     pub(crate) key: CalloopKey,
     pub(crate) sub_id: u32,
@@ -178,6 +201,12 @@ impl Token {
 /// source and delegate the implementations to it.
 pub struct Poll {
     poller: Poller,
+    // the tokens and interests of the currently registered file descriptors
+    registrations: HashMap<RawFd, (Token, Interest, Mode)>,
+    // the number of registered sources that need the loop not to wait for events
+    busy_sources: usize,
+    // the tokens of the registered sources the loop wakes up at their deadline
+    deadline_sources: Vec<Token>,
 }
 
 impl std::fmt::Debug for Poll {
@@ -187,19 +216,102 @@ impl std::fmt::Debug for Poll {
 }
 
 impl Poll {
-    pub(crate) fn new() -> io::Result<Poll> {
+    /// Create a new polling system
+    ///
+    /// You only need this to create an [`EventLoop`](crate::EventLoop) from an existing
+    /// `Poll`, using [`EventLoop::from_poll`](crate::EventLoop#method.from_poll).
+    pub fn new() -> io::Result<Poll> {
         Ok(Poll {
             poller: Poller::new()?,
+            registrations: HashMap::new(),
+            busy_sources: 0,
+            deadline_sources: Vec::new(),
         })
     }
 
+    /// Check whether any file descriptor is registered for the given source
+    #[cfg(feature = "test-util")]
+    pub(crate) fn has_registrations_for(&self, key: CalloopKey) -> bool {
+        self.registrations
+            .values()
+            .any(|(token, _, _)| token.key == key)
+    }
+
+    /// The tokens and interests of the file descriptors registered for the given source
+    pub(crate) fn registrations_for(&self, key: CalloopKey) -> Vec<(Token, Interest)> {
+        self.registrations
+            .values()
+            .filter(|(token, _, _)| token.key == key)
+            .map(|&(token, interest, _)| (token, interest))
+            .collect()
+    }
+
+    /// The file descriptors registered for the given source, as last passed to the poller
+    pub(crate) fn effective_registrations(&self, key: CalloopKey) -> Vec<Registration> {
+        let mut registrations: Vec<_> = self
+            .registrations
+            .iter()
+            .filter(|(_, (token, _, _))| token.key == key)
+            .map(|(&fd, &(_, interest, mode))| Registration { fd, interest, mode })
+            .collect();
+        registrations.sort_by_key(|registration| registration.fd);
+        registrations
+    }
+
     pub(crate) fn poll(
         &mut self,
         timeout: Option<std::time::Duration>,
     ) -> io::Result<Vec<PollEvent>> {
+        if self.has_busy_sources() {
+            return self.poller.poll(Some(std::time::Duration::from_millis(0)));
+        }
         self.poller.poll(timeout)
     }
 
+    // Sources without file descriptor, which the loop must check at each pass
+    pub(crate) fn add_busy_source(&mut self) {
+        self.busy_sources += 1;
+    }
+
+    pub(crate) fn remove_busy_source(&mut self) {
+        self.busy_sources -= 1;
+    }
+
+    pub(crate) fn has_busy_sources(&self) -> bool {
+        self.busy_sources > 0
+    }
+
+    // Sources without file descriptor, which the loop wakes up at their deadline
+    pub(crate) fn add_deadline_source(&mut self, token: Token) {
+        self.deadline_sources.push(token);
+    }
+
+    pub(crate) fn remove_deadline_source(&mut self, token: Token) {
+        self.deadline_sources.retain(|&t| t != token);
+    }
+
+    pub(crate) fn deadline_sources(&self) -> &[Token] {
+        &self.deadline_sources
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.poller.as_raw_fd()
+    }
+
+    /// Check whether some events are waiting to be polled, without consuming them
+    pub(crate) fn has_pending_events(&self) -> io::Result<bool> {
+        use nix::poll::{poll, PollFd, PollFlags};
+        // the polling system's own fd is readable when it has pending events
+        let mut fds = [PollFd::new(self.as_raw_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, 0) {
+                Ok(n) => return Ok(n > 0),
+                Err(nix::errno::Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
     /// Register a new file descriptor for polling
     ///
     /// The file descriptor will be registered with given interest,
@@ -224,7 +336,9 @@ impl Poll {
                 "Invalid Token provided to register().",
             ));
         }
-        self.poller.register(fd, interest, mode, token)
+        self.poller.register(fd, interest, mode, token)?;
+        self.registrations.insert(fd, (*token, interest, mode));
+        Ok(())
     }
 
     /// Update the registration for a file descriptor
@@ -249,7 +363,9 @@ impl Poll {
                 "Invalid Token provided to reregister().",
             ));
         }
-        self.poller.reregister(fd, interest, mode, token)
+        self.poller.reregister(fd, interest, mode, token)?;
+        self.registrations.insert(fd, (*token, interest, mode));
+        Ok(())
     }
 
     /// Unregister a file descriptor
@@ -257,6 +373,10 @@ impl Poll {
     /// This file descriptor will no longer generate events. Fails if the
     /// provided file descriptor is not currently registered.
     pub fn unregister(&mut self, fd: RawFd) -> io::Result<()> {
-        self.poller.unregister(fd)
+        let ret = self.poller.unregister(fd);
+        // the fd may have been closed in the meantime, in which case the polling
+        // system forgot about it anyway
+        self.registrations.remove(&fd);
+        ret
     }
 }
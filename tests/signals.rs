@@ -19,15 +19,53 @@ mod test {
     extern crate nix;
 
     use std::io;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
-    use self::calloop::signals::{Signal, Signals};
+    use self::calloop::signals::{
+        report_unwatched_signal, SigHandler, Signal, Signals, SignalsError,
+    };
+    use self::calloop::threaded_signals::ThreadedSignals;
+    use self::calloop::timer::ThreadConfig;
     use self::calloop::{Dispatcher, EventLoop};
 
     use self::nix::sys::signal::{kill, SigSet};
+    use self::nix::sys::signalfd::SfdFlags;
     use self::nix::unistd::Pid;
 
-    pub const TESTS: &[fn()] = &[single_usr1, usr2_added_afterwards, usr2_signal_removed];
+    pub const TESTS: &[fn()] = &[
+        single_usr1,
+        usr2_added_afterwards,
+        usr2_signal_removed,
+        buffered_delivery,
+        add_signal_from_callback,
+        #[cfg(feature = "test-util")]
+        removed_source_is_unregistered,
+        ignored_signal_then_watched,
+        sigchld_child_info,
+        effective_mask,
+        delivery_counts,
+        one_signal_per_pass,
+        #[cfg(feature = "test-util")]
+        collected_signals,
+        reinit_in_child,
+        unmanaged_mask,
+        live_signalfd_changes,
+        blocking_wait,
+        typed_info,
+        posix_timer,
+        blocking_mode,
+        shared_signal,
+        adopted_signalfd,
+        sender_enrichment,
+        recorded_event,
+        config_round_trip,
+        ordered_delivery,
+        threaded_delivery,
+        unwatched_default,
+        drop_error_policy,
+        remove_from_callback,
+        thread_handoff,
+    ];
 
     pub fn reset_mask() {
         SigSet::empty().thread_set_mask().unwrap();
@@ -44,6 +82,7 @@ mod test {
                 Signals::new(&[Signal::SIGUSR1]).unwrap(),
                 move |evt, &mut (), rcv| {
                     assert!(evt.signal() == Signal::SIGUSR1);
+                    assert!(evt.received_at() <= Instant::now());
                     *rcv = true;
                 },
             )
@@ -138,4 +177,805 @@ mod test {
         // we should get back the pending SIGUSR2 now
         assert_eq!(signal_received, Some(Signal::SIGUSR2));
     }
+
+    fn buffered_delivery() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+
+        let mut received = Vec::new();
+        let dispatcher = Dispatcher::new(
+            Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2])
+                .unwrap()
+                .with_buffered_delivery(1),
+            move |evt, &mut (), rcv: &mut Vec<Signal>| {
+                rcv.push(evt.signal());
+            },
+        );
+
+        let _signal_token = event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        // send ourselves two signals, the queue can only hold one
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+
+        assert_eq!(received.len(), 1);
+        assert_eq!(dispatcher.as_source_ref().dropped_events(), 1);
+        assert_eq!(
+            dispatcher.as_source_ref().stats(),
+            calloop::BufferStats {
+                sent: 2,
+                delivered: 1,
+                dropped: 1,
+                occupancy: 0,
+            }
+        );
+    }
+
+    fn add_signal_from_callback() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+
+        let mut received = Vec::new();
+        let signals = Signals::new(&[Signal::SIGHUP]).unwrap();
+        let signals_handle = signals.handle();
+
+        let _signal_token = event_loop
+            .handle()
+            .insert_source(signals, move |evt, &mut (), rcv: &mut Vec<Signal>| {
+                if evt.signal() == Signal::SIGHUP {
+                    signals_handle.add_signals(&[Signal::SIGUSR1]);
+                }
+                rcv.push(evt.signal());
+            })
+            .map_err(Into::<io::Error>::into)
+            .unwrap();
+
+        kill(Pid::this(), Signal::SIGHUP).unwrap();
+
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGHUP]);
+
+        // SIGUSR1 is now watched by the source
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGHUP, Signal::SIGUSR1]);
+    }
+
+    #[cfg(feature = "test-util")]
+    fn removed_source_is_unregistered() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+
+        let signal_token = event_loop
+            .handle()
+            .insert_source(
+                Signals::new(&[Signal::SIGUSR1]).unwrap(),
+                move |_, &mut (), _| {},
+            )
+            .map_err(Into::<io::Error>::into)
+            .unwrap();
+
+        assert!(event_loop.is_registered(&signal_token));
+        assert!(SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+
+        event_loop.handle().remove(signal_token);
+
+        // the signalfd is no longer registered, and the thread mask has been restored
+        assert!(!event_loop.is_registered(&signal_token));
+        assert!(!SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+    }
+
+    fn ignored_signal_then_watched() {
+        Signals::ignore(&[Signal::SIGUSR2]).unwrap();
+        // if it was not ignored, this would terminate the process
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+
+        // watching the signal restores its delivery
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let mut signal_received = false;
+        let _signal_source = event_loop
+            .handle()
+            .insert_source(
+                Signals::new(&[Signal::SIGUSR2]).unwrap(),
+                move |evt, &mut (), rcv| {
+                    assert!(evt.signal() == Signal::SIGUSR2);
+                    *rcv = true;
+                },
+            )
+            .map_err(Into::<io::Error>::into)
+            .unwrap();
+
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut signal_received)
+            .unwrap();
+        assert!(signal_received);
+    }
+
+    fn sigchld_child_info() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let mut child_info = None;
+        let _signal_source = event_loop
+            .handle()
+            .insert_source(
+                Signals::new(&[Signal::SIGCHLD]).unwrap(),
+                move |evt, &mut (), info| {
+                    assert!(evt.child_user_time().is_some());
+                    assert!(evt.child_system_time().is_some());
+                    *info = Some((evt.child_pid().unwrap(), evt.child_status().unwrap()));
+                },
+            )
+            .map_err(Into::<io::Error>::into)
+            .unwrap();
+
+        let mut child = std::process::Command::new("sh")
+            .args(&["-c", "exit 3"])
+            .spawn()
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while child_info.is_none() && Instant::now() < deadline {
+            event_loop
+                .dispatch(Some(Duration::from_millis(100)), &mut child_info)
+                .unwrap();
+        }
+        assert_eq!(child_info, Some((Pid::from_raw(child.id() as i32), 3)));
+        // the child still needs to be reaped
+        assert_eq!(child.wait().unwrap().code(), Some(3));
+    }
+
+    fn effective_mask() {
+        // a signal blocked before the creation of the source
+        let mut hup = SigSet::empty();
+        hup.add(Signal::SIGHUP);
+        hup.thread_block().unwrap();
+
+        let mut signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        let blocked = signals.add_signals(&[Signal::SIGUSR2]).unwrap();
+        assert!(blocked.contains(Signal::SIGUSR1));
+        assert!(blocked.contains(Signal::SIGUSR2));
+        assert!(blocked.contains(Signal::SIGHUP));
+
+        let watched = signals.signals();
+        assert!(watched.contains(Signal::SIGUSR1));
+        assert!(watched.contains(Signal::SIGUSR2));
+        assert!(!watched.contains(Signal::SIGHUP));
+
+        let blocked = signals.remove_signals(&[Signal::SIGUSR1]).unwrap();
+        assert!(!blocked.contains(Signal::SIGUSR1));
+        assert!(blocked.contains(Signal::SIGHUP));
+    }
+
+    fn delivery_counts() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let dispatcher = Dispatcher::new(
+            Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2]).unwrap(),
+            |_, &mut (), &mut ()| {},
+        );
+        let _signal_source = event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        for &signal in &[Signal::SIGUSR1, Signal::SIGUSR1, Signal::SIGUSR2] {
+            kill(Pid::this(), signal).unwrap();
+            event_loop
+                .dispatch(Some(Duration::from_millis(10)), &mut ())
+                .unwrap();
+        }
+
+        let counts = dispatcher.as_source_ref().counts();
+        assert_eq!(counts.get(&Signal::SIGUSR1), Some(&2));
+        assert_eq!(counts.get(&Signal::SIGUSR2), Some(&1));
+
+        // removing a signal resets its count
+        dispatcher
+            .as_source_mut()
+            .remove_signals(&[Signal::SIGUSR2])
+            .unwrap();
+        let counts = dispatcher.as_source_ref().counts();
+        assert_eq!(counts.get(&Signal::SIGUSR1), Some(&2));
+        assert_eq!(counts.get(&Signal::SIGUSR2), None);
+
+        dispatcher.as_source_mut().reset_counts();
+        assert!(dispatcher.as_source_ref().counts().is_empty());
+    }
+
+    fn one_signal_per_pass() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let _signal_source = event_loop
+            .handle()
+            .insert_source(
+                Signals::one_per_pass(&[Signal::SIGUSR1, Signal::SIGUSR2]).unwrap(),
+                |evt, &mut (), received: &mut Vec<Signal>| received.push(evt.signal()),
+            )
+            .map_err(Into::<io::Error>::into)
+            .unwrap();
+
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received.contains(&Signal::SIGUSR1));
+        assert!(received.contains(&Signal::SIGUSR2));
+
+        // nothing left to read
+        event_loop
+            .dispatch(Some(Duration::from_millis(0)), &mut received)
+            .unwrap();
+        assert_eq!(received.len(), 2);
+    }
+
+    #[cfg(feature = "test-util")]
+    fn collected_signals() {
+        use std::{cell::RefCell, rc::Rc};
+
+        use self::calloop::test_util::collector;
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        event_loop
+            .handle()
+            .insert_source(
+                Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2]).unwrap(),
+                collector(events.clone()),
+            )
+            .unwrap();
+
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        event_loop.dispatch_until_quiescent(&mut ()).unwrap();
+
+        let mut signals = events
+            .borrow()
+            .iter()
+            .map(|event| event.signal())
+            .collect::<Vec<_>>();
+        signals.sort_unstable_by_key(|signal| *signal as i32);
+        assert_eq!(signals, &[Signal::SIGUSR1, Signal::SIGUSR2]);
+    }
+
+    fn reinit_in_child() {
+        use self::nix::sys::wait::{waitpid, WaitStatus};
+        use self::nix::unistd::{fork, ForkResult};
+
+        let mut signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Child => {
+                // the test harness is single-threaded, so the child can go on normally
+                let ok = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                    signals.reinit_after_fork().unwrap();
+                    let mut event_loop = EventLoop::<bool>::try_new().unwrap();
+                    let dispatcher =
+                        Dispatcher::new(signals, |evt, &mut (), received: &mut bool| {
+                            *received = evt.signal() == Signal::SIGUSR1
+                        });
+                    let token = event_loop
+                        .handle()
+                        .register_dispatcher(dispatcher.clone())
+                        .unwrap();
+
+                    kill(Pid::this(), Signal::SIGUSR1).unwrap();
+                    let mut received = false;
+                    event_loop
+                        .dispatch(Some(Duration::from_millis(10)), &mut received)
+                        .unwrap();
+                    assert!(received);
+
+                    event_loop.handle().remove(token);
+                    let signals = dispatcher.into_source_inner();
+                    signals.abandon().unwrap();
+                    assert!(!SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+                }))
+                .is_ok();
+                unsafe { self::nix::libc::_exit(if ok { 0 } else { 1 }) };
+            }
+            ForkResult::Parent { child } => {
+                assert_eq!(waitpid(child, None).unwrap(), WaitStatus::Exited(child, 0));
+                // the parent keeps its own signalfd
+                let mut event_loop = EventLoop::<bool>::try_new().unwrap();
+                event_loop
+                    .handle()
+                    .insert_source(signals, |_, &mut (), received| *received = true)
+                    .map_err(Into::<io::Error>::into)
+                    .unwrap();
+                kill(Pid::this(), Signal::SIGUSR1).unwrap();
+                let mut received = false;
+                event_loop
+                    .dispatch(Some(Duration::from_millis(10)), &mut received)
+                    .unwrap();
+                assert!(received);
+            }
+        }
+    }
+
+    fn unmanaged_mask() {
+        let mut blocked = SigSet::empty();
+        blocked.add(Signal::SIGUSR1);
+        blocked.thread_block().unwrap();
+
+        let mut event_loop = EventLoop::<bool>::try_new().unwrap();
+        let dispatcher = Dispatcher::new(
+            Signals::unmanaged(&[Signal::SIGUSR1]).unwrap(),
+            |_, &mut (), received: &mut bool| *received = true,
+        );
+        let token = event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let mut received = false;
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert!(received);
+
+        // adding a signal does not block it
+        let mask = dispatcher
+            .as_source_mut()
+            .add_signals(&[Signal::SIGUSR2])
+            .unwrap();
+        assert!(!mask.contains(Signal::SIGUSR2));
+
+        // dropping the source leaves the mask untouched
+        event_loop.handle().remove(token);
+        drop(dispatcher);
+        let mask = SigSet::thread_get_mask().unwrap();
+        assert!(mask.contains(Signal::SIGUSR1));
+        assert!(!mask.contains(Signal::SIGUSR2));
+    }
+
+    fn live_signalfd_changes() {
+        let mut event_loop = EventLoop::<Vec<Signal>>::try_new().unwrap();
+        let dispatcher = Dispatcher::new(
+            Signals::new(&[Signal::SIGUSR1]).unwrap(),
+            |evt, &mut (), received: &mut Vec<Signal>| received.push(evt.signal()),
+        );
+        let _token = event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        // change the signals while the source is inserted
+        dispatcher
+            .as_source_mut()
+            .set_signals(&[Signal::SIGUSR2])
+            .unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGUSR2]);
+
+        // replace the signalfd, the source is reregistered by the loop
+        dispatcher.as_source_mut().reinit_after_fork().unwrap();
+        for _ in 0..2 {
+            kill(Pid::this(), Signal::SIGUSR2).unwrap();
+            event_loop
+                .dispatch(Some(Duration::from_millis(10)), &mut received)
+                .unwrap();
+        }
+        assert_eq!(
+            received,
+            &[Signal::SIGUSR2, Signal::SIGUSR2, Signal::SIGUSR2]
+        );
+    }
+
+    fn blocking_wait() {
+        let mut signals = Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2]).unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        let event = signals.wait().unwrap();
+        assert_eq!(event.signal(), Signal::SIGUSR2);
+    }
+
+    fn typed_info() {
+        let mut signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let info = signals.wait().unwrap().info();
+        assert_eq!(info.signal, Signal::SIGUSR1);
+        assert_eq!(info.code, self::nix::libc::SI_USER);
+        assert_eq!(info.pid, Pid::this());
+        assert_eq!(info.uid, self::nix::unistd::getuid().as_raw());
+    }
+
+    fn posix_timer() {
+        use self::calloop::signals::PosixTimer;
+
+        let mut signals = Signals::new(&[Signal::SIGALRM]).unwrap();
+        let timer = PosixTimer::new(Signal::SIGALRM, 42).unwrap();
+        timer.set(Duration::from_millis(10), None).unwrap();
+
+        let event = signals.wait().unwrap();
+        assert_eq!(event.value_int(), 42);
+        assert_eq!(event.info().code, self::nix::libc::SI_TIMER);
+        assert_eq!(timer.overrun().unwrap(), 0);
+    }
+
+    fn blocking_mode() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let mut signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        assert!(signals.is_nonblocking());
+
+        // drive the source outside of the loop
+        signals.set_nonblocking(false).unwrap();
+        assert!(!signals.is_nonblocking());
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        assert_eq!(signals.wait().unwrap().signal(), Signal::SIGUSR1);
+
+        // a blocking source cannot be inserted
+        let signals = event_loop
+            .handle()
+            .insert_source(signals, |_, _, _| {})
+            .unwrap_err()
+            .source;
+
+        // then drive it with the loop
+        let dispatcher = Dispatcher::new(signals, |_, _, _: &mut ()| {});
+        let mut signals = dispatcher.as_source_mut();
+        signals.set_nonblocking(true).unwrap();
+        drop(signals);
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+        // it cannot be made blocking while registered
+        assert!(matches!(
+            dispatcher.as_source_mut().set_nonblocking(false),
+            Err(SignalsError::FdFlags(_))
+        ));
+    }
+
+    fn shared_signal() {
+        let first = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        let mut second = Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2]).unwrap();
+
+        // the first source does not unblock the signal still watched by the second one
+        drop(first);
+        let blocked = second.blocked_signals().unwrap();
+        assert!(blocked.contains(Signal::SIGUSR1));
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        assert_eq!(second.wait().unwrap().signal(), Signal::SIGUSR1);
+
+        // the same goes for the changes of the watched signals
+        let mut third = Signals::new(&[Signal::SIGUSR2]).unwrap();
+        let blocked = third.remove_signals(&[Signal::SIGUSR2]).unwrap();
+        assert!(blocked.contains(Signal::SIGUSR2));
+        let blocked = second.set_signals(&[Signal::SIGUSR1]).unwrap();
+        assert!(!blocked.contains(Signal::SIGUSR2));
+        drop(second);
+        assert!(!third.blocked_signals().unwrap().contains(Signal::SIGUSR1));
+    }
+
+    fn adopted_signalfd() {
+        use self::calloop::signals::SignalFd;
+
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGUSR1);
+        mask.thread_block().unwrap();
+        let sfd = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK).unwrap();
+
+        let mut event_loop = EventLoop::<Vec<Signal>>::try_new().unwrap();
+        let dispatcher = Dispatcher::new(
+            Signals::from_signalfd(sfd, mask).unwrap(),
+            |evt, &mut (), received: &mut Vec<Signal>| received.push(evt.signal()),
+        );
+        let token = event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGUSR1]);
+
+        // the changes apply to the adopted mask, not to the thread
+        let blocked = dispatcher
+            .as_source_mut()
+            .add_signals(&[Signal::SIGUSR2])
+            .unwrap();
+        assert!(!blocked.contains(Signal::SIGUSR2));
+        assert!(dispatcher
+            .as_source_ref()
+            .signals()
+            .contains(Signal::SIGUSR2));
+
+        // by default, the mask is left to the caller
+        event_loop.handle().remove(token);
+        drop(dispatcher);
+        assert!(SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+
+        let sfd = SignalFd::new(&mask).unwrap();
+        let signals = Signals::from_signalfd(sfd, mask).unwrap();
+        // the adopted signalfd is blocking
+        assert!(!signals.is_nonblocking());
+        drop(signals.restore_mask_on_drop(true));
+        assert!(!SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+    }
+
+    fn sender_enrichment() {
+        use self::calloop::signals::{EnrichedEvent, SerializableSignalEvent};
+
+        let mut signals = Signals::new(&[Signal::SIGUSR1])
+            .unwrap()
+            .with_sender_enrichment();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let enriched = signals.wait().unwrap();
+        let cgroup = std::fs::read_to_string("/proc/self/cgroup").unwrap();
+        assert_eq!(enriched.sender_cgroup.as_deref(), Some(cgroup.trim_end()));
+        assert_eq!(enriched.event.signal(), Signal::SIGUSR1);
+        // the plain event stays Copy
+        let event = enriched.event;
+        assert_eq!(event.signal(), enriched.event.signal());
+        assert_eq!(
+            SerializableSignalEvent::from(&enriched).sender_cgroup,
+            enriched.sender_cgroup
+        );
+        assert_eq!(SerializableSignalEvent::from(&event).sender_cgroup, None);
+
+        // as an event source
+        let mut event_loop = EventLoop::<Vec<EnrichedEvent>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(signals, |event, &mut (), received| received.push(event))
+            .unwrap();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut received)
+            .unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].sender_cgroup.as_deref(),
+            Some(cgroup.trim_end())
+        );
+    }
+
+    fn recorded_event() {
+        use self::calloop::signals::{Event, SerializableSignalEvent};
+
+        let mut signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let event = signals.wait().unwrap();
+
+        let record = SerializableSignalEvent::from(&event);
+        assert_eq!(record.signal, Signal::SIGUSR1 as i32);
+        assert_eq!(record.pid, Pid::this().as_raw());
+
+        // the decoded fields survive the round trip
+        let replayed = Event::from(record.clone());
+        assert_eq!(replayed.signal(), Signal::SIGUSR1);
+        assert_eq!(replayed.info().pid, Pid::this());
+        assert_eq!(replayed.info().code, event.info().code);
+        assert!(replayed.received_at() <= Instant::now());
+        assert_eq!(
+            SerializableSignalEvent::from(&replayed).signal,
+            record.signal
+        );
+
+        // a garbage time is replayed as now
+        let garbage = SerializableSignalEvent {
+            received_at: Duration::new(std::u64::MAX, 0),
+            ..record
+        };
+        let before = Instant::now();
+        assert!(Event::from(garbage).received_at() >= before);
+
+        #[cfg(feature = "serde")]
+        {
+            fn serializable<T: serde::Serialize + serde::de::DeserializeOwned>() {}
+            serializable::<SerializableSignalEvent>();
+        }
+    }
+
+    fn config_round_trip() {
+        use self::calloop::signals::SignalsConfig;
+
+        let signals = Signals::new(&[Signal::SIGUSR2, Signal::SIGUSR1]).unwrap();
+        let config = signals.export_config();
+        assert_eq!(
+            config.signals,
+            &[Signal::SIGUSR1 as i32, Signal::SIGUSR2 as i32]
+        );
+        drop(signals);
+
+        let mut restored = Signals::new(&[Signal::SIGHUP]).unwrap();
+        restored.apply_config(&config).unwrap();
+        assert_eq!(restored.export_config(), config);
+
+        // an invalid configuration is not partially applied
+        let invalid = SignalsConfig {
+            signals: vec![Signal::SIGHUP as i32, 0, 1000],
+        };
+        match restored.apply_config(&invalid) {
+            Err(SignalsError::InvalidConfig(e)) => {
+                assert_eq!(e.to_string(), "invalid signal numbers: 0, 1000")
+            }
+            ret => panic!("unexpected result: {:?}", ret),
+        }
+        assert_eq!(restored.export_config(), config);
+    }
+
+    fn ordered_delivery() {
+        let mut event_loop = EventLoop::<Vec<Signal>>::try_new().unwrap();
+        // the highest signal numbers first
+        let signals = Signals::new(&[Signal::SIGUSR1, Signal::SIGUSR2])
+            .unwrap()
+            .with_ordered_delivery(|a, b| b.signal().cmp(&a.signal()));
+        event_loop
+            .handle()
+            .insert_source(signals, |event, &mut (), received| {
+                received.push(event.signal())
+            })
+            .unwrap();
+
+        // the kernel queues the lowest standard signals first
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGUSR2, Signal::SIGUSR1]);
+    }
+
+    fn threaded_delivery() {
+        let mut event_loop = EventLoop::<Vec<Signal>>::try_new().unwrap();
+        let config = ThreadConfig::new().name_prefix("watcher");
+        let signals =
+            ThreadedSignals::with_thread_config(&[Signal::SIGUSR1, Signal::SIGUSR2], &config)
+                .unwrap();
+        let dispatcher = Dispatcher::new(signals, |event, &mut (), received: &mut Vec<_>| {
+            assert_eq!(event.pid(), Some(Pid::this()));
+            received.push(event.signal())
+        });
+        // the thread is named after the configuration
+        assert!(std::fs::read_dir("/proc/self/task").unwrap().any(|task| {
+            let comm = std::fs::read_to_string(task.unwrap().path().join("comm")).unwrap();
+            comm.trim_end() == "watcher signals"
+        }));
+        let token = event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        // the thread forwards the signal asynchronously
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while received.is_empty() && start.elapsed() < Duration::from_secs(1) {
+            event_loop
+                .dispatch(Duration::from_millis(10), &mut received)
+                .unwrap();
+        }
+        assert_eq!(received, &[Signal::SIGUSR2]);
+        assert_eq!(dispatcher.as_source_ref().counts()[&Signal::SIGUSR2], 1);
+
+        // dropping the source stops its thread and unblocks the signals
+        event_loop.handle().remove(token);
+        drop(dispatcher);
+        let mask = SigSet::thread_get_mask().unwrap();
+        assert!(!mask.contains(Signal::SIGUSR1));
+        assert!(!mask.contains(Signal::SIGUSR2));
+    }
+
+    fn unwatched_default() {
+        let mut event_loop = EventLoop::<Vec<Signal>>::try_new().unwrap();
+        let signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        let changed = signals.set_unwatched_default(SigHandler::SigIgn).unwrap();
+        assert!(!changed.contains(Signal::SIGUSR1));
+        assert!(changed.contains(Signal::SIGUSR2));
+        assert!(!changed.contains(Signal::SIGSEGV));
+        event_loop
+            .handle()
+            .insert_source(signals, |event, &mut (), received| {
+                received.push(event.signal())
+            })
+            .unwrap();
+
+        // the stray signals do not kill the process
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        let mut received = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGUSR1]);
+
+        let signals = Signals::new(&[]).unwrap();
+        signals
+            .set_unwatched_default(SigHandler::Handler(report_unwatched_signal))
+            .unwrap();
+        kill(Pid::this(), Signal::SIGUSR2).unwrap();
+        signals.set_unwatched_default(SigHandler::SigDfl).unwrap();
+    }
+
+    fn drop_error_policy() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let reported = Arc::new(AtomicBool::new(false));
+        let hook_reported = reported.clone();
+        let mut signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        signals.set_drop_error_policy(Some(Box::new(move |_| {
+            hook_reported.store(true, Ordering::SeqCst)
+        })));
+        drop(signals);
+        // unblocking the signals succeeded, the hook is not invoked
+        assert!(!reported.load(Ordering::SeqCst));
+        assert!(!SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+    }
+
+    fn remove_from_callback() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let handle = event_loop.handle();
+        let token = Rc::new(Cell::new(None));
+        let own_token = token.clone();
+        let inserted = event_loop
+            .handle()
+            .insert_source(
+                Signals::new(&[Signal::SIGUSR1]).unwrap(),
+                move |evt, &mut (), rcv: &mut Vec<Signal>| {
+                    // a one-shot source removing itself
+                    handle.remove(own_token.get().unwrap());
+                    rcv.push(evt.signal());
+                },
+            )
+            .unwrap();
+        token.set(Some(inserted));
+
+        let mut received = Vec::new();
+        kill(Pid::this(), Signal::SIGUSR1).unwrap();
+        event_loop
+            .dispatch(Some(Duration::from_millis(10)), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGUSR1]);
+        // the source is dropped once the pass is over, unblocking its signals
+        assert!(!SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+        event_loop
+            .dispatch(Some(Duration::from_millis(0)), &mut received)
+            .unwrap();
+        assert_eq!(received, &[Signal::SIGUSR1]);
+    }
+
+    fn thread_handoff() {
+        let signals = Signals::new(&[Signal::SIGUSR1]).unwrap();
+        let detached = signals.detach_from_thread();
+        assert!(!SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+
+        let received = std::thread::spawn(move || {
+            let mut signals = detached.attach_to_current_thread().unwrap();
+            assert!(SigSet::thread_get_mask().unwrap().contains(Signal::SIGUSR1));
+            // sent to this thread, as the main one does not block the signal anymore
+            nix::sys::signal::raise(Signal::SIGUSR1).unwrap();
+            signals.wait().unwrap().signal()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(received, Signal::SIGUSR1);
+    }
 }